@@ -4,6 +4,7 @@ pub mod config;
 pub mod editor;
 #[cfg(not(target_arch = "wasm32"))]
 pub mod protocol;
+pub mod loaders;
 pub mod raytracer;
 pub mod utils;
 
@@ -0,0 +1,416 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use ::gltf::camera::Projection as GltfProjection;
+use ::gltf::image::Format;
+use ::gltf::mesh::Mode;
+use glam::{Mat3, Mat4, Vec2, Vec3};
+use thiserror::Error;
+use tracing::warn;
+
+use crate::raytracer::{
+    Aabb, Bounded, Camera, Geometry, ImageTexture, Material, Mesh, Model, NormalMap, Principled,
+    Projection, Scene, SceneError, Texture,
+};
+
+/// glTF extensions whose content is imported.
+const SUPPORTED_EXTENSIONS: &[&str] = &[
+    "KHR_materials_emissive_strength",
+    "KHR_materials_ior",
+    "KHR_materials_transmission",
+];
+
+#[derive(Debug, Error)]
+pub enum GltfError {
+    #[error("{0}")]
+    Gltf(#[from] ::gltf::Error),
+    #[error("glTF file has no scene")]
+    NoScene,
+    #[error("glTF scene has no triangle meshes")]
+    NoMeshes,
+    #[error("{0}")]
+    InvalidScene(#[from] SceneError),
+}
+
+type GltfResult<T> = Result<T, GltfError>;
+
+/// Content of a glTF scene, with node transforms applied to the meshes.
+pub struct GltfImport {
+    pub models: Vec<Arc<Model>>,
+    /// First camera of the node hierarchy, if any.
+    pub camera: Option<Camera>,
+}
+
+/// Import the default scene of a `.gltf` or `.glb` file, along with its
+/// embedded or external buffers and textures. Content that can't be
+/// represented, such as lights or unsupported extensions, is skipped with a
+/// warning.
+pub fn import_gltf<P: AsRef<Path>>(path: P, aspect_ratio: f32) -> GltfResult<GltfImport> {
+    let (document, buffers, images) = ::gltf::import(path)?;
+    for extension in document.extensions_used() {
+        if !SUPPORTED_EXTENSIONS.contains(&extension) {
+            warn!("Unsupported glTF extension '{extension}' is ignored");
+        }
+    }
+
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .ok_or(GltfError::NoScene)?;
+    let mut importer = Importer {
+        buffers: &buffers,
+        images: &images,
+        aspect_ratio,
+        materials: HashMap::new(),
+        textures: HashMap::new(),
+        models: Vec::new(),
+        camera: None,
+    };
+    for node in scene.nodes() {
+        importer.import_node(&node, Mat4::IDENTITY);
+    }
+    if importer.models.is_empty() {
+        return Err(GltfError::NoMeshes);
+    }
+
+    Ok(GltfImport {
+        models: importer.models,
+        camera: importer.camera,
+    })
+}
+
+/// Load a glTF file as a scene lit by a uniform white background. Without a
+/// camera in the file, a camera looking at the whole scene along -z is used.
+pub fn load_gltf<P: AsRef<Path>>(path: P, aspect_ratio: f32) -> GltfResult<Scene> {
+    let import = import_gltf(path, aspect_ratio)?;
+    let camera = match import.camera {
+        Some(camera) => camera,
+        None => {
+            let aabb = import.models.iter().fold(Aabb::empty(), |aabb, model| {
+                Aabb::surround(&aabb, &model.aabb())
+            });
+            framing_camera(&aabb, aspect_ratio)?
+        }
+    };
+    Ok(Scene::try_with_background(
+        camera,
+        import.models,
+        Vec3::ONE,
+    )?)
+}
+
+/// Camera looking along -z at the whole bounding box.
+fn framing_camera(aabb: &Aabb, aspect_ratio: f32) -> Result<Camera, SceneError> {
+    let fov: f32 = 45.0;
+    let center = (aabb.min_position + aabb.max_position) / 2.0;
+    let radius = (aabb.max_position - aabb.min_position).length() / 2.0;
+    let distance = radius / (fov.to_radians() / 2.0).sin();
+    Camera::try_new(
+        center + Vec3::Z * distance,
+        Vec3::NEG_Z,
+        Vec3::NEG_Y,
+        fov,
+        aspect_ratio,
+    )
+}
+
+/// Kind of texture created from a glTF image, since the same image may be
+/// used as color, normals or packed metallic and roughness.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum TextureKind {
+    Color,
+    Linear,
+    Roughness,
+    Metallic,
+}
+
+struct Importer<'a> {
+    buffers: &'a [::gltf::buffer::Data],
+    images: &'a [::gltf::image::Data],
+    aspect_ratio: f32,
+    materials: HashMap<Option<usize>, Arc<Material>>,
+    textures: HashMap<(usize, TextureKind), Arc<ImageTexture>>,
+    models: Vec<Arc<Model>>,
+    camera: Option<Camera>,
+}
+
+impl Importer<'_> {
+    fn import_node(&mut self, node: &::gltf::Node, parent_transform: Mat4) {
+        let transform = parent_transform * Mat4::from_cols_array_2d(&node.transform().matrix());
+
+        if let Some(mesh) = node.mesh() {
+            for primitive in mesh.primitives() {
+                self.import_primitive(&primitive, transform);
+            }
+        }
+        if let Some(camera) = node.camera()
+            && self.camera.is_none()
+        {
+            self.camera = self.import_camera(&camera, transform);
+        }
+
+        for child in node.children() {
+            self.import_node(&child, transform);
+        }
+    }
+
+    fn import_camera(&self, camera: &::gltf::Camera, transform: Mat4) -> Option<Camera> {
+        // Cameras keep the aspect ratio of the rendered image
+        let projection = match camera.projection() {
+            GltfProjection::Perspective(perspective) => Projection::Perspective {
+                fov: perspective.yfov().to_degrees(),
+            },
+            GltfProjection::Orthographic(orthographic) => Projection::Orthographic {
+                height: 2.0 * orthographic.ymag(),
+            },
+        };
+        let forward = transform.transform_vector3(Vec3::NEG_Z).try_normalize()?;
+        let up = transform.transform_vector3(Vec3::Y);
+        let up = (up - forward * forward.dot(up)).try_normalize()?;
+        // NOTE: Viewport rows grow along the camera up vector, so the world up
+        // is flipped as in the test scenes
+        Camera::try_new_with_projection(
+            transform.transform_point3(Vec3::ZERO),
+            forward,
+            -up,
+            projection,
+            self.aspect_ratio,
+        )
+        .inspect_err(|err| warn!("glTF camera is ignored: {err}"))
+        .ok()
+    }
+
+    fn import_primitive(&mut self, primitive: &::gltf::Primitive, transform: Mat4) {
+        if primitive.mode() != Mode::Triangles {
+            warn!(
+                "glTF primitive mode {:?} is not supported, only triangles",
+                primitive.mode()
+            );
+            return;
+        }
+        let reader = primitive.reader(|buffer| Some(&self.buffers[buffer.index()]));
+        let Some(positions) = reader.read_positions() else {
+            warn!("glTF primitive without positions is ignored");
+            return;
+        };
+        let positions: Vec<Vec3> = positions
+            .map(|position| transform.transform_point3(Vec3::from(position)))
+            .collect();
+        let mut indices: Vec<[u32; 3]> = match reader.read_indices() {
+            Some(indices) => {
+                let indices: Vec<u32> = indices.into_u32().collect();
+                indices
+                    .chunks_exact(3)
+                    .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+                    .collect()
+            }
+            None => (0..positions.len() as u32 / 3)
+                .map(|idx| [3 * idx, 3 * idx + 1, 3 * idx + 2])
+                .collect(),
+        };
+        if indices.is_empty() {
+            return;
+        }
+        if indices
+            .iter()
+            .flatten()
+            .any(|&idx| idx as usize >= positions.len())
+        {
+            warn!("glTF primitive with out of bounds indices is ignored");
+            return;
+        }
+        // Mirroring transforms flip the triangles winding
+        if transform.determinant() < 0.0 {
+            for triangle in indices.iter_mut() {
+                triangle.swap(1, 2);
+            }
+        }
+
+        let vertex_count = positions.len();
+        let mut mesh = Mesh::new(positions, indices);
+        if let Some(normals) = reader.read_normals() {
+            let normal_matrix = Mat3::from_mat4(transform).inverse().transpose();
+            let normals: Vec<Vec3> = normals
+                .map(|normal| (normal_matrix * Vec3::from(normal)).normalize_or_zero())
+                .collect();
+            if normals.len() == vertex_count {
+                mesh = mesh.with_normals(normals);
+            } else {
+                warn!("glTF primitive normals don't match its positions, ignoring them");
+            }
+        }
+        if let Some(uvs) = reader.read_tex_coords(0) {
+            // glTF uvs start at the top left corner of images
+            let uvs: Vec<Vec2> = uvs.into_f32().map(|[u, v]| Vec2::new(u, 1.0 - v)).collect();
+            if uvs.len() == vertex_count {
+                mesh = mesh.with_uvs(uvs);
+            } else {
+                warn!("glTF primitive uvs don't match its positions, ignoring them");
+            }
+        }
+
+        let material = self.import_material(&primitive.material());
+        self.models.push(Arc::new(Model::new(
+            Geometry::Mesh(Arc::new(mesh)),
+            material,
+        )));
+    }
+
+    fn import_material(&mut self, material: &::gltf::Material) -> Arc<Material> {
+        if let Some(material) = self.materials.get(&material.index()) {
+            return material.clone();
+        }
+
+        let pbr = material.pbr_metallic_roughness();
+        let [r, g, b, _] = pbr.base_color_factor();
+        let base_color = self.factor_texture(
+            Vec3::new(r, g, b),
+            pbr.base_color_texture(),
+            TextureKind::Color,
+        );
+        let metallic = self.factor_texture(
+            Vec3::splat(pbr.metallic_factor()),
+            pbr.metallic_roughness_texture(),
+            TextureKind::Metallic,
+        );
+        let roughness = self.factor_texture(
+            Vec3::splat(pbr.roughness_factor()),
+            pbr.metallic_roughness_texture(),
+            TextureKind::Roughness,
+        );
+        let emission = self.factor_texture(
+            Vec3::from(material.emissive_factor()) * material.emissive_strength().unwrap_or(1.0),
+            material.emissive_texture(),
+            TextureKind::Color,
+        );
+
+        let principled = Material::Principled(Principled {
+            base_color,
+            metallic,
+            roughness,
+            specular: 0.5,
+            transmission: material
+                .transmission()
+                .map_or(0.0, |transmission| transmission.transmission_factor()),
+            ior: material.ior().unwrap_or(1.5),
+            emission,
+            ..Default::default()
+        });
+        let imported = match material.normal_texture() {
+            Some(normal) => {
+                if normal.tex_coord() != 0 {
+                    warn!("glTF textures only use the first texture coordinates set");
+                }
+                let texture = self.texture(&normal.texture(), TextureKind::Linear);
+                Material::NormalMapped {
+                    material: Arc::new(principled),
+                    normal_map: NormalMap::Tangent {
+                        texture: Texture::Image(texture),
+                        strength: normal.scale(),
+                    },
+                }
+            }
+            None => principled,
+        };
+
+        let imported = Arc::new(imported);
+        self.materials.insert(material.index(), imported.clone());
+        imported
+    }
+
+    /// Texture multiplying a constant factor by an optional image texture.
+    fn factor_texture(
+        &mut self,
+        factor: Vec3,
+        info: Option<::gltf::texture::Info>,
+        kind: TextureKind,
+    ) -> Texture {
+        let Some(info) = info else {
+            return factor.into();
+        };
+        if info.tex_coord() != 0 {
+            warn!("glTF textures only use the first texture coordinates set");
+        }
+        let texture = Texture::Image(self.texture(&info.texture(), kind));
+        if factor == Vec3::ONE {
+            texture
+        } else {
+            Texture::Scale {
+                texture: Arc::new(texture),
+                factor,
+            }
+        }
+    }
+
+    fn texture(&mut self, texture: &::gltf::Texture, kind: TextureKind) -> Arc<ImageTexture> {
+        let image_idx = texture.source().index();
+        if let Some(texture) = self.textures.get(&(image_idx, kind)) {
+            return texture.clone();
+        }
+
+        let image = &self.images[image_idx];
+        let rgb = image_to_rgb8(image);
+        let pixels = match kind {
+            TextureKind::Color | TextureKind::Linear => rgb,
+            // Roughness and metallic are packed in the green and blue channels
+            TextureKind::Roughness => rgb.chunks_exact(3).flat_map(|p| [p[1]; 3]).collect(),
+            TextureKind::Metallic => rgb.chunks_exact(3).flat_map(|p| [p[2]; 3]).collect(),
+        };
+        let texture = Arc::new(ImageTexture::new(
+            image.width,
+            image.height,
+            pixels,
+            kind == TextureKind::Color,
+        ));
+        self.textures.insert((image_idx, kind), texture.clone());
+        texture
+    }
+}
+
+/// Convert decoded glTF image pixels to 8 bit RGB, dropping alpha and
+/// keeping the most significant bits of wider formats.
+fn image_to_rgb8(image: &::gltf::image::Data) -> Vec<u8> {
+    let to_u8 = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+    let float_channels = |channels: usize| -> Vec<u8> {
+        image
+            .pixels
+            .chunks_exact(4 * channels)
+            .flat_map(|pixel| {
+                let channel = |idx: usize| {
+                    f32::from_le_bytes(pixel[4 * idx..4 * idx + 4].try_into().unwrap())
+                };
+                [to_u8(channel(0)), to_u8(channel(1)), to_u8(channel(2))]
+            })
+            .collect()
+    };
+    // 16 bit channels are little endian, so the high byte comes second
+    let pixels = &image.pixels;
+    match image.format {
+        Format::R8 => pixels.iter().flat_map(|&v| [v; 3]).collect(),
+        Format::R8G8 => pixels
+            .chunks_exact(2)
+            .flat_map(|p| [p[0], p[1], 0])
+            .collect(),
+        Format::R8G8B8 => pixels.clone(),
+        Format::R8G8B8A8 => pixels
+            .chunks_exact(4)
+            .flat_map(|p| [p[0], p[1], p[2]])
+            .collect(),
+        Format::R16 => pixels.chunks_exact(2).flat_map(|p| [p[1]; 3]).collect(),
+        Format::R16G16 => pixels
+            .chunks_exact(4)
+            .flat_map(|p| [p[1], p[3], 0])
+            .collect(),
+        Format::R16G16B16 => pixels
+            .chunks_exact(6)
+            .flat_map(|p| [p[1], p[3], p[5]])
+            .collect(),
+        Format::R16G16B16A16 => pixels
+            .chunks_exact(8)
+            .flat_map(|p| [p[1], p[3], p[5]])
+            .collect(),
+        Format::R32G32B32FLOAT => float_channels(3),
+        Format::R32G32B32A32FLOAT => float_channels(4),
+    }
+}
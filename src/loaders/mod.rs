@@ -0,0 +1,7 @@
+pub mod gltf;
+pub mod obj;
+pub mod scene_file;
+
+pub use self::gltf::*;
+pub use obj::*;
+pub use scene_file::*;
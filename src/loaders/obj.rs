@@ -0,0 +1,496 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use glam::{Vec2, Vec3};
+use thiserror::Error;
+use tracing::warn;
+
+use crate::raytracer::{
+    ConductorReflectance, Geometry, ImageTexture, Material, Mesh, Model, Principled, Texture,
+};
+
+#[derive(Debug, Error)]
+pub enum ObjError {
+    #[error("Failed to read '{}': {source}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Failed to load texture '{}': {source}", path.display())]
+    Texture {
+        path: PathBuf,
+        #[source]
+        source: image::ImageError,
+    },
+    #[error("{}:{line}: Face index {index} is out of bounds", path.display())]
+    BadFaceIndex {
+        path: PathBuf,
+        line: usize,
+        index: i64,
+    },
+    #[error("{}:{line}: Unsupported statement '{statement}'", path.display())]
+    UnsupportedStatement {
+        path: PathBuf,
+        line: usize,
+        statement: String,
+    },
+    #[error("{}:{line}: {message}", path.display())]
+    Syntax {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+}
+
+type ObjResult<T> = Result<T, ObjError>;
+
+/// Load a Wavefront OBJ file and the MTL files it references. Faces are
+/// grouped into one mesh per object or group and material, so each `Model`
+/// is a single entry of the scene BVH.
+pub fn load_obj<P: AsRef<Path>>(path: P) -> ObjResult<Vec<Arc<Model>>> {
+    let path = path.as_ref();
+    let mut parser = ObjParser::default();
+    for (line, keyword, args) in statements(path)? {
+        parser.parse_statement(path, line, keyword, &args)?;
+    }
+    parser.into_models(path)
+}
+
+/// Logical lines of an OBJ or MTL file, split into a keyword and arguments,
+/// along with their line number. Comments are removed and lines ending with
+/// a backslash are joined with the next one.
+fn statements(path: &Path) -> ObjResult<Vec<(usize, String, Vec<String>)>> {
+    let content = std::fs::read_to_string(path).map_err(|source| ObjError::Io {
+        path: path.to_owned(),
+        source,
+    })?;
+
+    let mut statements = Vec::new();
+    let mut pending: Option<(usize, String)> = None;
+    for (idx, raw_line) in content.lines().enumerate() {
+        let raw_line = raw_line.split('#').next().unwrap_or_default();
+        let (line, mut text) = pending.take().unwrap_or((idx + 1, String::new()));
+        text.push(' ');
+        match raw_line.trim_end().strip_suffix('\\') {
+            Some(continued) => {
+                text.push_str(continued);
+                pending = Some((line, text));
+                continue;
+            }
+            None => text.push_str(raw_line),
+        }
+
+        let mut tokens = text.split_whitespace().map(str::to_owned);
+        if let Some(keyword) = tokens.next() {
+            statements.push((line, keyword, tokens.collect()));
+        }
+    }
+    Ok(statements)
+}
+
+fn syntax_error(path: &Path, line: usize, message: impl Into<String>) -> ObjError {
+    ObjError::Syntax {
+        path: path.to_owned(),
+        line,
+        message: message.into(),
+    }
+}
+
+/// Parse the first `N` statement arguments as floats, where only the first
+/// `required` ones must be present and missing ones are zero.
+fn parse_floats<const N: usize>(
+    path: &Path,
+    line: usize,
+    args: &[String],
+    required: usize,
+) -> ObjResult<[f32; N]> {
+    if args.len() < required {
+        return Err(syntax_error(
+            path,
+            line,
+            format!("Expected at least {required} values"),
+        ));
+    }
+    let mut values = [0.0; N];
+    for (value, arg) in values.iter_mut().zip(args) {
+        *value = arg
+            .parse()
+            .map_err(|_| syntax_error(path, line, format!("Invalid number '{arg}'")))?;
+    }
+    Ok(values)
+}
+
+/// Parse an MTL color given either as a single grey value or as 3 values.
+fn parse_color(path: &Path, line: usize, args: &[String]) -> ObjResult<Vec3> {
+    if args.len() == 1 {
+        let [value] = parse_floats(path, line, args, 1)?;
+        return Ok(Vec3::splat(value));
+    }
+    Ok(Vec3::from(parse_floats(path, line, args, 3)?))
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// OBJ
+////////////////////////////////////////////////////////////////////////////////
+
+/// Vertex of a face, as indices into the positions, uvs and normals.
+type FaceVertex = (usize, Option<usize>, Option<usize>);
+
+/// Triangles sharing the same object or group and material.
+#[derive(Default)]
+struct MeshBuilder {
+    /// Line of the first face, to report problems with the mesh.
+    line: usize,
+    material: Option<String>,
+    positions: Vec<Vec3>,
+    uvs: Vec<Vec2>,
+    normals: Vec<Vec3>,
+    indices: Vec<[u32; 3]>,
+    vertices: HashMap<FaceVertex, u32>,
+    has_uvs: bool,
+    has_normals: bool,
+}
+
+#[derive(Default)]
+struct ObjParser {
+    positions: Vec<Vec3>,
+    uvs: Vec<Vec2>,
+    normals: Vec<Vec3>,
+    materials: HashMap<String, Arc<Material>>,
+    group: String,
+    material: Option<String>,
+    meshes: Vec<MeshBuilder>,
+    mesh_indices: HashMap<(String, Option<String>), usize>,
+}
+
+impl ObjParser {
+    fn parse_statement(
+        &mut self,
+        path: &Path,
+        line: usize,
+        keyword: String,
+        args: &[String],
+    ) -> ObjResult<()> {
+        match keyword.as_str() {
+            "v" => {
+                // Optional w and vertex colors are ignored
+                let [x, y, z] = parse_floats(path, line, args, 3)?;
+                self.positions.push(Vec3::new(x, y, z));
+            }
+            "vt" => {
+                let [u, v] = parse_floats(path, line, args, 1)?;
+                self.uvs.push(Vec2::new(u, v));
+            }
+            "vn" => {
+                let [x, y, z] = parse_floats(path, line, args, 3)?;
+                self.normals.push(Vec3::new(x, y, z));
+            }
+            "f" => {
+                if args.len() < 3 {
+                    return Err(syntax_error(path, line, "Face has less than 3 vertices"));
+                }
+                let vertices = args
+                    .iter()
+                    .map(|arg| self.parse_face_vertex(path, line, arg))
+                    .collect::<ObjResult<Vec<_>>>()?;
+                self.add_polygon(path, line, &vertices)?;
+            }
+            "o" | "g" => self.group = args.join(" "),
+            "usemtl" => {
+                let name = args.join(" ");
+                if !self.materials.contains_key(&name) {
+                    warn!("{}:{line}: Unknown material '{name}'", path.display());
+                }
+                self.material = Some(name);
+            }
+            "mtllib" => {
+                let directory = path.parent().unwrap_or(Path::new(""));
+                for file in args {
+                    self.materials.extend(load_mtl(&directory.join(file))?);
+                }
+            }
+            // Smoothing groups are implied by vertex normals
+            "s" => {}
+            _ => {
+                return Err(ObjError::UnsupportedStatement {
+                    path: path.to_owned(),
+                    line,
+                    statement: keyword,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Parse a face vertex in one of the `v`, `v/vt`, `v//vn` or `v/vt/vn`
+    /// forms, where negative indices are relative to the last element.
+    fn parse_face_vertex(&self, path: &Path, line: usize, arg: &str) -> ObjResult<FaceVertex> {
+        let resolve = |index: &str, count: usize| -> ObjResult<usize> {
+            let index: i64 = index
+                .parse()
+                .map_err(|_| syntax_error(path, line, format!("Invalid face vertex '{arg}'")))?;
+            let resolved = if index > 0 {
+                index - 1
+            } else {
+                count as i64 + index
+            };
+            if index == 0 || resolved < 0 || resolved >= count as i64 {
+                return Err(ObjError::BadFaceIndex {
+                    path: path.to_owned(),
+                    line,
+                    index,
+                });
+            }
+            Ok(resolved as usize)
+        };
+
+        let mut parts = arg.split('/');
+        let position = resolve(parts.next().unwrap_or_default(), self.positions.len())?;
+        let uv = match parts.next() {
+            Some("") | None => None,
+            Some(index) => Some(resolve(index, self.uvs.len())?),
+        };
+        let normal = match parts.next() {
+            Some("") | None => None,
+            Some(index) => Some(resolve(index, self.normals.len())?),
+        };
+        if parts.next().is_some() {
+            return Err(syntax_error(
+                path,
+                line,
+                format!("Invalid face vertex '{arg}'"),
+            ));
+        }
+        Ok((position, uv, normal))
+    }
+
+    /// Add a convex polygon to the current mesh as a triangle fan.
+    fn add_polygon(&mut self, path: &Path, line: usize, vertices: &[FaceVertex]) -> ObjResult<()> {
+        let key = (self.group.clone(), self.material.clone());
+        let mesh_idx = *self.mesh_indices.entry(key).or_insert_with(|| {
+            self.meshes.push(MeshBuilder {
+                line,
+                material: self.material.clone(),
+                has_uvs: true,
+                has_normals: true,
+                ..Default::default()
+            });
+            self.meshes.len() - 1
+        });
+
+        let mesh = &mut self.meshes[mesh_idx];
+        let mut indices = Vec::with_capacity(vertices.len());
+        for &vertex in vertices {
+            let (position, uv, normal) = vertex;
+            mesh.has_uvs &= uv.is_some();
+            mesh.has_normals &= normal.is_some();
+            if let Some(&idx) = mesh.vertices.get(&vertex) {
+                indices.push(idx);
+                continue;
+            }
+            let idx = u32::try_from(mesh.positions.len())
+                .map_err(|_| syntax_error(path, line, "Mesh has too many vertices"))?;
+            mesh.positions.push(self.positions[position]);
+            mesh.uvs.push(uv.map_or(Vec2::ZERO, |idx| self.uvs[idx]));
+            mesh.normals
+                .push(normal.map_or(Vec3::ZERO, |idx| self.normals[idx]));
+            mesh.vertices.insert(vertex, idx);
+            indices.push(idx);
+        }
+        for i in 1..indices.len() - 1 {
+            mesh.indices.push([indices[0], indices[i], indices[i + 1]]);
+        }
+        Ok(())
+    }
+
+    fn into_models(self, path: &Path) -> ObjResult<Vec<Arc<Model>>> {
+        let default_material = Arc::new(Material::Diffuse {
+            albedo: Vec3::splat(0.8).into(),
+        });
+        self.meshes
+            .into_iter()
+            .map(|builder| {
+                // Normals and uvs are dropped unless every vertex has them
+                let normals = if builder.has_normals {
+                    builder.normals
+                } else {
+                    Vec::new()
+                };
+                let uvs = if builder.has_uvs {
+                    builder.uvs
+                } else {
+                    Vec::new()
+                };
+                let mesh = Mesh::from_parts(builder.positions, normals, uvs, builder.indices)
+                    .ok_or_else(|| syntax_error(path, builder.line, "Invalid mesh data"))?;
+                let material = builder
+                    .material
+                    .and_then(|name| self.materials.get(&name).cloned())
+                    .unwrap_or_else(|| default_material.clone());
+                Ok(Arc::new(Model::new(
+                    Geometry::Mesh(Arc::new(mesh)),
+                    material,
+                )))
+            })
+            .collect()
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// MTL
+////////////////////////////////////////////////////////////////////////////////
+
+/// MTL statements that have no equivalent in our materials and are skipped.
+const IGNORED_MTL_STATEMENTS: &[&str] = &[
+    "Ka",
+    "Tf",
+    "illum",
+    "sharpness",
+    "map_Ka",
+    "map_Ks",
+    "map_Ns",
+    "map_d",
+    "map_Ke",
+    "map_Bump",
+    "map_bump",
+    "bump",
+    "disp",
+    "decal",
+    "refl",
+    "norm",
+    "Pr",
+    "Pm",
+    "Ps",
+    "Pc",
+    "Pcr",
+    "aniso",
+    "anisor",
+    "map_Pr",
+    "map_Pm",
+];
+
+/// Material parameters as described by an MTL file.
+struct MtlMaterial {
+    diffuse: Vec3,
+    diffuse_map: Option<PathBuf>,
+    specular: Vec3,
+    emission: Vec3,
+    shininess: f32,
+    refraction_index: f32,
+    dissolve: f32,
+}
+
+impl Default for MtlMaterial {
+    fn default() -> Self {
+        Self {
+            diffuse: Vec3::splat(0.8),
+            diffuse_map: None,
+            specular: Vec3::ZERO,
+            emission: Vec3::ZERO,
+            shininess: 0.0,
+            refraction_index: 1.5,
+            dissolve: 1.0,
+        }
+    }
+}
+
+impl MtlMaterial {
+    /// Map the MTL parameters onto the closest material:
+    /// - `Ke` makes a light,
+    /// - `d` below 1 makes glass with refraction index `Ni`,
+    /// - `Ks` without `Kd` makes a metal,
+    /// - `Ks` with `Kd` makes a plastic,
+    /// - `Kd` alone makes a diffuse surface.
+    fn to_material(&self) -> Result<Material, ObjError> {
+        // Shininess to roughness, as exported by Blender
+        let roughness = (1.0 - self.shininess.max(0.0).sqrt() / 30.0).clamp(0.0, 1.0);
+        let albedo = match &self.diffuse_map {
+            Some(path) => Texture::Image(Arc::new(ImageTexture::from_file(path, true).map_err(
+                |source| ObjError::Texture {
+                    path: path.clone(),
+                    source,
+                },
+            )?)),
+            None => self.diffuse.into(),
+        };
+        let has_diffuse = self.diffuse_map.is_some() || self.diffuse != Vec3::ZERO;
+        let has_specular = self.specular.max_element() > 0.0;
+
+        let material = if self.emission != Vec3::ZERO {
+            Material::DiffuseLight {
+                emission: self.emission.into(),
+            }
+        } else if self.dissolve < 1.0 {
+            Material::RoughDielectric {
+                refraction_index: self.refraction_index,
+                roughness: roughness.into(),
+            }
+        } else if has_specular && !has_diffuse {
+            Material::Conductor {
+                reflectance: ConductorReflectance::Tint(self.specular),
+                roughness: roughness.into(),
+            }
+        } else if has_specular {
+            Material::Principled(Principled {
+                base_color: albedo,
+                roughness: roughness.into(),
+                specular: self.specular.max_element().min(1.0),
+                ior: self.refraction_index,
+                ..Default::default()
+            })
+        } else {
+            Material::Diffuse { albedo }
+        };
+        Ok(material)
+    }
+}
+
+fn load_mtl(path: &Path) -> ObjResult<HashMap<String, Arc<Material>>> {
+    let mut materials: Vec<(String, MtlMaterial)> = Vec::new();
+    for (line, keyword, args) in statements(path)? {
+        if keyword == "newmtl" {
+            materials.push((args.join(" "), MtlMaterial::default()));
+            continue;
+        }
+        if IGNORED_MTL_STATEMENTS.contains(&keyword.as_str()) {
+            continue;
+        }
+        let Some((_, material)) = materials.last_mut() else {
+            return Err(syntax_error(path, line, "Statement before 'newmtl'"));
+        };
+        match keyword.as_str() {
+            "Kd" => material.diffuse = parse_color(path, line, &args)?,
+            "Ks" => material.specular = parse_color(path, line, &args)?,
+            "Ke" => material.emission = parse_color(path, line, &args)?,
+            "Ns" => [material.shininess] = parse_floats(path, line, &args, 1)?,
+            "Ni" => [material.refraction_index] = parse_floats(path, line, &args, 1)?,
+            "d" => [material.dissolve] = parse_floats(path, line, &args, 1)?,
+            "Tr" => {
+                let [transparency] = parse_floats(path, line, &args, 1)?;
+                material.dissolve = 1.0 - transparency;
+            }
+            "map_Kd" => {
+                // Texture options come before the file name
+                let file = args
+                    .last()
+                    .ok_or_else(|| syntax_error(path, line, "Missing texture file"))?;
+                let directory = path.parent().unwrap_or(Path::new(""));
+                material.diffuse_map = Some(directory.join(file));
+            }
+            _ => {
+                return Err(ObjError::UnsupportedStatement {
+                    path: path.to_owned(),
+                    line,
+                    statement: keyword,
+                });
+            }
+        }
+    }
+
+    materials
+        .into_iter()
+        .map(|(name, material)| Ok((name, Arc::new(material.to_material()?))))
+        .collect()
+}
@@ -0,0 +1,463 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use glam::Vec3;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::raytracer::{
+    Aperture, Camera, Environment, EnvironmentMap, Geometry, Material, Medium, Model, Motion,
+    Projection, Scene, SceneError, Sky, Transform,
+};
+
+#[derive(Debug, Error)]
+pub enum SceneFileError {
+    #[error("Failed to read '{}': {source}", path.display())]
+    Read {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Failed to write '{}': {source}", path.display())]
+    Write {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("{}: {source}", path.display())]
+    Parse {
+        path: PathBuf,
+        #[source]
+        source: toml::de::Error,
+    },
+    #[error("Failed to serialize scene: {0}")]
+    Serialize(#[from] toml::ser::Error),
+    #[error("{}: Unknown material '{name}'", path.display())]
+    UnknownMaterial { path: PathBuf, name: String },
+    #[error("{}: Unknown geometry '{name}'", path.display())]
+    UnknownGeometry { path: PathBuf, name: String },
+    #[error(
+        "{}: Object {object} needs exactly one of 'geometry' or 'instance'",
+        path.display()
+    )]
+    InvalidObjectGeometry { path: PathBuf, object: usize },
+    #[error("{}: Invalid camera, {message}", path.display())]
+    InvalidCamera { path: PathBuf, message: String },
+    #[error(
+        "{}: Scene needs at most one of 'environment' or 'sky'",
+        path.display()
+    )]
+    ConflictingEnvironment { path: PathBuf },
+    #[error("{}: {source}", path.display())]
+    InvalidScene {
+        path: PathBuf,
+        #[source]
+        source: SceneError,
+    },
+}
+
+type SceneFileResult<T> = Result<T, SceneFileError>;
+
+/// Scene file content. Scene files are TOML documents such as:
+///
+/// ```toml
+/// background = [0.1, 0.1, 0.1]
+///
+/// [camera]
+/// position = [0.0, 1.0, 5.0]
+/// look_at = [0.0, 1.0, 0.0]
+/// fov = 40.0
+/// aperture = { radius = 0.05, focus_distance = 5.0, blades = 6 }
+///
+/// [materials.red]
+/// type = "diffuse"
+/// albedo = [0.8, 0.1, 0.1]
+///
+/// [materials.glass]
+/// type = "dielectric"
+/// refraction_index = 1.5
+///
+/// [geometries.box]
+/// type = "cuboid"
+/// position = [0.0, 0.0, 0.0]
+/// size = [1.0, 1.0, 1.0]
+///
+/// [[objects]]
+/// material = "red"
+/// geometry = { type = "sphere", position = [0.0, 1.0, 0.0], radius = 1.0 }
+///
+/// [[objects]]
+/// material = "red"
+/// instance = "box"
+/// transform = { translation = [2.0, 0.5, 0.0], rotation = [0.0, 30.0, 0.0] }
+///
+/// [[objects]]
+/// material = "red"
+/// geometry = { type = "sphere", position = [0.0, 1.0, -3.0], radius = 0.5 }
+/// motion = { type = "linear", start = {}, end = { translation = [1.0, 0.0, 0.0] } }
+///
+/// [[objects]]
+/// material = "glass"
+/// geometry = { type = "sphere", position = [-2.0, 1.0, 0.0], radius = 1.0 }
+/// medium = { type = "homogeneous", sigma_a = [0.0, 0.5, 1.0], sigma_s = [0.0, 0.0, 0.0] }
+/// ```
+///
+/// Moving objects are blurred over the camera `shutter` interval, such as
+/// `shutter = [0.0, 1.0]`. Their `motion` is either `linear` from a `start`
+/// transform at time 0 to an `end` one at time 1, or `keyframed` through
+/// `keyframes = [{ time = 0.0, transform = { ... } }, ...]`, and applies
+/// after their `transform`.
+///
+/// Cameras may replace `fov` by a `projection`, such as
+/// `projection = { type = "orthographic", height = 4.0 }`,
+/// `{ type = "fisheye", fov = 180.0 }` or `{ type = "equirectangular" }`.
+///
+/// A top level `environment` table lights the scene with an equirectangular
+/// `.hdr` or `.exr` image instead of the background color, such as
+/// `environment = { path = "studio.hdr", rotation = 90.0 }`, rotated in
+/// degrees around the y axis. A top level `sky` table lights it with a clear
+/// sky and sun instead, such as
+/// `sky = { sun_direction = [1.0, 0.5, 0.0], turbidity = 3.0, intensity = 1.0 }`,
+/// where the turbidity goes from 1.7 for a very clear sky to 10 for a hazy
+/// one. A scene has at most one of them.
+///
+/// A top level `medium` table fills the scene with fog. Media of type `grid`
+/// read their densities from a Mitsuba `.vol` file, such as
+/// `grid = { path = "smoke.vol" }`, spanning `bounds`.
+#[derive(Serialize, Deserialize)]
+struct SceneFile {
+    #[serde(default)]
+    background: Vec3,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    environment: Option<EnvironmentMap>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sky: Option<Sky>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    medium: Option<Arc<Medium>>,
+    camera: CameraFile,
+    #[serde(default)]
+    materials: BTreeMap<String, Arc<Material>>,
+    /// Geometries shared by the objects which instance them.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    geometries: BTreeMap<String, Arc<Geometry>>,
+    objects: Vec<ObjectFile>,
+}
+
+/// Camera orientation given either by a `forward` direction or a `look_at`
+/// position. `up` is the world up direction, +y by default. Perspective
+/// cameras only need a `fov`, other cameras give a `projection` instead.
+#[derive(Serialize, Deserialize)]
+struct CameraFile {
+    position: Vec3,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    forward: Option<Vec3>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    look_at: Option<Vec3>,
+    #[serde(default = "default_up")]
+    up: Vec3,
+    /// Vertical field of view in degrees.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    fov: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    projection: Option<Projection>,
+    #[serde(default, skip_serializing_if = "Aperture::is_pinhole")]
+    aperture: Aperture,
+    /// Times at which the shutter opens and closes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    shutter: Option<[f32; 2]>,
+}
+
+fn default_up() -> Vec3 {
+    Vec3::Y
+}
+
+/// Object with either its own `geometry` or an `instance` of a shared
+/// geometry.
+#[derive(Serialize, Deserialize)]
+struct ObjectFile {
+    material: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    geometry: Option<Geometry>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    instance: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    transform: Option<Transform>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    motion: Option<Motion>,
+    /// Medium inside the geometry.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    medium: Option<Arc<Medium>>,
+}
+
+/// Load a TOML scene file. Texture image, environment map and voxel grid
+/// paths are relative to the scene file.
+pub fn load_scene<P: AsRef<Path>>(path: P, aspect_ratio: f32) -> SceneFileResult<Scene> {
+    let path = path.as_ref();
+    let content = std::fs::read_to_string(path).map_err(|source| SceneFileError::Read {
+        path: path.to_owned(),
+        source,
+    })?;
+    parse_scene(path, &content, aspect_ratio)
+}
+
+/// Save a scene as a TOML scene file. Shared materials and instanced
+/// geometries are written once and named after their order of appearance.
+/// Image textures, environment maps and voxel grids must have been loaded from
+/// a file.
+pub fn save_scene<P: AsRef<Path>>(scene: &Scene, path: P) -> SceneFileResult<()> {
+    let path = path.as_ref();
+    let content = scene_to_string(scene, path.parent().unwrap_or(Path::new("")))?;
+    std::fs::write(path, content).map_err(|source| SceneFileError::Write {
+        path: path.to_owned(),
+        source,
+    })
+}
+
+fn parse_scene(path: &Path, content: &str, aspect_ratio: f32) -> SceneFileResult<Scene> {
+    let parse_error = |source| SceneFileError::Parse {
+        path: path.to_owned(),
+        source,
+    };
+    let mut document: toml::Value = toml::from_str(content).map_err(parse_error)?;
+    let base_dir = path.parent().unwrap_or(Path::new(""));
+    map_file_paths(&mut document, &|file_path| base_dir.join(file_path));
+    let file: SceneFile = document.try_into().map_err(parse_error)?;
+
+    let camera = camera_from_file(path, &file.camera, aspect_ratio)?;
+    let objects = file
+        .objects
+        .into_iter()
+        .enumerate()
+        .map(|(idx, object)| {
+            let material = file.materials.get(&object.material).ok_or_else(|| {
+                SceneFileError::UnknownMaterial {
+                    path: path.to_owned(),
+                    name: object.material.clone(),
+                }
+            })?;
+            let geometry = match (object.geometry, object.instance) {
+                (Some(geometry), None) => geometry,
+                (None, Some(name)) => match file.geometries.get(&name) {
+                    Some(geometry) => Geometry::Instance(geometry.clone()),
+                    None => {
+                        return Err(SceneFileError::UnknownGeometry {
+                            path: path.to_owned(),
+                            name,
+                        });
+                    }
+                },
+                _ => {
+                    return Err(SceneFileError::InvalidObjectGeometry {
+                        path: path.to_owned(),
+                        object: idx,
+                    });
+                }
+            };
+            Ok(Arc::new(Model {
+                geometry,
+                material: material.clone(),
+                transform: object.transform,
+                motion: object.motion,
+                medium: object.medium,
+            }))
+        })
+        .collect::<SceneFileResult<_>>()?;
+
+    let environment = match (file.environment, file.sky) {
+        (Some(_), Some(_)) => {
+            return Err(SceneFileError::ConflictingEnvironment {
+                path: path.to_owned(),
+            });
+        }
+        (Some(map), None) => Some(Environment::Map(map)),
+        (None, Some(sky)) => Some(Environment::Sky(sky)),
+        (None, None) => None,
+    };
+    let scene = match environment {
+        Some(environment) => Scene::try_with_environment(camera, objects, Arc::new(environment)),
+        None => Scene::try_with_background(camera, objects, file.background),
+    };
+    scene
+        .and_then(|scene| match file.medium {
+            Some(medium) => scene.try_with_medium(medium),
+            None => Ok(scene),
+        })
+        .map_err(|source| SceneFileError::InvalidScene {
+            path: path.to_owned(),
+            source,
+        })
+}
+
+fn scene_to_string(scene: &Scene, base_dir: &Path) -> SceneFileResult<String> {
+    let camera = scene.camera();
+    let (fov, projection) = match *camera.projection() {
+        Projection::Perspective { fov } => (Some(fov), None),
+        projection => (None, Some(projection)),
+    };
+    let mut material_names = HashMap::new();
+    let mut materials = BTreeMap::new();
+    let mut geometry_names = HashMap::new();
+    let mut geometries = BTreeMap::new();
+    let objects = scene
+        .objects()
+        .iter()
+        .map(|object| {
+            let name = material_names
+                .entry(Arc::as_ptr(&object.material))
+                .or_insert_with(|| {
+                    let name = format!("material{}", materials.len());
+                    materials.insert(name.clone(), object.material.clone());
+                    name
+                });
+            let (geometry, instance) = match object.geometry {
+                Geometry::Instance(ref geometry) => {
+                    let name = geometry_names
+                        .entry(Arc::as_ptr(geometry))
+                        .or_insert_with(|| {
+                            let name = format!("geometry{}", geometries.len());
+                            geometries.insert(name.clone(), geometry.clone());
+                            name
+                        });
+                    (None, Some(name.clone()))
+                }
+                ref geometry => (Some(geometry.clone()), None),
+            };
+            ObjectFile {
+                material: name.clone(),
+                geometry,
+                instance,
+                transform: object.transform.clone(),
+                motion: object.motion.clone(),
+                medium: object.medium.clone(),
+            }
+        })
+        .collect();
+    let (environment, sky) = match scene.environment().map(|environment| &**environment) {
+        Some(Environment::Map(map)) => (Some(map.clone()), None),
+        Some(Environment::Sky(sky)) => (None, Some(sky.clone())),
+        None => (None, None),
+    };
+    let file = SceneFile {
+        background: scene.background(),
+        environment,
+        sky,
+        medium: scene.medium().cloned(),
+        camera: CameraFile {
+            position: camera.position(),
+            forward: Some(camera.forward()),
+            look_at: None,
+            // NOTE: Viewport rows grow along the camera up vector, which is
+            // opposite to the world up
+            up: -camera.up(),
+            fov,
+            projection,
+            aperture: *camera.aperture(),
+            shutter: match camera.shutter() {
+                (0.0, 0.0) => None,
+                (open, close) => Some([open, close]),
+            },
+        },
+        materials,
+        geometries,
+        objects,
+    };
+
+    // File paths are relative to the working directory, or to the scene file
+    // once saved
+    let absolute = |path: &Path| std::path::absolute(path).unwrap_or_else(|_| path.to_owned());
+    let base_dir = absolute(base_dir);
+    let mut document = toml::Value::try_from(&file)?;
+    map_file_paths(&mut document, &|file_path| {
+        let file_path = absolute(file_path);
+        file_path
+            .strip_prefix(&base_dir)
+            .map_or_else(|_| file_path.clone(), Path::to_owned)
+    });
+    shorten_floats(&mut document);
+    Ok(toml::to_string(&document)?)
+}
+
+fn camera_from_file(
+    path: &Path,
+    camera: &CameraFile,
+    aspect_ratio: f32,
+) -> SceneFileResult<Camera> {
+    let invalid_camera = |message: &str| SceneFileError::InvalidCamera {
+        path: path.to_owned(),
+        message: message.to_owned(),
+    };
+    let forward = match (camera.forward, camera.look_at) {
+        (Some(forward), None) => forward,
+        (None, Some(look_at)) => look_at - camera.position,
+        _ => {
+            return Err(invalid_camera(
+                "exactly one of 'forward' or 'look_at' is required",
+            ));
+        }
+    };
+    let forward = forward
+        .try_normalize()
+        .ok_or_else(|| invalid_camera("forward direction can't be zero"))?;
+    let up = (camera.up - forward * forward.dot(camera.up))
+        .try_normalize()
+        .ok_or_else(|| invalid_camera("up direction can't be parallel to the forward direction"))?;
+    let projection = match (camera.fov, camera.projection) {
+        (Some(fov), None) => Projection::Perspective { fov },
+        (None, Some(projection)) => projection,
+        _ => {
+            return Err(invalid_camera(
+                "exactly one of 'fov' or 'projection' is required",
+            ));
+        }
+    };
+    Camera::try_new_with_projection(camera.position, forward, -up, projection, aspect_ratio)
+        .and_then(|pinhole| pinhole.try_with_aperture(camera.aperture))
+        .and_then(|still| match camera.shutter {
+            Some([open, close]) => still.try_with_shutter(open, close),
+            None => Ok(still),
+        })
+        .map_err(|source| SceneFileError::InvalidScene {
+            path: path.to_owned(),
+            source,
+        })
+}
+
+/// Apply `map` to the path of every image texture, environment map and voxel
+/// grid of a scene document.
+fn map_file_paths(value: &mut toml::Value, map: &dyn Fn(&Path) -> PathBuf) {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, value) in table.iter_mut() {
+                if (key == "image" || key == "environment" || key == "grid")
+                    && let Some(toml::Value::String(path)) = value.get_mut("path")
+                {
+                    *path = map(Path::new(path)).to_string_lossy().into_owned();
+                } else {
+                    map_file_paths(value, map);
+                }
+            }
+        }
+        toml::Value::Array(array) => {
+            for value in array {
+                map_file_paths(value, map);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Replace floats by the shortest decimal representing the same `f32`, since
+/// TOML documents store `f64` values.
+fn shorten_floats(value: &mut toml::Value) {
+    match value {
+        toml::Value::Float(float) => {
+            *float = (*float as f32).to_string().parse().unwrap_or(*float);
+        }
+        toml::Value::Table(table) => table
+            .iter_mut()
+            .for_each(|(_, value)| shorten_floats(value)),
+        toml::Value::Array(array) => array.iter_mut().for_each(shorten_floats),
+        _ => {}
+    }
+}
@@ -12,6 +12,7 @@ use tracing::info;
 use tracing_subscriber::fmt::{format::Writer, time::FormatTime};
 
 use mirror::config::Config;
+use mirror::loaders::{load_gltf, load_scene};
 use mirror::protocol::{Peer, listen_task};
 use mirror::raytracer::{RenderBackend, Renderer};
 use mirror::test_scenes::*;
@@ -82,9 +83,32 @@ pub fn main() -> anyhow::Result<()> {
         match args.scene.as_deref() {
             Some("cornell2") => cornell_box2_scene(aspect_ratio),
             Some("cornell") => cornell_box_scene(aspect_ratio),
+            Some("cornell_rotated") => cornell_box_rotated_scene(aspect_ratio),
             Some("spheres") => spheres_scene(aspect_ratio),
             Some("spheres2") => spheres2_scene(aspect_ratio),
             Some("quads") => quads_scene(aspect_ratio),
+            Some("dof") => depth_of_field_scene(aspect_ratio),
+            Some("motion") => motion_blur_scene(aspect_ratio),
+            Some("environment") => environment_scene(aspect_ratio),
+            Some("sky") => sky_scene(aspect_ratio),
+            Some("orennayar") => oren_nayar_scene(aspect_ratio),
+            Some("textures") => textures_scene(aspect_ratio),
+            Some("mesh") => mesh_scene(aspect_ratio),
+            Some("primitives") => primitives_scene(aspect_ratio),
+            Some("csg") => csg_scene(aspect_ratio),
+            Some("sdf") => sdf_scene(aspect_ratio),
+            Some("media") => media_scene(aspect_ratio),
+            Some("volume") => volume_scene(aspect_ratio),
+            Some(path) if path.ends_with(".gltf") || path.ends_with(".glb") => {
+                let scene = load_gltf(path, aspect_ratio)?;
+                info!("Loaded scene from '{}'", path);
+                scene
+            }
+            Some(path) if path.ends_with(".toml") => {
+                let scene = load_scene(path, aspect_ratio)?;
+                info!("Loaded scene from '{}'", path);
+                scene
+            }
             None => cornell_box2_scene(aspect_ratio),
             _ => {
                 tracing::error!("Unkown scene '{}'", args.scene.unwrap());
@@ -2,14 +2,15 @@ use core::f32;
 
 use bincode::{Decode, Encode};
 use glam::Vec3;
+use serde::{Deserialize, Serialize};
 
-use crate::raytracer::Ray;
+use crate::raytracer::{Ray, SceneError, SceneIssue};
 
 pub trait Intersectable {
     fn intersect(&self, ray: &Ray) -> bool;
 }
 
-#[derive(Debug, Clone, Encode, Decode)]
+#[derive(Debug, Clone, Encode, Decode, Serialize, Deserialize)]
 pub struct Aabb {
     #[bincode(with_serde)]
     pub min_position: Vec3,
@@ -27,6 +28,14 @@ impl Aabb {
         }
     }
 
+    /// Bounds of unbounded geometry, containing every position.
+    pub fn infinite() -> Self {
+        Self {
+            min_position: Vec3::NEG_INFINITY,
+            max_position: Vec3::INFINITY,
+        }
+    }
+
     pub fn new(position: Vec3, size: Vec3) -> Self {
         assert!(
             size.x >= 0.0 && size.y >= 0.0 && size.z >= 0.0,
@@ -40,6 +49,15 @@ impl Aabb {
         }
     }
 
+    /// Same as `new`, but returns an error for negative sizes instead of
+    /// panicking.
+    pub fn try_new(position: Vec3, size: Vec3) -> Result<Self, SceneError> {
+        if !(size.x >= 0.0 && size.y >= 0.0 && size.z >= 0.0) {
+            return Err(SceneIssue::NegativeAabbSize.into());
+        }
+        Ok(Self::new(position, size))
+    }
+
     pub fn from_positions(min_position: Vec3, max_position: Vec3) -> Self {
         let size = max_position - min_position;
         Self::new(min_position + (size / 2.0), size.abs())
@@ -51,10 +69,10 @@ impl Aabb {
             max_position: aabb1.max_position.max(aabb2.max_position),
         }
     }
-}
 
-impl Intersectable for Aabb {
-    fn intersect(&self, ray: &Ray) -> bool {
+    /// Distances along the ray where it enters and leaves the box, clamped
+    /// to the ray interval, or `None` if it misses the box.
+    pub fn clip(&self, ray: &Ray) -> Option<(f32, f32)> {
         let inv_dir = ray.direction().map(|d| {
             if d.abs() < f32::MIN_POSITIVE {
                 f32::MAX
@@ -69,6 +87,12 @@ impl Intersectable for Aabb {
         let t_max = t0.max(t1);
         let t_enter = t_min.max_element().max(ray.tmin());
         let t_exit = t_max.min_element().min(ray.tmax());
-        t_enter <= t_exit && t_exit >= 0.0
+        (t_enter <= t_exit && t_exit >= 0.0).then_some((t_enter, t_exit))
+    }
+}
+
+impl Intersectable for Aabb {
+    fn intersect(&self, ray: &Ray) -> bool {
+        self.clip(ray).is_some()
     }
 }
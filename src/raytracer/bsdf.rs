@@ -0,0 +1,994 @@
+use core::f32;
+
+use bincode::{Decode, Encode};
+use glam::{Vec2, Vec3};
+use serde::{Deserialize, Serialize};
+
+use crate::raytracer::{Hit, Principled};
+use crate::utils;
+
+/// Orthonormal basis around a surface normal. BSDFs work with directions
+/// expressed in this local frame, where the normal is the z axis.
+#[derive(Debug, Clone, Copy)]
+pub struct Frame {
+    pub tangent: Vec3,
+    pub bitangent: Vec3,
+    pub normal: Vec3,
+}
+
+impl Frame {
+    pub fn from_normal(normal: Vec3) -> Self {
+        let (tangent, bitangent) = normal.any_orthonormal_pair();
+        Self {
+            tangent,
+            bitangent,
+            normal,
+        }
+    }
+
+    /// Frame around `normal` whose tangent follows the projection of
+    /// `tangent`, such as a surface derivative. Falls back to an arbitrary
+    /// tangent when `tangent` is parallel to the normal.
+    pub fn from_normal_tangent(normal: Vec3, tangent: Vec3) -> Self {
+        let projected = tangent - normal * normal.dot(tangent);
+        // NOTE: Rounding leaves a tiny projection instead of zero for tangents
+        // parallel to the normal.
+        if projected.length_squared() <= 1e-8 * tangent.length_squared() {
+            return Self::from_normal(normal);
+        }
+        match projected.try_normalize() {
+            Some(tangent) => Self {
+                tangent,
+                bitangent: normal.cross(tangent),
+                normal,
+            },
+            None => Self::from_normal(normal),
+        }
+    }
+
+    /// Convert a world space direction into the local frame.
+    pub fn to_local(&self, v: Vec3) -> Vec3 {
+        Vec3::new(
+            v.dot(self.tangent),
+            v.dot(self.bitangent),
+            v.dot(self.normal),
+        )
+    }
+
+    /// Convert a local frame direction into world space.
+    pub fn to_world(&self, v: Vec3) -> Vec3 {
+        self.tangent * v.x + self.bitangent * v.y + self.normal * v.z
+    }
+}
+
+/// Result of sampling a BSDF.
+pub struct BsdfSample {
+    /// Sampled incident direction, in the local frame.
+    pub wi: Vec3,
+    /// BSDF value for the sampled pair of directions, without the cosine term.
+    pub f: Vec3,
+    /// Solid angle density of `wi`. For specular samples this is the discrete
+    /// probability of choosing the sampled lobe instead.
+    pub pdf: f32,
+    pub is_specular: bool,
+}
+
+/// Bidirectional scattering distribution function. Every direction is given
+/// in the local shading frame, where the normal faces the side the outgoing
+/// direction `wo` leaves from, so `wo.z` is always positive.
+pub trait Bsdf {
+    /// Sample an incident direction for the outgoing direction `wo`. The
+    /// random sample `uc` is used to choose between discrete lobes, while `u`
+    /// chooses the direction within the lobe. Both must be within [0, 1).
+    /// Returns `None` if the light is absorbed.
+    fn sample(&self, wo: Vec3, uc: f32, u: Vec2) -> Option<BsdfSample>;
+
+    /// Evaluate the BSDF for a pair of directions, without the cosine term.
+    /// Specular BSDFs are singular and always evaluate to zero.
+    fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3;
+
+    /// Solid angle density with which `sample` would choose `wi`. Specular
+    /// BSDFs are singular and always return zero.
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f32;
+
+    /// Whether the BSDF only scatters light in singular directions, which
+    /// direct light sampling cannot reach.
+    fn is_specular(&self) -> bool {
+        false
+    }
+}
+
+fn reflect(wo: Vec3) -> Vec3 {
+    Vec3::new(-wo.x, -wo.y, wo.z)
+}
+
+fn same_hemisphere(wo: Vec3, wi: Vec3) -> bool {
+    wo.z * wi.z > 0.0
+}
+
+/// Reflect `wo` about an arbitrary microfacet normal `wm`.
+fn reflect_about(wo: Vec3, wm: Vec3) -> Vec3 {
+    -wo + 2.0 * wo.dot(wm) * wm
+}
+
+/// Exact Fresnel reflectance of a dielectric interface, where `eta` is the
+/// ratio between the refraction index of the incident side and the
+/// refraction index of the transmitted side.
+pub fn fresnel_dielectric(cos_theta_i: f32, eta: f32) -> f32 {
+    let cos_theta_i = cos_theta_i.clamp(0.0, 1.0);
+    let sin2_theta_t = eta * eta * (1.0 - cos_theta_i * cos_theta_i);
+    // Total internal reflection
+    if sin2_theta_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+
+    let r_perp = (eta * cos_theta_i - cos_theta_t) / (eta * cos_theta_i + cos_theta_t);
+    let r_parl = (cos_theta_i - eta * cos_theta_t) / (cos_theta_i + eta * cos_theta_t);
+    (r_perp * r_perp + r_parl * r_parl) / 2.0
+}
+
+/// Exact Fresnel reflectance of a conductor with complex refraction index
+/// `eta + i k`, per color channel.
+pub fn fresnel_conductor(cos_theta_i: f32, eta: Vec3, k: Vec3) -> Vec3 {
+    let cos_theta_i = cos_theta_i.clamp(0.0, 1.0);
+    let cos2_theta_i = cos_theta_i * cos_theta_i;
+    let sin2_theta_i = 1.0 - cos2_theta_i;
+    let sin4_theta_i = sin2_theta_i * sin2_theta_i;
+
+    let temp = eta * eta - k * k - sin2_theta_i;
+    let a2_plus_b2 = (temp * temp + 4.0 * eta * eta * k * k).map(f32::sqrt);
+    let a = ((a2_plus_b2 + temp) * 0.5).max(Vec3::ZERO).map(f32::sqrt);
+
+    let term1 = a2_plus_b2 + cos2_theta_i;
+    let term2 = 2.0 * a * cos_theta_i;
+    let r_perp = (term1 - term2) / (term1 + term2);
+
+    let term3 = cos2_theta_i * a2_plus_b2 + sin4_theta_i;
+    let term4 = term2 * sin2_theta_i;
+    let r_parl = r_perp * (term3 - term4) / (term3 + term4);
+
+    (r_perp + r_parl) * 0.5
+}
+
+/// Schlick's approximation of the Fresnel reflectance given the reflectance
+/// at normal incidence.
+pub fn fresnel_schlick(cos_theta_i: f32, f0: Vec3) -> Vec3 {
+    f0 + (Vec3::ONE - f0) * (1.0 - cos_theta_i.clamp(0.0, 1.0)).powi(5)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Trowbridge-Reitz (GGX) microfacet distribution
+////////////////////////////////////////////////////////////////////////////////
+
+/// Isotropic Trowbridge-Reitz (GGX) distribution of microfacet normals.
+#[derive(Debug, Clone, Copy)]
+pub struct TrowbridgeReitz {
+    pub alpha: f32,
+}
+
+impl TrowbridgeReitz {
+    /// Below this alpha the surface is treated as perfectly smooth, since
+    /// the distribution becomes too narrow to be evaluated accurately.
+    const MIN_ALPHA: f32 = 1e-3;
+
+    /// Create a distribution from a perceptual roughness in [0, 1].
+    pub fn from_roughness(roughness: f32) -> Self {
+        Self {
+            alpha: roughness * roughness,
+        }
+    }
+
+    pub fn is_smooth(&self) -> bool {
+        self.alpha < Self::MIN_ALPHA
+    }
+
+    /// Microfacet normal distribution function.
+    pub fn d(&self, wm: Vec3) -> f32 {
+        let cos2_theta = wm.z * wm.z;
+        if cos2_theta <= 0.0 {
+            return 0.0;
+        }
+        let tan2_theta = (1.0 - cos2_theta) / cos2_theta;
+        let alpha2 = self.alpha * self.alpha;
+        let e = 1.0 + tan2_theta / alpha2;
+        1.0 / (f32::consts::PI * alpha2 * cos2_theta * cos2_theta * e * e)
+    }
+
+    /// Smith's auxiliary function, measuring invisible microfacet area per
+    /// visible microfacet area.
+    pub fn lambda(&self, w: Vec3) -> f32 {
+        let cos2_theta = w.z * w.z;
+        if cos2_theta <= 0.0 {
+            return 0.0;
+        }
+        let tan2_theta = (1.0 - cos2_theta) / cos2_theta;
+        ((1.0 + self.alpha * self.alpha * tan2_theta).sqrt() - 1.0) / 2.0
+    }
+
+    /// Masking function, fraction of microfacets visible from `w`.
+    pub fn g1(&self, w: Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// Height correlated masking-shadowing function.
+    pub fn g(&self, wo: Vec3, wi: Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Distribution of microfacet normals visible from `w`, which is also the
+    /// density of `sample_visible`.
+    pub fn d_visible(&self, w: Vec3, wm: Vec3) -> f32 {
+        if w.z == 0.0 {
+            return 0.0;
+        }
+        // Microfacets facing away from w are hidden
+        self.g1(w) / w.z.abs() * self.d(wm) * w.dot(wm).max(0.0)
+    }
+
+    /// Sample a microfacet normal visible from `w`, following Heitz 2018,
+    /// "Sampling the GGX Distribution of Visible Normals".
+    pub fn sample_visible(&self, w: Vec3, u: Vec2) -> Vec3 {
+        // Transform view direction to the hemisphere configuration
+        let mut wh = Vec3::new(self.alpha * w.x, self.alpha * w.y, w.z).normalize();
+        if wh.z < 0.0 {
+            wh = -wh;
+        }
+        let t1 = if wh.z < 0.99999 {
+            Vec3::Z.cross(wh).normalize()
+        } else {
+            Vec3::X
+        };
+        let t2 = wh.cross(t1);
+
+        // Uniformly sample the projected area, warped by the view direction
+        let r = u.x.sqrt();
+        let phi = 2.0 * f32::consts::PI * u.y;
+        let p1 = r * phi.cos();
+        let h = (1.0 - p1 * p1).max(0.0).sqrt();
+        let s = (1.0 + wh.z) / 2.0;
+        let p2 = (1.0 - s) * h + s * r * phi.sin();
+        let pz = (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+        let nh = p1 * t1 + p2 * t2 + pz * wh;
+
+        // Transform back to the ellipsoid configuration
+        Vec3::new(self.alpha * nh.x, self.alpha * nh.y, nh.z.max(1e-6)).normalize()
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Lambertian
+////////////////////////////////////////////////////////////////////////////////
+
+pub struct LambertianBsdf {
+    pub albedo: Vec3,
+}
+
+impl Bsdf for LambertianBsdf {
+    fn sample(&self, wo: Vec3, _uc: f32, u: Vec2) -> Option<BsdfSample> {
+        let wi = utils::sample_cosine_hemisphere(u);
+        if wi.z <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            f: self.eval(wo, wi),
+            pdf: self.pdf(wo, wi),
+            is_specular: false,
+        })
+    }
+
+    fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        if !same_hemisphere(wo, wi) {
+            return Vec3::ZERO;
+        }
+        self.albedo * f32::consts::FRAC_1_PI
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f32 {
+        if !same_hemisphere(wo, wi) {
+            return 0.0;
+        }
+        wi.z.abs() * f32::consts::FRAC_1_PI
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Oren-Nayar
+////////////////////////////////////////////////////////////////////////////////
+
+/// Rough diffuse reflection, following the qualitative model of Oren and
+/// Nayar 1994, "Generalization of Lambert's Reflectance Model".
+pub struct OrenNayarBsdf {
+    pub albedo: Vec3,
+    a: f32,
+    b: f32,
+}
+
+impl OrenNayarBsdf {
+    /// Create the BSDF given the standard deviation of the microfacet
+    /// orientation angle `sigma`, in degrees.
+    pub fn new(albedo: Vec3, sigma: f32) -> Self {
+        let sigma = sigma.to_radians();
+        let sigma2 = sigma * sigma;
+        Self {
+            albedo,
+            a: 1.0 - sigma2 / (2.0 * (sigma2 + 0.33)),
+            b: 0.45 * sigma2 / (sigma2 + 0.09),
+        }
+    }
+}
+
+impl Bsdf for OrenNayarBsdf {
+    fn sample(&self, wo: Vec3, _uc: f32, u: Vec2) -> Option<BsdfSample> {
+        let wi = utils::sample_cosine_hemisphere(u);
+        if wi.z <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            f: self.eval(wo, wi),
+            pdf: self.pdf(wo, wi),
+            is_specular: false,
+        })
+    }
+
+    fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        if !same_hemisphere(wo, wi) {
+            return Vec3::ZERO;
+        }
+        let sin_theta_i = (1.0 - wi.z * wi.z).max(0.0).sqrt();
+        let sin_theta_o = (1.0 - wo.z * wo.z).max(0.0).sqrt();
+
+        // Cosine of the azimuthal difference between both directions
+        let max_cos = if sin_theta_i > 1e-4 && sin_theta_o > 1e-4 {
+            let cos_phi_diff = (wi.x * wo.x + wi.y * wo.y) / (sin_theta_i * sin_theta_o);
+            cos_phi_diff.max(0.0)
+        } else {
+            0.0
+        };
+
+        // Sine of the largest and tangent of the smallest polar angle
+        let (sin_alpha, tan_beta) = if wi.z.abs() > wo.z.abs() {
+            (sin_theta_o, sin_theta_i / wi.z.abs())
+        } else {
+            (sin_theta_i, sin_theta_o / wo.z.abs())
+        };
+
+        self.albedo * f32::consts::FRAC_1_PI * (self.a + self.b * max_cos * sin_alpha * tan_beta)
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f32 {
+        if !same_hemisphere(wo, wi) {
+            return 0.0;
+        }
+        wi.z.abs() * f32::consts::FRAC_1_PI
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Fuzzy metal
+////////////////////////////////////////////////////////////////////////////////
+
+/// Mirror reflection perturbed by a random offset of length `fuzzyness`.
+/// Directions below the surface are absorbed.
+pub struct FuzzyMetalBsdf {
+    pub albedo: Vec3,
+    pub fuzzyness: f32,
+}
+
+impl FuzzyMetalBsdf {
+    /// Solid angle density of directions generated by normalizing
+    /// `reflected + fuzzyness * s` with `s` uniformly distributed over the
+    /// unit sphere. Directions are the projection of a sphere of radius
+    /// `fuzzyness` centered at the reflected direction, so the density follows
+    /// from the ray/sphere intersection distances.
+    fn fuzzy_pdf(&self, reflected: Vec3, wi: Vec3) -> f32 {
+        let c = wi.dot(reflected);
+        let discriminant = c * c + self.fuzzyness * self.fuzzyness - 1.0;
+        if discriminant <= 0.0 {
+            return 0.0;
+        }
+        let sqrt_discriminant = discriminant.sqrt();
+        let norm = 4.0 * f32::consts::PI * self.fuzzyness * sqrt_discriminant;
+
+        if self.fuzzyness < 1.0 {
+            // Both intersections are in front of the origin when facing the
+            // sphere
+            if c <= 0.0 {
+                return 0.0;
+            }
+            let t0 = c - sqrt_discriminant;
+            let t1 = c + sqrt_discriminant;
+            (t0 * t0 + t1 * t1) / norm
+        } else {
+            // Origin is inside the sphere, only the farthest intersection
+            // counts
+            let t = c + sqrt_discriminant;
+            t * t / norm
+        }
+    }
+}
+
+impl Bsdf for FuzzyMetalBsdf {
+    fn sample(&self, wo: Vec3, _uc: f32, u: Vec2) -> Option<BsdfSample> {
+        let reflected = reflect(wo);
+        if self.is_specular() {
+            return Some(BsdfSample {
+                wi: reflected,
+                f: self.albedo / reflected.z,
+                pdf: 1.0,
+                is_specular: true,
+            });
+        }
+
+        let wi = (reflected + self.fuzzyness * utils::sample_uniform_sphere(u)).try_normalize()?;
+        let pdf = self.pdf(wo, wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            f: self.eval(wo, wi),
+            pdf,
+            is_specular: false,
+        })
+    }
+
+    fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        if self.is_specular() || wi.z <= 0.0 {
+            return Vec3::ZERO;
+        }
+        // Scattering with attenuation 'albedo' implies that the BSDF times the
+        // cosine term equals 'albedo' times the sampling density.
+        self.albedo * self.pdf(wo, wi) / wi.z
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f32 {
+        if self.is_specular() || wi.z <= 0.0 {
+            return 0.0;
+        }
+        self.fuzzy_pdf(reflect(wo), wi)
+    }
+
+    fn is_specular(&self) -> bool {
+        self.fuzzyness <= 0.0
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Dielectric
+////////////////////////////////////////////////////////////////////////////////
+
+/// Smooth interface between two dielectrics, such as air and glass.
+pub struct DielectricBsdf {
+    /// Ratio between the refraction index of the side `wo` leaves from and
+    /// the refraction index of the opposite side.
+    pub eta: f32,
+}
+
+impl Bsdf for DielectricBsdf {
+    fn sample(&self, wo: Vec3, uc: f32, _u: Vec2) -> Option<BsdfSample> {
+        // Same reflectance as rough dielectrics, which become smooth ones
+        // below a roughness threshold. Light which can't refract is fully
+        // reflected.
+        let reflectance = fresnel_dielectric(wo.z, self.eta);
+
+        // Choose between reflection and refraction proportionally to the
+        // Fresnel reflectance
+        if uc < reflectance {
+            let wi = reflect(wo);
+            Some(BsdfSample {
+                wi,
+                f: Vec3::splat(reflectance / wi.z.abs()),
+                pdf: reflectance,
+                is_specular: true,
+            })
+        } else {
+            let wi = (-wo).refract(Vec3::Z, self.eta).try_normalize()?;
+            Some(BsdfSample {
+                wi,
+                f: Vec3::splat((1.0 - reflectance) / wi.z.abs()),
+                pdf: 1.0 - reflectance,
+                is_specular: true,
+            })
+        }
+    }
+
+    fn eval(&self, _wo: Vec3, _wi: Vec3) -> Vec3 {
+        Vec3::ZERO
+    }
+
+    fn pdf(&self, _wo: Vec3, _wi: Vec3) -> f32 {
+        0.0
+    }
+
+    fn is_specular(&self) -> bool {
+        true
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Conductor
+////////////////////////////////////////////////////////////////////////////////
+
+/// Fresnel reflectance model of a conductor.
+#[derive(Debug, Clone, Copy, Encode, Decode, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConductorReflectance {
+    /// Complex refraction index `eta + i k`, per color channel.
+    ComplexIor {
+        #[bincode(with_serde)]
+        eta: Vec3,
+        #[bincode(with_serde)]
+        k: Vec3,
+    },
+    /// Reflectance at normal incidence, using Schlick's approximation.
+    Tint(#[bincode(with_serde)] Vec3),
+}
+
+impl ConductorReflectance {
+    pub fn eval(&self, cos_theta_i: f32) -> Vec3 {
+        match self {
+            Self::ComplexIor { eta, k } => fresnel_conductor(cos_theta_i, *eta, *k),
+            Self::Tint(f0) => fresnel_schlick(cos_theta_i, *f0),
+        }
+    }
+}
+
+/// Rough metal with a Trowbridge-Reitz microfacet distribution.
+pub struct ConductorBsdf {
+    pub reflectance: ConductorReflectance,
+    pub distribution: TrowbridgeReitz,
+}
+
+impl Bsdf for ConductorBsdf {
+    fn sample(&self, wo: Vec3, _uc: f32, u: Vec2) -> Option<BsdfSample> {
+        if self.is_specular() {
+            let wi = reflect(wo);
+            return Some(BsdfSample {
+                wi,
+                f: self.reflectance.eval(wi.z) / wi.z,
+                pdf: 1.0,
+                is_specular: true,
+            });
+        }
+
+        let wm = self.distribution.sample_visible(wo, u);
+        let wi = reflect_about(wo, wm);
+        if !same_hemisphere(wo, wi) {
+            return None;
+        }
+        let pdf = self.pdf(wo, wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            f: self.eval(wo, wi),
+            pdf,
+            is_specular: false,
+        })
+    }
+
+    fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        if self.is_specular() || !same_hemisphere(wo, wi) {
+            return Vec3::ZERO;
+        }
+        let cos_theta_o = wo.z.abs();
+        let cos_theta_i = wi.z.abs();
+        let Some(wm) = (wo + wi).try_normalize() else {
+            return Vec3::ZERO;
+        };
+        let fresnel = self.reflectance.eval(wo.dot(wm).abs());
+        self.distribution.d(wm) * self.distribution.g(wo, wi) * fresnel
+            / (4.0 * cos_theta_o * cos_theta_i)
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f32 {
+        if self.is_specular() || !same_hemisphere(wo, wi) {
+            return 0.0;
+        }
+        let Some(mut wm) = (wo + wi).try_normalize() else {
+            return 0.0;
+        };
+        if wm.z < 0.0 {
+            wm = -wm;
+        }
+        self.distribution.d_visible(wo, wm) / (4.0 * wo.dot(wm).abs())
+    }
+
+    fn is_specular(&self) -> bool {
+        self.distribution.is_smooth()
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Rough dielectric
+////////////////////////////////////////////////////////////////////////////////
+
+/// Rough interface between two dielectrics with a Trowbridge-Reitz
+/// microfacet distribution, following Walter et al. 2007, "Microfacet Models
+/// for Refraction through Rough Surfaces".
+///
+/// NOTE: Like `DielectricBsdf`, radiance is not scaled by the squared
+/// refraction index ratio on transmission, since both scalings cancel out
+/// when light enters and leaves a closed object.
+pub struct RoughDielectricBsdf {
+    /// Ratio between the refraction index of the side `wo` leaves from and
+    /// the refraction index of the opposite side.
+    pub eta: f32,
+    pub distribution: TrowbridgeReitz,
+}
+
+impl RoughDielectricBsdf {
+    /// Generalized half vector of a pair of directions, oriented towards the
+    /// side of `wo`. Returns `None` for degenerate or back facing
+    /// configurations.
+    fn half_vector(&self, wo: Vec3, wi: Vec3) -> Option<Vec3> {
+        let is_reflection = same_hemisphere(wo, wi);
+        let etap = if is_reflection { 1.0 } else { 1.0 / self.eta };
+        let mut wm = (wo + wi * etap).try_normalize()?;
+        if wm.z < 0.0 {
+            wm = -wm;
+        }
+        // Discard microfacets that face away from either direction
+        if wm.dot(wo) * wo.z < 0.0 || wm.dot(wi) * wi.z < 0.0 {
+            return None;
+        }
+        Some(wm)
+    }
+}
+
+impl Bsdf for RoughDielectricBsdf {
+    fn sample(&self, wo: Vec3, uc: f32, u: Vec2) -> Option<BsdfSample> {
+        let wm = self.distribution.sample_visible(wo, u);
+        let reflectance = fresnel_dielectric(wo.dot(wm), self.eta);
+
+        let wi = if uc < reflectance {
+            let wi = reflect_about(wo, wm);
+            if !same_hemisphere(wo, wi) {
+                return None;
+            }
+            wi
+        } else {
+            let wi = (-wo).refract(wm, self.eta).try_normalize()?;
+            if same_hemisphere(wo, wi) || wi.z == 0.0 {
+                return None;
+            }
+            wi
+        };
+
+        let pdf = self.pdf(wo, wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            f: self.eval(wo, wi),
+            pdf,
+            is_specular: false,
+        })
+    }
+
+    fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        let cos_theta_o = wo.z.abs();
+        let cos_theta_i = wi.z.abs();
+        if cos_theta_o == 0.0 || cos_theta_i == 0.0 {
+            return Vec3::ZERO;
+        }
+        let Some(wm) = self.half_vector(wo, wi) else {
+            return Vec3::ZERO;
+        };
+
+        let reflectance = fresnel_dielectric(wo.dot(wm), self.eta);
+        let d = self.distribution.d(wm);
+        let g = self.distribution.g(wo, wi);
+        if same_hemisphere(wo, wi) {
+            Vec3::splat(d * g * reflectance / (4.0 * cos_theta_o * cos_theta_i))
+        } else {
+            let denom = wi.dot(wm) + wo.dot(wm) * self.eta;
+            let f = (1.0 - reflectance) * d * g * (wi.dot(wm) * wo.dot(wm)).abs()
+                / (cos_theta_i * cos_theta_o * denom * denom);
+            Vec3::splat(f)
+        }
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f32 {
+        let Some(wm) = self.half_vector(wo, wi) else {
+            return 0.0;
+        };
+
+        let reflectance = fresnel_dielectric(wo.dot(wm), self.eta);
+        let d_visible = self.distribution.d_visible(wo, wm);
+        if same_hemisphere(wo, wi) {
+            d_visible / (4.0 * wo.dot(wm).abs()) * reflectance
+        } else {
+            let denom = wi.dot(wm) + wo.dot(wm) * self.eta;
+            let dwm_dwi = wi.dot(wm).abs() / (denom * denom);
+            d_visible * dwm_dwi * (1.0 - reflectance)
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Principled
+////////////////////////////////////////////////////////////////////////////////
+
+/// Diffuse lobe of the principled BSDF, with Burley's retro-reflection at
+/// grazing angles and an additional sheen term. Light reflected by the
+/// specular layer on top never reaches the diffuse layer, so the lobe is
+/// attenuated by the Fresnel transmittance towards both directions.
+pub struct BurleyDiffuseBsdf {
+    pub base_color: Vec3,
+    pub roughness: f32,
+    pub sheen: Vec3,
+    /// Reflectance at normal incidence of the specular layer.
+    pub specular_f0: Vec3,
+}
+
+impl Bsdf for BurleyDiffuseBsdf {
+    fn sample(&self, wo: Vec3, _uc: f32, u: Vec2) -> Option<BsdfSample> {
+        let wi = utils::sample_cosine_hemisphere(u);
+        if wi.z <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            f: self.eval(wo, wi),
+            pdf: self.pdf(wo, wi),
+            is_specular: false,
+        })
+    }
+
+    fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        if !same_hemisphere(wo, wi) {
+            return Vec3::ZERO;
+        }
+        let Some(wh) = (wo + wi).try_normalize() else {
+            return Vec3::ZERO;
+        };
+        let cos_theta_d = wi.dot(wh);
+        let schlick_weight = |cos: f32| (1.0 - cos.abs()).clamp(0.0, 1.0).powi(5);
+
+        let fd90 = 0.5 + 2.0 * self.roughness * cos_theta_d * cos_theta_d;
+        let fd = (1.0 + (fd90 - 1.0) * schlick_weight(wi.z))
+            * (1.0 + (fd90 - 1.0) * schlick_weight(wo.z));
+        let transmittance = (Vec3::ONE - fresnel_schlick(wi.z.abs(), self.specular_f0))
+            * (Vec3::ONE - fresnel_schlick(wo.z.abs(), self.specular_f0));
+        (self.base_color * f32::consts::FRAC_1_PI * fd + self.sheen * schlick_weight(cos_theta_d))
+            * transmittance
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f32 {
+        if !same_hemisphere(wo, wi) {
+            return 0.0;
+        }
+        wi.z.abs() * f32::consts::FRAC_1_PI
+    }
+}
+
+/// Clearcoat lobe of the principled BSDF, using the GTR1 (Berry) microfacet
+/// distribution and a fixed refraction index of 1.5.
+pub struct ClearcoatBsdf {
+    pub alpha: f32,
+}
+
+impl ClearcoatBsdf {
+    const F0: Vec3 = Vec3::splat(0.04);
+    const MASKING: TrowbridgeReitz = TrowbridgeReitz { alpha: 0.25 };
+
+    fn d(&self, cos_theta_h: f32) -> f32 {
+        let alpha2 = self.alpha * self.alpha;
+        (alpha2 - 1.0)
+            / (f32::consts::PI * alpha2.ln() * (1.0 + (alpha2 - 1.0) * cos_theta_h * cos_theta_h))
+    }
+}
+
+impl Bsdf for ClearcoatBsdf {
+    fn sample(&self, wo: Vec3, _uc: f32, u: Vec2) -> Option<BsdfSample> {
+        let alpha2 = self.alpha * self.alpha;
+        let cos_theta_h = ((1.0 - alpha2.powf(1.0 - u.x)) / (1.0 - alpha2))
+            .clamp(0.0, 1.0)
+            .sqrt();
+        let sin_theta_h = (1.0 - cos_theta_h * cos_theta_h).max(0.0).sqrt();
+        let phi = 2.0 * f32::consts::PI * u.y;
+        let wh = Vec3::new(
+            sin_theta_h * phi.cos(),
+            sin_theta_h * phi.sin(),
+            cos_theta_h,
+        );
+
+        let wi = reflect_about(wo, wh);
+        if !same_hemisphere(wo, wi) {
+            return None;
+        }
+        let pdf = self.pdf(wo, wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            f: self.eval(wo, wi),
+            pdf,
+            is_specular: false,
+        })
+    }
+
+    fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        if !same_hemisphere(wo, wi) {
+            return Vec3::ZERO;
+        }
+        let Some(wh) = (wo + wi).try_normalize() else {
+            return Vec3::ZERO;
+        };
+        let fresnel = fresnel_schlick(wo.dot(wh), Self::F0);
+        let masking = Self::MASKING.g1(wo) * Self::MASKING.g1(wi);
+        self.d(wh.z) * masking * fresnel / (4.0 * wo.z.abs() * wi.z.abs())
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f32 {
+        if !same_hemisphere(wo, wi) {
+            return 0.0;
+        }
+        let Some(wh) = (wo + wi).try_normalize() else {
+            return 0.0;
+        };
+        self.d(wh.z) * wh.z.abs() / (4.0 * wo.dot(wh).abs())
+    }
+}
+
+/// Weighted BSDF lobe, chosen for sampling with a given probability.
+struct Lobe {
+    bsdf: Box<dyn Bsdf>,
+    weight: f32,
+    probability: f32,
+}
+
+/// Principled BSDF following Burley 2012, "Physically-Based Shading at
+/// Disney" and Burley 2015, "Extending the Disney BRDF to a BSDF with
+/// Integrated Subsurface Scattering". It blends a diffuse plastic, a metal and
+/// a glass layer, with an optional clearcoat on top.
+pub struct PrincipledBsdf {
+    lobes: Vec<Lobe>,
+    /// Color multiplying light transmitted through the surface.
+    transmission_tint: Vec3,
+}
+
+impl PrincipledBsdf {
+    /// Create the BSDF for the given parameters evaluated at a hit, where
+    /// `eta` is the ratio between the refraction index of the side `wo` leaves
+    /// from and the refraction index of the opposite side.
+    pub fn new(params: &Principled, hit: &Hit, eta: f32) -> Self {
+        let base_color = params.base_color.eval(hit.uv, hit.position);
+        let roughness = params.roughness.eval_scalar(hit.uv, hit.position);
+        let luminance = base_color.dot(Vec3::new(0.2126, 0.7152, 0.0722));
+        let tint = if luminance > 0.0 {
+            base_color / luminance
+        } else {
+            Vec3::ONE
+        };
+
+        let metallic = params
+            .metallic
+            .eval_scalar(hit.uv, hit.position)
+            .clamp(0.0, 1.0);
+        let transmission = params.transmission.clamp(0.0, 1.0);
+        let diffuse_weight = (1.0 - metallic) * (1.0 - transmission);
+        let transmission_weight = (1.0 - metallic) * transmission;
+        let clearcoat_weight = 0.25 * params.clearcoat.max(0.0);
+
+        let distribution = TrowbridgeReitz::from_roughness(roughness);
+        let dielectric_f0 = params.specular * 0.08 * Vec3::ONE.lerp(tint, params.specular_tint);
+        let specular_f0 = dielectric_f0.lerp(base_color, metallic);
+
+        let mut lobes: Vec<(Box<dyn Bsdf>, f32)> = Vec::with_capacity(4);
+        if diffuse_weight > 0.0 {
+            let diffuse = BurleyDiffuseBsdf {
+                base_color,
+                roughness,
+                sheen: params.sheen * Vec3::ONE.lerp(tint, params.sheen_tint),
+                specular_f0: dielectric_f0,
+            };
+            lobes.push((Box::new(diffuse), diffuse_weight));
+        }
+        if transmission_weight < 1.0 {
+            let specular = ConductorBsdf {
+                reflectance: ConductorReflectance::Tint(specular_f0),
+                distribution,
+            };
+            lobes.push((Box::new(specular), 1.0 - transmission_weight));
+        }
+        if clearcoat_weight > 0.0 {
+            let alpha = 0.1 + (0.001 - 0.1) * params.clearcoat_gloss.clamp(0.0, 1.0);
+            lobes.push((Box::new(ClearcoatBsdf { alpha }), clearcoat_weight));
+        }
+        if transmission_weight > 0.0 {
+            let glass: Box<dyn Bsdf> = if distribution.is_smooth() {
+                Box::new(DielectricBsdf { eta })
+            } else {
+                Box::new(RoughDielectricBsdf { eta, distribution })
+            };
+            lobes.push((glass, transmission_weight));
+        }
+
+        let total_weight: f32 = lobes.iter().map(|(_, weight)| weight).sum();
+        Self {
+            lobes: lobes
+                .into_iter()
+                .map(|(bsdf, weight)| Lobe {
+                    bsdf,
+                    weight,
+                    probability: weight / total_weight,
+                })
+                .collect(),
+            transmission_tint: base_color,
+        }
+    }
+
+    fn tint(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        if same_hemisphere(wo, wi) {
+            Vec3::ONE
+        } else {
+            self.transmission_tint
+        }
+    }
+}
+
+impl Bsdf for PrincipledBsdf {
+    fn sample(&self, wo: Vec3, uc: f32, u: Vec2) -> Option<BsdfSample> {
+        // Choose a lobe and remap the discrete sample so it can be reused
+        let mut uc = uc;
+        let mut chosen = self.lobes.last()?;
+        for lobe in self.lobes.iter() {
+            if uc < lobe.probability {
+                chosen = lobe;
+                uc /= lobe.probability;
+                break;
+            }
+            uc -= lobe.probability;
+        }
+        let uc = uc.clamp(0.0, 1.0 - f32::EPSILON);
+
+        let sample = chosen.bsdf.sample(wo, uc, u)?;
+        if sample.is_specular {
+            // No other lobe can scatter towards a singular direction
+            return Some(BsdfSample {
+                f: sample.f * chosen.weight * self.tint(wo, sample.wi),
+                pdf: sample.pdf * chosen.probability,
+                ..sample
+            });
+        }
+
+        let pdf = self.pdf(wo, sample.wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            wi: sample.wi,
+            f: self.eval(wo, sample.wi),
+            pdf,
+            is_specular: false,
+        })
+    }
+
+    fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        let f: Vec3 = self
+            .lobes
+            .iter()
+            .map(|lobe| lobe.bsdf.eval(wo, wi) * lobe.weight)
+            .sum();
+        f * self.tint(wo, wi)
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f32 {
+        self.lobes
+            .iter()
+            .map(|lobe| lobe.bsdf.pdf(wo, wi) * lobe.probability)
+            .sum()
+    }
+
+    fn is_specular(&self) -> bool {
+        self.lobes.iter().all(|lobe| lobe.bsdf.is_specular())
+    }
+}
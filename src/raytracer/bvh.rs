@@ -4,7 +4,9 @@ use std::sync::Arc;
 use bincode::{Decode, Encode};
 use tracing::debug;
 
-use crate::raytracer::{Aabb, Geometry, Hit, Hittable, Intersectable, Model, Ray};
+use crate::raytracer::{
+    Aabb, Geometry, Hit, Hittable, Intersectable, Model, Ray, SceneError, SceneIssue,
+};
 
 pub trait Bounded {
     fn aabb(&self) -> Aabb;
@@ -17,46 +19,71 @@ pub enum BvhNode<H: Hittable + Bounded> {
         right: Arc<BvhNode<H>>,
         aabb: Aabb,
     },
-    Leaf(Arc<H>),
+    /// Leaves keep the bounds of their object, which are costly to compute
+    /// for transformed and moving objects.
+    Leaf { object: Arc<H>, aabb: Aabb },
 }
 
 impl<H: Hittable + Bounded> BvhNode<H> {
     pub fn new(elems: &mut [Arc<H>]) -> Self {
         assert!(elems.len() > 0, "Cannot create a BVH with 0 elements");
 
+        // Bounds are computed once, since they're costly for moving objects.
+        // Elements are still reordered like the BVH leaves.
+        let mut bounded: Vec<_> = elems.iter().map(|h| (h.clone(), h.aabb())).collect();
+        let node = Self::build(&mut bounded);
+        for (elem, (h, _)) in elems.iter_mut().zip(bounded) {
+            *elem = h;
+        }
+        node
+    }
+
+    fn build(elems: &mut [(Arc<H>, Aabb)]) -> Self {
         let mut aabb = Aabb::empty();
-        for h in elems.iter() {
-            aabb = Aabb::surround(&aabb, &h.aabb());
+        for (_, h_aabb) in elems.iter() {
+            aabb = Aabb::surround(&aabb, h_aabb);
         }
         let cmp_axis = (aabb.max_position - aabb.min_position).max_position();
 
         match elems.len() {
-            1 => Self::Leaf(elems[0].clone()),
+            1 => Self::Leaf {
+                object: elems[0].0.clone(),
+                aabb,
+            },
             _ => {
-                elems.sort_by(|a, b| {
-                    a.aabb().min_position[cmp_axis].total_cmp(&b.aabb().min_position[cmp_axis])
+                elems.sort_by(|(_, a), (_, b)| {
+                    a.min_position[cmp_axis].total_cmp(&b.min_position[cmp_axis])
                 });
                 let mid = elems.len() / 2;
                 let (left_slice, right_slice) = elems.split_at_mut(mid);
-                let left = Arc::new(BvhNode::new(left_slice));
-                let right = Arc::new(BvhNode::new(right_slice));
+                let left = Arc::new(BvhNode::build(left_slice));
+                let right = Arc::new(BvhNode::build(right_slice));
 
                 Self::Branch { left, right, aabb }
             }
         }
     }
 
+    /// Same as `new`, but returns an error for an empty slice instead of
+    /// panicking.
+    pub fn try_new(elems: &mut [Arc<H>]) -> Result<Self, SceneError> {
+        if elems.is_empty() {
+            return Err(SceneIssue::EmptyBvh.into());
+        }
+        Ok(Self::new(elems))
+    }
+
     pub fn aabb(&self) -> Aabb {
         match self {
             Self::Branch { aabb, .. } => aabb.clone(),
-            Self::Leaf(obj) => obj.aabb(),
+            Self::Leaf { aabb, .. } => aabb.clone(),
         }
     }
 
     pub fn depth(&self) -> usize {
         match self {
             Self::Branch { left, right, .. } => left.depth().max(right.depth()) + 1,
-            Self::Leaf(_) => 1,
+            Self::Leaf { .. } => 1,
         }
     }
 }
@@ -82,11 +109,11 @@ impl<H: Hittable + Bounded> Hittable for BvhNode<H> {
                     left_hit
                 }
             }
-            Self::Leaf(obj) => {
-                if !obj.aabb().intersect(&ray) {
+            Self::Leaf { object, aabb } => {
+                if !aabb.intersect(ray) {
                     return None;
                 }
-                obj.hit(&ray)
+                object.hit(ray)
             }
         }
     }
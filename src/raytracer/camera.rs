@@ -1,7 +1,120 @@
 use bincode::{Decode, Encode};
-use glam::Vec3;
+use glam::{Vec2, Vec3};
+use serde::{Deserialize, Serialize};
 
-use crate::raytracer::Ray;
+use crate::raytracer::{Frame, Ray, SceneError, SceneIssue};
+use crate::utils;
+
+/// Lens aperture of a thin lens camera, which blurs everything away from the
+/// plane in focus. Apertures with blades are regular polygons, giving
+/// polygonal bokeh, while apertures without blades are round.
+#[derive(Debug, Clone, Copy, PartialEq, Encode, Decode, Serialize, Deserialize)]
+pub struct Aperture {
+    /// Lens radius, or zero for a pinhole camera.
+    pub radius: f32,
+    /// Distance along the camera forward vector of the plane in focus.
+    pub focus_distance: f32,
+    /// Number of diaphragm blades, at least 3, or zero for a round aperture.
+    #[serde(default)]
+    pub blades: u32,
+    /// Rotation of the blades in degrees.
+    #[serde(default)]
+    pub rotation: f32,
+}
+
+impl Aperture {
+    /// Pinhole aperture, keeping everything in focus.
+    pub const PINHOLE: Self = Self {
+        radius: 0.0,
+        focus_distance: 1.0,
+        blades: 0,
+        rotation: 0.0,
+    };
+
+    pub fn is_pinhole(&self) -> bool {
+        self.radius == 0.0
+    }
+
+    /// Whether the radius is finite and non negative, the focus distance
+    /// positive and the number of blades valid.
+    pub fn is_valid(&self) -> bool {
+        self.radius >= 0.0
+            && self.radius.is_finite()
+            && self.focus_distance > 0.0
+            && self.focus_distance.is_finite()
+            && self.rotation.is_finite()
+            && (self.blades == 0 || self.blades >= 3)
+    }
+
+    /// Map a uniform sample in [0, 1)^2 to a uniformly distributed point of
+    /// the aperture, relative to the lens center.
+    pub fn sample(&self, u: Vec2) -> Vec2 {
+        if self.blades == 0 {
+            return self.radius * utils::sample_uniform_disk(u);
+        }
+        // Choose one of the triangles between the center and each edge, all
+        // of them with the same area
+        let blades = self.blades as f32;
+        let blade = (u.x * blades).floor().min(blades - 1.0);
+        let u = Vec2::new(u.x * blades - blade, u.y);
+        let angle =
+            |idx: f32| self.rotation.to_radians() + 2.0 * std::f32::consts::PI * idx / blades;
+        let (sin0, cos0) = angle(blade).sin_cos();
+        let (sin1, cos1) = angle(blade + 1.0).sin_cos();
+        let edge_point = Vec2::new(cos0, sin0).lerp(Vec2::new(cos1, sin1), u.y);
+        self.radius * u.x.sqrt() * edge_point
+    }
+}
+
+impl Default for Aperture {
+    fn default() -> Self {
+        Self::PINHOLE
+    }
+}
+
+/// Mapping from viewport coordinates to camera rays.
+#[derive(Debug, Clone, Copy, PartialEq, Encode, Decode, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Projection {
+    /// Rays through a pinhole, or a thin lens, with a vertical field of view
+    /// in degrees.
+    Perspective { fov: f32 },
+    /// Parallel rays along the forward vector, from a viewport `height`
+    /// world units tall.
+    Orthographic { height: f32 },
+    /// Every direction around the camera, with longitude along the viewport
+    /// width and latitude along its height.
+    Equirectangular,
+    /// Equidistant fisheye, where the angle from the forward vector grows
+    /// linearly with the distance to the viewport center, up to half of the
+    /// vertical field of view `fov`, in degrees, at the top and bottom edges.
+    /// Viewport points further than 180 degrees from the forward vector see
+    /// nothing.
+    Fisheye { fov: f32 },
+}
+
+impl Projection {
+    /// Whether rays start at the camera position and cover directions
+    /// beyond a plane, so that the plane in focus becomes a sphere.
+    fn is_panoramic(&self) -> bool {
+        matches!(self, Self::Equirectangular | Self::Fisheye { .. })
+    }
+
+    fn issue(&self) -> Option<SceneIssue> {
+        match *self {
+            Self::Perspective { fov } if !(fov > 0.0 && fov < 180.0) => {
+                Some(SceneIssue::InvalidFov(fov))
+            }
+            Self::Orthographic { height } if !(height > 0.0 && height.is_finite()) => {
+                Some(SceneIssue::InvalidViewportHeight(height))
+            }
+            Self::Fisheye { fov } if !(fov > 0.0 && fov <= 360.0) => {
+                Some(SceneIssue::InvalidFisheyeFov(fov))
+            }
+            _ => None,
+        }
+    }
+}
 
 #[derive(Debug, Clone, Encode, Decode)]
 pub struct Camera {
@@ -13,25 +126,80 @@ pub struct Camera {
     right: Vec3,
     #[bincode(with_serde)]
     up: Vec3,
-    fov: f32,
+    projection: Projection,
     aspect_ratio: f32,
+    aperture: Aperture,
+    /// Times at which the shutter opens and closes.
+    shutter_open: f32,
+    shutter_close: f32,
 }
 
 impl Camera {
+    /// Camera with a perspective projection of vertical field of view `fov`,
+    /// in degrees.
     pub fn new(position: Vec3, forward: Vec3, world_up: Vec3, fov: f32, aspect_ratio: f32) -> Self {
-        assert!(
-            forward.is_normalized() && world_up.is_normalized(),
-            "Camera vectors must be normalized"
-        );
-        assert!(
-            fov > 0.0 && fov < 180.0,
-            "Invalid field of view value range '0 < fov < 180'"
-        );
-        assert!(
-            aspect_ratio > 0.0 && fov < 180.0,
-            "Invalid aspect ratio value ('0 < aspect_ratio')"
-        );
+        Self::try_new(position, forward, world_up, fov, aspect_ratio)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
 
+    /// Same as `new`, but returns every problem of the arguments instead of
+    /// panicking.
+    pub fn try_new(
+        position: Vec3,
+        forward: Vec3,
+        world_up: Vec3,
+        fov: f32,
+        aspect_ratio: f32,
+    ) -> Result<Self, SceneError> {
+        Self::try_new_with_projection(
+            position,
+            forward,
+            world_up,
+            Projection::Perspective { fov },
+            aspect_ratio,
+        )
+    }
+
+    pub fn new_with_projection(
+        position: Vec3,
+        forward: Vec3,
+        world_up: Vec3,
+        projection: Projection,
+        aspect_ratio: f32,
+    ) -> Self {
+        Self::try_new_with_projection(position, forward, world_up, projection, aspect_ratio)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Same as `new_with_projection`, but returns every problem of the
+    /// arguments instead of panicking.
+    pub fn try_new_with_projection(
+        position: Vec3,
+        forward: Vec3,
+        world_up: Vec3,
+        projection: Projection,
+        aspect_ratio: f32,
+    ) -> Result<Self, SceneError> {
+        let camera = Self::from_parts(position, forward, world_up, projection, aspect_ratio);
+        let mut issues = camera.issues();
+        if !(forward.is_normalized() && world_up.is_normalized()) {
+            if !issues.contains(&SceneIssue::NonNormalizedCameraVectors) {
+                issues.push(SceneIssue::NonNormalizedCameraVectors);
+            }
+        } else if forward.cross(world_up).length_squared() < f32::EPSILON {
+            issues.push(SceneIssue::ParallelCameraVectors);
+        }
+        SceneError::check(issues)?;
+        Ok(camera)
+    }
+
+    fn from_parts(
+        position: Vec3,
+        forward: Vec3,
+        world_up: Vec3,
+        projection: Projection,
+        aspect_ratio: f32,
+    ) -> Self {
         let right = forward.cross(world_up);
         Self {
             position,
@@ -40,9 +208,77 @@ impl Camera {
             // No need to normalize since 'forward' 'right' are already unit
             // vectors.
             up: right.cross(forward),
-            fov,
+            projection,
             aspect_ratio,
+            aperture: Aperture::PINHOLE,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+        }
+    }
+
+    /// Replace the pinhole by a thin lens with the given `aperture`.
+    pub fn with_aperture(self, aperture: Aperture) -> Self {
+        self.try_with_aperture(aperture)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Same as `with_aperture`, but returns an error for invalid apertures
+    /// instead of panicking.
+    pub fn try_with_aperture(mut self, aperture: Aperture) -> Result<Self, SceneError> {
+        if !aperture.is_valid() {
+            return Err(SceneIssue::InvalidAperture.into());
+        }
+        self.aperture = aperture;
+        Ok(self)
+    }
+
+    /// Keep the shutter open from `open` to `close`, so that moving objects
+    /// are blurred along their motion over this interval.
+    pub fn with_shutter(self, open: f32, close: f32) -> Self {
+        self.try_with_shutter(open, close)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Same as `with_shutter`, but returns an error for invalid intervals
+    /// instead of panicking.
+    pub fn try_with_shutter(mut self, open: f32, close: f32) -> Result<Self, SceneError> {
+        self.shutter_open = open;
+        self.shutter_close = close;
+        if !self.is_shutter_valid() {
+            return Err(SceneIssue::InvalidShutter { open, close }.into());
         }
+        Ok(self)
+    }
+
+    fn is_shutter_valid(&self) -> bool {
+        self.shutter_open.is_finite()
+            && self.shutter_close.is_finite()
+            && self.shutter_open <= self.shutter_close
+    }
+
+    /// Problems of the camera parameters, which would make rendering fail.
+    pub(crate) fn issues(&self) -> Vec<SceneIssue> {
+        let mut issues = Vec::new();
+        if !self.position.is_finite() {
+            issues.push(SceneIssue::NonFiniteCameraPosition);
+        }
+        if !(self.forward.is_normalized() && self.right.is_finite() && self.up.is_finite()) {
+            issues.push(SceneIssue::NonNormalizedCameraVectors);
+        }
+        issues.extend(self.projection.issue());
+        if !(self.aspect_ratio > 0.0 && self.aspect_ratio.is_finite()) {
+            issues.push(SceneIssue::InvalidAspectRatio(self.aspect_ratio));
+        }
+        if !self.aperture.is_valid() {
+            issues.push(SceneIssue::InvalidAperture);
+        }
+        if !self.is_shutter_valid() {
+            issues.push(SceneIssue::InvalidShutter {
+                open: self.shutter_open,
+                close: self.shutter_close,
+            });
+        }
+        issues
     }
 
     /// Camera position.
@@ -65,9 +301,8 @@ impl Camera {
         self.up
     }
 
-    /// Vertical field of view in radians.
-    pub fn fov(&self) -> f32 {
-        self.fov
+    pub fn projection(&self) -> &Projection {
+        &self.projection
     }
 
     /// Camera viewport aspect ratio.
@@ -75,15 +310,77 @@ impl Camera {
         self.aspect_ratio
     }
 
-    /// Create a ray according to the camera orientation and viewport
-    /// coordinate. Both u and v must be within [-1, 1].
-    pub fn create_viewport_ray(&self, u: f32, v: f32) -> Ray {
-        let vfov = (self.fov as f32).to_radians();
-        let half_height = (vfov / 2.0).tan();
-        let half_width = self.aspect_ratio * half_height;
+    pub fn aperture(&self) -> &Aperture {
+        &self.aperture
+    }
 
-        let direction = self.forward + self.right * (u * half_width) + self.up * (v * half_height);
+    /// Times at which the shutter opens and closes.
+    pub fn shutter(&self) -> (f32, f32) {
+        (self.shutter_open, self.shutter_close)
+    }
+
+    /// Create a ray according to the camera projection, orientation and
+    /// viewport coordinate. Both u and v must be within [-1, 1]. The `lens`
+    /// sample, within [0, 1)^2, chooses the ray origin on the aperture, and
+    /// the `shutter` sample, within [0, 1), the ray time while the shutter is
+    /// open. Returns `None` for viewport points which see nothing.
+    pub fn create_viewport_ray(&self, u: f32, v: f32, lens: Vec2, shutter: f32) -> Option<Ray> {
+        use std::f32::consts::PI;
+
+        let time = self.shutter_open + (self.shutter_close - self.shutter_open) * shutter;
+        // Directions of planar projections have a unit forward component, so
+        // that the plane in focus is at the focus distance
+        let (origin, direction) = match self.projection {
+            Projection::Perspective { fov } => {
+                let half_height = (fov.to_radians() / 2.0).tan();
+                let half_width = self.aspect_ratio * half_height;
+                let direction =
+                    self.forward + self.right * (u * half_width) + self.up * (v * half_height);
+                (self.position, direction)
+            }
+            Projection::Orthographic { height } => {
+                let half_height = height / 2.0;
+                let half_width = self.aspect_ratio * half_height;
+                let origin =
+                    self.position + self.right * (u * half_width) + self.up * (v * half_height);
+                (origin, self.forward)
+            }
+            Projection::Equirectangular => {
+                let (sin_phi, cos_phi) = (u * PI).sin_cos();
+                let (sin_theta, cos_theta) = (v * PI / 2.0).sin_cos();
+                let direction = (self.forward * cos_phi + self.right * sin_phi) * cos_theta
+                    + self.up * sin_theta;
+                (self.position, direction)
+            }
+            Projection::Fisheye { fov } => {
+                let offset = Vec2::new(u * self.aspect_ratio, v);
+                let angle = offset.length() * fov.to_radians() / 2.0;
+                if angle > PI {
+                    return None;
+                }
+                let offset = offset.normalize_or_zero();
+                let (sin_angle, cos_angle) = angle.sin_cos();
+                let direction = self.forward * cos_angle
+                    + (self.right * offset.x + self.up * offset.y) * sin_angle;
+                (self.position, direction)
+            }
+        };
+        if self.aperture.is_pinhole() {
+            return Some(Ray::new(origin, direction.normalize()).with_time(time));
+        }
 
-        Ray::new(self.position, direction.normalize())
+        // Every ray through the lens converges at the same point of the plane
+        // in focus as the ray through its center. Panoramic lenses face each
+        // ray and focus on a sphere instead.
+        let focus_point = origin + direction * self.aperture.focus_distance;
+        let offset = self.aperture.sample(lens);
+        let (lens_right, lens_up) = if self.projection.is_panoramic() {
+            let lens_right = Frame::from_normal_tangent(direction, self.right).tangent;
+            (lens_right, lens_right.cross(direction))
+        } else {
+            (self.right, self.up)
+        };
+        let origin = origin + lens_right * offset.x + lens_up * offset.y;
+        Some(Ray::new(origin, (focus_point - origin).normalize()).with_time(time))
     }
 }
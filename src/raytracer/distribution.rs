@@ -0,0 +1,128 @@
+use glam::Vec2;
+
+/// Piecewise constant distribution over [0, 1), proportional to a function
+/// given by its values over equally sized intervals. Functions which are zero
+/// everywhere are sampled uniformly.
+#[derive(Debug, Clone)]
+pub struct Distribution1D {
+    func: Vec<f32>,
+    /// Cumulative distribution at the interval boundaries, from 0 to 1.
+    cdf: Vec<f32>,
+    /// Integral of the function over [0, 1).
+    integral: f32,
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<f32>) -> Self {
+        assert!(!func.is_empty(), "Cannot create an empty distribution");
+        assert!(
+            func.iter().all(|value| value.is_finite() && *value >= 0.0),
+            "Distribution function must be finite and non negative"
+        );
+        let n = func.len() as f32;
+        let mut cdf = Vec::with_capacity(func.len() + 1);
+        cdf.push(0.0);
+        for value in func.iter() {
+            cdf.push(cdf[cdf.len() - 1] + value / n);
+        }
+        let integral = cdf[func.len()];
+        for (idx, c) in cdf.iter_mut().enumerate() {
+            *c = if integral > 0.0 {
+                *c / integral
+            } else {
+                idx as f32 / n
+            };
+        }
+        Self {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.func.len()
+    }
+
+    pub fn integral(&self) -> f32 {
+        self.integral
+    }
+
+    /// Map a uniform sample in [0, 1) to a point distributed proportionally
+    /// to the function, along with its density and interval index.
+    pub fn sample(&self, u: f32) -> (f32, f32, usize) {
+        // Last boundary at or below the sample, which can't be the end since
+        // samples are below 1
+        let idx = (self.cdf.partition_point(|c| *c <= u).max(1) - 1).min(self.len() - 1);
+        let width = self.cdf[idx + 1] - self.cdf[idx];
+        let offset = if width > 0.0 {
+            ((u - self.cdf[idx]) / width).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let x = ((idx as f32 + offset) / self.len() as f32).min(1.0 - f32::EPSILON);
+        (x, self.pdf_at(idx), idx)
+    }
+
+    /// Density of sampling `x`.
+    pub fn pdf(&self, x: f32) -> f32 {
+        let idx = ((x * self.len() as f32) as usize).min(self.len() - 1);
+        self.pdf_at(idx)
+    }
+
+    fn pdf_at(&self, idx: usize) -> f32 {
+        if self.integral > 0.0 {
+            self.func[idx] / self.integral
+        } else {
+            1.0
+        }
+    }
+}
+
+/// Piecewise constant distribution over [0, 1)^2, proportional to a function
+/// given by its values over a grid of `width` by `height` cells. Rows are
+/// chosen by their integral first, then a point within the chosen row.
+#[derive(Debug, Clone)]
+pub struct Distribution2D {
+    /// Distribution within each row.
+    rows: Vec<Distribution1D>,
+    /// Distribution of the rows.
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    /// Distribution of a function with `width` values per row, rows being
+    /// stored one after the other.
+    pub fn new(func: &[f32], width: usize, height: usize) -> Self {
+        assert_eq!(
+            func.len(),
+            width * height,
+            "Distribution function must have one value per cell"
+        );
+        let rows: Vec<_> = func
+            .chunks_exact(width)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(rows.iter().map(Distribution1D::integral).collect());
+        Self { rows, marginal }
+    }
+
+    /// Integral of the function over [0, 1)^2.
+    pub fn integral(&self) -> f32 {
+        self.marginal.integral()
+    }
+
+    /// Map a uniform sample in [0, 1)^2 to a point distributed proportionally
+    /// to the function, along with its density.
+    pub fn sample(&self, u: Vec2) -> (Vec2, f32) {
+        let (y, row_pdf, row) = self.marginal.sample(u.y);
+        let (x, pdf, _) = self.rows[row].sample(u.x);
+        (Vec2::new(x, y), row_pdf * pdf)
+    }
+
+    /// Density of sampling `p`.
+    pub fn pdf(&self, p: Vec2) -> f32 {
+        let row = ((p.y * self.rows.len() as f32) as usize).min(self.rows.len() - 1);
+        self.marginal.pdf(p.y) * self.rows[row].pdf(p.x)
+    }
+}
@@ -0,0 +1,370 @@
+use std::f32::consts::PI;
+use std::path::{Path, PathBuf};
+
+use bincode::de::Decoder;
+use bincode::enc::Encoder;
+use bincode::error::{DecodeError, EncodeError};
+use bincode::{Decode, Encode};
+use glam::{Quat, Vec2, Vec3};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::raytracer::{Distribution2D, SceneIssue, Sky};
+
+/// Relative luminance of a linear RGB color.
+pub(crate) fn luminance(color: Vec3) -> f32 {
+    color.dot(Vec3::new(0.2126, 0.7152, 0.0722))
+}
+
+/// Direction of equirectangular image coordinates in [0, 1]^2 whose center
+/// faces -z, along with the sine of their polar angle.
+pub(crate) fn equirectangular_direction(uv: Vec2) -> (Vec3, f32) {
+    let (sin_phi, cos_phi) = ((uv.x - 0.5) * 2.0 * PI).sin_cos();
+    let (sin_theta, cos_theta) = (uv.y * PI).sin_cos();
+    let d = Vec3::new(-sin_phi * sin_theta, cos_theta, -cos_phi * sin_theta);
+    (d, sin_theta)
+}
+
+/// Light arriving from infinitely far away around the scene, either from an
+/// image or from a procedural sky.
+#[derive(Debug, Clone, Encode, Decode)]
+pub enum Environment {
+    Map(EnvironmentMap),
+    Sky(Sky),
+}
+
+impl Environment {
+    /// Radiance arriving from the world space `direction`.
+    pub fn eval(&self, direction: Vec3) -> Vec3 {
+        match self {
+            Self::Map(map) => map.eval(direction),
+            Self::Sky(sky) => sky.eval(direction),
+        }
+    }
+
+    /// Map a uniform sample in [0, 1)^2 to a direction, along with the
+    /// radiance arriving from it and its solid angle density.
+    pub fn sample(&self, u: Vec2) -> (Vec3, Vec3, f32) {
+        match self {
+            Self::Map(map) => map.sample(u),
+            Self::Sky(sky) => sky.sample(u),
+        }
+    }
+
+    /// Solid angle density with which `sample` chooses the world space
+    /// `direction`.
+    pub fn pdf(&self, direction: Vec3) -> f32 {
+        match self {
+            Self::Map(map) => map.pdf(direction),
+            Self::Sky(sky) => sky.pdf(direction),
+        }
+    }
+
+    /// Whether no light comes from any direction.
+    pub fn is_black(&self) -> bool {
+        match self {
+            Self::Map(map) => map.is_black(),
+            Self::Sky(sky) => sky.intensity() == 0.0,
+        }
+    }
+
+    /// First reason why the environment can't be rendered, if any.
+    pub(crate) fn issue(&self) -> Option<SceneIssue> {
+        match self {
+            Self::Map(map) if !map.rotation().is_finite() => {
+                Some(SceneIssue::NonFiniteEnvironmentRotation)
+            }
+            Self::Sky(sky) if !sky.is_valid() => Some(SceneIssue::InvalidSky),
+            _ => None,
+        }
+    }
+}
+
+impl From<EnvironmentMap> for Environment {
+    fn from(map: EnvironmentMap) -> Self {
+        Self::Map(map)
+    }
+}
+
+impl From<Sky> for Environment {
+    fn from(sky: Sky) -> Self {
+        Self::Sky(sky)
+    }
+}
+
+/// Light arriving from infinitely far away, given by an equirectangular image
+/// of linear RGB radiance, usually loaded from an `.hdr` or `.exr` file.
+///
+/// Image columns span the directions around the y axis and rows go from +y
+/// at the top to -y at the bottom. The image center faces -z, so that a
+/// camera at the origin looking at -z with an equirectangular projection
+/// sees the image unchanged. The map may be rotated around the y axis.
+///
+/// Directions are sampled proportionally to the luminance of the pixels, so
+/// that small bright regions such as the sun or studio lights are found by
+/// direct light sampling.
+///
+/// Scene files only reference the image file, so maps which weren't loaded
+/// from a file can't be serialized.
+#[derive(Debug, Clone)]
+pub struct EnvironmentMap {
+    width: u32,
+    height: u32,
+    pixels: Vec<Vec3>,
+    /// Rotation around the y axis, in degrees.
+    rotation: f32,
+    /// Distribution of the pixels, weighted by their luminance and the solid
+    /// angle they cover.
+    distribution: Distribution2D,
+    /// File the image was loaded from, if any.
+    path: Option<PathBuf>,
+}
+
+/// Serde representation of environment maps.
+#[derive(Serialize, Deserialize)]
+struct EnvironmentSource {
+    path: PathBuf,
+    #[serde(default)]
+    rotation: f32,
+}
+
+impl EnvironmentMap {
+    pub fn new(width: u32, height: u32, pixels: Vec<Vec3>) -> Self {
+        Self::from_parts(width, height, pixels).expect(
+            "Environment map must have one finite and non negative color per pixel of a non empty image",
+        )
+    }
+
+    /// Map with the given pixels, or `None` if they don't match the size or
+    /// aren't finite and non negative.
+    fn from_parts(width: u32, height: u32, pixels: Vec<Vec3>) -> Option<Self> {
+        let is_valid = width > 0
+            && height > 0
+            && pixels.len() == width as usize * height as usize
+            && pixels
+                .iter()
+                .all(|pixel| pixel.is_finite() && pixel.min_element() >= 0.0);
+        if !is_valid {
+            return None;
+        }
+
+        // Pixels are weighted by the average of the bilinearly filtered
+        // luminance over them, since filtering spreads bright pixels over
+        // their neighbors, which must be sampled too. Rows near the poles are
+        // squeezed into a smaller solid angle.
+        let (w, h) = (width as i64, height as i64);
+        let lum =
+            |x: i64, y: i64| luminance(pixels[(y.clamp(0, h - 1) * w + x.rem_euclid(w)) as usize]);
+        let kernel = [(-1, 0.125), (0, 0.75), (1, 0.125)];
+        let weights: Vec<f32> = (0..h)
+            .flat_map(|y| {
+                let sin_theta = (PI * (y as f32 + 0.5) / height as f32).sin();
+                (0..w).map(move |x| {
+                    let filtered: f32 = kernel
+                        .iter()
+                        .flat_map(|&(dy, ky)| {
+                            kernel
+                                .iter()
+                                .map(move |&(dx, kx)| ky * kx * lum(x + dx, y + dy))
+                        })
+                        .sum();
+                    filtered * sin_theta
+                })
+            })
+            .collect();
+        let distribution = Distribution2D::new(&weights, width as usize, height as usize);
+        Some(Self {
+            width,
+            height,
+            pixels,
+            rotation: 0.0,
+            distribution,
+            path: None,
+        })
+    }
+
+    /// Convert an image to linear RGB radiance. Negative and non finite
+    /// values, which some EXR files hold, are replaced by zero.
+    pub fn from_image(image: &image::DynamicImage) -> Self {
+        let rgb = image.to_rgb32f();
+        let pixels = rgb
+            .pixels()
+            .map(|pixel| {
+                Vec3::from_array(pixel.0).map(|value| {
+                    if value.is_finite() {
+                        value.max(0.0)
+                    } else {
+                        0.0
+                    }
+                })
+            })
+            .collect();
+        Self::new(rgb.width(), rgb.height(), pixels)
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, image::ImageError> {
+        let path = path.as_ref();
+        Ok(Self {
+            path: Some(path.to_owned()),
+            ..Self::from_image(&image::open(path)?)
+        })
+    }
+
+    /// Rotate the map by `degrees` around the y axis.
+    pub fn with_rotation(mut self, degrees: f32) -> Self {
+        self.rotation = degrees;
+        self
+    }
+
+    /// File the image was loaded from, if any.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Rotation around the y axis, in degrees.
+    pub fn rotation(&self) -> f32 {
+        self.rotation
+    }
+
+    /// Whether no light comes from any direction.
+    pub fn is_black(&self) -> bool {
+        self.distribution.integral() == 0.0
+    }
+
+    fn to_world(&self) -> Quat {
+        Quat::from_rotation_y(self.rotation.to_radians())
+    }
+
+    /// Image coordinates in [0, 1]^2 of a world space direction.
+    fn direction_to_uv(&self, direction: Vec3) -> Vec2 {
+        let d = self.to_world().inverse() * direction;
+        let phi = (-d.x).atan2(-d.z);
+        let theta = d.y.clamp(-1.0, 1.0).acos();
+        Vec2::new(0.5 + phi / (2.0 * PI), theta / PI)
+    }
+
+    /// World space direction of image coordinates, along with the sine of
+    /// their polar angle.
+    fn uv_to_direction(&self, uv: Vec2) -> (Vec3, f32) {
+        let (d, sin_theta) = equirectangular_direction(uv);
+        (self.to_world() * d, sin_theta)
+    }
+
+    fn texel(&self, x: i64, y: i64) -> Vec3 {
+        // Columns wrap around, while rows stop at the poles
+        let x = x.rem_euclid(self.width as i64) as usize;
+        let y = y.clamp(0, self.height as i64 - 1) as usize;
+        self.pixels[y * self.width as usize + x]
+    }
+
+    /// Radiance arriving from the world space `direction`, bilinearly
+    /// filtered.
+    pub fn eval(&self, direction: Vec3) -> Vec3 {
+        let uv = self.direction_to_uv(direction);
+        let x = uv.x * self.width as f32 - 0.5;
+        let y = uv.y * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (dx, dy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let top = self.texel(x0, y0).lerp(self.texel(x0 + 1, y0), dx);
+        let bottom = self.texel(x0, y0 + 1).lerp(self.texel(x0 + 1, y0 + 1), dx);
+        top.lerp(bottom, dy)
+    }
+
+    /// Map a uniform sample in [0, 1)^2 to a direction chosen proportionally
+    /// to the luminance of the map, along with the radiance arriving from it
+    /// and its solid angle density. The density is zero for directions which
+    /// can't be sampled.
+    pub fn sample(&self, u: Vec2) -> (Vec3, Vec3, f32) {
+        let (uv, pdf) = self.distribution.sample(u);
+        let (direction, sin_theta) = self.uv_to_direction(uv);
+        if pdf == 0.0 || sin_theta == 0.0 {
+            return (direction, Vec3::ZERO, 0.0);
+        }
+        // Image coordinates span 2 pi radians horizontally and pi vertically
+        let pdf = pdf / (2.0 * PI * PI * sin_theta);
+        (direction, self.eval(direction), pdf)
+    }
+
+    /// Solid angle density with which `sample` chooses the world space
+    /// `direction`.
+    pub fn pdf(&self, direction: Vec3) -> f32 {
+        let uv = self.direction_to_uv(direction);
+        let sin_theta = (uv.y * PI).sin();
+        if sin_theta == 0.0 {
+            return 0.0;
+        }
+        self.distribution.pdf(uv) / (2.0 * PI * PI * sin_theta)
+    }
+}
+
+// Only the pixels are sent to peers, the sampling distribution is rebuilt when
+// decoding.
+impl Encode for EnvironmentMap {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        self.width.encode(encoder)?;
+        self.height.encode(encoder)?;
+        let pixels: Vec<[f32; 3]> = self.pixels.iter().map(|pixel| pixel.to_array()).collect();
+        pixels.encode(encoder)?;
+        self.rotation.encode(encoder)?;
+        self.path.encode(encoder)
+    }
+}
+
+impl<Context> Decode<Context> for EnvironmentMap {
+    fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let width = u32::decode(decoder)?;
+        let height = u32::decode(decoder)?;
+        let pixels = Vec::<[f32; 3]>::decode(decoder)?
+            .into_iter()
+            .map(Vec3::from_array)
+            .collect();
+        let rotation = f32::decode(decoder)?;
+        let path = Option::<PathBuf>::decode(decoder)?;
+        let map = Self::from_parts(width, height, pixels)
+            .ok_or(DecodeError::Other("Invalid environment map data"))?;
+        Ok(Self {
+            rotation,
+            path,
+            ..map
+        })
+    }
+}
+
+bincode::impl_borrow_decode!(EnvironmentMap);
+
+impl Serialize for EnvironmentMap {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let Some(path) = &self.path else {
+            return Err(serde::ser::Error::custom(
+                "Environment maps without a source file can't be serialized",
+            ));
+        };
+        EnvironmentSource {
+            path: path.clone(),
+            rotation: self.rotation,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for EnvironmentMap {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let source = EnvironmentSource::deserialize(deserializer)?;
+        let map = Self::from_file(&source.path).map_err(|err| {
+            serde::de::Error::custom(format!(
+                "Failed to load environment map '{}': {err}",
+                source.path.display()
+            ))
+        })?;
+        Ok(map.with_rotation(source.rotation))
+    }
+}
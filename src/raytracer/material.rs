@@ -1,127 +1,315 @@
+use std::sync::Arc;
+
 use bincode::{Decode, Encode};
-use glam::Vec3;
-use rand::Rng;
+use glam::{Vec2, Vec3};
+use serde::{Deserialize, Serialize};
 
-use crate::raytracer::{Hit, Ray};
-use crate::utils;
+use crate::raytracer::{
+    Bsdf, ConductorBsdf, ConductorReflectance, DielectricBsdf, Frame, FuzzyMetalBsdf, Hit,
+    ImageTexture, LambertianBsdf, OrenNayarBsdf, PrincipledBsdf, RoughDielectricBsdf, Texture,
+    TrowbridgeReitz,
+};
 
-#[derive(Debug, Clone, Encode, Decode)]
+#[derive(Debug, Clone, Encode, Decode, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum Material {
     DiffuseLight {
-        #[bincode(with_serde)]
-        emission: Vec3,
+        emission: Texture,
     },
     Diffuse {
-        #[bincode(with_serde)]
-        albedo: Vec3,
+        albedo: Texture,
+    },
+    /// Rough diffuse surface, where `sigma` is the standard deviation of the
+    /// microfacet orientation angle in degrees. A `sigma` of zero is
+    /// equivalent to `Diffuse`.
+    OrenNayar {
+        albedo: Texture,
+        sigma: f32,
     },
     Metalic {
-        #[bincode(with_serde)]
-        albedo: Vec3,
-        fuzzyness: f32,
+        albedo: Texture,
+        fuzzyness: Texture,
     },
     Dielectric {
         refraction_index: f32,
     },
+    /// Rough metal with a GGX microfacet distribution. Roughness is
+    /// perceptual, within [0, 1].
+    Conductor {
+        reflectance: ConductorReflectance,
+        roughness: Texture,
+    },
+    /// Rough glass with a GGX microfacet distribution. Roughness is
+    /// perceptual, within [0, 1].
+    RoughDielectric {
+        refraction_index: f32,
+        roughness: Texture,
+    },
+    /// Disney principled material, see `Principled`.
+    Principled(Principled),
+    /// Another material whose shading normal is perturbed by a normal or
+    /// bump map.
+    NormalMapped {
+        material: Arc<Material>,
+        normal_map: NormalMap,
+    },
+    /// Invisible surface which only marks the boundary of the medium inside
+    /// an object, such as smoke. Rays go through it unchanged.
+    Interface,
 }
 
-pub struct ScatteredRay {
-    pub ray: Ray,
-    pub attenuation: Vec3,
+/// Perturbation of the shading normal, following the surface derivatives of
+/// the hit geometry.
+#[derive(Debug, Clone, Encode, Decode, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NormalMap {
+    /// Tangent space normal map, where colors in [0, 1] encode a direction
+    /// whose green channel points towards increasing v, as in OpenGL and
+    /// glTF. Image textures must be loaded as linear. `strength` scales the
+    /// tangential components.
+    Tangent { texture: Texture, strength: f32 },
+    /// Scalar height map, displacing the surface along its normal by `scale`
+    /// times the height.
+    Bump { height: Texture, scale: f32 },
 }
 
-impl Material {
-    pub fn scatter(&self, ray: &Ray, hit: &Hit) -> Option<ScatteredRay> {
-        let mut rng = rand::rng();
+/// Step in surface coordinates used to differentiate bump maps.
+const BUMP_DELTA: f32 = 1e-3;
 
+impl NormalMap {
+    /// Perturbed outward normal at a hit, or `None` if the surface derivatives
+    /// are degenerate.
+    fn outward_normal(&self, hit: &Hit) -> Option<Vec3> {
+        let outward_normal = if hit.is_front_face {
+            hit.shading_normal
+        } else {
+            -hit.shading_normal
+        };
         match self {
-            Self::DiffuseLight { .. } => None,
-            Self::Diffuse { albedo } => {
-                let rnd_dir = utils::random_vector(&mut rng);
-                let mut direction = (hit.normal + rnd_dir).normalize();
-
-                if direction.is_nan() {
-                    direction = hit.normal;
+            Self::Tangent { texture, strength } => {
+                let color = texture.eval(hit.uv, hit.position);
+                let local = 2.0 * color - Vec3::ONE;
+                let local = Vec3::new(local.x * strength, local.y * strength, local.z);
+                let mut frame = Frame::from_normal_tangent(outward_normal, hit.dpdu);
+                // Mirrored surface coordinates flip the bitangent
+                if frame.bitangent.dot(hit.dpdv) < 0.0 {
+                    frame.bitangent = -frame.bitangent;
                 }
-
-                Some(ScatteredRay {
-                    ray: Ray::new(hit.position, direction),
-                    attenuation: *albedo,
-                })
+                frame.to_world(local).try_normalize()
             }
-            Self::Metalic { albedo, fuzzyness } => {
-                let reflected_dir = ray.direction().reflect(hit.normal).normalize();
-                let mut scattered_dir =
-                    (reflected_dir + *fuzzyness * utils::random_vector(&mut rng)).normalize();
-                if scattered_dir.is_nan() {
-                    scattered_dir = reflected_dir;
+            Self::Bump { height, scale } => {
+                let h = |uv: Vec2, position: Vec3| scale * height.eval_scalar(uv, position);
+                let base = h(hit.uv, hit.position);
+                let dhdu = (h(
+                    hit.uv + Vec2::new(BUMP_DELTA, 0.0),
+                    hit.position + BUMP_DELTA * hit.dpdu,
+                ) - base)
+                    / BUMP_DELTA;
+                let dhdv = (h(
+                    hit.uv + Vec2::new(0.0, BUMP_DELTA),
+                    hit.position + BUMP_DELTA * hit.dpdv,
+                ) - base)
+                    / BUMP_DELTA;
+                // Derivatives of the displaced surface, neglecting the
+                // variation of the normal itself
+                let dpdu = hit.dpdu + dhdu * outward_normal;
+                let dpdv = hit.dpdv + dhdv * outward_normal;
+                let normal = dpdu.cross(dpdv).try_normalize()?;
+                if normal.dot(outward_normal) < 0.0 {
+                    Some(-normal)
+                } else {
+                    Some(normal)
                 }
+            }
+        }
+    }
+}
 
-                let scattered_ray = Ray::new(hit.position, scattered_dir);
-                if scattered_ray.direction().dot(hit.normal) > 0.0 {
-                    Some(ScatteredRay {
-                        ray: scattered_ray,
-                        attenuation: *albedo,
-                    })
+/// Parameters of the Disney principled material. Every parameter except
+/// colors and the refraction index is within [0, 1]. Only the base color,
+/// metallic, roughness and emission parameters may vary over the surface.
+/// Missing parameters of scene files take their default value.
+#[derive(Debug, Clone, Encode, Decode, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Principled {
+    pub base_color: Texture,
+    pub metallic: Texture,
+    /// Perceptual roughness shared by the specular and transmission lobes.
+    pub roughness: Texture,
+    /// Specular reflectance of the non-metallic layer, where 0.5 corresponds
+    /// to a refraction index of 1.5.
+    pub specular: f32,
+    /// Amount by which the non-metallic specular is tinted by the base color.
+    pub specular_tint: f32,
+    /// Additional grazing component, mostly intended for cloth.
+    pub sheen: f32,
+    pub sheen_tint: f32,
+    /// Strength of a second, white specular lobe on top of the material.
+    pub clearcoat: f32,
+    /// Clearcoat glossiness, 0 for a satin and 1 for a gloss appearance.
+    pub clearcoat_gloss: f32,
+    /// Amount of light transmitted through the non-metallic layer.
+    pub transmission: f32,
+    /// Refraction index used for transmission.
+    pub ior: f32,
+    pub emission: Texture,
+}
+
+impl Default for Principled {
+    fn default() -> Self {
+        Self {
+            base_color: Vec3::splat(0.8).into(),
+            metallic: 0.0.into(),
+            roughness: 0.5.into(),
+            specular: 0.5,
+            specular_tint: 0.0,
+            sheen: 0.0,
+            sheen_tint: 0.5,
+            clearcoat: 0.0,
+            clearcoat_gloss: 1.0,
+            transmission: 0.0,
+            ior: 1.5,
+            emission: Vec3::ZERO.into(),
+        }
+    }
+}
+
+/// Ratio between the refraction index of the side the ray arrived from and
+/// the refraction index of the opposite side, assuming the object is
+/// surrounded by air.
+fn relative_eta(hit: &Hit, refraction_index: f32) -> f32 {
+    if hit.is_front_face {
+        1.0 / refraction_index
+    } else {
+        refraction_index
+    }
+}
+
+impl Material {
+    /// Build the BSDF describing how the material scatters light at a hit.
+    /// Returns `None` for materials that don't scatter light.
+    pub fn bsdf(&self, hit: &Hit) -> Option<Box<dyn Bsdf>> {
+        let (uv, position) = (hit.uv, hit.position);
+        match self {
+            Self::DiffuseLight { .. } | Self::Interface => None,
+            Self::Diffuse { albedo } => Some(Box::new(LambertianBsdf {
+                albedo: albedo.eval(uv, position),
+            })),
+            Self::OrenNayar { albedo, sigma } => Some(Box::new(OrenNayarBsdf::new(
+                albedo.eval(uv, position),
+                *sigma,
+            ))),
+            Self::Metalic { albedo, fuzzyness } => Some(Box::new(FuzzyMetalBsdf {
+                albedo: albedo.eval(uv, position),
+                fuzzyness: fuzzyness.eval_scalar(uv, position),
+            })),
+            Self::Dielectric { refraction_index } => Some(Box::new(DielectricBsdf {
+                eta: relative_eta(hit, *refraction_index),
+            })),
+            Self::Conductor {
+                reflectance,
+                roughness,
+            } => Some(Box::new(ConductorBsdf {
+                reflectance: *reflectance,
+                distribution: TrowbridgeReitz::from_roughness(roughness.eval_scalar(uv, position)),
+            })),
+            Self::RoughDielectric {
+                refraction_index,
+                roughness,
+            } => {
+                let eta = relative_eta(hit, *refraction_index);
+                let distribution =
+                    TrowbridgeReitz::from_roughness(roughness.eval_scalar(uv, position));
+                if distribution.is_smooth() {
+                    Some(Box::new(DielectricBsdf { eta }))
                 } else {
-                    None
+                    Some(Box::new(RoughDielectricBsdf { eta, distribution }))
                 }
             }
-            Self::Dielectric { refraction_index } => {
-                let attenuation = Vec3::new(1.0, 1.0, 1.0);
-                let real_refraction_index = if hit.is_front_face {
-                    1.0 / *refraction_index
-                } else {
-                    *refraction_index
-                };
-
-                let unit_ray_dir = ray.direction().normalize();
-                let cos_theta = f32::min((-unit_ray_dir).dot(hit.normal), 1.0);
-                let sin_theta = f32::sqrt(1.0 - cos_theta * cos_theta);
-                let cannot_refract = real_refraction_index * sin_theta > 1.0;
-
-                let schlick_approximation = |cosine: f32, ri: f32| {
-                    let r0 = (1.0 - ri) / (1.0 + ri);
-                    let r0_squared = r0 * r0;
-                    r0_squared + (1.0 - r0_squared) * f32::powf(1.0 - cosine, 5.0)
-                };
-
-                let ray_direction = if cannot_refract
-                    || schlick_approximation(cos_theta, real_refraction_index)
-                        > rng.random_range(0f32..1f32)
-                {
-                    let ray_direction = unit_ray_dir.reflect(hit.normal);
-                    if ray_direction.normalize().is_nan() {
-                        println!(
-                            "Its the dialetric reflection!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!"
-                        );
-                    }
-                    ray_direction
-                } else {
-                    let ray_direction = unit_ray_dir.refract(hit.normal, real_refraction_index);
-                    if ray_direction.normalize().is_nan() {
-                        println!(
-                            "Its the dialetric refraction: unit_ray_dir={}, hit.normal={}, ray.direction={} !!",
-                            unit_ray_dir,
-                            hit.normal,
-                            ray.direction()
-                        );
-                    }
-                    ray_direction
-                };
-
-                Some(ScatteredRay {
-                    ray: Ray::new(hit.position, ray_direction.normalize()),
-                    attenuation,
-                })
+            Self::Principled(params) => Some(Box::new(PrincipledBsdf::new(
+                params,
+                hit,
+                relative_eta(hit, params.ior),
+            ))),
+            Self::NormalMapped { material, .. } => material.bsdf(hit),
+        }
+    }
+
+    /// Shading normal at a hit, on the side of the geometric normal. Falls
+    /// back to the geometric normal when the shading normal would place the
+    /// outgoing direction `wo` below the surface.
+    pub fn shading_normal(&self, hit: &Hit, wo: Vec3) -> Vec3 {
+        let normal = match self {
+            Self::NormalMapped { normal_map, .. } => {
+                normal_map
+                    .outward_normal(hit)
+                    .map_or(hit.shading_normal, |normal| {
+                        if hit.is_front_face { normal } else { -normal }
+                    })
             }
+            _ => hit.shading_normal,
+        };
+        if normal.dot(hit.normal) > 0.0 && normal.dot(wo) > 0.0 {
+            normal
+        } else {
+            hit.normal
+        }
+    }
+
+    pub fn is_interface(&self) -> bool {
+        matches!(self, Self::Interface)
+    }
+
+    pub fn is_emissive(&self) -> bool {
+        self.emission_texture()
+            .is_some_and(|emission| !emission.is_zero())
+    }
+
+    fn emission_texture(&self) -> Option<&Texture> {
+        match self {
+            Self::DiffuseLight { emission } => Some(emission),
+            Self::Principled(params) => Some(&params.emission),
+            Self::NormalMapped { material, .. } => material.emission_texture(),
+            _ => None,
         }
     }
 
-    pub fn emission(&self) -> Vec3 {
-        if let Self::DiffuseLight { emission } = &self {
-            return *emission;
+    /// Radiance emitted at a surface point with coordinates `uv`.
+    pub fn emission(&self, uv: Vec2, position: Vec3) -> Vec3 {
+        self.emission_texture()
+            .map_or(Vec3::ZERO, |emission| emission.eval(uv, position))
+    }
+
+    /// Call `f` on every image texture of the material, in a fixed order.
+    /// Nested materials and textures shared with other ones are cloned first.
+    pub(crate) fn for_each_image_mut(&mut self, f: &mut impl FnMut(&mut Arc<ImageTexture>)) {
+        let textures = match self {
+            Self::DiffuseLight { emission } => vec![emission],
+            Self::Diffuse { albedo } | Self::OrenNayar { albedo, .. } => vec![albedo],
+            Self::Metalic { albedo, fuzzyness } => vec![albedo, fuzzyness],
+            Self::Conductor { roughness, .. } | Self::RoughDielectric { roughness, .. } => {
+                vec![roughness]
+            }
+            Self::Principled(params) => vec![
+                &mut params.base_color,
+                &mut params.metallic,
+                &mut params.roughness,
+                &mut params.emission,
+            ],
+            Self::NormalMapped {
+                material,
+                normal_map,
+            } => {
+                Arc::make_mut(material).for_each_image_mut(f);
+                match normal_map {
+                    NormalMap::Tangent { texture, .. } => vec![texture],
+                    NormalMap::Bump { height, .. } => vec![height],
+                }
+            }
+            Self::Dielectric { .. } | Self::Interface => vec![],
+        };
+        for texture in textures {
+            texture.for_each_image_mut(f);
         }
-        Vec3::new(0.0, 0.0, 0.0)
     }
 }
@@ -0,0 +1,304 @@
+use std::sync::Arc;
+
+use bincode::{Decode, Encode};
+use glam::{Vec2, Vec3};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::raytracer::{Aabb, Frame, MajorantTraversal, Ray, VoxelGrid};
+
+/// Participating medium, such as fog, smoke or the tinted interior of glass.
+/// Coefficients are given per unit of distance for each color channel.
+#[derive(Debug, Clone, Encode, Decode, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Medium {
+    /// Medium of constant density. `sigma_a` is the absorption coefficient,
+    /// `sigma_s` the scattering coefficient and `g` the asymmetry of the
+    /// Henyey-Greenstein phase function, within ]-1, 1[, where positive
+    /// values scatter light forward.
+    Homogeneous {
+        #[bincode(with_serde)]
+        sigma_a: Vec3,
+        #[bincode(with_serde)]
+        sigma_s: Vec3,
+        #[serde(default)]
+        g: f32,
+    },
+    /// Medium whose density is given by a voxel grid spanning `bounds`, in
+    /// world space, such as smoke or clouds. Coefficients are given for a
+    /// unit density and scaled by the grid density, which is zero outside of
+    /// the bounds. Grids are shared between the clones of a scene.
+    Grid {
+        grid: Arc<VoxelGrid>,
+        bounds: Aabb,
+        #[bincode(with_serde)]
+        sigma_a: Vec3,
+        #[bincode(with_serde)]
+        sigma_s: Vec3,
+        #[serde(default)]
+        g: f32,
+    },
+}
+
+/// Result of tracking a ray through a medium up to a surface.
+pub enum MediumSample {
+    /// The ray reached the end of the segment. Throughput must be multiplied
+    /// by `weight`.
+    Transmitted { weight: Vec3 },
+    /// The ray scattered at `distance` along it. Throughput must be
+    /// multiplied by `weight`.
+    Scattered { distance: f32, weight: Vec3 },
+    /// The ray was absorbed, ending the path.
+    Absorbed,
+}
+
+impl Medium {
+    /// Absorption and scattering coefficients at a point.
+    fn coefficients(&self, position: Vec3) -> (Vec3, Vec3) {
+        match self {
+            Self::Homogeneous {
+                sigma_a, sigma_s, ..
+            } => (*sigma_a, *sigma_s),
+            Self::Grid {
+                grid,
+                bounds,
+                sigma_a,
+                sigma_s,
+                ..
+            } => {
+                let size = bounds.max_position - bounds.min_position;
+                let p = (position - bounds.min_position) / size;
+                if p.cmplt(Vec3::ZERO).any() || p.cmpgt(Vec3::ONE).any() {
+                    return (Vec3::ZERO, Vec3::ZERO);
+                }
+                let density = grid.density(p);
+                (density * *sigma_a, density * *sigma_s)
+            }
+        }
+    }
+
+    /// Segments of a ray between its `tmin` and `tmax` along which the
+    /// extinction coefficient is bounded by a constant majorant, over every
+    /// color channel.
+    fn majorants(&self, ray: &Ray, tmax: f32) -> Majorants<'_> {
+        match self {
+            Self::Homogeneous {
+                sigma_a, sigma_s, ..
+            } => Majorants::Constant(Some((
+                ray.tmin(),
+                tmax,
+                (*sigma_a + *sigma_s).max_element(),
+            ))),
+            Self::Grid {
+                grid,
+                bounds,
+                sigma_a,
+                sigma_s,
+                ..
+            } => {
+                // Rays keep their distances in grid space
+                let size = bounds.max_position - bounds.min_position;
+                let origin = (ray.origin() - bounds.min_position) / size;
+                let direction = ray.direction() / size;
+                Majorants::Grid {
+                    traversal: grid.majorants(origin, direction, ray.tmin(), tmax),
+                    scale: (*sigma_a + *sigma_s).max_element(),
+                }
+            }
+        }
+    }
+
+    pub fn phase(&self) -> HenyeyGreenstein {
+        match self {
+            Self::Homogeneous { g, .. } | Self::Grid { g, .. } => HenyeyGreenstein { g: *g },
+        }
+    }
+
+    /// Whether the coefficients are finite and non negative, the phase
+    /// function asymmetry is within ]-1, 1[ and grid bounds are finite and
+    /// not empty.
+    pub fn is_valid(&self) -> bool {
+        let (sigma_a, sigma_s, g) = match self {
+            Self::Homogeneous {
+                sigma_a,
+                sigma_s,
+                g,
+            } => (sigma_a, sigma_s, g),
+            Self::Grid {
+                bounds,
+                sigma_a,
+                sigma_s,
+                g,
+                ..
+            } => {
+                let size = bounds.max_position - bounds.min_position;
+                if !(bounds.min_position.is_finite()
+                    && size.is_finite()
+                    && size.min_element() > 0.0)
+                {
+                    return false;
+                }
+                (sigma_a, sigma_s, g)
+            }
+        };
+        sigma_a.is_finite()
+            && sigma_s.is_finite()
+            && sigma_a.min_element() >= 0.0
+            && sigma_s.min_element() >= 0.0
+            && g.abs() < 1.0
+    }
+
+    /// Track a ray through the medium between its `tmin` and `tmax`, with
+    /// delta tracking. Tentative collisions are sampled against the majorant
+    /// of each segment and classified as absorption, scattering or null
+    /// collisions, with probabilities averaged over the color channels.
+    /// Weights correct the estimate of channels whose coefficients differ
+    /// from the average.
+    pub fn sample(&self, ray: &Ray, tmax: f32, rng: &mut impl Rng) -> MediumSample {
+        // Media which only absorb light are integrated exactly
+        if let Self::Homogeneous { sigma_s, .. } = self
+            && *sigma_s == Vec3::ZERO
+        {
+            return MediumSample::Transmitted {
+                weight: self.transmittance(ray, tmax, rng),
+            };
+        }
+        let mut weight = Vec3::ONE;
+        for (tmin, tmax, majorant) in self.majorants(ray, tmax) {
+            if majorant <= 0.0 {
+                continue;
+            }
+            // Exponential distances are memoryless, so tracking restarts at
+            // each segment
+            let mut t = tmin;
+            loop {
+                t -= (1.0 - rng.random::<f32>()).ln() / majorant;
+                if t >= tmax {
+                    break;
+                }
+                let (sigma_a, sigma_s) = self.coefficients(ray.at(t));
+                let sigma_n = (Vec3::splat(majorant) - sigma_a - sigma_s).max(Vec3::ZERO);
+                let p_absorb = sigma_a.element_sum() / (3.0 * majorant);
+                let p_scatter = sigma_s.element_sum() / (3.0 * majorant);
+                let u = rng.random::<f32>();
+                if u < p_absorb {
+                    return MediumSample::Absorbed;
+                }
+                if u < p_absorb + p_scatter {
+                    weight *= 3.0 * sigma_s / sigma_s.element_sum();
+                    return MediumSample::Scattered {
+                        distance: t,
+                        weight,
+                    };
+                }
+                weight *= 3.0 * sigma_n / sigma_n.element_sum();
+            }
+        }
+        MediumSample::Transmitted { weight }
+    }
+
+    /// Fraction of light going through the medium between the `tmin` and
+    /// `tmax` of a ray. Homogeneous media follow the Beer-Lambert law, while
+    /// other media are estimated with ratio tracking.
+    pub fn transmittance(&self, ray: &Ray, tmax: f32, rng: &mut impl Rng) -> Vec3 {
+        if let Self::Homogeneous {
+            sigma_a, sigma_s, ..
+        } = self
+        {
+            let sigma_t = *sigma_a + *sigma_s;
+            let distance = tmax - ray.tmin();
+            // Avoid NaN for transparent channels of infinite segments
+            return Vec3::select(
+                sigma_t.cmpgt(Vec3::ZERO),
+                (-sigma_t * distance).exp(),
+                Vec3::ONE,
+            );
+        }
+
+        let mut transmittance = Vec3::ONE;
+        for (tmin, tmax, majorant) in self.majorants(ray, tmax) {
+            if majorant <= 0.0 {
+                continue;
+            }
+            let mut t = tmin;
+            loop {
+                t -= (1.0 - rng.random::<f32>()).ln() / majorant;
+                if t >= tmax {
+                    break;
+                }
+                let (sigma_a, sigma_s) = self.coefficients(ray.at(t));
+                transmittance *= (1.0 - (sigma_a + sigma_s) / majorant).max(Vec3::ZERO);
+                // Russian roulette, since dense regions would otherwise be
+                // tracked collision after collision for a tiny contribution
+                if transmittance.max_element() < 0.1 {
+                    if rng.random::<f32>() < 0.5 {
+                        return Vec3::ZERO;
+                    }
+                    transmittance *= 2.0;
+                }
+            }
+        }
+        transmittance
+    }
+}
+
+/// Segments of a ray with a constant majorant, as `(tmin, tmax, majorant)`.
+enum Majorants<'a> {
+    Constant(Option<(f32, f32, f32)>),
+    Grid {
+        traversal: MajorantTraversal<'a>,
+        /// Maximum extinction of a unit density.
+        scale: f32,
+    },
+}
+
+impl Iterator for Majorants<'_> {
+    type Item = (f32, f32, f32);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::Constant(segment) => segment.take(),
+            Self::Grid { traversal, scale } => traversal
+                .next()
+                .map(|(tmin, tmax, density)| (tmin, tmax, density * *scale)),
+        }
+    }
+}
+
+/// Henyey-Greenstein phase function, describing the directions in which a
+/// medium scatters light. Like BSDFs, `wo` points away from the scattering
+/// point, towards where light goes.
+#[derive(Debug, Clone, Copy)]
+pub struct HenyeyGreenstein {
+    /// Average cosine between incoming and scattered light directions.
+    pub g: f32,
+}
+
+impl HenyeyGreenstein {
+    /// Density of scattering towards `wo` light arriving along `-wi`. Phase
+    /// functions are normalized, so it's also the sampling density.
+    pub fn eval(&self, wo: Vec3, wi: Vec3) -> f32 {
+        let g = self.g;
+        let denom = 1.0 + g * g + 2.0 * g * wo.dot(wi);
+        (1.0 - g * g) / (4.0 * std::f32::consts::PI * denom * denom.sqrt())
+    }
+
+    /// Sample an incoming direction `wi` for the outgoing direction `wo`,
+    /// along with its density. The random sample `u` must be within
+    /// [0, 1)^2.
+    pub fn sample(&self, wo: Vec3, u: Vec2) -> (Vec3, f32) {
+        let g = self.g;
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u.x
+        } else {
+            let square = (1.0 - g * g) / (1.0 + g - 2.0 * g * u.x);
+            -(1.0 + g * g - square * square) / (2.0 * g)
+        }
+        .clamp(-1.0, 1.0);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let (sin_phi, cos_phi) = (2.0 * std::f32::consts::PI * u.y).sin_cos();
+        let local = Vec3::new(sin_theta * cos_phi, sin_theta * sin_phi, cos_theta);
+        let wi = Frame::from_normal(wo).to_world(local).normalize();
+        (wi, self.eval(wo, wi))
+    }
+}
@@ -0,0 +1,475 @@
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use bincode::de::Decoder;
+use bincode::enc::Encoder;
+use bincode::error::{DecodeError, EncodeError};
+use bincode::serde::Compat;
+use bincode::{Decode, Encode};
+use glam::{Vec2, Vec3};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::raytracer::{Aabb, Hit, Intersectable, Material, Ray, SurfaceSample};
+
+/// Maximum number of triangles in a mesh BVH leaf.
+const MAX_LEAF_TRIANGLES: usize = 4;
+
+/// Node of a flattened mesh BVH, where the first child of a branch is stored
+/// right after it.
+#[derive(Debug, Clone)]
+struct MeshBvhNode {
+    aabb: Aabb,
+    /// First triangle of a leaf, or index of the second child of a branch.
+    offset: u32,
+    /// Number of triangles of a leaf, zero for branches.
+    count: u32,
+}
+
+/// Indexed triangle mesh with optional per vertex normals and UVs. Each mesh
+/// holds its own BVH over its triangles, so the scene BVH only needs one
+/// entry per mesh. Triangles are counter clockwise when seen from outside.
+#[derive(Debug, Clone)]
+pub struct Mesh {
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<Vec2>,
+    indices: Vec<[u32; 3]>,
+    bvh: Vec<MeshBvhNode>,
+    /// Cumulative triangle areas, used to sample points on the surface.
+    area_cdf: Vec<f32>,
+}
+
+impl Mesh {
+    pub fn new(positions: Vec<Vec3>, mut indices: Vec<[u32; 3]>) -> Self {
+        assert!(!indices.is_empty(), "Cannot create a mesh with 0 triangles");
+        assert!(
+            indices
+                .iter()
+                .flatten()
+                .all(|&idx| (idx as usize) < positions.len()),
+            "Mesh indices must be within bounds"
+        );
+
+        let mut bvh = Vec::with_capacity(2 * indices.len() / MAX_LEAF_TRIANGLES + 1);
+        build_bvh(&mut bvh, &positions, &mut indices, 0);
+        let area_cdf = indices
+            .iter()
+            .scan(0.0, |area, triangle| {
+                let [p0, p1, p2] = triangle.map(|idx| positions[idx as usize]);
+                *area += 0.5 * (p1 - p0).cross(p2 - p0).length();
+                Some(*area)
+            })
+            .collect();
+
+        Self {
+            positions,
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            indices,
+            bvh,
+            area_cdf,
+        }
+    }
+
+    /// Mesh with optional normals and uvs, which are left empty when
+    /// missing. Returns `None` if the data is inconsistent.
+    pub(crate) fn from_parts(
+        positions: Vec<Vec3>,
+        normals: Vec<Vec3>,
+        uvs: Vec<Vec2>,
+        indices: Vec<[u32; 3]>,
+    ) -> Option<Self> {
+        let is_valid = !indices.is_empty()
+            && indices
+                .iter()
+                .flatten()
+                .all(|&idx| (idx as usize) < positions.len())
+            && (normals.is_empty() || normals.len() == positions.len())
+            && (uvs.is_empty() || uvs.len() == positions.len());
+        if !is_valid {
+            return None;
+        }
+
+        let mut mesh = Self::new(positions, indices);
+        mesh.normals = normals;
+        mesh.uvs = uvs;
+        Some(mesh)
+    }
+
+    /// Set per vertex shading normals, which must match the positions.
+    pub fn with_normals(mut self, normals: Vec<Vec3>) -> Self {
+        assert_eq!(
+            normals.len(),
+            self.positions.len(),
+            "Mesh must have one normal per vertex"
+        );
+        self.normals = normals;
+        self
+    }
+
+    /// Set per vertex UVs, which must match the positions.
+    pub fn with_uvs(mut self, uvs: Vec<Vec2>) -> Self {
+        assert_eq!(
+            uvs.len(),
+            self.positions.len(),
+            "Mesh must have one uv per vertex"
+        );
+        self.uvs = uvs;
+        self
+    }
+
+    pub fn positions(&self) -> &[Vec3] {
+        &self.positions
+    }
+
+    /// Triangle vertex indices. Triangles are reordered by BVH construction.
+    pub fn indices(&self) -> &[[u32; 3]] {
+        &self.indices
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len()
+    }
+
+    pub fn area(&self) -> f32 {
+        *self
+            .area_cdf
+            .last()
+            .expect("Mesh has at least one triangle")
+    }
+
+    pub fn aabb(&self) -> Aabb {
+        self.bvh[0].aabb.clone()
+    }
+
+    /// Closest intersection between the ray and the mesh triangles.
+    pub fn hit(&self, ray: &Ray, material: &Arc<Material>) -> Option<Hit> {
+        let triangle_ray = TriangleRay::new(ray);
+        let mut closest: Option<(f32, usize, Vec3)> = None;
+        let mut tmax = ray.tmax();
+
+        let mut stack = [0u32; 64];
+        let mut stack_len = 1;
+        while stack_len > 0 {
+            stack_len -= 1;
+            let node_idx = stack[stack_len] as usize;
+            let node = &self.bvh[node_idx];
+            if !node.aabb.intersect(&ray.with_tmax(tmax)) {
+                continue;
+            }
+
+            if node.count == 0 {
+                stack[stack_len] = node.offset;
+                stack[stack_len + 1] = node_idx as u32 + 1;
+                stack_len += 2;
+                continue;
+            }
+            let first = node.offset as usize;
+            for triangle in first..first + node.count as usize {
+                let [p0, p1, p2] = self.indices[triangle].map(|idx| self.positions[idx as usize]);
+                if let Some((distance, barycentric)) =
+                    triangle_ray.intersect(p0, p1, p2, ray.tmin(), tmax)
+                {
+                    tmax = distance;
+                    closest = Some((distance, triangle, barycentric));
+                }
+            }
+        }
+
+        let (distance, triangle, barycentric) = closest?;
+        let surface = self.surface(triangle, barycentric);
+        let is_front_face = surface.normal.dot(ray.direction()) < 0.0;
+        let side = if is_front_face { 1.0 } else { -1.0 };
+        Some(Hit {
+            distance,
+            position: surface.position,
+            normal: side * surface.normal,
+            shading_normal: side * surface.shading_normal,
+            uv: surface.uv,
+            dpdu: surface.dpdu,
+            dpdv: surface.dpdv,
+            material: material.clone(),
+            medium: None,
+            is_front_face,
+            object_area: self.area(),
+        })
+    }
+
+    /// Sample a point uniformly distributed over the mesh surface. The random
+    /// sample `u` must be within [0, 1)^2.
+    pub fn sample_surface(&self, u: Vec2) -> SurfaceSample {
+        // Choose a triangle proportionally to its area, reusing the first
+        // sample dimension to pick the point within the triangle.
+        let area = self.area();
+        let target = u.x * area;
+        let triangle = self
+            .area_cdf
+            .partition_point(|&cdf| cdf <= target)
+            .min(self.indices.len() - 1);
+        let start = if triangle > 0 {
+            self.area_cdf[triangle - 1]
+        } else {
+            0.0
+        };
+        let triangle_area = self.area_cdf[triangle] - start;
+        let u0 = ((target - start) / triangle_area).clamp(0.0, 1.0);
+
+        let su0 = u0.sqrt();
+        let barycentric = Vec3::new(1.0 - su0, u.y * su0, su0 * (1.0 - u.y));
+        let surface = self.surface(triangle, barycentric);
+        SurfaceSample {
+            position: surface.position,
+            normal: surface.normal,
+            uv: surface.uv,
+            pdf: 1.0 / area,
+        }
+    }
+
+    /// Surface attributes at a point of a triangle given by its barycentric
+    /// coordinates, with normals facing outwards.
+    fn surface(&self, triangle: usize, barycentric: Vec3) -> TriangleSurface {
+        let [i0, i1, i2] = self.indices[triangle].map(|idx| idx as usize);
+        let [p0, p1, p2] = [i0, i1, i2].map(|idx| self.positions[idx]);
+        let [uv0, uv1, uv2] = if self.uvs.is_empty() {
+            [Vec2::ZERO, Vec2::new(1.0, 0.0), Vec2::ONE]
+        } else {
+            [i0, i1, i2].map(|idx| self.uvs[idx])
+        };
+        let interpolate_vec3 =
+            |a: Vec3, b: Vec3, c: Vec3| barycentric.x * a + barycentric.y * b + barycentric.z * c;
+
+        let mut normal = (p1 - p0).cross(p2 - p0).normalize();
+        let shading_normal = if self.normals.is_empty() {
+            normal
+        } else {
+            let [n0, n1, n2] = [i0, i1, i2].map(|idx| self.normals[idx]);
+            let shading_normal = interpolate_vec3(n0, n1, n2)
+                .try_normalize()
+                .unwrap_or(normal);
+            // Trust vertex normals over the winding order
+            if shading_normal.dot(normal) < 0.0 {
+                normal = -normal;
+            }
+            shading_normal
+        };
+
+        let duv02 = uv0 - uv2;
+        let duv12 = uv1 - uv2;
+        let dp02 = p0 - p2;
+        let dp12 = p1 - p2;
+        let determinant = duv02.x * duv12.y - duv02.y * duv12.x;
+        let (dpdu, dpdv) = if determinant.abs() > 1e-9 {
+            let inv_determinant = 1.0 / determinant;
+            (
+                (duv12.y * dp02 - duv02.y * dp12) * inv_determinant,
+                (duv02.x * dp12 - duv12.x * dp02) * inv_determinant,
+            )
+        } else {
+            normal.any_orthonormal_pair()
+        };
+
+        TriangleSurface {
+            position: interpolate_vec3(p0, p1, p2),
+            normal,
+            shading_normal,
+            uv: barycentric.x * uv0 + barycentric.y * uv1 + barycentric.z * uv2,
+            dpdu,
+            dpdv,
+        }
+    }
+}
+
+struct TriangleSurface {
+    position: Vec3,
+    normal: Vec3,
+    shading_normal: Vec3,
+    uv: Vec2,
+    dpdu: Vec3,
+    dpdv: Vec3,
+}
+
+/// Recursively build the BVH nodes over `indices`, whose first triangle has
+/// index `offset` in the mesh, splitting at the median centroid along the
+/// largest axis.
+fn build_bvh(
+    nodes: &mut Vec<MeshBvhNode>,
+    positions: &[Vec3],
+    indices: &mut [[u32; 3]],
+    offset: usize,
+) {
+    let bounds = |triangle: &[u32; 3]| {
+        let [p0, p1, p2] = triangle.map(|idx| positions[idx as usize]);
+        (p0.min(p1).min(p2), p0.max(p1).max(p2))
+    };
+    let centroid = |triangle: &[u32; 3]| {
+        let (min, max) = bounds(triangle);
+        (min + max) / 2.0
+    };
+
+    let (mut min, mut max) = (Vec3::INFINITY, Vec3::NEG_INFINITY);
+    let (mut centroid_min, mut centroid_max) = (Vec3::INFINITY, Vec3::NEG_INFINITY);
+    for triangle in indices.iter() {
+        let (triangle_min, triangle_max) = bounds(triangle);
+        min = min.min(triangle_min);
+        max = max.max(triangle_max);
+        centroid_min = centroid_min.min(centroid(triangle));
+        centroid_max = centroid_max.max(centroid(triangle));
+    }
+
+    let node_idx = nodes.len();
+    nodes.push(MeshBvhNode {
+        aabb: Aabb::from_positions(min, max),
+        offset: offset as u32,
+        count: indices.len() as u32,
+    });
+    if indices.len() <= MAX_LEAF_TRIANGLES {
+        return;
+    }
+
+    let axis = (centroid_max - centroid_min).max_position();
+    let mid = indices.len() / 2;
+    indices.select_nth_unstable_by(mid, |a, b| centroid(a)[axis].total_cmp(&centroid(b)[axis]));
+    let (left, right) = indices.split_at_mut(mid);
+    build_bvh(nodes, positions, left, offset);
+    let right_idx = nodes.len();
+    build_bvh(nodes, positions, right, offset + mid);
+
+    nodes[node_idx].offset = right_idx as u32;
+    nodes[node_idx].count = 0;
+}
+
+/// Ray transformed for watertight ray-triangle intersection, following Woop
+/// et al. 2013, "Watertight Ray/Triangle Intersection". Rays passing through
+/// an edge or vertex shared by several triangles always hit one of them.
+struct TriangleRay {
+    origin: Vec3,
+    /// Permutation of the axes so that the ray direction is largest along z.
+    axes: [usize; 3],
+    shear: Vec3,
+}
+
+impl TriangleRay {
+    fn new(ray: &Ray) -> Self {
+        let direction = ray.direction();
+        let kz = direction.abs().max_position();
+        let mut kx = (kz + 1) % 3;
+        let mut ky = (kx + 1) % 3;
+        // Preserve the winding direction of triangles
+        if direction[kz] < 0.0 {
+            std::mem::swap(&mut kx, &mut ky);
+        }
+        Self {
+            origin: ray.origin(),
+            axes: [kx, ky, kz],
+            shear: Vec3::new(
+                direction[kx] / direction[kz],
+                direction[ky] / direction[kz],
+                1.0 / direction[kz],
+            ),
+        }
+    }
+
+    /// Distance and barycentric coordinates of the intersection with a
+    /// triangle, if within [tmin, tmax].
+    fn intersect(&self, p0: Vec3, p1: Vec3, p2: Vec3, tmin: f32, tmax: f32) -> Option<(f32, Vec3)> {
+        let [kx, ky, kz] = self.axes;
+        let (a, b, c) = (p0 - self.origin, p1 - self.origin, p2 - self.origin);
+        let ax = a[kx] - self.shear.x * a[kz];
+        let ay = a[ky] - self.shear.y * a[kz];
+        let bx = b[kx] - self.shear.x * b[kz];
+        let by = b[ky] - self.shear.y * b[kz];
+        let cx = c[kx] - self.shear.x * c[kz];
+        let cy = c[ky] - self.shear.y * c[kz];
+
+        let mut u = cx * by - cy * bx;
+        let mut v = ax * cy - ay * cx;
+        let mut w = bx * ay - by * ax;
+        // Recompute edge functions with double precision when the ray hits
+        // an edge, so that neighbouring triangles agree on the result
+        if u == 0.0 || v == 0.0 || w == 0.0 {
+            let (ax, ay, bx, by, cx, cy) = (
+                ax as f64, ay as f64, bx as f64, by as f64, cx as f64, cy as f64,
+            );
+            u = (cx * by - cy * bx) as f32;
+            v = (ax * cy - ay * cx) as f32;
+            w = (bx * ay - by * ax) as f32;
+        }
+        if (u < 0.0 || v < 0.0 || w < 0.0) && (u > 0.0 || v > 0.0 || w > 0.0) {
+            return None;
+        }
+        let determinant = u + v + w;
+        if determinant == 0.0 {
+            return None;
+        }
+
+        let az = self.shear.z * a[kz];
+        let bz = self.shear.z * b[kz];
+        let cz = self.shear.z * c[kz];
+        let inv_determinant = 1.0 / determinant;
+        let distance = (u * az + v * bz + w * cz) * inv_determinant;
+        if distance < tmin || distance > tmax {
+            return None;
+        }
+        Some((distance, Vec3::new(u, v, w) * inv_determinant))
+    }
+}
+
+// Only the mesh data is encoded, the BVH and areas are rebuilt when decoding.
+// Triangles are encoded in BVH order, so the rebuilt BVH is the same.
+impl Encode for Mesh {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        Compat(&self.positions).encode(encoder)?;
+        Compat(&self.normals).encode(encoder)?;
+        Compat(&self.uvs).encode(encoder)?;
+        self.indices.encode(encoder)
+    }
+}
+
+impl<Context> Decode<Context> for Mesh {
+    fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let positions = Compat::<Vec<Vec3>>::decode(decoder)?.0;
+        let normals = Compat::<Vec<Vec3>>::decode(decoder)?.0;
+        let uvs = Compat::<Vec<Vec2>>::decode(decoder)?.0;
+        let indices = Vec::<[u32; 3]>::decode(decoder)?;
+        Self::from_parts(positions, normals, uvs, indices)
+            .ok_or(DecodeError::Other("Invalid mesh data"))
+    }
+}
+
+bincode::impl_borrow_decode!(Mesh);
+
+/// Serde representation of meshes, without the BVH.
+#[derive(Serialize, Deserialize)]
+struct MeshData<'a> {
+    positions: Cow<'a, [Vec3]>,
+    #[serde(default, skip_serializing_if = "<[Vec3]>::is_empty")]
+    normals: Cow<'a, [Vec3]>,
+    #[serde(default, skip_serializing_if = "<[Vec2]>::is_empty")]
+    uvs: Cow<'a, [Vec2]>,
+    indices: Cow<'a, [[u32; 3]]>,
+}
+
+impl Serialize for Mesh {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        MeshData {
+            positions: Cow::Borrowed(&self.positions),
+            normals: Cow::Borrowed(&self.normals),
+            uvs: Cow::Borrowed(&self.uvs),
+            indices: Cow::Borrowed(&self.indices),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Mesh {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let data = MeshData::deserialize(deserializer)?;
+        Self::from_parts(
+            data.positions.into_owned(),
+            data.normals.into_owned(),
+            data.uvs.into_owned(),
+            data.indices.into_owned(),
+        )
+        .ok_or_else(|| serde::de::Error::custom("Invalid mesh data"))
+    }
+}
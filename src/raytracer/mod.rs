@@ -1,23 +1,47 @@
 pub mod aabb;
 pub mod accum_image;
+pub mod bsdf;
 pub mod bvh;
 pub mod camera;
+pub mod distribution;
+pub mod environment;
 pub mod image;
 pub mod material;
+pub mod medium;
+pub mod mesh;
+pub mod motion;
 pub mod ray;
 // #[cfg(not(target_arch = "wasm32"))]
 pub mod render_backend;
 pub mod renderer;
 pub mod scene;
+pub mod sdf;
+pub mod sky;
+pub mod texture;
+pub mod transform;
+pub mod validation;
+pub mod volume;
 
 pub use aabb::*;
 pub use accum_image::*;
+pub use bsdf::*;
 pub use bvh::*;
 pub use camera::*;
+pub use distribution::*;
+pub use environment::*;
 pub use image::*;
 pub use material::*;
+pub use medium::*;
+pub use mesh::*;
+pub use motion::*;
 pub use ray::*;
 // #[cfg(not(target_arch = "wasm32"))]
 pub use render_backend::*;
 pub use renderer::*;
 pub use scene::*;
+pub use sdf::*;
+pub use sky::*;
+pub use texture::*;
+pub use transform::*;
+pub use validation::*;
+pub use volume::*;
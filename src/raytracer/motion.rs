@@ -0,0 +1,140 @@
+use bincode::{Decode, Encode};
+use glam::{BVec3, Vec3};
+use serde::{Deserialize, Serialize};
+
+use crate::raytracer::{Aabb, Transform};
+
+/// Largest rotation between two steps of the bounds of a moving object,
+/// in radians.
+const MAX_BOUNDS_STEP_ANGLE: f32 = std::f32::consts::PI / 64.0;
+
+/// Movement of a model over time, given by its transform at some instants.
+/// Transforms are interpolated between them, with rotations following the
+/// shortest arc, and held before the first and after the last one.
+#[derive(Debug, Clone, PartialEq, Encode, Decode, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Motion {
+    /// Movement from `start` at time 0 to `end` at time 1.
+    Linear {
+        start: Box<Transform>,
+        end: Box<Transform>,
+    },
+    /// Movement through keyframes of strictly increasing times.
+    Keyframed { keyframes: Vec<Keyframe> },
+}
+
+#[derive(Debug, Clone, PartialEq, Encode, Decode, Serialize, Deserialize)]
+pub struct Keyframe {
+    pub time: f32,
+    pub transform: Transform,
+}
+
+impl Motion {
+    /// Pairs of consecutive transforms.
+    fn segments(&self) -> Vec<(&Transform, &Transform)> {
+        match self {
+            Self::Linear { start, end } => vec![(&**start, &**end)],
+            Self::Keyframed { keyframes } => match keyframes.as_slice() {
+                [keyframe] => vec![(&keyframe.transform, &keyframe.transform)],
+                keyframes => keyframes
+                    .windows(2)
+                    .map(|pair| (&pair[0].transform, &pair[1].transform))
+                    .collect(),
+            },
+        }
+    }
+
+    /// Transform at `time`.
+    pub fn at(&self, time: f32) -> Transform {
+        let (start, end, t) = match self {
+            Self::Linear { start, end } => (&**start, &**end, time.clamp(0.0, 1.0)),
+            Self::Keyframed { keyframes } => {
+                let next = keyframes.partition_point(|keyframe| keyframe.time <= time);
+                if next == 0 {
+                    return keyframes[0].transform.clone();
+                }
+                if next == keyframes.len() {
+                    return keyframes[next - 1].transform.clone();
+                }
+                let (prev, next) = (&keyframes[next - 1], &keyframes[next]);
+                let t = (time - prev.time) / (next.time - prev.time);
+                (&prev.transform, &next.transform, t)
+            }
+        };
+        // Keyframes are kept exactly, even with shear
+        if t == 0.0 {
+            start.clone()
+        } else if t == 1.0 {
+            end.clone()
+        } else {
+            start.interpolate(end, t)
+        }
+    }
+
+    /// Whether there is at least one keyframe, keyframe times are finite and
+    /// strictly increasing and every transform is invertible.
+    pub fn is_valid(&self) -> bool {
+        match self {
+            Self::Linear { start, end } => start.is_invertible() && end.is_invertible(),
+            Self::Keyframed { keyframes } => {
+                !keyframes.is_empty()
+                    && keyframes.iter().all(|keyframe| {
+                        keyframe.time.is_finite() && keyframe.transform.is_invertible()
+                    })
+                    && keyframes.windows(2).all(|pair| pair[0].time < pair[1].time)
+            }
+        }
+    }
+
+    /// World space bounds swept by object space bounds over the whole
+    /// motion.
+    ///
+    /// Each segment between two transforms is bounded at steps small enough
+    /// for rotations to be nearly linear. Between two steps, points stray
+    /// from the segment joining their positions by at most their distance to
+    /// the rotation center times half the step angle, which pads the bounds.
+    pub fn aabb_to_world(&self, aabb: &Aabb) -> Aabb {
+        let mut bounds = Aabb::empty();
+        for (start, end) in self.segments() {
+            let (_, rotation0, _) = start.to_world().to_scale_rotation_translation();
+            let (_, rotation1, _) = end.to_world().to_scale_rotation_translation();
+            let angle = rotation0.angle_between(rotation1);
+            let steps = (angle / MAX_BOUNDS_STEP_ANGLE).ceil().max(1.0) as u32;
+
+            let mut segment_bounds = Aabb::empty();
+            let mut radius = 0.0f32;
+            for step in 0..=steps {
+                let transform = match step {
+                    0 => start.clone(),
+                    step if step == steps => end.clone(),
+                    step => start.interpolate(end, step as f32 / steps as f32),
+                };
+                segment_bounds = Aabb::surround(&segment_bounds, &transform.aabb_to_world(aabb));
+                if angle > 0.0 {
+                    let center = transform.point_to_world(Vec3::ZERO);
+                    radius = radius.max(max_corner_distance(&transform, aabb, center));
+                }
+            }
+            let padding = Vec3::splat(radius * angle / steps as f32 / 2.0);
+            let segment_bounds = Aabb::from_positions(
+                segment_bounds.min_position - padding,
+                segment_bounds.max_position + padding,
+            );
+            bounds = Aabb::surround(&bounds, &segment_bounds);
+        }
+        bounds
+    }
+}
+
+/// Largest distance between `center` and the world space corners of object
+/// space bounds.
+fn max_corner_distance(transform: &Transform, aabb: &Aabb, center: Vec3) -> f32 {
+    (0..8)
+        .map(|corner| {
+            let select = |axis: usize| corner & (1 << axis) != 0;
+            let mask = BVec3::new(select(0), select(1), select(2));
+            let corner = Vec3::select(mask, aabb.max_position, aabb.min_position);
+            transform.point_to_world(corner).distance(center)
+        })
+        .fold(0.0, f32::max)
+}
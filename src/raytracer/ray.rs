@@ -2,12 +2,16 @@ use core::f32;
 
 use glam::Vec3;
 
+use crate::raytracer::{SceneError, SceneIssue};
+
 #[derive(Debug, Clone)]
 pub struct Ray {
     origin: Vec3,
     direction: Vec3,
     tmin: f32,
     tmax: f32,
+    /// Instant at which the ray travels, where moving objects are found.
+    time: f32,
 }
 
 impl Ray {
@@ -24,7 +28,17 @@ impl Ray {
             direction,
             tmin: Self::MIN_RAY_DISTANCE,
             tmax: Self::MAX_RAY_DISTANCE,
+            time: 0.0,
+        }
+    }
+
+    /// Same as `new`, but returns an error for non normalized directions
+    /// instead of panicking.
+    pub fn try_new(origin: Vec3, direction: Vec3) -> Result<Self, SceneError> {
+        if !direction.is_normalized() {
+            return Err(SceneIssue::NonNormalizedRayDirection.into());
         }
+        Ok(Self::new(origin, direction))
     }
 
     pub fn origin(&self) -> Vec3 {
@@ -43,6 +57,10 @@ impl Ray {
         self.tmax
     }
 
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
     /// Compute ray position at a certain t.
     pub fn at(&self, t: f32) -> Vec3 {
         self.origin + t * self.direction
@@ -63,4 +81,20 @@ impl Ray {
         ray.tmin = new_tmin.max(self.tmin);
         ray
     }
+
+    /// Creates a new ray with the given interval. Unlike `with_tmin` and
+    /// `with_tmax`, the interval may be wider than the current one.
+    pub fn with_interval(&self, tmin: f32, tmax: f32) -> Ray {
+        let mut ray = self.clone();
+        ray.tmin = tmin;
+        ray.tmax = tmax;
+        ray
+    }
+
+    /// Creates a new ray traveling at the given time.
+    pub fn with_time(&self, time: f32) -> Ray {
+        let mut ray = self.clone();
+        ray.time = time;
+        ray
+    }
 }
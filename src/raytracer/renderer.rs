@@ -1,8 +1,98 @@
-use crate::raytracer::{Hittable, Ray, Scene, Tile};
+use std::sync::Arc;
 
-use glam::Vec3;
+use crate::raytracer::{
+    Bsdf, Frame, HenyeyGreenstein, Hit, Hittable, Medium, MediumSample, Ray, Scene, Tile,
+};
+
+use glam::{Vec2, Vec3};
 use rand::{Rng, SeedableRng, rngs::SmallRng};
 
+/// Multiple importance sampling weight of a sample drawn with density `pdf`
+/// against another strategy with density `other_pdf`.
+fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let pdf_squared = pdf * pdf;
+    let other_pdf_squared = other_pdf * other_pdf;
+    if pdf_squared + other_pdf_squared == 0.0 {
+        return 0.0;
+    }
+    pdf_squared / (pdf_squared + other_pdf_squared)
+}
+
+/// Point where a path scatters light, either on a surface or inside a
+/// medium.
+enum Vertex<'a> {
+    Surface {
+        hit: &'a Hit,
+        frame: &'a Frame,
+        bsdf: &'a dyn Bsdf,
+        /// Outgoing direction in the local frame.
+        wo: Vec3,
+    },
+    Medium {
+        position: Vec3,
+        phase: HenyeyGreenstein,
+        wo: Vec3,
+    },
+}
+
+impl Vertex<'_> {
+    fn position(&self) -> Vec3 {
+        match self {
+            Self::Surface { hit, .. } => hit.position,
+            Self::Medium { position, .. } => *position,
+        }
+    }
+
+    /// Scattering function times the cosine factor of surfaces for light
+    /// arriving from the world space `direction`, along with the density of
+    /// sampling it.
+    fn eval(&self, direction: Vec3) -> (Vec3, f32) {
+        match self {
+            Self::Surface {
+                frame, bsdf, wo, ..
+            } => {
+                let wi = frame.to_local(direction);
+                (bsdf.eval(*wo, wi) * wi.z.abs(), bsdf.pdf(*wo, wi))
+            }
+            Self::Medium { phase, wo, .. } => {
+                let pdf = phase.eval(*wo, direction);
+                (Vec3::splat(pdf), pdf)
+            }
+        }
+    }
+}
+
+/// Medium a ray leaving a hit along `direction` travels through, given the
+/// `medium` it arrived from. Rays enter the medium of an object from the
+/// outside and leave it to the scene medium, while surfaces of objects
+/// without a medium keep the current one.
+fn medium_after(
+    scene: &Scene,
+    hit: &Hit,
+    direction: Vec3,
+    medium: Option<Arc<Medium>>,
+) -> Option<Arc<Medium>> {
+    let Some(interior) = &hit.medium else {
+        return medium;
+    };
+    let outward_normal = if hit.is_front_face {
+        hit.normal
+    } else {
+        -hit.normal
+    };
+    if direction.dot(outward_normal) < 0.0 {
+        Some(interior.clone())
+    } else {
+        scene.medium().cloned()
+    }
+}
+
+/// Continuation of `ray` past a medium boundary at `hit`. The offset grows
+/// with the distance, since the precision of far hits is lower.
+fn past_interface(ray: &Ray, hit: &Hit) -> Ray {
+    ray.with_tmin(hit.distance + Ray::MIN_RAY_DISTANCE.max(hit.distance * 1e-5))
+}
+
 pub struct Renderer {
     max_bounces: usize,
 }
@@ -12,22 +102,224 @@ impl Renderer {
         Self { max_bounces: 50 }
     }
 
-    pub fn trace(&self, scene: &Scene, ray: &Ray, depth: usize) -> Vec3 {
-        // Depth is the maximum number of recursive ray bounces possible
-        if depth == 0 {
+    pub fn trace(&self, scene: &Scene, ray: &Ray, depth: usize, rng: &mut impl Rng) -> Vec3 {
+        let mut radiance = Vec3::ZERO;
+        let mut throughput = Vec3::ONE;
+        let mut ray = ray.clone();
+        // Density of the previous scattered direction. Camera rays and
+        // specular bounces have no density and can't be reached by direct
+        // light sampling, so emission found by them is fully accounted.
+        let mut scattered_pdf: Option<f32> = None;
+        // Medium the ray travels through, the camera being in the scene one
+        let mut medium = scene.medium().cloned();
+
+        // Depth is the maximum number of ray bounces possible. Crossing a
+        // medium boundary doesn't count as a bounce.
+        let mut bounces = 0;
+        while bounces < depth {
+            let hit = scene.hit(&ray);
+
+            if let Some(current_medium) = &medium {
+                let tmax = hit.as_ref().map_or(ray.tmax(), |hit| hit.distance);
+                match current_medium.sample(&ray, tmax, rng) {
+                    MediumSample::Transmitted { weight } => throughput *= weight,
+                    MediumSample::Absorbed => break,
+                    MediumSample::Scattered { distance, weight } => {
+                        throughput *= weight;
+                        let position = ray.at(distance);
+                        let phase = current_medium.phase();
+                        let wo = -ray.direction();
+                        let vertex = Vertex::Medium {
+                            position,
+                            phase,
+                            wo,
+                        };
+                        radiance += throughput
+                            * self.sample_direct_light(
+                                scene,
+                                &vertex,
+                                ray.time(),
+                                medium.as_ref(),
+                                rng,
+                            );
+
+                        // Phase functions are sampled exactly, so the
+                        // throughput is unchanged
+                        let (wi, pdf) = phase.sample(wo, Vec2::new(rng.random(), rng.random()));
+                        scattered_pdf = Some(pdf);
+                        ray = Ray::new(position, wi).with_time(ray.time());
+                        bounces += 1;
+                        continue;
+                    }
+                }
+            }
+
+            let Some(mut hit) = hit else {
+                let weight = match scattered_pdf {
+                    Some(pdf) => power_heuristic(pdf, scene.environment_pdf(ray.direction())),
+                    None => 1.0,
+                };
+                radiance += throughput * scene.escaped_radiance(ray.direction()) * weight;
+                break;
+            };
+
+            // Medium boundaries are crossed without changing the ray, so the
+            // density of emission found behind them still refers to its
+            // origin
+            if hit.material.is_interface() {
+                medium = medium_after(scene, &hit, ray.direction(), medium);
+                ray = past_interface(&ray, &hit);
+                continue;
+            }
+
+            let emission = hit.material.emission(hit.uv, hit.position);
+            if emission != Vec3::ZERO {
+                let weight = match scattered_pdf {
+                    Some(pdf) => power_heuristic(pdf, scene.light_pdf(&ray, &hit)),
+                    None => 1.0,
+                };
+                radiance += throughput * emission * weight;
+            }
+            hit.shading_normal = hit.material.shading_normal(&hit, -ray.direction());
+            let Some(bsdf) = hit.material.bsdf(&hit) else {
+                break;
+            };
+            let frame = Frame::from_normal_tangent(hit.shading_normal, hit.dpdu);
+            let wo = frame.to_local(-ray.direction());
+
+            if !bsdf.is_specular() {
+                let vertex = Vertex::Surface {
+                    hit: &hit,
+                    frame: &frame,
+                    bsdf: &*bsdf,
+                    wo,
+                };
+                radiance += throughput
+                    * self.sample_direct_light(scene, &vertex, ray.time(), medium.as_ref(), rng);
+            }
+
+            let u = Vec2::new(rng.random(), rng.random());
+            let Some(sample) = bsdf.sample(wo, rng.random(), u) else {
+                break;
+            };
+            throughput *= sample.f * sample.wi.z.abs() / sample.pdf;
+            scattered_pdf = (!sample.is_specular).then_some(sample.pdf);
+            let direction = frame.to_world(sample.wi).normalize();
+            medium = medium_after(scene, &hit, direction, medium);
+            ray = Ray::new(hit.position, direction).with_time(ray.time());
+            bounces += 1;
+        }
+
+        radiance
+    }
+
+    /// Estimate the radiance arriving at a scattering vertex directly from a
+    /// randomly chosen emissive object or the environment of the scene,
+    /// weighted against the sampling of the vertex scattering function.
+    /// `time` is the time of the path and `medium` the medium the vertex was
+    /// reached through.
+    fn sample_direct_light(
+        &self,
+        scene: &Scene,
+        vertex: &Vertex,
+        time: f32,
+        medium: Option<&Arc<Medium>>,
+        rng: &mut impl Rng,
+    ) -> Vec3 {
+        let num_lights = scene.num_lights();
+        if num_lights == 0 {
             return Vec3::ZERO;
         }
+        let light_idx = rng.random_range(0..num_lights);
+        let u = Vec2::new(rng.random(), rng.random());
 
-        let Some(hit) = scene.hit(&ray) else {
-            return scene.background();
+        // Direction towards the light, distance the shadow ray travels,
+        // emitted radiance and solid angle density of the light sample
+        let position = vertex.position();
+        let (direction, distance, emission, light_pdf) = match scene.light(light_idx) {
+            Some(light) => {
+                let sample = light.sample_surface(u, time);
+                let to_light = sample.position - position;
+                let distance_squared = to_light.length_squared();
+                // Emissive surfaces may sample the point being shaded
+                if distance_squared <= Ray::MIN_RAY_DISTANCE * Ray::MIN_RAY_DISTANCE {
+                    return Vec3::ZERO;
+                }
+                let distance = distance_squared.sqrt();
+                let direction = to_light / distance;
+                let cos_light = sample.normal.dot(direction).abs();
+                if cos_light < f32::EPSILON {
+                    return Vec3::ZERO;
+                }
+                // Convert area density to solid angle density. The shadow ray
+                // stops just before reaching the light surface.
+                (
+                    direction,
+                    distance - Ray::MIN_RAY_DISTANCE,
+                    light.material.emission(sample.uv, sample.position),
+                    sample.pdf * distance_squared / cos_light,
+                )
+            }
+            None => {
+                let environment = scene
+                    .environment()
+                    .expect("Lights past the emissive objects are the environment");
+                let (direction, emission, pdf) = environment.sample(u);
+                if pdf == 0.0 {
+                    return Vec3::ZERO;
+                }
+                (direction, Ray::MAX_RAY_DISTANCE, emission, pdf)
+            }
         };
 
-        let Some(scattered) = hit.material.scatter(ray, &hit) else {
-            return hit.material.emission();
+        let (f, scattering_pdf) = vertex.eval(direction);
+        if f == Vec3::ZERO {
+            return Vec3::ZERO;
+        }
+
+        let shadow_ray = Ray::new(position, direction)
+            .with_tmax(distance)
+            .with_time(time);
+        let medium = match vertex {
+            Vertex::Surface { hit, .. } => medium_after(scene, hit, direction, medium.cloned()),
+            Vertex::Medium { .. } => medium.cloned(),
         };
+        let transmittance = self.transmittance(scene, shadow_ray, medium, rng);
+        if transmittance == Vec3::ZERO {
+            return Vec3::ZERO;
+        }
 
-        let scattering = scattered.attenuation * self.trace(scene, &scattered.ray, depth - 1);
-        scattering + hit.material.emission()
+        let light_pdf = light_pdf / num_lights as f32;
+        let weight = power_heuristic(light_pdf, scattering_pdf);
+        f * transmittance * emission * weight / light_pdf
+    }
+
+    /// Fraction of light going along a shadow ray through the media of the
+    /// scene. Medium boundaries let light through, while any other surface
+    /// blocks it.
+    fn transmittance(
+        &self,
+        scene: &Scene,
+        mut ray: Ray,
+        mut medium: Option<Arc<Medium>>,
+        rng: &mut impl Rng,
+    ) -> Vec3 {
+        let mut transmittance = Vec3::ONE;
+        loop {
+            let hit = scene.hit(&ray);
+            if let Some(medium) = &medium {
+                let tmax = hit.as_ref().map_or(ray.tmax(), |hit| hit.distance);
+                transmittance *= medium.transmittance(&ray, tmax, rng);
+            }
+            let Some(hit) = hit else {
+                return transmittance;
+            };
+            if !hit.material.is_interface() {
+                return Vec3::ZERO;
+            }
+            medium = medium_after(scene, &hit, ray.direction(), medium);
+            ray = past_interface(&ray, &hit);
+        }
     }
 
     pub fn render_tile(
@@ -53,8 +345,14 @@ impl Renderer {
                         + rng.random_range(0.0..(2.0 / image_size.1 as f32));
 
                     // Trace pixel color
-                    let ray = scene.camera().create_viewport_ray(sample_u, sample_v);
-                    let sample_color = self.trace(&scene, &ray, self.max_bounces);
+                    let lens = Vec2::new(rng.random(), rng.random());
+                    let camera = scene.camera();
+                    let Some(ray) =
+                        camera.create_viewport_ray(sample_u, sample_v, lens, rng.random())
+                    else {
+                        continue;
+                    };
+                    let sample_color = self.trace(&scene, &ray, self.max_bounces, &mut rng);
 
                     pixel_color += sample_color * sample_weight;
                 }
@@ -1,20 +1,57 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::sync::Arc;
 
+use bincode::de::Decoder;
+use bincode::enc::Encoder;
+use bincode::error::{DecodeError, EncodeError};
+use bincode::serde::Compat;
 use bincode::{Decode, Encode};
-use glam::Vec3;
+use glam::{Vec2, Vec3};
+use serde::{Deserialize, Serialize};
 use tracing::{debug, warn};
 
-use crate::raytracer::{Aabb, Bounded, BvhNode, Camera, Intersectable, Material, Ray};
+use crate::raytracer::{
+    Aabb, Bounded, BvhNode, Camera, Environment, Frame, ImageTexture, Intersectable, Material,
+    Medium, Mesh, Motion, Ray, SceneError, SceneIssue, Sdf, Transform, objects_issues,
+};
+use crate::utils;
 
 pub struct Hit {
     pub distance: f32,
     pub position: Vec3,
+    /// Geometric normal, facing the side the ray arrived from.
     pub normal: Vec3,
+    /// Normal used for shading, on the same side as `normal`. It differs from
+    /// the geometric normal for meshes with vertex normals, or when the
+    /// material perturbs it.
+    pub shading_normal: Vec3,
+    /// Surface coordinates of the hit, used for texture lookups.
+    pub uv: Vec2,
+    /// Partial derivatives of the position with respect to the surface
+    /// coordinates. `dpdu.cross(dpdv)` points along the outward normal,
+    /// unless the surface coordinates are mirrored.
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
     pub material: Arc<Material>,
+    /// Medium inside the hit object, if any.
+    pub medium: Option<Arc<Medium>>,
     pub is_front_face: bool,
+    /// Surface area of the hit object, needed to evaluate the density of
+    /// sampling the hit position through direct light sampling.
+    pub object_area: f32,
 }
 
-impl Hit {}
+/// Point sampled on the surface of a model, used for direct light sampling.
+pub struct SurfaceSample {
+    pub position: Vec3,
+    /// Outward surface normal at the sampled position.
+    pub normal: Vec3,
+    /// Surface coordinates of the sampled position.
+    pub uv: Vec2,
+    /// Probability density of the sample with respect to surface area.
+    pub pdf: f32,
+}
 
 pub trait Hittable {
     fn hit(&self, ray: &Ray) -> Option<Hit>;
@@ -24,7 +61,8 @@ pub trait Hittable {
 // Model
 ////////////////////////////////////////////////////////////////////////////////
 
-#[derive(Debug, Clone, Encode, Decode)]
+#[derive(Debug, Clone, Encode, Decode, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum Geometry {
     Sphere {
         #[bincode(with_serde)]
@@ -45,175 +83,1030 @@ pub enum Geometry {
         #[bincode(with_serde)]
         size: Vec3,
     },
+    /// Disk of `radius` centered at `position`, facing along `normal`.
+    Disk {
+        #[bincode(with_serde)]
+        position: Vec3,
+        #[bincode(with_serde)]
+        normal: Vec3,
+        radius: f32,
+    },
+    /// Capped cylinder from the base center at `position` to the top center
+    /// at `position + axis`.
+    Cylinder {
+        #[bincode(with_serde)]
+        position: Vec3,
+        #[bincode(with_serde)]
+        axis: Vec3,
+        radius: f32,
+    },
+    /// Capped cone from the base center at `position` to the apex at
+    /// `position + axis`.
+    Cone {
+        #[bincode(with_serde)]
+        position: Vec3,
+        #[bincode(with_serde)]
+        axis: Vec3,
+        radius: f32,
+    },
+    /// Infinite plane through `position`, facing along `normal`. Its surface
+    /// coordinates are distances along the plane, so textures repeat every
+    /// unit.
+    Plane {
+        #[bincode(with_serde)]
+        position: Vec3,
+        #[bincode(with_serde)]
+        normal: Vec3,
+    },
+    Mesh(Arc<Mesh>),
+    /// Combination of two solids, such as the intersection of two spheres
+    /// forming a lens. Both children must be closed surfaces. CSG surfaces
+    /// have no closed form area, so they can't be sampled as lights.
+    Csg {
+        operation: CsgOperation,
+        left: Arc<Geometry>,
+        right: Arc<Geometry>,
+    },
+    /// Signed distance field intersected by sphere tracing within `bounds`.
+    /// Like CSG, it can't be sampled as a light.
+    Sdf {
+        sdf: Arc<Sdf>,
+        bounds: Aabb,
+    },
+    /// Geometry shared by many models, usually placed with different
+    /// transforms. Shared geometry is stored once when a scene is encoded.
+    #[serde(skip)]
+    Instance(Arc<Geometry>),
+}
+
+/// Boolean operation combining the solids of a CSG geometry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CsgOperation {
+    /// Space inside either solid.
+    Union,
+    /// Space inside both solids.
+    Intersection,
+    /// Space inside the left solid but outside the right one.
+    Difference,
+}
+
+impl CsgOperation {
+    /// Whether a point inside or outside each solid is inside the result.
+    pub fn contains(self, in_left: bool, in_right: bool) -> bool {
+        match self {
+            Self::Union => in_left || in_right,
+            Self::Intersection => in_left && in_right,
+            Self::Difference => in_left && !in_right,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Encode, Decode)]
 pub struct Model {
     pub geometry: Geometry,
     pub material: Arc<Material>,
+    /// Object to world transform of the geometry, none for geometry given in
+    /// world space.
+    pub transform: Option<Transform>,
+    /// Movement of the model, applied after `transform`. Rays find moving
+    /// models where they are at the ray time.
+    pub motion: Option<Motion>,
+    /// Medium filling the inside of the geometry, which must be closed.
+    /// Media can't be nested, the outside of every model is the scene
+    /// medium.
+    pub medium: Option<Arc<Medium>>,
 }
 
 impl Model {
     pub fn new(geometry: Geometry, material: Arc<Material>) -> Self {
-        Self { geometry, material }
+        Self {
+            geometry,
+            material,
+            transform: None,
+            motion: None,
+            medium: None,
+        }
     }
 
-    fn hit_sphere(&self, ray: &Ray, position: Vec3, radius: f32) -> Option<Hit> {
-        let oc = position - ray.origin();
-        let a = ray.direction().dot(ray.direction());
-        let half_b = ray.direction().dot(oc);
-        let c = oc.length_squared() - radius * radius;
-        let discriminant = half_b * half_b - a * c;
+    pub fn with_transform(mut self, transform: Transform) -> Self {
+        self.transform = Some(transform);
+        self
+    }
 
-        // Check if first solution is valid
-        let mut distance = (half_b - discriminant.sqrt()) / a;
-        if distance < ray.tmin() || distance > ray.tmax() {
-            // Check if second solution is valid
-            // Note: its possible this second solution is the same as solution 1
-            // in case the discriminant was zero.
-            distance = (half_b + discriminant.sqrt()) / a;
-            if distance < ray.tmin() || distance > ray.tmax() {
-                // Both possible solutions are behind camera
-                return None;
-            }
+    pub fn with_motion(mut self, motion: Motion) -> Self {
+        self.motion = Some(motion);
+        self
+    }
+
+    pub fn with_medium(mut self, medium: Arc<Medium>) -> Self {
+        self.medium = Some(medium);
+        self
+    }
+
+    /// Object to world transform at `time`, none for static geometry given
+    /// in world space.
+    pub fn transform_at(&self, time: f32) -> Option<Cow<'_, Transform>> {
+        match (&self.transform, &self.motion) {
+            (transform, None) => transform.as_ref().map(Cow::Borrowed),
+            (None, Some(motion)) => Some(Cow::Owned(motion.at(time))),
+            (Some(transform), Some(motion)) => Some(Cow::Owned(transform.then(&motion.at(time)))),
         }
+    }
 
-        if discriminant >= 0.0 {
-            let intersection = ray.at(distance);
-            let outward_normal = (intersection - position) / radius;
-            let is_front_face = outward_normal.dot(ray.direction()) <= 0.0;
-            let normal = if is_front_face {
-                outward_normal
-            } else {
-                -outward_normal
-            };
+    /// Whether the model has finite bounds, see `Geometry::is_bounded`.
+    pub fn is_bounded(&self) -> bool {
+        self.geometry.is_bounded()
+    }
+
+    /// Whether the model surface can be sampled, see
+    /// `Geometry::is_sampleable`.
+    pub fn is_sampleable(&self) -> bool {
+        self.geometry.is_sampleable()
+    }
 
-            Some(Hit {
-                distance,
-                position: intersection,
+    /// Total surface area of the model in world space, at time zero for
+    /// moving models. Curved surfaces under non uniform scales are only
+    /// approximated, see `Geometry::transformed_area`. Light sampling doesn't
+    /// use it, since it relies on the area scale at each sampled point.
+    pub fn area(&self) -> f32 {
+        match self.transform_at(0.0) {
+            Some(transform) => self.geometry.transformed_area(&transform),
+            None => self.geometry.area(),
+        }
+    }
+
+    /// Sample a point uniformly distributed over the geometry surface, in
+    /// world space at `time`. The random sample `u` must be within [0, 1)^2.
+    pub fn sample_surface(&self, u: Vec2, time: f32) -> SurfaceSample {
+        let sample = self.geometry.sample_surface(u);
+        match self.transform_at(time) {
+            Some(transform) => transform.sample_to_world(sample),
+            None => sample,
+        }
+    }
+}
+
+impl Geometry {
+    /// Intersect the geometry in its own space, giving hits with `material`.
+    pub fn hit(&self, ray: &Ray, material: &Arc<Material>) -> Option<Hit> {
+        match *self {
+            Geometry::Sphere { position, radius } => hit_sphere(ray, material, position, radius),
+            Geometry::Quad { position, u, v } => hit_quad(ray, material, position, u, v),
+            Geometry::Cuboid { position, size } => hit_cuboid(ray, material, position, size),
+            Geometry::Disk {
+                position,
                 normal,
-                material: self.material.clone(),
-                is_front_face,
-            })
-        } else {
-            None
+                radius,
+            } => hit_disk(ray, material, position, normal, radius),
+            Geometry::Cylinder {
+                position,
+                axis,
+                radius,
+            } => hit_cylinder(ray, material, position, axis, radius),
+            Geometry::Cone {
+                position,
+                axis,
+                radius,
+            } => hit_cone(ray, material, position, axis, radius),
+            Geometry::Plane { position, normal } => hit_plane(ray, material, position, normal),
+            Geometry::Mesh(ref mesh) => mesh.hit(ray, material),
+            Geometry::Csg {
+                operation,
+                ref left,
+                ref right,
+            } => hit_csg(ray, material, operation, left, right),
+            Geometry::Sdf {
+                ref sdf,
+                ref bounds,
+            } => hit_sdf(ray, material, sdf, bounds),
+            Geometry::Instance(ref geometry) => geometry.hit(ray, material),
         }
     }
 
-    fn hit_quad(&self, ray: &Ray, position: Vec3, u: Vec3, v: Vec3) -> Option<Hit> {
-        // NOTE: These values can be cached in Quad
-        let n = u.cross(v);
-        let normal = n.normalize();
-        let d = normal.dot(position);
-        let w = n / n.dot(n);
+    /// Total surface area of the geometry.
+    pub fn area(&self) -> f32 {
+        match *self {
+            Geometry::Sphere { radius, .. } => 4.0 * std::f32::consts::PI * radius * radius,
+            Geometry::Quad { u, v, .. } => u.cross(v).length(),
+            Geometry::Cuboid { size, .. } => {
+                2.0 * (size.x * size.y + size.y * size.z + size.z * size.x)
+            }
+            Geometry::Disk { radius, .. } => std::f32::consts::PI * radius * radius,
+            Geometry::Cylinder { axis, radius, .. } => {
+                2.0 * std::f32::consts::PI * radius * (axis.length() + radius)
+            }
+            Geometry::Cone { axis, radius, .. } => {
+                let slant = (radius * radius + axis.length_squared()).sqrt();
+                std::f32::consts::PI * radius * (slant + radius)
+            }
+            Geometry::Plane { .. } | Geometry::Csg { .. } | Geometry::Sdf { .. } => f32::INFINITY,
+            Geometry::Mesh(ref mesh) => mesh.area(),
+            Geometry::Instance(ref geometry) => geometry.area(),
+        }
+    }
 
-        let denom = normal.dot(ray.direction());
-        // Check if ray is parallel to quad plane
-        if denom.abs() < f32::MIN_POSITIVE {
-            return None;
+    /// Approximate surface area of the geometry once transformed by
+    /// `transform`. Flat faces and triangles are transformed exactly, but the
+    /// area scale of curved surfaces varies over them under non uniform
+    /// scales, so it is averaged over a 64x64 grid of surface samples.
+    pub fn transformed_area(&self, transform: &Transform) -> f32 {
+        let parallelogram_area = |u: Vec3, v: Vec3| {
+            transform
+                .vector_to_world(u)
+                .cross(transform.vector_to_world(v))
+                .length()
+        };
+        match *self {
+            Geometry::Quad { u, v, .. } => parallelogram_area(u, v),
+            Geometry::Cuboid { position, size } => cuboid_faces(position, size)
+                .iter()
+                .map(|&(_, u, v)| parallelogram_area(u, v))
+                .sum(),
+            Geometry::Disk { normal, .. } => self.area() * transform.area_scale(normal.normalize()),
+            Geometry::Sphere { .. } | Geometry::Cylinder { .. } | Geometry::Cone { .. } => {
+                // Samples are uniformly distributed over the surface, so the
+                // area scale is averaged with equal weights
+                let n = 64;
+                let area_scale: f32 = (0..n * n)
+                    .map(|idx| {
+                        let u = Vec2::new((idx % n) as f32 + 0.5, (idx / n) as f32 + 0.5);
+                        transform.area_scale(self.sample_surface(u / n as f32).normal)
+                    })
+                    .sum();
+                self.area() * area_scale / (n * n) as f32
+            }
+            Geometry::Plane { .. } | Geometry::Csg { .. } | Geometry::Sdf { .. } => f32::INFINITY,
+            Geometry::Mesh(ref mesh) => mesh
+                .indices()
+                .iter()
+                .map(|triangle| {
+                    let [p0, p1, p2] = triangle.map(|idx| mesh.positions()[idx as usize]);
+                    0.5 * parallelogram_area(p1 - p0, p2 - p0)
+                })
+                .sum(),
+            Geometry::Instance(ref geometry) => geometry.transformed_area(transform),
+        }
+    }
+
+    /// Whether the geometry has finite bounds. Unbounded geometry, such as
+    /// infinite planes, is kept out of the scene BVH.
+    pub fn is_bounded(&self) -> bool {
+        match self {
+            Geometry::Plane { .. } => false,
+            Geometry::Csg {
+                operation,
+                left,
+                right,
+            } => match operation {
+                CsgOperation::Union => left.is_bounded() && right.is_bounded(),
+                CsgOperation::Intersection => left.is_bounded() || right.is_bounded(),
+                CsgOperation::Difference => left.is_bounded(),
+            },
+            Geometry::Instance(geometry) => geometry.is_bounded(),
+            _ => true,
         }
+    }
 
-        let distance = (d - normal.dot(ray.origin())) / denom;
-        // Check if intersection is within acceptable ray interval
-        if distance < ray.tmin() || distance > ray.tmax() {
-            return None;
+    /// Whether points can be sampled on the geometry surface, which is
+    /// needed to sample it as a light. Infinite planes, CSG and SDFs can't be
+    /// sampled, and only light the surfaces whose BSDF samples reach them.
+    pub fn is_sampleable(&self) -> bool {
+        match self {
+            Geometry::Plane { .. } | Geometry::Csg { .. } | Geometry::Sdf { .. } => false,
+            Geometry::Instance(geometry) => geometry.is_sampleable(),
+            _ => true,
         }
-        let intersection = ray.at(distance);
-        let plain_hit_vector = intersection - position;
-        let alpha = w.dot(plain_hit_vector.cross(v));
-        let beta = w.dot(u.cross(plain_hit_vector));
+    }
 
-        if alpha > 1.0 || alpha < 0.0 || beta > 1.0 || beta < 0.0 {
+    /// Sample a point uniformly distributed over the geometry surface. The
+    /// random sample `u` must be within [0, 1)^2. Geometry which can't be
+    /// sampled, see `is_sampleable`, gives samples with a zero density.
+    pub fn sample_surface(&self, u: Vec2) -> SurfaceSample {
+        let pdf = 1.0 / self.area();
+        match *self {
+            Geometry::Sphere { position, radius } => {
+                let normal = utils::sample_uniform_sphere(u);
+                SurfaceSample {
+                    position: position + radius * normal,
+                    normal,
+                    uv: sphere_uv(normal),
+                    pdf,
+                }
+            }
+            Geometry::Quad {
+                position,
+                u: qu,
+                v: qv,
+            } => SurfaceSample {
+                position: position + u.x * qu + u.y * qv,
+                normal: qu.cross(qv).normalize(),
+                uv: u,
+                pdf,
+            },
+            Geometry::Cuboid { position, size } => {
+                // Choose a face proportionally to its area, reusing the first
+                // sample dimension to pick the point within the face.
+                let faces = cuboid_faces(position, size);
+                let face_areas = faces.map(|(_, qu, qv)| qu.cross(qv).length());
+                let mut target = u.x * face_areas.iter().sum::<f32>();
+                let mut face = 0;
+                while face < 5 && target >= face_areas[face] {
+                    target -= face_areas[face];
+                    face += 1;
+                }
+                let face_uv = Vec2::new((target / face_areas[face]).min(1.0), u.y);
+
+                let (corner, qu, qv) = faces[face];
+                SurfaceSample {
+                    position: corner + face_uv.x * qu + face_uv.y * qv,
+                    normal: qu.cross(qv).normalize(),
+                    uv: face_uv,
+                    pdf,
+                }
+            }
+            Geometry::Disk {
+                position,
+                normal,
+                radius,
+            } => SurfaceSample {
+                pdf,
+                ..sample_disk(position, normal, radius, u)
+            },
+            Geometry::Cylinder {
+                position,
+                axis,
+                radius,
+            } => {
+                let height = axis.length();
+                let frame = axis_frame(axis);
+                let side_area = 2.0 * std::f32::consts::PI * radius * height;
+                let target = u.x * self.area();
+                if target < side_area {
+                    let z = target / side_area * height;
+                    let (sin, cos) = (2.0 * std::f32::consts::PI * u.y).sin_cos();
+                    SurfaceSample {
+                        position: position
+                            + frame.to_world(Vec3::new(radius * cos, radius * sin, z)),
+                        normal: frame.to_world(Vec3::new(cos, sin, 0.0)),
+                        uv: Vec2::new(u.y, z / height),
+                        pdf,
+                    }
+                } else {
+                    // Pick the base or the top cap, reusing the remaining
+                    // part of the first sample dimension
+                    let cap_u = 2.0 * (target - side_area) / (self.area() - side_area);
+                    let (center, normal, disk_u) = if cap_u < 1.0 {
+                        (position, -axis, cap_u)
+                    } else {
+                        (position + axis, axis, cap_u - 1.0)
+                    };
+                    SurfaceSample {
+                        pdf,
+                        ..sample_disk(center, normal, radius, Vec2::new(disk_u.min(1.0), u.y))
+                    }
+                }
+            }
+            Geometry::Cone {
+                position,
+                axis,
+                radius,
+            } => {
+                let height = axis.length();
+                let frame = axis_frame(axis);
+                let side_area = self.area() - std::f32::consts::PI * radius * radius;
+                let target = u.x * self.area();
+                if target < side_area {
+                    // Distance from the apex, as a fraction of the slant
+                    // height, grows with the square root of the area
+                    let s = (target / side_area).sqrt();
+                    let z = height * (1.0 - s);
+                    let (sin, cos) = (2.0 * std::f32::consts::PI * u.y).sin_cos();
+                    SurfaceSample {
+                        position: position
+                            + frame.to_world(Vec3::new(radius * s * cos, radius * s * sin, z)),
+                        normal: frame
+                            .to_world(Vec3::new(cos, sin, radius / height))
+                            .normalize(),
+                        uv: Vec2::new(u.y, z / height),
+                        pdf,
+                    }
+                } else {
+                    let disk_u = (target - side_area) / (self.area() - side_area);
+                    SurfaceSample {
+                        pdf,
+                        ..sample_disk(position, -axis, radius, Vec2::new(disk_u.min(1.0), u.y))
+                    }
+                }
+            }
+            Geometry::Plane { position, normal } => SurfaceSample {
+                position,
+                normal: normal.normalize(),
+                uv: Vec2::ZERO,
+                pdf: 0.0,
+            },
+            Geometry::Csg { ref left, .. } => SurfaceSample {
+                pdf: 0.0,
+                ..left.sample_surface(u)
+            },
+            Geometry::Sdf { ref bounds, .. } => SurfaceSample {
+                position: (bounds.min_position + bounds.max_position) / 2.0,
+                normal: Vec3::Y,
+                uv: Vec2::ZERO,
+                pdf: 0.0,
+            },
+            Geometry::Mesh(ref mesh) => mesh.sample_surface(u),
+            Geometry::Instance(ref geometry) => geometry.sample_surface(u),
+        }
+    }
+}
+
+impl Bounded for Geometry {
+    fn aabb(&self) -> Aabb {
+        match *self {
+            Geometry::Sphere { position, radius } => {
+                Aabb::from_positions(position - radius, position + radius)
+            }
+            Geometry::Quad { position, u, v } => Aabb::surround(
+                &Aabb::from_positions(position, position + u + v),
+                &Aabb::from_positions(position + u, position + v),
+            ),
+            Geometry::Cuboid { position, size } => Aabb::new(position, size),
+            Geometry::Disk {
+                position,
+                normal,
+                radius,
+            } => disk_aabb(position, normal, radius),
+            Geometry::Cylinder {
+                position,
+                axis,
+                radius,
+            } => Aabb::surround(
+                &disk_aabb(position, axis, radius),
+                &disk_aabb(position + axis, axis, radius),
+            ),
+            Geometry::Cone {
+                position,
+                axis,
+                radius,
+            } => Aabb::surround(
+                &disk_aabb(position, axis, radius),
+                &Aabb::new(position + axis, Vec3::ZERO),
+            ),
+            Geometry::Plane { .. } => Aabb::infinite(),
+            Geometry::Csg {
+                operation,
+                ref left,
+                ref right,
+            } => {
+                let (left, right) = (left.aabb(), right.aabb());
+                match operation {
+                    CsgOperation::Union => Aabb::surround(&left, &right),
+                    CsgOperation::Intersection => {
+                        let min_position = left.min_position.max(right.min_position);
+                        Aabb {
+                            max_position: left
+                                .max_position
+                                .min(right.max_position)
+                                .max(min_position),
+                            min_position,
+                        }
+                    }
+                    CsgOperation::Difference => left,
+                }
+            }
+            Geometry::Sdf { ref bounds, .. } => bounds.clone(),
+            Geometry::Mesh(ref mesh) => mesh.aabb(),
+            Geometry::Instance(ref geometry) => geometry.aabb(),
+        }
+    }
+}
+
+fn hit_sphere(ray: &Ray, material: &Arc<Material>, position: Vec3, radius: f32) -> Option<Hit> {
+    let oc = position - ray.origin();
+    let a = ray.direction().dot(ray.direction());
+    let half_b = ray.direction().dot(oc);
+    let c = oc.length_squared() - radius * radius;
+    let discriminant = half_b * half_b - a * c;
+
+    // Check if first solution is valid
+    let mut distance = (half_b - discriminant.sqrt()) / a;
+    if distance < ray.tmin() || distance > ray.tmax() {
+        // Check if second solution is valid
+        // Note: its possible this second solution is the same as solution 1
+        // in case the discriminant was zero.
+        distance = (half_b + discriminant.sqrt()) / a;
+        if distance < ray.tmin() || distance > ray.tmax() {
+            // Both possible solutions are behind camera
             return None;
         }
+    }
+
+    if discriminant >= 0.0 {
+        let intersection = ray.at(distance);
+        let outward_normal = (intersection - position) / radius;
+        let is_front_face = outward_normal.dot(ray.direction()) <= 0.0;
+        let uv = sphere_uv(outward_normal);
+        let (dpdu, dpdv) = sphere_tangents(intersection - position);
+        let normal = if is_front_face {
+            outward_normal
+        } else {
+            -outward_normal
+        };
 
         Some(Hit {
             distance,
             position: intersection,
             normal,
-            material: self.material.clone(),
-            is_front_face: ray.direction().dot(normal) < 0.0,
+            shading_normal: normal,
+            uv,
+            dpdu,
+            dpdv,
+            material: material.clone(),
+            medium: None,
+            is_front_face,
+            object_area: 4.0 * std::f32::consts::PI * radius * radius,
         })
+    } else {
+        None
     }
+}
 
-    fn hit_cuboid(&self, ray: &Ray, position: Vec3, size: Vec3) -> Option<Hit> {
-        let half_size = size / 2.0;
-        let mut closest_hit_distance = ray.tmax();
-        let mut closest_hit = None;
-
-        let pos_x_hit = self.hit_quad(
-            &ray,
-            position - half_size,
-            Vec3::new(0.0, 0.0, size.z),
-            Vec3::new(0.0, size.y, 0.0),
-        );
-        let neg_x_hit = self.hit_quad(
-            &ray,
-            position + half_size,
-            Vec3::new(0.0, -size.y, 0.0),
-            Vec3::new(0.0, 0.0, -size.z),
-        );
-        let neg_z_hit = self.hit_quad(
-            &ray,
-            position - half_size,
-            Vec3::new(0.0, size.y, 0.0),
-            Vec3::new(size.x, 0.0, 0.0),
-        );
-        let pos_z_hit = self.hit_quad(
-            &ray,
-            position + half_size,
-            Vec3::new(-size.x, 0.0, 0.0),
-            Vec3::new(0.0, -size.y, 0.0),
-        );
-        let neg_y_hit = self.hit_quad(
-            &ray,
-            position - half_size,
-            Vec3::new(size.x, 0.0, 0.0),
-            Vec3::new(0.0, 0.0, size.z),
-        );
-        let pos_y_hit = self.hit_quad(
-            &ray,
-            position + half_size,
-            Vec3::new(0.0, 0.0, -size.z),
-            Vec3::new(-size.x, 0.0, 0.0),
-        );
-        let quads_hits = [
-            pos_x_hit, neg_x_hit, pos_z_hit, neg_z_hit, pos_y_hit, neg_y_hit,
-        ];
-        for quad_hit in quads_hits {
-            if let Some(hit) = quad_hit {
-                if hit.distance < closest_hit_distance {
-                    closest_hit_distance = hit.distance;
-                    closest_hit = Some(hit);
-                }
+fn hit_quad(ray: &Ray, material: &Arc<Material>, position: Vec3, u: Vec3, v: Vec3) -> Option<Hit> {
+    // NOTE: These values can be cached in Quad
+    let n = u.cross(v);
+    let normal = n.normalize();
+    let d = normal.dot(position);
+    let w = n / n.dot(n);
+
+    let denom = normal.dot(ray.direction());
+    // Check if ray is parallel to quad plane
+    if denom.abs() < f32::MIN_POSITIVE {
+        return None;
+    }
+
+    let distance = (d - normal.dot(ray.origin())) / denom;
+    // Check if intersection is within acceptable ray interval
+    if distance < ray.tmin() || distance > ray.tmax() {
+        return None;
+    }
+    let intersection = ray.at(distance);
+    let plain_hit_vector = intersection - position;
+    let alpha = w.dot(plain_hit_vector.cross(v));
+    let beta = w.dot(u.cross(plain_hit_vector));
+
+    if alpha > 1.0 || alpha < 0.0 || beta > 1.0 || beta < 0.0 {
+        return None;
+    }
+
+    let is_front_face = ray.direction().dot(normal) < 0.0;
+    let normal = if is_front_face { normal } else { -normal };
+    Some(Hit {
+        distance,
+        position: intersection,
+        normal,
+        shading_normal: normal,
+        uv: Vec2::new(alpha, beta),
+        dpdu: u,
+        dpdv: v,
+        material: material.clone(),
+        medium: None,
+        is_front_face,
+        object_area: n.length(),
+    })
+}
+
+fn hit_cuboid(ray: &Ray, material: &Arc<Material>, position: Vec3, size: Vec3) -> Option<Hit> {
+    let mut closest_hit_distance = ray.tmax();
+    let mut closest_hit = None;
+    for (corner, u, v) in cuboid_faces(position, size) {
+        if let Some(hit) = hit_quad(ray, material, corner, u, v) {
+            if hit.distance < closest_hit_distance {
+                closest_hit_distance = hit.distance;
+                closest_hit = Some(hit);
             }
         }
+    }
 
-        closest_hit
+    closest_hit.map(|hit| Hit {
+        object_area: 2.0 * (size.x * size.y + size.y * size.z + size.z * size.x),
+        ..hit
+    })
+}
+
+/// Spherical coordinates of an outward sphere normal, mapped to [0, 1]^2.
+fn sphere_uv(normal: Vec3) -> Vec2 {
+    let phi = (-normal.z).atan2(normal.x) + std::f32::consts::PI;
+    let theta = (-normal.y).clamp(-1.0, 1.0).acos();
+    Vec2::new(
+        phi / (2.0 * std::f32::consts::PI),
+        theta / std::f32::consts::PI,
+    )
+}
+
+/// Partial derivatives of a sphere surface parameterized by `sphere_uv`, where
+/// `p` is the position relative to the sphere center. The u derivative
+/// vanishes at the poles.
+fn sphere_tangents(p: Vec3) -> (Vec3, Vec3) {
+    use std::f32::consts::PI;
+    let dpdu = 2.0 * PI * Vec3::new(p.z, 0.0, -p.x);
+    let rho = (p.x * p.x + p.z * p.z).sqrt();
+    let dpdv = if rho > f32::EPSILON {
+        PI * Vec3::new(-p.x * p.y / rho, rho, -p.y * p.z / rho)
+    } else {
+        PI * Vec3::new(p.y, 0.0, 0.0)
+    };
+    (dpdu, dpdv)
+}
+
+/// Right handed frame whose normal points along `axis`, used to intersect
+/// and sample geometry around an axis.
+fn axis_frame(axis: Vec3) -> Frame {
+    let normal = axis.normalize();
+    Frame::from_normal_tangent(normal, normal.any_orthogonal_vector())
+}
+
+/// Geometric normal of a hit facing the side the ray arrived from, and
+/// whether the ray arrived from the outside.
+fn face_forward(ray: &Ray, outward_normal: Vec3) -> (Vec3, bool) {
+    let is_front_face = ray.direction().dot(outward_normal) < 0.0;
+    if is_front_face {
+        (outward_normal, true)
+    } else {
+        (-outward_normal, false)
     }
 }
 
+/// Angle of a local position around the frame normal, within [0, 2pi).
+fn polar_angle(p: Vec3) -> f32 {
+    p.y.atan2(p.x).rem_euclid(2.0 * std::f32::consts::PI)
+}
+
+/// Real roots of `a * t^2 + 2 * half_b * t + c = 0` in increasing order.
+fn solve_quadratic(a: f32, half_b: f32, c: f32) -> Option<[f32; 2]> {
+    if a.abs() < f32::MIN_POSITIVE {
+        if half_b.abs() < f32::MIN_POSITIVE {
+            return None;
+        }
+        let t = -c / (2.0 * half_b);
+        return Some([t, t]);
+    }
+    let discriminant = half_b * half_b - a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let root = discriminant.sqrt();
+    let (t0, t1) = ((-half_b - root) / a, (-half_b + root) / a);
+    Some([t0.min(t1), t0.max(t1)])
+}
+
+fn hit_disk(
+    ray: &Ray,
+    material: &Arc<Material>,
+    position: Vec3,
+    normal: Vec3,
+    radius: f32,
+) -> Option<Hit> {
+    let frame = axis_frame(normal);
+    let origin = frame.to_local(ray.origin() - position);
+    let direction = frame.to_local(ray.direction());
+    // Check if ray is parallel to disk plane
+    if direction.z.abs() < f32::MIN_POSITIVE {
+        return None;
+    }
+    let distance = -origin.z / direction.z;
+    if distance < ray.tmin() || distance > ray.tmax() {
+        return None;
+    }
+    let p = origin + distance * direction;
+    let r = p.truncate().length();
+    if r > radius {
+        return None;
+    }
+
+    // Surface coordinates are the angle around the center and the distance
+    // to the rim, so that the derivatives follow the outward normal
+    let phi = polar_angle(p);
+    let (sin, cos) = phi.sin_cos();
+    let (normal, is_front_face) = face_forward(ray, frame.normal);
+    Some(Hit {
+        distance,
+        position: ray.at(distance),
+        normal,
+        shading_normal: normal,
+        uv: Vec2::new(phi / (2.0 * std::f32::consts::PI), 1.0 - r / radius),
+        dpdu: frame.to_world(2.0 * std::f32::consts::PI * Vec3::new(-p.y, p.x, 0.0)),
+        dpdv: frame.to_world(-radius * Vec3::new(cos, sin, 0.0)),
+        material: material.clone(),
+        medium: None,
+        is_front_face,
+        object_area: std::f32::consts::PI * radius * radius,
+    })
+}
+
+fn hit_cylinder(
+    ray: &Ray,
+    material: &Arc<Material>,
+    position: Vec3,
+    axis: Vec3,
+    radius: f32,
+) -> Option<Hit> {
+    let height = axis.length();
+    let frame = axis_frame(axis);
+    let origin = frame.to_local(ray.origin() - position);
+    let direction = frame.to_local(ray.direction());
+
+    // Side, where x^2 + y^2 = radius^2 and 0 <= z <= height
+    let (o, d) = (origin.truncate(), direction.truncate());
+    let side_distance = solve_quadratic(d.dot(d), o.dot(d), o.dot(o) - radius * radius)
+        .into_iter()
+        .flatten()
+        .find(|&t| {
+            let z = origin.z + t * direction.z;
+            t >= ray.tmin() && t <= ray.tmax() && (0.0..=height).contains(&z)
+        });
+    let mut closest_hit = side_distance.map(|distance| {
+        let p = origin + distance * direction;
+        let phi = polar_angle(p);
+        let (normal, is_front_face) =
+            face_forward(ray, frame.to_world(Vec3::new(p.x, p.y, 0.0) / radius));
+        Hit {
+            distance,
+            position: ray.at(distance),
+            normal,
+            shading_normal: normal,
+            uv: Vec2::new(phi / (2.0 * std::f32::consts::PI), p.z / height),
+            dpdu: frame.to_world(2.0 * std::f32::consts::PI * Vec3::new(-p.y, p.x, 0.0)),
+            dpdv: axis,
+            material: material.clone(),
+            medium: None,
+            is_front_face,
+            object_area: 0.0,
+        }
+    });
+
+    for (center, normal) in [(position, -axis), (position + axis, axis)] {
+        let tmax = closest_hit.as_ref().map_or(ray.tmax(), |hit| hit.distance);
+        if let Some(hit) = hit_disk(&ray.with_tmax(tmax), material, center, normal, radius) {
+            closest_hit = Some(hit);
+        }
+    }
+
+    closest_hit.map(|hit| Hit {
+        object_area: 2.0 * std::f32::consts::PI * radius * (height + radius),
+        ..hit
+    })
+}
+
+fn hit_cone(
+    ray: &Ray,
+    material: &Arc<Material>,
+    position: Vec3,
+    axis: Vec3,
+    radius: f32,
+) -> Option<Hit> {
+    let height = axis.length();
+    let frame = axis_frame(axis);
+    let origin = frame.to_local(ray.origin() - position);
+    let direction = frame.to_local(ray.direction());
+
+    // Side, where x^2 + y^2 = (k * (height - z))^2 and 0 <= z <= height
+    let k2 = (radius / height).powi(2);
+    let (o, d) = (origin.truncate(), direction.truncate());
+    let oh = height - origin.z;
+    let side_distance = solve_quadratic(
+        d.dot(d) - k2 * direction.z * direction.z,
+        o.dot(d) + k2 * oh * direction.z,
+        o.dot(o) - k2 * oh * oh,
+    )
+    .into_iter()
+    .flatten()
+    .find(|&t| {
+        let z = origin.z + t * direction.z;
+        t >= ray.tmin() && t <= ray.tmax() && (0.0..=height).contains(&z)
+    });
+    let mut closest_hit = side_distance.map(|distance| {
+        let p = origin + distance * direction;
+        let phi = polar_angle(p);
+        let (sin, cos) = phi.sin_cos();
+        let outward_normal = Vec3::new(p.x, p.y, k2 * (height - p.z))
+            .try_normalize()
+            .unwrap_or(Vec3::Z);
+        let (normal, is_front_face) = face_forward(ray, frame.to_world(outward_normal));
+        Hit {
+            distance,
+            position: ray.at(distance),
+            normal,
+            shading_normal: normal,
+            uv: Vec2::new(phi / (2.0 * std::f32::consts::PI), p.z / height),
+            dpdu: frame.to_world(2.0 * std::f32::consts::PI * Vec3::new(-p.y, p.x, 0.0)),
+            dpdv: frame.to_world(Vec3::new(-radius * cos, -radius * sin, height)),
+            material: material.clone(),
+            medium: None,
+            is_front_face,
+            object_area: 0.0,
+        }
+    });
+
+    let tmax = closest_hit.as_ref().map_or(ray.tmax(), |hit| hit.distance);
+    if let Some(hit) = hit_disk(&ray.with_tmax(tmax), material, position, -axis, radius) {
+        closest_hit = Some(hit);
+    }
+
+    let slant = (radius * radius + height * height).sqrt();
+    closest_hit.map(|hit| Hit {
+        object_area: std::f32::consts::PI * radius * (slant + radius),
+        ..hit
+    })
+}
+
+fn hit_plane(ray: &Ray, material: &Arc<Material>, position: Vec3, normal: Vec3) -> Option<Hit> {
+    let frame = axis_frame(normal);
+    let denom = frame.normal.dot(ray.direction());
+    // Check if ray is parallel to the plane
+    if denom.abs() < f32::MIN_POSITIVE {
+        return None;
+    }
+    let distance = frame.normal.dot(position - ray.origin()) / denom;
+    if distance < ray.tmin() || distance > ray.tmax() {
+        return None;
+    }
+
+    let intersection = ray.at(distance);
+    let p = frame.to_local(intersection - position);
+    let (normal, is_front_face) = face_forward(ray, frame.normal);
+    Some(Hit {
+        distance,
+        position: intersection,
+        normal,
+        shading_normal: normal,
+        uv: p.truncate(),
+        dpdu: frame.tangent,
+        dpdv: frame.bitangent,
+        material: material.clone(),
+        medium: None,
+        is_front_face,
+        object_area: f32::INFINITY,
+    })
+}
+
+/// Intersect a CSG geometry by walking the boundaries of both solids along
+/// the ray, until the ray crosses the boundary of the combined solid.
+fn hit_csg(
+    ray: &Ray,
+    material: &Arc<Material>,
+    operation: CsgOperation,
+    left: &Geometry,
+    right: &Geometry,
+) -> Option<Hit> {
+    // Children are intersected without the maximum distance, since the next
+    // crossing of each solid tells whether the ray starts inside of it
+    let full_ray = ray.with_interval(ray.tmin(), Ray::MAX_RAY_DISTANCE);
+    let mut left_hit = left.hit(&full_ray, material);
+    let mut right_hit = right.hit(&full_ray, material);
+    let mut in_left = left_hit.as_ref().is_some_and(|hit| !hit.is_front_face);
+    let mut in_right = right_hit.as_ref().is_some_and(|hit| !hit.is_front_face);
+
+    loop {
+        let is_left = match (&left_hit, &right_hit) {
+            (Some(l), Some(r)) => l.distance <= r.distance,
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (None, None) => return None,
+        };
+        let hit = if is_left {
+            left_hit.take()
+        } else {
+            right_hit.take()
+        }
+        .expect("Closest hit exists");
+        if hit.distance > ray.tmax() {
+            return None;
+        }
+
+        let was_inside = operation.contains(in_left, in_right);
+        if is_left {
+            in_left = !in_left;
+        } else {
+            in_right = !in_right;
+        }
+        let is_inside = operation.contains(in_left, in_right);
+        if was_inside != is_inside {
+            // Surfaces subtracted from the left solid face the other way, so
+            // the tangents are flipped to follow the outward normal
+            let dpdv = if hit.is_front_face == is_inside {
+                hit.dpdv
+            } else {
+                -hit.dpdv
+            };
+            return Some(Hit {
+                dpdv,
+                is_front_face: is_inside,
+                object_area: f32::INFINITY,
+                ..hit
+            });
+        }
+
+        let next_ray = full_ray.with_tmin(hit.distance + Ray::MIN_RAY_DISTANCE);
+        if is_left {
+            left_hit = left.hit(&next_ray, material);
+        } else {
+            right_hit = right.hit(&next_ray, material);
+        }
+    }
+}
+
+fn hit_sdf(ray: &Ray, material: &Arc<Material>, sdf: &Sdf, bounds: &Aabb) -> Option<Hit> {
+    let (tmin, tmax) = bounds.clip(ray)?;
+    let distance = sdf.trace(ray, tmin, tmax)?;
+    let position = ray.at(distance);
+    let outward_normal = sdf.normal(position);
+    let (normal, is_front_face) = face_forward(ray, outward_normal);
+    // Distance fields have no surface parameterization, so surface
+    // coordinates follow the normal direction like on a sphere
+    let frame = axis_frame(outward_normal);
+    Some(Hit {
+        distance,
+        position,
+        normal,
+        shading_normal: normal,
+        uv: sphere_uv(outward_normal),
+        dpdu: frame.tangent,
+        dpdv: frame.bitangent,
+        material: material.clone(),
+        medium: None,
+        is_front_face,
+        object_area: f32::INFINITY,
+    })
+}
+
+/// Uniform sample of a disk surface, parameterized like its hits. The density
+/// is left for the caller to fill.
+fn sample_disk(position: Vec3, normal: Vec3, radius: f32, u: Vec2) -> SurfaceSample {
+    let frame = axis_frame(normal);
+    let r = u.x.sqrt();
+    let (sin, cos) = (2.0 * std::f32::consts::PI * u.y).sin_cos();
+    SurfaceSample {
+        position: position + frame.to_world(radius * r * Vec3::new(cos, sin, 0.0)),
+        normal: frame.normal,
+        uv: Vec2::new(u.y, 1.0 - r),
+        pdf: 0.0,
+    }
+}
+
+/// Bounds of a disk, which extends `radius` along the directions
+/// perpendicular to its normal.
+fn disk_aabb(position: Vec3, normal: Vec3, radius: f32) -> Aabb {
+    let normal = normal.normalize();
+    let extent = radius * (Vec3::ONE - normal * normal).max(Vec3::ZERO).map(f32::sqrt);
+    Aabb::new(position, 2.0 * extent)
+}
+
+/// Faces of a cuboid as quads given by a corner and two edges, with outward
+/// facing normals.
+fn cuboid_faces(position: Vec3, size: Vec3) -> [(Vec3, Vec3, Vec3); 6] {
+    let half_size = size / 2.0;
+    let (x, y, z) = (
+        Vec3::new(size.x, 0.0, 0.0),
+        Vec3::new(0.0, size.y, 0.0),
+        Vec3::new(0.0, 0.0, size.z),
+    );
+    [
+        (position - half_size, z, y),
+        (position + half_size, -y, -z),
+        (position + half_size, -x, -y),
+        (position - half_size, y, x),
+        (position + half_size, -z, -x),
+        (position - half_size, x, z),
+    ]
+}
+
 impl Hittable for Model {
     fn hit(&self, ray: &Ray) -> Option<Hit> {
-        match self.geometry {
-            Geometry::Sphere { position, radius } => self.hit_sphere(&ray, position, radius),
-            Geometry::Quad { position, u, v } => self.hit_quad(&ray, position, u, v),
-            Geometry::Cuboid { position, size } => self.hit_cuboid(&ray, position, size),
-        }
+        let hit = match self.transform_at(ray.time()) {
+            Some(transform) => {
+                let (object_ray, length) = transform.ray_to_object(ray)?;
+                let hit = self.geometry.hit(&object_ray, &self.material)?;
+                transform.hit_to_world(hit, length)
+            }
+            None => self.geometry.hit(ray, &self.material)?,
+        };
+        Some(Hit {
+            medium: self.medium.clone(),
+            ..hit
+        })
     }
 }
 
 impl Bounded for Model {
     fn aabb(&self) -> Aabb {
-        match self.geometry {
-            Geometry::Sphere { position, radius } => {
-                Aabb::from_positions(position - radius, position + radius)
-            }
-            Geometry::Quad { position, u, v } => Aabb::surround(
-                &Aabb::from_positions(position, position + u + v),
-                &Aabb::from_positions(position + u, position + v),
-            ),
-            Geometry::Cuboid { position, size } => Aabb::new(position, size),
+        let aabb = match self.transform {
+            Some(ref transform) => transform.aabb_to_world(&self.geometry.aabb()),
+            None => self.geometry.aabb(),
+        };
+        match self.motion {
+            Some(ref motion) => motion.aabb_to_world(&aabb),
+            None => aabb,
         }
     }
 }
@@ -222,39 +1115,172 @@ impl Bounded for Model {
 // Scene
 ////////////////////////////////////////////////////////////////////////////////
 
-#[derive(Debug, Clone, Encode, Decode)]
+#[derive(Debug, Clone)]
 pub struct Scene {
     camera: Camera,
     objects: Vec<Arc<Model>>,
-    #[bincode(with_serde)]
+    /// Indices of the emissive objects which can be sampled, used for direct
+    /// light sampling.
+    lights: Vec<usize>,
     background: Vec3,
-    bvh: BvhNode<Model>,
+    /// Environment map or sky lighting the scene instead of the background
+    /// color.
+    environment: Option<Arc<Environment>>,
+    /// Medium filling the space outside of every object, such as fog.
+    medium: Option<Arc<Medium>>,
+    /// BVH of the bounded objects, which come before the unbounded ones.
+    bvh: Option<BvhNode<Model>>,
+    /// Index of every object in the list given to the scene, since unbounded
+    /// objects are moved last and building the BVH reorders the others.
+    object_indices: Vec<usize>,
+    num_bounded: usize,
     use_bvh: bool,
 }
 
 impl Scene {
-    pub fn new(camera: Camera, mut objects: Vec<Arc<Model>>) -> Self {
-        let bvh = BvhNode::new(&mut objects[..]);
-        Self {
-            camera,
-            objects,
-            background: Vec3::ZERO,
-            bvh,
-            use_bvh: true,
-        }
+    pub fn new(camera: Camera, objects: Vec<Arc<Model>>) -> Self {
+        Self::with_background(camera, objects, Vec3::ZERO)
     }
 
     pub fn with_background(camera: Camera, mut objects: Vec<Arc<Model>>, background: Vec3) -> Self {
-        let bvh = BvhNode::new(&mut objects[..]);
+        // Objects may be listed several times, so their indices are stacked
+        // to be popped in order
+        let mut indices: HashMap<*const Model, Vec<usize>> = HashMap::new();
+        for (idx, object) in objects.iter().enumerate().rev() {
+            indices.entry(Arc::as_ptr(object)).or_default().push(idx);
+        }
+        // Unbounded objects can't be part of the BVH and are intersected
+        // separately
+        objects.sort_by_key(|object| !object.is_bounded());
+        let num_bounded = objects.partition_point(|object| object.is_bounded());
+        let bvh = (num_bounded > 0).then(|| BvhNode::new(&mut objects[..num_bounded]));
+        // NOTE: Light indices must be gathered after BVH construction since it
+        // reorders the objects.
+        let lights = objects
+            .iter()
+            .enumerate()
+            .filter(|(_, object)| object.material.is_emissive() && object.is_sampleable())
+            .map(|(idx, _)| idx)
+            .collect();
+        let object_indices = objects
+            .iter()
+            .map(|object| {
+                indices
+                    .get_mut(&Arc::as_ptr(object))
+                    .and_then(Vec::pop)
+                    .expect("Reordered objects come from the given list")
+            })
+            .collect();
         Self {
             camera,
             objects,
+            lights,
             background,
+            environment: None,
+            medium: None,
             bvh,
+            object_indices,
+            num_bounded,
             use_bvh: true,
         }
     }
 
+    /// Same as `new`, but validates the scene first, see `validate`.
+    pub fn try_new(camera: Camera, objects: Vec<Arc<Model>>) -> Result<Self, SceneError> {
+        Self::try_with_background(camera, objects, Vec3::ZERO)
+    }
+
+    /// Same as `with_background`, but validates the scene first, see
+    /// `validate`. Object indices of the problems refer to `objects`.
+    pub fn try_with_background(
+        camera: Camera,
+        objects: Vec<Arc<Model>>,
+        background: Vec3,
+    ) -> Result<Self, SceneError> {
+        Self::check(&camera, &objects, background, None)?;
+        Ok(Self::with_background(camera, objects, background))
+    }
+
+    /// Scene lit by an environment map or a sky instead of a background
+    /// color.
+    pub fn with_environment(
+        camera: Camera,
+        objects: Vec<Arc<Model>>,
+        environment: Arc<Environment>,
+    ) -> Self {
+        Self {
+            environment: Some(environment),
+            ..Self::new(camera, objects)
+        }
+    }
+
+    /// Same as `with_environment`, but validates the scene first, see
+    /// `validate`. Object indices of the problems refer to `objects`.
+    pub fn try_with_environment(
+        camera: Camera,
+        objects: Vec<Arc<Model>>,
+        environment: Arc<Environment>,
+    ) -> Result<Self, SceneError> {
+        Self::check(&camera, &objects, Vec3::ZERO, Some(&environment))?;
+        Ok(Self::with_environment(camera, objects, environment))
+    }
+
+    /// Fill the space outside of every object with `medium`. The camera is
+    /// assumed to be in it.
+    pub fn with_medium(mut self, medium: Arc<Medium>) -> Self {
+        self.medium = Some(medium);
+        self
+    }
+
+    /// Same as `with_medium`, but validates the medium first.
+    pub fn try_with_medium(self, medium: Arc<Medium>) -> Result<Self, SceneError> {
+        if !medium.is_valid() {
+            return Err(SceneIssue::InvalidFog.into());
+        }
+        Ok(self.with_medium(medium))
+    }
+
+    /// Check the scene for problems which would make rendering fail or
+    /// produce an unexpected result, such as degenerate geometry, invalid
+    /// refraction indices or missing light sources. Object indices of the
+    /// problems refer to the list given to the scene, not to `objects`, which
+    /// is reordered.
+    pub fn validate(&self) -> Result<(), SceneError> {
+        let mut objects = self.objects.clone();
+        for (object, &idx) in self.objects.iter().zip(&self.object_indices) {
+            objects[idx] = object.clone();
+        }
+        Self::check(
+            &self.camera,
+            &objects,
+            self.background,
+            self.environment.as_deref(),
+        )?;
+        match self.medium {
+            Some(ref medium) if !medium.is_valid() => Err(SceneIssue::InvalidFog.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn check(
+        camera: &Camera,
+        objects: &[Arc<Model>],
+        background: Vec3,
+        environment: Option<&Environment>,
+    ) -> Result<(), SceneError> {
+        let mut issues = camera.issues();
+        if !background.is_finite() {
+            issues.push(SceneIssue::NonFiniteBackground);
+        }
+        issues.extend(environment.and_then(Environment::issue));
+        let is_background_lit = match environment {
+            Some(environment) => !environment.is_black(),
+            None => background != Vec3::ZERO,
+        };
+        issues.extend(objects_issues(objects, is_background_lit));
+        SceneError::check(issues)
+    }
+
     pub fn camera(&self) -> &Camera {
         &self.camera
     }
@@ -263,15 +1289,259 @@ impl Scene {
         &self.objects
     }
 
+    /// Constant background color, unused when the scene has an environment.
     pub fn background(&self) -> Vec3 {
         self.background
     }
+
+    pub fn environment(&self) -> Option<&Arc<Environment>> {
+        self.environment.as_ref()
+    }
+
+    /// Radiance arriving along rays which leave the scene in `direction`.
+    pub fn escaped_radiance(&self, direction: Vec3) -> Vec3 {
+        match self.environment {
+            Some(ref environment) => environment.eval(direction),
+            None => self.background,
+        }
+    }
+
+    pub fn medium(&self) -> Option<&Arc<Medium>> {
+        self.medium.as_ref()
+    }
+
+    /// Solid angle density with which direct light sampling would choose the
+    /// direction of `ray`, given that it hits an emissive object at `hit`.
+    pub fn light_pdf(&self, ray: &Ray, hit: &Hit) -> f32 {
+        let cos_light = hit.normal.dot(ray.direction()).abs();
+        if self.lights.is_empty() || cos_light < f32::EPSILON {
+            return 0.0;
+        }
+        let distance_squared = (hit.position - ray.origin()).length_squared();
+        distance_squared / (cos_light * hit.object_area * self.num_lights() as f32)
+    }
+
+    /// Solid angle density with which direct light sampling would choose the
+    /// environment in `direction`.
+    pub fn environment_pdf(&self, direction: Vec3) -> f32 {
+        match self.environment {
+            Some(ref environment) => environment.pdf(direction) / self.num_lights() as f32,
+            None => 0.0,
+        }
+    }
+
+    /// Number of lights direct light sampling chooses from, the environment
+    /// counting as one after the emissive objects.
+    pub fn num_lights(&self) -> usize {
+        self.lights.len() + self.environment.is_some() as usize
+    }
+
+    /// Iterator over all emissive objects of the scene.
+    pub fn lights(&self) -> impl ExactSizeIterator<Item = &Arc<Model>> {
+        self.lights.iter().map(|&idx| &self.objects[idx])
+    }
+
+    /// Emissive object at `idx` in the order of `lights`, or `None` past the
+    /// emissive objects.
+    pub fn light(&self, idx: usize) -> Option<&Arc<Model>> {
+        self.lights.get(idx).map(|&idx| &self.objects[idx])
+    }
 }
 
+// Materials are usually shared between many objects and may hold large
+// textures, so scenes are encoded with a table of unique materials referenced
+// by index. Instanced geometry and media, which may hold large voxel grids,
+// are shared the same way. The BVH and light indices are rebuilt when
+// decoding instead.
+impl Encode for Scene {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        let mut material_indices = HashMap::new();
+        let mut materials: Vec<&Material> = Vec::new();
+        let mut image_indices = HashMap::new();
+        let mut images: Vec<Arc<ImageTexture>> = Vec::new();
+        let mut geometry_indices = HashMap::new();
+        let mut geometries: Vec<&Geometry> = Vec::new();
+        let mut medium_indices = HashMap::new();
+        let mut media: Vec<&Medium> = Vec::new();
+        let objects: Vec<_> = self
+            .objects
+            .iter()
+            .map(|object| {
+                let idx = *material_indices
+                    .entry(Arc::as_ptr(&object.material))
+                    .or_insert_with(|| {
+                        materials.push(&object.material);
+                        materials.len() as u32 - 1
+                    });
+                let geometry = match object.geometry {
+                    Geometry::Instance(ref geometry) => EncodedGeometry::Instance(
+                        *geometry_indices
+                            .entry(Arc::as_ptr(geometry))
+                            .or_insert_with(|| {
+                                geometries.push(geometry);
+                                geometries.len() as u32 - 1
+                            }),
+                    ),
+                    ref geometry => EncodedGeometry::Inline(geometry),
+                };
+                let medium = object.medium.as_ref().map(|medium| {
+                    *medium_indices
+                        .entry(Arc::as_ptr(medium))
+                        .or_insert_with(|| {
+                            media.push(medium);
+                            media.len() as u32 - 1
+                        })
+                });
+                (geometry, idx, &object.transform, &object.motion, medium)
+            })
+            .collect();
+
+        // Images are shared by materials that differ otherwise, so materials
+        // refer to the table of images by index, in the order of their
+        // textures. Their own images are replaced by a cheap placeholder.
+        let placeholder = Arc::new(ImageTexture::new(1, 1, vec![0; 3], false));
+        let materials: Vec<EncodedMaterial> = materials
+            .into_iter()
+            .map(|material| {
+                let mut material = material.clone();
+                let mut indices = Vec::new();
+                material.for_each_image_mut(&mut |image| {
+                    let idx = *image_indices.entry(Arc::as_ptr(image)).or_insert_with(|| {
+                        images.push(image.clone());
+                        images.len() as u32 - 1
+                    });
+                    indices.push(idx);
+                    *image = placeholder.clone();
+                });
+                (material, indices)
+            })
+            .collect();
+
+        self.camera.encode(encoder)?;
+        Compat(self.background).encode(encoder)?;
+        self.environment.encode(encoder)?;
+        self.medium.encode(encoder)?;
+        self.use_bvh.encode(encoder)?;
+        images.encode(encoder)?;
+        materials.encode(encoder)?;
+        geometries.encode(encoder)?;
+        media.encode(encoder)?;
+        objects.encode(encoder)
+    }
+}
+
+impl<Context> Decode<Context> for Scene {
+    fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let camera = Camera::decode(decoder)?;
+        let background = Compat::<Vec3>::decode(decoder)?.0;
+        let environment = Option::<Arc<Environment>>::decode(decoder)?;
+        let medium = Option::<Arc<Medium>>::decode(decoder)?;
+        let use_bvh = bool::decode(decoder)?;
+        let images = Vec::<Arc<ImageTexture>>::decode(decoder)?;
+        let materials: Vec<Arc<Material>> = Vec::<EncodedMaterial>::decode(decoder)?
+            .into_iter()
+            .map(|(mut material, indices)| {
+                let mut indices = indices.into_iter();
+                let mut is_valid = true;
+                material.for_each_image_mut(&mut |image| match indices
+                    .next()
+                    .and_then(|idx| images.get(idx as usize))
+                {
+                    Some(shared) => *image = shared.clone(),
+                    None => is_valid = false,
+                });
+                if !is_valid || indices.next().is_some() {
+                    return Err(DecodeError::Other("Invalid scene image index"));
+                }
+                Ok(Arc::new(material))
+            })
+            .collect::<Result<_, DecodeError>>()?;
+        let geometries: Vec<Arc<Geometry>> = Vec::<Geometry>::decode(decoder)?
+            .into_iter()
+            .map(Arc::new)
+            .collect();
+        let media: Vec<Arc<Medium>> = Vec::<Medium>::decode(decoder)?
+            .into_iter()
+            .map(Arc::new)
+            .collect();
+        let objects =
+            Vec::<EncodedObject<Geometry, Option<Transform>, Option<Motion>>>::decode(decoder)?
+                .into_iter()
+                .map(|(geometry, idx, transform, motion, medium)| {
+                    let material = materials
+                        .get(idx as usize)
+                        .ok_or(DecodeError::Other("Invalid scene material index"))?;
+                    let geometry = match geometry {
+                        EncodedGeometry::Inline(geometry) => geometry,
+                        EncodedGeometry::Instance(idx) => Geometry::Instance(
+                            geometries
+                                .get(idx as usize)
+                                .ok_or(DecodeError::Other("Invalid scene geometry index"))?
+                                .clone(),
+                        ),
+                    };
+                    let medium = medium
+                        .map(|idx| {
+                            media
+                                .get(idx as usize)
+                                .ok_or(DecodeError::Other("Invalid scene medium index"))
+                                .cloned()
+                        })
+                        .transpose()?;
+                    Ok(Arc::new(Model {
+                        geometry,
+                        material: material.clone(),
+                        transform,
+                        motion,
+                        medium,
+                    }))
+                })
+                .collect::<Result<_, DecodeError>>()?;
+
+        let scene = match environment {
+            Some(environment) => Self::try_with_environment(camera, objects, environment),
+            None => Self::try_with_background(camera, objects, background),
+        };
+        let mut scene = scene
+            .and_then(|scene| match medium {
+                Some(medium) => scene.try_with_medium(medium),
+                None => Ok(scene),
+            })
+            .map_err(|err| DecodeError::OtherString(err.to_string()))?;
+        scene.use_bvh = use_bvh;
+        Ok(scene)
+    }
+}
+
+/// Encoded scene material, with the indices of its images in the table of
+/// shared images.
+type EncodedMaterial = (Material, Vec<u32>);
+
+/// Encoded scene object, with its geometry, material index, transform,
+/// motion and medium index.
+type EncodedObject<G, T, M> = (EncodedGeometry<G>, u32, T, M, Option<u32>);
+
+/// Geometry of an encoded scene object. Instanced geometry refers to the
+/// table of shared geometries by index.
+#[derive(Encode, Decode)]
+enum EncodedGeometry<G> {
+    Inline(G),
+    Instance(u32),
+}
+
+bincode::impl_borrow_decode!(Scene);
+
 impl Hittable for Scene {
     fn hit(&self, ray: &Ray) -> Option<Hit> {
         if self.use_bvh {
-            self.bvh.hit(&ray)
+            let mut closest_hit = self.bvh.as_ref().and_then(|bvh| bvh.hit(&ray));
+            for object in self.objects[self.num_bounded..].iter() {
+                let tmax = closest_hit.as_ref().map_or(ray.tmax(), |hit| hit.distance);
+                if let Some(hit) = object.hit(&ray.with_tmax(tmax)) {
+                    closest_hit = Some(hit);
+                }
+            }
+            closest_hit
         } else {
             let mut closest_hit_distance = ray.tmax();
             let mut closest_hit = None;
@@ -0,0 +1,167 @@
+use std::sync::Arc;
+
+use bincode::{Decode, Encode};
+use glam::{Vec2, Vec3};
+use serde::{Deserialize, Serialize};
+
+use crate::raytracer::Ray;
+
+/// Signed distance field given by an expression tree of primitives and
+/// operators. Distances are negative inside the surface. Primitives are
+/// centered at the origin, and placed with `Translate`.
+#[derive(Debug, Clone, Encode, Decode, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Sdf {
+    Sphere {
+        radius: f32,
+    },
+    Box {
+        #[bincode(with_serde)]
+        size: Vec3,
+    },
+    /// Box of outer size `size` whose edges are rounded by `radius`.
+    RoundedBox {
+        #[bincode(with_serde)]
+        size: Vec3,
+        radius: f32,
+    },
+    /// Torus around the y axis.
+    Torus {
+        major_radius: f32,
+        minor_radius: f32,
+    },
+    Translate {
+        #[bincode(with_serde)]
+        offset: Vec3,
+        sdf: Arc<Sdf>,
+    },
+    /// Union blending both surfaces where they are closer than
+    /// `smoothness`.
+    SmoothUnion {
+        left: Arc<Sdf>,
+        right: Arc<Sdf>,
+        smoothness: f32,
+    },
+    /// Rotation around the y axis by `rate` radians per unit of height.
+    Twist {
+        sdf: Arc<Sdf>,
+        rate: f32,
+    },
+    /// Infinite repetition with a cell size of `period` along each axis,
+    /// where zero disables repetition. The shape should fit in one cell.
+    Repeat {
+        sdf: Arc<Sdf>,
+        #[bincode(with_serde)]
+        period: Vec3,
+    },
+}
+
+impl Sdf {
+    /// Maximum number of sphere tracing steps before giving up on a ray.
+    const MAX_STEPS: usize = 512;
+    /// Distance to the surface under which sphere tracing stops.
+    const HIT_DISTANCE: f32 = 1e-4;
+    /// Step used to differentiate the distance field.
+    const NORMAL_DELTA: f32 = 1e-4;
+
+    /// Signed distance from `p` to the surface. Operators other than
+    /// translation and repetition give a lower bound of the distance
+    /// instead, which is still safe for sphere tracing.
+    pub fn distance(&self, p: Vec3) -> f32 {
+        match self {
+            Self::Sphere { radius } => p.length() - radius,
+            Self::Box { size } => box_distance(p, *size / 2.0),
+            Self::RoundedBox { size, radius } => {
+                box_distance(p, *size / 2.0 - Vec3::splat(*radius)) - radius
+            }
+            Self::Torus {
+                major_radius,
+                minor_radius,
+            } => {
+                let q = Vec2::new(Vec2::new(p.x, p.z).length() - major_radius, p.y);
+                q.length() - minor_radius
+            }
+            Self::Translate { offset, sdf } => sdf.distance(p - *offset),
+            Self::SmoothUnion {
+                left,
+                right,
+                smoothness,
+            } => {
+                let (d1, d2) = (left.distance(p), right.distance(p));
+                if *smoothness <= 0.0 {
+                    return d1.min(d2);
+                }
+                // Polynomial smooth minimum
+                let h = (0.5 + 0.5 * (d2 - d1) / smoothness).clamp(0.0, 1.0);
+                d2 + (d1 - d2) * h - smoothness * h * (1.0 - h)
+            }
+            Self::Twist { sdf, rate } => {
+                let (sin, cos) = (rate * p.y).sin_cos();
+                let q = Vec3::new(cos * p.x - sin * p.z, p.y, sin * p.x + cos * p.z);
+                // Twisting stretches distances by up to this factor at the
+                // distance of `p` from the axis
+                let stretch = (1.0 + (rate * Vec2::new(p.x, p.z).length()).powi(2)).sqrt();
+                sdf.distance(q) / stretch
+            }
+            Self::Repeat { sdf, period } => {
+                let cell = Vec3::select(
+                    period.cmpgt(Vec3::ZERO),
+                    *period * (p / *period).round(),
+                    Vec3::ZERO,
+                );
+                sdf.distance(p - cell)
+            }
+        }
+    }
+
+    /// Outward surface normal at `p`, from the gradient of the distance.
+    pub fn normal(&self, p: Vec3) -> Vec3 {
+        [
+            Vec3::new(1.0, -1.0, -1.0),
+            Vec3::new(-1.0, -1.0, 1.0),
+            Vec3::new(-1.0, 1.0, -1.0),
+            Vec3::new(1.0, 1.0, 1.0),
+        ]
+        .into_iter()
+        .map(|k| k * self.distance(p + k * Self::NORMAL_DELTA))
+        .sum::<Vec3>()
+        .normalize_or(Vec3::Y)
+    }
+
+    /// Distance along the ray to the first surface crossing between `tmin`
+    /// and `tmax`, found by sphere tracing. The surface a ray origin lies on
+    /// is ignored when the ray leaves it, so rays spawned at a hit don't
+    /// intersect the surface again.
+    pub fn trace(&self, ray: &Ray, tmin: f32, tmax: f32) -> Option<f32> {
+        let mut t = tmin;
+        // Rays starting inside march on the negated distance. Rays whose
+        // origin is at the surface are inside if they go through it, while
+        // rays entering bounds at the surface hit it right away
+        let start = ray.at(t);
+        let start_distance = self.distance(start);
+        let is_inside = if start_distance.abs() < Ray::MIN_RAY_DISTANCE + Self::HIT_DISTANCE {
+            t <= ray.tmin() && self.normal(start).dot(ray.direction()) < 0.0
+        } else {
+            start_distance < 0.0
+        };
+        let side = if is_inside { -1.0 } else { 1.0 };
+        for _ in 0..Self::MAX_STEPS {
+            if t > tmax {
+                return None;
+            }
+            let p = ray.at(t);
+            let distance = side * self.distance(p);
+            if distance < Self::HIT_DISTANCE && side * self.normal(p).dot(ray.direction()) < 0.0 {
+                return Some(t);
+            }
+            t += distance.max(Self::HIT_DISTANCE);
+        }
+        None
+    }
+}
+
+/// Distance to a box centered at the origin.
+fn box_distance(p: Vec3, half_size: Vec3) -> f32 {
+    let q = p.abs() - half_size;
+    q.max(Vec3::ZERO).length() + q.max_element().min(0.0)
+}
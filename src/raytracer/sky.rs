@@ -0,0 +1,378 @@
+use std::f32::consts::PI;
+use std::ops::RangeInclusive;
+
+use bincode::de::Decoder;
+use bincode::enc::Encoder;
+use bincode::error::{DecodeError, EncodeError};
+use bincode::{Decode, Encode};
+use glam::{Vec2, Vec3};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::raytracer::environment::{equirectangular_direction, luminance};
+use crate::raytracer::{EnvironmentMap, Frame, SceneError, SceneIssue};
+use crate::utils::sample_uniform_cone;
+
+/// Range of turbidities the sky model was fitted for, from a very clear to
+/// a hazy sky.
+pub const TURBIDITY_RANGE: RangeInclusive<f32> = 1.7..=10.0;
+
+/// Angular radius of the sun seen from the earth, in radians.
+pub const SUN_ANGULAR_RADIUS: f32 = 0.004_651;
+
+/// Luminance in kcd/m² corresponding to a radiance of 1, so that a clear sky
+/// is around 0.2 at the zenith.
+const LUMINANCE_UNIT: f32 = 40.0;
+
+/// Luminance of the sun above the atmosphere in kcd/m², giving an
+/// illuminance of 128 klux.
+const SUN_LUMINANCE: f32 = 1.88e6;
+
+/// Wavelengths in micrometers standing for the red, green and blue channels
+/// when attenuating sunlight.
+const RGB_WAVELENGTHS: Vec3 = Vec3::new(0.68, 0.55, 0.44);
+
+/// Resolution of the table of sky radiance used to sample the sky.
+const TABLE_WIDTH: u32 = 64;
+const TABLE_HEIGHT: u32 = 32;
+
+/// Coefficients A to E of the Perez sky distribution function.
+type Perez = [f32; 5];
+
+/// Perez distribution function for a direction at zenith angle cosine
+/// `cos_theta` and at angle `gamma` from the sun.
+fn perez(coefficients: &Perez, cos_theta: f32, gamma: f32) -> f32 {
+    let [a, b, c, d, e] = *coefficients;
+    let cos_gamma = gamma.cos();
+    (1.0 + a * (b / cos_theta).exp()) * (1.0 + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+}
+
+/// Convert a color given by its xy chromaticity and luminance into linear
+/// sRGB. Colors outside of the sRGB gamut are clamped.
+fn xyy_to_rgb(x: f32, y: f32, luminance: f32) -> Vec3 {
+    let xyz = Vec3::new(x / y, 1.0, (1.0 - x - y) / y) * luminance;
+    Vec3::new(
+        Vec3::new(3.2406, -1.5372, -0.4986).dot(xyz),
+        Vec3::new(-0.9689, 1.8758, 0.0415).dot(xyz),
+        Vec3::new(0.0557, -0.2040, 1.0570).dot(xyz),
+    )
+    .max(Vec3::ZERO)
+}
+
+/// `1 - cos` of the angular radius of the sun, precise for its tiny size.
+fn sun_one_minus_cos() -> f32 {
+    2.0 * (SUN_ANGULAR_RADIUS / 2.0).sin().powi(2)
+}
+
+/// Solid angle covered by the sun disc, in steradians.
+pub fn sun_solid_angle() -> f32 {
+    2.0 * PI * sun_one_minus_cos()
+}
+
+/// Clear sky lit by the sun, following the analytic model of Preetham,
+/// Shirley and Smits. The sky radiance only depends on the sun direction and
+/// the turbidity of the atmosphere, so it's cheap to synchronize with peers.
+///
+/// The sun is a disc of the same solid angle as seen from the earth, whose
+/// light is attenuated by the atmosphere as it gets closer to the horizon.
+/// No light comes from below the horizon, which is usually hidden by the
+/// ground. Both the sky and the sun are scaled by `intensity`.
+///
+/// The sun is sampled within its disc, while the sky is sampled from a table
+/// of its radiance, proportionally to their power.
+#[derive(Debug, Clone)]
+pub struct Sky {
+    sun_direction: Vec3,
+    turbidity: f32,
+    intensity: f32,
+    /// Perez coefficients of the luminance and of the x and y chromaticities.
+    perez: [Perez; 3],
+    /// Luminance and chromaticities at the zenith, each divided by its
+    /// distribution function there.
+    zenith: [f32; 3],
+    sun_radiance: Vec3,
+    /// Table of the sky radiance, without the sun, used to sample it.
+    table: EnvironmentMap,
+    /// Probability of sampling the sun rather than the sky.
+    sun_probability: f32,
+}
+
+/// Serde representation of skies.
+#[derive(Serialize, Deserialize)]
+struct SkySource {
+    sun_direction: Vec3,
+    #[serde(default = "default_turbidity")]
+    turbidity: f32,
+    #[serde(default = "default_intensity")]
+    intensity: f32,
+}
+
+fn default_turbidity() -> f32 {
+    3.0
+}
+
+fn default_intensity() -> f32 {
+    1.0
+}
+
+impl Sky {
+    /// Sky lit by the sun in `sun_direction`, which must be above the
+    /// horizon, with a turbidity within `TURBIDITY_RANGE`.
+    pub fn new(sun_direction: Vec3, turbidity: f32) -> Self {
+        // NOTE: Normalizing again may change the last bits, so that decoded
+        // skies would differ from the encoded ones.
+        let sun_direction = if sun_direction.is_normalized() {
+            sun_direction
+        } else {
+            sun_direction.normalize_or_zero()
+        };
+        assert!(
+            sun_direction.y >= 0.0 && TURBIDITY_RANGE.contains(&turbidity),
+            "Sun must be above the horizon and turbidity within [{}, {}]",
+            TURBIDITY_RANGE.start(),
+            TURBIDITY_RANGE.end()
+        );
+
+        let t = turbidity;
+        let coefficients = [
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+        ];
+
+        let theta_sun = sun_direction.y.clamp(-1.0, 1.0).acos();
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_sun);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        // Chromaticities are cubic in the sun zenith angle and quadratic in
+        // the turbidity
+        let chromaticity = |coefficients: [[f32; 4]; 3]| {
+            let [t2, t1, t0] = coefficients
+                .map(|[a, b, c, d]| ((a * theta_sun + b) * theta_sun + c) * theta_sun + d);
+            t2 * t * t + t1 * t + t0
+        };
+        let zenith_x = chromaticity([
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let zenith_y = chromaticity([
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+        let zenith = [zenith_luminance, zenith_x, zenith_y];
+        let zenith = std::array::from_fn(|i| zenith[i] / perez(&coefficients[i], 1.0, theta_sun));
+
+        // Sunlight goes through the relative optical mass of the atmosphere
+        // along the sun direction, where Rayleigh and aerosol scattering
+        // attenuate shorter wavelengths more
+        let elevation = 90.0 - theta_sun.to_degrees();
+        let optical_mass = 1.0 / (sun_direction.y + 0.15 * (elevation + 3.885).powf(-1.253));
+        let beta = 0.04608 * t - 0.04586;
+        let rayleigh = 0.008735 * RGB_WAVELENGTHS.powf(-4.08);
+        let aerosol = beta * RGB_WAVELENGTHS.powf(-1.3);
+        let transmittance = (-(rayleigh + aerosol) * optical_mass).exp();
+        let sun_radiance = transmittance * SUN_LUMINANCE / LUMINANCE_UNIT;
+
+        let mut sky = Self {
+            sun_direction,
+            turbidity,
+            intensity: 1.0,
+            perez: coefficients,
+            zenith,
+            sun_radiance,
+            table: EnvironmentMap::new(1, 1, vec![Vec3::ZERO]),
+            sun_probability: 1.0,
+        };
+        let pixels: Vec<Vec3> = (0..TABLE_HEIGHT)
+            .flat_map(|y| (0..TABLE_WIDTH).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let uv = Vec2::new(
+                    (x as f32 + 0.5) / TABLE_WIDTH as f32,
+                    (y as f32 + 0.5) / TABLE_HEIGHT as f32,
+                );
+                sky.sky_radiance(equirectangular_direction(uv).0)
+            })
+            .collect();
+        // Pixels cover 2 pi^2 sin(theta) / (width * height) steradians
+        let sky_power: f32 = pixels
+            .chunks_exact(TABLE_WIDTH as usize)
+            .enumerate()
+            .map(|(y, row)| {
+                let sin_theta = (PI * (y as f32 + 0.5) / TABLE_HEIGHT as f32).sin();
+                row.iter().map(|pixel| luminance(*pixel)).sum::<f32>() * sin_theta
+            })
+            .sum::<f32>()
+            * 2.0
+            * PI
+            * PI
+            / (TABLE_WIDTH * TABLE_HEIGHT) as f32;
+        let sun_power = luminance(sun_radiance) * sun_solid_angle();
+        sky.table = EnvironmentMap::new(TABLE_WIDTH, TABLE_HEIGHT, pixels);
+        sky.sun_probability = sun_power / (sun_power + sky_power);
+        sky
+    }
+
+    /// Same as `new`, but returns an error for a sun below the horizon or an
+    /// out of range turbidity instead of panicking.
+    pub fn try_new(sun_direction: Vec3, turbidity: f32) -> Result<Self, SceneError> {
+        let is_valid = sun_direction.is_finite()
+            && sun_direction
+                .try_normalize()
+                .is_some_and(|sun| sun.y >= 0.0)
+            && TURBIDITY_RANGE.contains(&turbidity);
+        if !is_valid {
+            return Err(SceneIssue::InvalidSky.into());
+        }
+        Ok(Self::new(sun_direction, turbidity))
+    }
+
+    /// Scale the radiance of the sky and the sun by `intensity`.
+    pub fn with_intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    /// Direction towards the center of the sun.
+    pub fn sun_direction(&self) -> Vec3 {
+        self.sun_direction
+    }
+
+    pub fn turbidity(&self) -> f32 {
+        self.turbidity
+    }
+
+    pub fn intensity(&self) -> f32 {
+        self.intensity
+    }
+
+    /// Radiance of the sun disc, before scaling by the intensity.
+    pub fn sun_radiance(&self) -> Vec3 {
+        self.sun_radiance
+    }
+
+    /// Whether the intensity is finite and not negative.
+    pub fn is_valid(&self) -> bool {
+        self.intensity.is_finite() && self.intensity >= 0.0
+    }
+
+    fn is_in_sun(&self, direction: Vec3) -> bool {
+        // Cross products are more precise than dot products for tiny angles
+        direction.dot(self.sun_direction) > 0.0
+            && direction.cross(self.sun_direction).length_squared()
+                < SUN_ANGULAR_RADIUS.sin().powi(2)
+    }
+
+    /// Radiance of the sky alone, before scaling by the intensity.
+    fn sky_radiance(&self, direction: Vec3) -> Vec3 {
+        if direction.y <= 0.0 {
+            return Vec3::ZERO;
+        }
+        let gamma = direction.dot(self.sun_direction).clamp(-1.0, 1.0).acos();
+        let [luminance, x, y] =
+            std::array::from_fn(|i| self.zenith[i] * perez(&self.perez[i], direction.y, gamma));
+        xyy_to_rgb(x, y, luminance) / LUMINANCE_UNIT
+    }
+
+    /// Radiance arriving from the world space `direction`.
+    pub fn eval(&self, direction: Vec3) -> Vec3 {
+        let sun = if self.is_in_sun(direction) {
+            self.sun_radiance
+        } else {
+            Vec3::ZERO
+        };
+        (self.sky_radiance(direction) + sun) * self.intensity
+    }
+
+    /// Map a uniform sample in [0, 1)^2 to a direction towards either the sun
+    /// or the sky, along with the radiance arriving from it and its solid
+    /// angle density.
+    pub fn sample(&self, u: Vec2) -> (Vec3, Vec3, f32) {
+        // The first sample dimension chooses between the sun and the sky,
+        // then is stretched back to [0, 1)
+        let direction = if u.x < self.sun_probability {
+            let u = Vec2::new(u.x / self.sun_probability, u.y);
+            let local = sample_uniform_cone(u, sun_one_minus_cos());
+            Frame::from_normal(self.sun_direction)
+                .to_world(local)
+                .normalize()
+        } else {
+            let u = Vec2::new(
+                (u.x - self.sun_probability) / (1.0 - self.sun_probability),
+                u.y,
+            );
+            self.table.sample(u).0
+        };
+        (direction, self.eval(direction), self.pdf(direction))
+    }
+
+    /// Solid angle density with which `sample` chooses the world space
+    /// `direction`.
+    pub fn pdf(&self, direction: Vec3) -> f32 {
+        let sun_pdf = if self.is_in_sun(direction) {
+            1.0 / sun_solid_angle()
+        } else {
+            0.0
+        };
+        self.sun_probability * sun_pdf + (1.0 - self.sun_probability) * self.table.pdf(direction)
+    }
+}
+
+// Only the parameters are sent to peers, the model is rebuilt when decoding.
+impl Encode for Sky {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        self.sun_direction.to_array().encode(encoder)?;
+        self.turbidity.encode(encoder)?;
+        self.intensity.encode(encoder)
+    }
+}
+
+impl<Context> Decode<Context> for Sky {
+    fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let sun_direction = Vec3::from_array(<[f32; 3]>::decode(decoder)?);
+        let turbidity = f32::decode(decoder)?;
+        let intensity = f32::decode(decoder)?;
+        let sky = Self::try_new(sun_direction, turbidity)
+            .map_err(|_| DecodeError::Other("Invalid sky parameters"))?;
+        Ok(sky.with_intensity(intensity))
+    }
+}
+
+bincode::impl_borrow_decode!(Sky);
+
+impl Serialize for Sky {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SkySource {
+            sun_direction: self.sun_direction,
+            turbidity: self.turbidity,
+            intensity: self.intensity,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Sky {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let source = SkySource::deserialize(deserializer)?;
+        let sky = Self::try_new(source.sun_direction, source.turbidity)
+            .map_err(|err| serde::de::Error::custom(err.to_string()))?;
+        Ok(sky.with_intensity(source.intensity))
+    }
+}
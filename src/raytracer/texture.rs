@@ -0,0 +1,385 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock};
+
+use bincode::de::Decoder;
+use bincode::error::DecodeError;
+use bincode::{Decode, Encode};
+use glam::{Vec2, Vec3};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Spatially varying material parameter. Color textures return linear RGB
+/// values, while scalar parameters such as roughness read the first channel.
+///
+/// In scene files, constants are written as a plain number or color while
+/// other textures are tables named after their variant.
+#[derive(Debug, Clone, Encode, Decode, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Texture {
+    /// Checkerboard alternating between two textures, with `scale` squares
+    /// per UV unit.
+    Checker {
+        scale: f32,
+        even: Arc<Texture>,
+        odd: Arc<Texture>,
+    },
+    /// Fractal Perlin noise blending between two textures. Noise is a solid
+    /// texture, evaluated at the hit position scaled by `scale`. Only the seed
+    /// is stored, so it's cheap to synchronize with peers.
+    Noise {
+        scale: f32,
+        octaves: u32,
+        seed: u32,
+        low: Arc<Texture>,
+        high: Arc<Texture>,
+    },
+    Image(Arc<ImageTexture>),
+    /// Another texture multiplied by a constant factor.
+    Scale {
+        texture: Arc<Texture>,
+        #[bincode(with_serde)]
+        factor: Vec3,
+    },
+    // NOTE: Untagged variants must come last
+    #[serde(untagged, with = "constant_texture")]
+    Constant(#[bincode(with_serde)] Vec3),
+}
+
+/// Serde representation of constant textures, as a number for grey values.
+mod constant_texture {
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(untagged)]
+    enum Constant {
+        Scalar(f32),
+        Color(Vec3),
+    }
+
+    pub fn serialize<S: Serializer>(value: &Vec3, serializer: S) -> Result<S::Ok, S::Error> {
+        if value.x == value.y && value.y == value.z {
+            Constant::Scalar(value.x).serialize(serializer)
+        } else {
+            Constant::Color(*value).serialize(serializer)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec3, D::Error> {
+        Ok(match Constant::deserialize(deserializer)? {
+            Constant::Scalar(value) => Vec3::splat(value),
+            Constant::Color(value) => value,
+        })
+    }
+}
+
+impl Texture {
+    /// Evaluate the texture at a surface point with coordinates `uv`.
+    pub fn eval(&self, uv: Vec2, position: Vec3) -> Vec3 {
+        match self {
+            Self::Constant(value) => *value,
+            Self::Checker { scale, even, odd } => {
+                let cell = (uv * *scale).floor();
+                if (cell.x + cell.y).rem_euclid(2.0) < 1.0 {
+                    even.eval(uv, position)
+                } else {
+                    odd.eval(uv, position)
+                }
+            }
+            Self::Noise {
+                scale,
+                octaves,
+                seed,
+                low,
+                high,
+            } => {
+                let t = 0.5 + 0.5 * fractal_noise(position * *scale, *octaves, *seed);
+                low.eval(uv, position)
+                    .lerp(high.eval(uv, position), t.clamp(0.0, 1.0))
+            }
+            Self::Image(image) => image.sample(uv),
+            Self::Scale { texture, factor } => *factor * texture.eval(uv, position),
+        }
+    }
+
+    /// Evaluate a scalar texture at a surface point with coordinates `uv`.
+    pub fn eval_scalar(&self, uv: Vec2, position: Vec3) -> f32 {
+        self.eval(uv, position).x
+    }
+
+    /// Whether the texture is zero everywhere. Only constant textures and
+    /// factors are inspected, every other texture is assumed to be non-zero.
+    pub fn is_zero(&self) -> bool {
+        match self {
+            Self::Constant(value) => *value == Vec3::ZERO,
+            Self::Scale { texture, factor } => *factor == Vec3::ZERO || texture.is_zero(),
+            _ => false,
+        }
+    }
+
+    /// Call `f` on every image the texture is made of, in a fixed order.
+    /// Nested textures shared with other ones are cloned first.
+    pub(crate) fn for_each_image_mut(&mut self, f: &mut impl FnMut(&mut Arc<ImageTexture>)) {
+        match self {
+            Self::Checker { even, odd, .. } => {
+                Arc::make_mut(even).for_each_image_mut(f);
+                Arc::make_mut(odd).for_each_image_mut(f);
+            }
+            Self::Noise { low, high, .. } => {
+                Arc::make_mut(low).for_each_image_mut(f);
+                Arc::make_mut(high).for_each_image_mut(f);
+            }
+            Self::Image(image) => f(image),
+            Self::Scale { texture, .. } => Arc::make_mut(texture).for_each_image_mut(f),
+            Self::Constant(_) => {}
+        }
+    }
+}
+
+impl From<Vec3> for Texture {
+    fn from(value: Vec3) -> Self {
+        Self::Constant(value)
+    }
+}
+
+impl From<f32> for Texture {
+    fn from(value: f32) -> Self {
+        Self::Constant(Vec3::splat(value))
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Image texture
+////////////////////////////////////////////////////////////////////////////////
+
+static SRGB_TO_LINEAR: LazyLock<[f32; 256]> = LazyLock::new(|| {
+    std::array::from_fn(|i| {
+        let v = i as f32 / 255.0;
+        if v <= 0.04045 {
+            v / 12.92
+        } else {
+            ((v + 0.055) / 1.055).powf(2.4)
+        }
+    })
+});
+
+/// Bitmap texture, bilinearly filtered and repeated outside of [0, 1]. Pixels
+/// are kept as 8 bit RGB to reduce memory and synchronization costs.
+///
+/// Scene files only reference the image file, so textures which weren't
+/// loaded from a file can't be serialized.
+#[derive(Debug, Clone, Encode)]
+pub struct ImageTexture {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+    /// Whether pixels are sRGB encoded, as usual for colors, or linear, as
+    /// usual for data such as roughness.
+    srgb: bool,
+    /// File the image was loaded from, if any.
+    path: Option<PathBuf>,
+}
+
+/// Serde representation of image textures.
+#[derive(Serialize, Deserialize)]
+struct ImageSource {
+    path: PathBuf,
+    #[serde(default = "default_srgb")]
+    srgb: bool,
+}
+
+fn default_srgb() -> bool {
+    true
+}
+
+impl ImageTexture {
+    pub fn new(width: u32, height: u32, pixels: Vec<u8>, srgb: bool) -> Self {
+        Self::from_parts(width, height, pixels, srgb)
+            .expect("Image texture must have one 8 bit RGB pixel per texel of a non empty image")
+    }
+
+    /// Texture with the given pixels, or `None` if they don't match the size.
+    fn from_parts(width: u32, height: u32, pixels: Vec<u8>, srgb: bool) -> Option<Self> {
+        // NOTE: Sizes are multiplied as u64, and checked since even that
+        // overflows for the largest sizes.
+        let len = (width as u64 * height as u64).checked_mul(3);
+        let is_valid = width > 0 && height > 0 && len == Some(pixels.len() as u64);
+        if !is_valid {
+            return None;
+        }
+        Some(Self {
+            width,
+            height,
+            pixels,
+            srgb,
+            path: None,
+        })
+    }
+
+    pub fn from_image(image: &image::DynamicImage, srgb: bool) -> Self {
+        let rgb = image.to_rgb8();
+        Self::new(rgb.width(), rgb.height(), rgb.into_raw(), srgb)
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P, srgb: bool) -> Result<Self, image::ImageError> {
+        let path = path.as_ref();
+        Ok(Self {
+            path: Some(path.to_owned()),
+            ..Self::from_image(&image::open(path)?, srgb)
+        })
+    }
+
+    /// File the image was loaded from, if any.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    fn texel(&self, x: i64, y: i64) -> Vec3 {
+        let x = x.rem_euclid(self.width as i64) as usize;
+        let y = y.rem_euclid(self.height as i64) as usize;
+        let idx = (y * self.width as usize + x) * 3;
+        let rgb = &self.pixels[idx..idx + 3];
+        if self.srgb {
+            Vec3::new(
+                SRGB_TO_LINEAR[rgb[0] as usize],
+                SRGB_TO_LINEAR[rgb[1] as usize],
+                SRGB_TO_LINEAR[rgb[2] as usize],
+            )
+        } else {
+            Vec3::new(rgb[0] as f32, rgb[1] as f32, rgb[2] as f32) / 255.0
+        }
+    }
+
+    /// Bilinearly filtered lookup. The v coordinate grows upwards, while
+    /// image rows grow downwards.
+    pub fn sample(&self, uv: Vec2) -> Vec3 {
+        let x = uv.x * self.width as f32 - 0.5;
+        let y = (1.0 - uv.y) * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (dx, dy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let top = self.texel(x0, y0).lerp(self.texel(x0 + 1, y0), dx);
+        let bottom = self.texel(x0, y0 + 1).lerp(self.texel(x0 + 1, y0 + 1), dx);
+        top.lerp(bottom, dy)
+    }
+}
+
+impl<Context> Decode<Context> for ImageTexture {
+    fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let width = u32::decode(decoder)?;
+        let height = u32::decode(decoder)?;
+        let pixels = Vec::<u8>::decode(decoder)?;
+        let srgb = bool::decode(decoder)?;
+        let path = Option::<PathBuf>::decode(decoder)?;
+        let texture = Self::from_parts(width, height, pixels, srgb)
+            .ok_or(DecodeError::Other("Invalid image texture data"))?;
+        Ok(Self { path, ..texture })
+    }
+}
+
+bincode::impl_borrow_decode!(ImageTexture);
+
+impl Serialize for ImageTexture {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let Some(path) = &self.path else {
+            return Err(serde::ser::Error::custom(
+                "Image textures without a source file can't be serialized",
+            ));
+        };
+        ImageSource {
+            path: path.clone(),
+            srgb: self.srgb,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ImageTexture {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let source = ImageSource::deserialize(deserializer)?;
+        Self::from_file(&source.path, source.srgb).map_err(|err| {
+            serde::de::Error::custom(format!(
+                "Failed to load texture '{}': {err}",
+                source.path.display()
+            ))
+        })
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Perlin noise
+////////////////////////////////////////////////////////////////////////////////
+
+/// Hash of a lattice point, replacing Perlin's permutation table so that a
+/// noise function is fully described by its seed.
+fn hash_lattice(x: i32, y: i32, z: i32, seed: u32) -> u32 {
+    let mut h = seed
+        ^ (x as u32).wrapping_mul(0x8da6_b343)
+        ^ (y as u32).wrapping_mul(0xd816_3841)
+        ^ (z as u32).wrapping_mul(0xcb1a_b31f);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7feb_352d);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846c_a68b);
+    h ^= h >> 16;
+    h
+}
+
+/// Dot product between the offset and one of Perlin's 12 gradient directions.
+fn gradient(hash: u32, d: Vec3) -> f32 {
+    match hash % 12 {
+        0 => d.x + d.y,
+        1 => -d.x + d.y,
+        2 => d.x - d.y,
+        3 => -d.x - d.y,
+        4 => d.x + d.z,
+        5 => -d.x + d.z,
+        6 => d.x - d.z,
+        7 => -d.x - d.z,
+        8 => d.y + d.z,
+        9 => -d.y + d.z,
+        10 => d.y - d.z,
+        _ => -d.y - d.z,
+    }
+}
+
+/// Ken Perlin's improved gradient noise, roughly within [-1, 1].
+pub fn perlin_noise(p: Vec3, seed: u32) -> f32 {
+    let cell = p.floor();
+    let d = p - cell;
+    let (x, y, z) = (cell.x as i32, cell.y as i32, cell.z as i32);
+    let fade = d.map(|t| t * t * t * (t * (t * 6.0 - 15.0) + 10.0));
+
+    let corner = |cx: i32, cy: i32, cz: i32| {
+        let offset = d - Vec3::new(cx as f32, cy as f32, cz as f32);
+        gradient(hash_lattice(x + cx, y + cy, z + cz, seed), offset)
+    };
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+
+    let x00 = lerp(corner(0, 0, 0), corner(1, 0, 0), fade.x);
+    let x10 = lerp(corner(0, 1, 0), corner(1, 1, 0), fade.x);
+    let x01 = lerp(corner(0, 0, 1), corner(1, 0, 1), fade.x);
+    let x11 = lerp(corner(0, 1, 1), corner(1, 1, 1), fade.x);
+    lerp(lerp(x00, x10, fade.y), lerp(x01, x11, fade.y), fade.z)
+}
+
+/// Sum of `octaves` noise layers, each with double the frequency and half
+/// the amplitude of the previous one.
+pub fn fractal_noise(p: Vec3, octaves: u32, seed: u32) -> f32 {
+    let mut sum = 0.0;
+    let mut amplitude = 1.0;
+    let mut frequency = 1.0;
+    for octave in 0..octaves.max(1) {
+        sum += amplitude * perlin_noise(p * frequency, seed.wrapping_add(octave));
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    sum
+}
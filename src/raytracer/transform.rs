@@ -0,0 +1,235 @@
+use bincode::{Decode, Encode};
+use glam::{Affine3A, BVec3, EulerRot, Mat3, Mat4, Quat, Vec3};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::raytracer::{Aabb, Hit, Ray, SurfaceSample};
+
+/// Affine transform from the object space of a model to world space. Models
+/// are intersected by transforming rays into object space, so their geometry
+/// is never modified.
+///
+/// In scene files, transforms are written either as a `translation`, a
+/// `rotation` in degrees around the x, y and z axes, applied in this order,
+/// and a `scale`, or as a 4x4 `matrix` of 16 values in column major order.
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+pub struct Transform {
+    #[bincode(with_serde)]
+    to_world: Affine3A,
+    #[bincode(with_serde)]
+    to_object: Affine3A,
+}
+
+impl Transform {
+    pub const IDENTITY: Self = Self {
+        to_world: Affine3A::IDENTITY,
+        to_object: Affine3A::IDENTITY,
+    };
+
+    /// Transform from an object to world matrix. Singular matrices give a
+    /// non finite inverse, which is reported by scene validation.
+    pub fn new(to_world: Affine3A) -> Self {
+        Self {
+            to_world,
+            to_object: to_world.inverse(),
+        }
+    }
+
+    pub fn from_translation(translation: Vec3) -> Self {
+        Self::new(Affine3A::from_translation(translation))
+    }
+
+    pub fn from_rotation(rotation: Quat) -> Self {
+        Self::new(Affine3A::from_quat(rotation))
+    }
+
+    pub fn from_scale(scale: Vec3) -> Self {
+        Self::new(Affine3A::from_scale(scale))
+    }
+
+    /// Scale, then rotate, then translate.
+    pub fn from_scale_rotation_translation(scale: Vec3, rotation: Quat, translation: Vec3) -> Self {
+        Self::new(Affine3A::from_scale_rotation_translation(
+            scale,
+            rotation,
+            translation,
+        ))
+    }
+
+    /// Transform applying `self` and then `other`.
+    pub fn then(&self, other: &Transform) -> Self {
+        Self::new(other.to_world * self.to_world)
+    }
+
+    /// Transform between `self`, at `t` = 0, and `other`, at `t` = 1.
+    /// Translations and scales are interpolated linearly and rotations
+    /// spherically, so shear is lost.
+    pub fn interpolate(&self, other: &Transform, t: f32) -> Self {
+        let (scale0, rotation0, translation0) = self.to_world.to_scale_rotation_translation();
+        let (scale1, rotation1, translation1) = other.to_world.to_scale_rotation_translation();
+        Self::from_scale_rotation_translation(
+            scale0.lerp(scale1, t),
+            rotation0.slerp(rotation1, t),
+            translation0.lerp(translation1, t),
+        )
+    }
+
+    pub fn to_world(&self) -> Affine3A {
+        self.to_world
+    }
+
+    pub fn to_object(&self) -> Affine3A {
+        self.to_object
+    }
+
+    /// Whether both the transform and its inverse are finite.
+    pub fn is_invertible(&self) -> bool {
+        self.to_world.is_finite() && self.to_object.is_finite()
+    }
+
+    pub fn point_to_world(&self, point: Vec3) -> Vec3 {
+        self.to_world.transform_point3(point)
+    }
+
+    pub fn vector_to_world(&self, vector: Vec3) -> Vec3 {
+        self.to_world.transform_vector3(vector)
+    }
+
+    /// Normals are transformed by the inverse transpose, which keeps them
+    /// perpendicular to the surface and on the same side of it.
+    pub fn normal_to_world(&self, normal: Vec3) -> Vec3 {
+        (Mat3::from(self.to_object.matrix3).transpose() * normal).normalize()
+    }
+
+    /// Ratio between a world space surface area and the object space area it
+    /// comes from, around a point with outward normal `normal`. It's constant
+    /// over the whole surface for rotations, translations and uniform scales.
+    pub fn area_scale(&self, normal: Vec3) -> f32 {
+        let normal_matrix = Mat3::from(self.to_object.matrix3).transpose();
+        self.to_world.matrix3.determinant().abs() * (normal_matrix * normal).length()
+    }
+
+    /// Ray in object space, along with the object space length of a world
+    /// space unit along the ray, which converts hit distances between spaces.
+    pub fn ray_to_object(&self, ray: &Ray) -> Option<(Ray, f32)> {
+        let direction = self.to_object.transform_vector3(ray.direction());
+        let length = direction.length();
+        if !(length > 0.0 && length.is_finite()) {
+            return None;
+        }
+        let object_ray = Ray::new(
+            self.to_object.transform_point3(ray.origin()),
+            direction / length,
+        )
+        .with_interval(ray.tmin() * length, ray.tmax() * length)
+        .with_time(ray.time());
+        Some((object_ray, length))
+    }
+
+    /// Hit in world space from an object space hit of the ray returned by
+    /// `ray_to_object`, with its `length`.
+    pub fn hit_to_world(&self, hit: Hit, length: f32) -> Hit {
+        Hit {
+            distance: hit.distance / length,
+            position: self.point_to_world(hit.position),
+            normal: self.normal_to_world(hit.normal),
+            shading_normal: self.normal_to_world(hit.shading_normal),
+            dpdu: self.vector_to_world(hit.dpdu),
+            dpdv: self.vector_to_world(hit.dpdv),
+            object_area: hit.object_area * self.area_scale(hit.normal),
+            ..hit
+        }
+    }
+
+    /// Surface sample in world space from an object space sample. The area
+    /// density changes with the area scale.
+    pub fn sample_to_world(&self, sample: SurfaceSample) -> SurfaceSample {
+        SurfaceSample {
+            position: self.point_to_world(sample.position),
+            normal: self.normal_to_world(sample.normal),
+            pdf: sample.pdf / self.area_scale(sample.normal),
+            ..sample
+        }
+    }
+
+    /// World space bounds of object space bounds.
+    pub fn aabb_to_world(&self, aabb: &Aabb) -> Aabb {
+        let (min, max) = (aabb.min_position, aabb.max_position);
+        let corners = (0..8).map(|corner| {
+            let select = |axis: usize| corner & (1 << axis) != 0;
+            let mask = BVec3::new(select(0), select(1), select(2));
+            self.point_to_world(Vec3::select(mask, max, min))
+        });
+        let (min, max) = corners.fold((Vec3::INFINITY, Vec3::NEG_INFINITY), |(min, max), p| {
+            (min.min(p), max.max(p))
+        });
+        Aabb::from_positions(min, max)
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+/// Serde representation of transforms.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+enum TransformRepr {
+    Matrix {
+        matrix: Mat4,
+    },
+    Components {
+        #[serde(default)]
+        translation: Vec3,
+        #[serde(default)]
+        rotation: Vec3,
+        #[serde(default = "default_scale")]
+        scale: Vec3,
+    },
+}
+
+fn default_scale() -> Vec3 {
+    Vec3::ONE
+}
+
+impl Serialize for Transform {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // Transforms with shear can't be written as components
+        let (scale, rotation, translation) = self.to_world.to_scale_rotation_translation();
+        let components = Affine3A::from_scale_rotation_translation(scale, rotation, translation);
+        let repr = if components.abs_diff_eq(self.to_world, 1e-5) {
+            let (z, y, x) = rotation.to_euler(EulerRot::ZYX);
+            TransformRepr::Components {
+                translation,
+                rotation: Vec3::new(x, y, z).map(f32::to_degrees),
+                scale,
+            }
+        } else {
+            TransformRepr::Matrix {
+                matrix: self.to_world.into(),
+            }
+        };
+        repr.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Transform {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(match TransformRepr::deserialize(deserializer)? {
+            TransformRepr::Matrix { matrix } => Self::new(Affine3A::from_mat4(matrix)),
+            TransformRepr::Components {
+                translation,
+                rotation,
+                scale,
+            } => {
+                let rotation = rotation.map(f32::to_radians);
+                Self::from_scale_rotation_translation(
+                    scale,
+                    Quat::from_euler(EulerRot::ZYX, rotation.z, rotation.y, rotation.x),
+                    translation,
+                )
+            }
+        })
+    }
+}
@@ -0,0 +1,254 @@
+use std::fmt;
+
+use thiserror::Error;
+
+use crate::raytracer::{Geometry, Material, Model};
+
+/// Valid range of refraction indices, from air to the densest common
+/// dielectrics.
+pub const IOR_RANGE: std::ops::RangeInclusive<f32> = 1.0..=4.0;
+
+/// Single problem found while validating a scene or constructor arguments.
+/// Object problems refer to the index of the object in the list given to the
+/// scene.
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum SceneIssue {
+    #[error("Camera position must be finite")]
+    NonFiniteCameraPosition,
+    #[error("Camera vectors must be normalized")]
+    NonNormalizedCameraVectors,
+    #[error("Camera forward and up vectors must not be parallel")]
+    ParallelCameraVectors,
+    #[error("Invalid field of view {0}, must be within ]0, 180[ degrees")]
+    InvalidFov(f32),
+    #[error("Invalid fisheye field of view {0}, must be within ]0, 360] degrees")]
+    InvalidFisheyeFov(f32),
+    #[error("Invalid orthographic viewport height {0}, must be positive")]
+    InvalidViewportHeight(f32),
+    #[error("Invalid aspect ratio {0}, must be positive")]
+    InvalidAspectRatio(f32),
+    #[error(
+        "Camera aperture radius must not be negative, its focus distance positive and its blades zero or at least 3"
+    )]
+    InvalidAperture,
+    #[error("Invalid shutter interval from {open} to {close}, must be finite and not reversed")]
+    InvalidShutter { open: f32, close: f32 },
+    #[error("Ray direction must be normalized")]
+    NonNormalizedRayDirection,
+    #[error("Aabb size must be positive")]
+    NegativeAabbSize,
+    #[error("Cannot create a BVH with 0 elements")]
+    EmptyBvh,
+    #[error("Background color must be finite")]
+    NonFiniteBackground,
+    #[error("Environment map rotation must be finite")]
+    NonFiniteEnvironmentRotation,
+    #[error(
+        "Sky sun must be above the horizon, turbidity within [1.7, 10] and intensity finite and not negative"
+    )]
+    InvalidSky,
+    #[error("Scene has no objects")]
+    NoObjects,
+    #[error("Object {object}: Geometry has NaN or infinite values")]
+    NonFiniteGeometry { object: usize },
+    #[error("Object {object}: Radius {radius} must be positive")]
+    NonPositiveRadius { object: usize, radius: f32 },
+    #[error("Object {object}: Quad edges are degenerate")]
+    DegenerateQuad { object: usize },
+    #[error("Object {object}: Cuboid size must be positive")]
+    NonPositiveCuboidSize { object: usize },
+    #[error("Object {object}: Normal or axis must not be zero")]
+    ZeroDirection { object: usize },
+    #[error("Object {object}: SDF bounds must be finite and not empty")]
+    InvalidSdfBounds { object: usize },
+    #[error(
+        "Object {object}: Refraction index {ior} is out of range [{}, {}]",
+        IOR_RANGE.start(),
+        IOR_RANGE.end()
+    )]
+    InvalidIor { object: usize, ior: f32 },
+    #[error("Object {object}: Transform must be finite and invertible")]
+    NonInvertibleTransform { object: usize },
+    #[error(
+        "Object {object}: Motion must have keyframes of increasing finite times and invertible transforms"
+    )]
+    InvalidMotion { object: usize },
+    #[error(
+        "Object {object}: Medium coefficients must be finite and not negative, its asymmetry within ]-1, 1[ and its bounds not empty"
+    )]
+    InvalidMedium { object: usize },
+    #[error(
+        "Scene medium coefficients must be finite and not negative, its asymmetry within ]-1, 1[ and its bounds not empty"
+    )]
+    InvalidFog,
+    #[error("Scene has no emissive objects and a black background")]
+    NoEmitters,
+}
+
+/// Every problem found in a scene, see `Scene::validate`.
+#[derive(Debug, Clone, PartialEq, Error)]
+pub struct SceneError {
+    pub issues: Vec<SceneIssue>,
+}
+
+impl SceneError {
+    /// Error listing `issues`, if there are any.
+    pub fn check(issues: Vec<SceneIssue>) -> Result<(), Self> {
+        if issues.is_empty() {
+            Ok(())
+        } else {
+            Err(Self { issues })
+        }
+    }
+}
+
+impl From<SceneIssue> for SceneError {
+    fn from(issue: SceneIssue) -> Self {
+        Self {
+            issues: vec![issue],
+        }
+    }
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid scene")?;
+        for issue in self.issues.iter() {
+            write!(f, "\n  - {issue}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Problems of the objects of a scene and of its lighting, `is_background_lit`
+/// telling whether light arrives from outside the scene.
+pub(crate) fn objects_issues(
+    objects: &[impl AsRef<Model>],
+    is_background_lit: bool,
+) -> Vec<SceneIssue> {
+    let mut issues = Vec::new();
+    if objects.is_empty() {
+        issues.push(SceneIssue::NoObjects);
+    }
+    for (object, model) in objects.iter().enumerate() {
+        geometry_issues(object, &model.as_ref().geometry, &mut issues);
+        if let Some(transform) = &model.as_ref().transform
+            && !transform.is_invertible()
+        {
+            issues.push(SceneIssue::NonInvertibleTransform { object });
+        }
+        if let Some(motion) = &model.as_ref().motion
+            && !motion.is_valid()
+        {
+            issues.push(SceneIssue::InvalidMotion { object });
+        }
+        if let Some(medium) = &model.as_ref().medium
+            && !medium.is_valid()
+        {
+            issues.push(SceneIssue::InvalidMedium { object });
+        }
+        if let Some(ior) = refraction_index(&model.as_ref().material)
+            && !IOR_RANGE.contains(&ior)
+        {
+            issues.push(SceneIssue::InvalidIor { object, ior });
+        }
+    }
+    let has_emitters = objects
+        .iter()
+        .any(|model| model.as_ref().material.is_emissive());
+    if !has_emitters && !is_background_lit {
+        issues.push(SceneIssue::NoEmitters);
+    }
+    issues
+}
+
+fn geometry_issues(object: usize, geometry: &Geometry, issues: &mut Vec<SceneIssue>) {
+    let is_finite = match geometry {
+        Geometry::Instance(geometry) => return geometry_issues(object, geometry, issues),
+        Geometry::Csg { left, right, .. } => {
+            geometry_issues(object, left, issues);
+            return geometry_issues(object, right, issues);
+        }
+        Geometry::Sphere { position, radius } => position.is_finite() && radius.is_finite(),
+        Geometry::Quad { position, u, v } => position.is_finite() && u.is_finite() && v.is_finite(),
+        Geometry::Cuboid { position, size } => position.is_finite() && size.is_finite(),
+        Geometry::Disk {
+            position,
+            normal: direction,
+            radius,
+        }
+        | Geometry::Cylinder {
+            position,
+            axis: direction,
+            radius,
+        }
+        | Geometry::Cone {
+            position,
+            axis: direction,
+            radius,
+        } => position.is_finite() && direction.is_finite() && radius.is_finite(),
+        Geometry::Plane { position, normal } => position.is_finite() && normal.is_finite(),
+        Geometry::Sdf { bounds, .. } => {
+            let is_valid = bounds.min_position.is_finite()
+                && bounds.max_position.is_finite()
+                && bounds.min_position.cmple(bounds.max_position).all();
+            if !is_valid {
+                issues.push(SceneIssue::InvalidSdfBounds { object });
+            }
+            return;
+        }
+        Geometry::Mesh(mesh) => mesh.positions().iter().all(|position| position.is_finite()),
+    };
+    if !is_finite {
+        issues.push(SceneIssue::NonFiniteGeometry { object });
+        return;
+    }
+
+    match geometry {
+        Geometry::Sphere { radius, .. }
+        | Geometry::Disk { radius, .. }
+        | Geometry::Cylinder { radius, .. }
+        | Geometry::Cone { radius, .. }
+            if *radius <= 0.0 =>
+        {
+            issues.push(SceneIssue::NonPositiveRadius {
+                object,
+                radius: *radius,
+            });
+        }
+        Geometry::Quad { u, v, .. } if u.cross(*v).length() <= 1e-6 * u.length() * v.length() => {
+            issues.push(SceneIssue::DegenerateQuad { object });
+        }
+        Geometry::Cuboid { size, .. } if size.min_element() <= 0.0 => {
+            issues.push(SceneIssue::NonPositiveCuboidSize { object });
+        }
+        Geometry::Disk {
+            normal: direction, ..
+        }
+        | Geometry::Cylinder {
+            axis: direction, ..
+        }
+        | Geometry::Cone {
+            axis: direction, ..
+        }
+        | Geometry::Plane {
+            normal: direction, ..
+        } if direction.length_squared() <= 0.0 => {
+            issues.push(SceneIssue::ZeroDirection { object });
+        }
+        _ => {}
+    }
+}
+
+/// Refraction index of transmissive materials.
+fn refraction_index(material: &Material) -> Option<f32> {
+    match material {
+        Material::Dielectric { refraction_index }
+        | Material::RoughDielectric {
+            refraction_index, ..
+        } => Some(*refraction_index),
+        Material::Principled(params) if params.transmission > 0.0 => Some(params.ior),
+        Material::NormalMapped { material, .. } => refraction_index(material),
+        _ => None,
+    }
+}
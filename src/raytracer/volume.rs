@@ -0,0 +1,352 @@
+use std::path::{Path, PathBuf};
+
+use bincode::de::Decoder;
+use bincode::enc::Encoder;
+use bincode::error::{DecodeError, EncodeError};
+use bincode::{Decode, Encode};
+use glam::{IVec3, UVec3, Vec3};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use thiserror::Error;
+
+/// Number of voxels along each axis of a majorant grid cell.
+const MAJORANT_CELL_VOXELS: u32 = 8;
+
+#[derive(Debug, Error)]
+pub enum VolumeError {
+    #[error("Failed to read '{}': {source}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("{}: {message}", path.display())]
+    Format { path: PathBuf, message: String },
+}
+
+/// Dense grid of densities, such as smoke or a cloud, spanning [0, 1]^3.
+/// Densities are stored at voxel centers and trilinearly interpolated.
+///
+/// A coarse grid of the maximum density around each block of voxels bounds
+/// the density along rays, so that empty regions are skipped quickly while
+/// tracking them.
+///
+/// Scene files only reference the grid file, so grids which weren't loaded
+/// from a file can't be serialized.
+#[derive(Debug, Clone)]
+pub struct VoxelGrid {
+    resolution: UVec3,
+    /// Densities with x varying fastest, then y, then z.
+    densities: Vec<f32>,
+    majorant_resolution: UVec3,
+    majorants: Vec<f32>,
+    /// File the grid was loaded from, if any.
+    path: Option<PathBuf>,
+}
+
+/// Serde representation of voxel grids.
+#[derive(Serialize, Deserialize)]
+struct GridSource {
+    path: PathBuf,
+}
+
+impl VoxelGrid {
+    pub fn new(resolution: UVec3, densities: Vec<f32>) -> Self {
+        Self::from_parts(resolution, densities).expect(
+            "Voxel grid must have one finite and non negative density per voxel of a non empty grid",
+        )
+    }
+
+    /// Grid with the given densities, or `None` if they don't match the
+    /// resolution or aren't finite and non negative.
+    fn from_parts(resolution: UVec3, densities: Vec<f32>) -> Option<Self> {
+        let is_valid = resolution.min_element() > 0
+            && (resolution.x as u64 * resolution.y as u64).checked_mul(resolution.z as u64)
+                == Some(densities.len() as u64)
+            && densities
+                .iter()
+                .all(|density| density.is_finite() && *density >= 0.0);
+        if !is_valid {
+            return None;
+        }
+
+        let majorant_resolution = resolution.map(|res| res.div_ceil(MAJORANT_CELL_VOXELS));
+        let mut grid = Self {
+            resolution,
+            densities,
+            majorant_resolution,
+            majorants: Vec::new(),
+            path: None,
+        };
+        grid.majorants = grid.compute_majorants();
+        Some(grid)
+    }
+
+    /// Load a grid from a Mitsuba `.vol` file of 32 bit float voxels. Only
+    /// the first channel is used as density, and the bounding box of the
+    /// file is ignored.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, VolumeError> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path).map_err(|source| VolumeError::Io {
+            path: path.to_owned(),
+            source,
+        })?;
+        let format_error = |message: &str| VolumeError::Format {
+            path: path.to_owned(),
+            message: message.to_owned(),
+        };
+
+        const HEADER_SIZE: usize = 48;
+        if bytes.len() < HEADER_SIZE || &bytes[0..3] != b"VOL" {
+            return Err(format_error("not a volume file"));
+        }
+        if bytes[3] != 3 {
+            return Err(format_error("unsupported version, expected 3"));
+        }
+        let int = |offset: usize| {
+            i32::from_le_bytes(bytes[offset..offset + 4].try_into().expect("4 bytes"))
+        };
+        if int(4) != 1 {
+            return Err(format_error("unsupported encoding, expected 32 bit floats"));
+        }
+        let [x, y, z, channels] = [int(8), int(12), int(16), int(20)];
+        if x <= 0 || y <= 0 || z <= 0 || channels <= 0 {
+            return Err(format_error("resolution and channels must be positive"));
+        }
+        let resolution = UVec3::new(x as u32, y as u32, z as u32);
+        let channels = channels as usize;
+        // NOTE: Sizes are multiplied as u64, and checked since even that
+        // overflows for the largest headers.
+        let data_size = (x as u64 * y as u64)
+            .checked_mul(z as u64)
+            .and_then(|num_voxels| num_voxels.checked_mul(channels as u64 * 4))
+            .ok_or_else(|| format_error("resolution is too large"))?;
+        // The header was checked to fit in the file
+        if (bytes.len() - HEADER_SIZE) as u64 != data_size {
+            return Err(format_error("data size doesn't match the resolution"));
+        }
+
+        let densities = bytes[HEADER_SIZE..]
+            .chunks_exact(4 * channels)
+            .map(|voxel| f32::from_le_bytes(voxel[0..4].try_into().expect("4 bytes")))
+            .collect();
+        let grid = Self::from_parts(resolution, densities)
+            .ok_or_else(|| format_error("densities must be finite and non negative"))?;
+        Ok(Self {
+            path: Some(path.to_owned()),
+            ..grid
+        })
+    }
+
+    /// File the grid was loaded from, if any.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn resolution(&self) -> UVec3 {
+        self.resolution
+    }
+
+    fn voxel(&self, voxel: IVec3) -> f32 {
+        let voxel = voxel
+            .clamp(IVec3::ZERO, self.resolution.as_ivec3() - 1)
+            .as_uvec3();
+        self.densities
+            [((voxel.z * self.resolution.y + voxel.y) * self.resolution.x + voxel.x) as usize]
+    }
+
+    /// Trilinearly interpolated density at a position within [0, 1]^3.
+    pub fn density(&self, p: Vec3) -> f32 {
+        let x = p * self.resolution.as_vec3() - 0.5;
+        let x0 = x.floor();
+        let d = x - x0;
+        let v0 = x0.as_ivec3();
+
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let row = |y: i32, z: i32| {
+            lerp(
+                self.voxel(v0 + IVec3::new(0, y, z)),
+                self.voxel(v0 + IVec3::new(1, y, z)),
+                d.x,
+            )
+        };
+        lerp(
+            lerp(row(0, 0), row(1, 0), d.y),
+            lerp(row(0, 1), row(1, 1), d.y),
+            d.z,
+        )
+    }
+
+    /// Maximum density of each majorant grid cell, including every voxel the
+    /// interpolation reads from within the cell.
+    fn compute_majorants(&self) -> Vec<f32> {
+        let res = self.resolution.as_vec3();
+        let cell_size = res / self.majorant_resolution.as_vec3();
+        let mut majorants = Vec::with_capacity(self.majorant_resolution.element_product() as usize);
+        for z in 0..self.majorant_resolution.z {
+            for y in 0..self.majorant_resolution.y {
+                for x in 0..self.majorant_resolution.x {
+                    let cell = UVec3::new(x, y, z).as_vec3();
+                    let min = (cell * cell_size - 0.5).floor().as_ivec3();
+                    let max = ((cell + 1.0) * cell_size - 0.5).floor().as_ivec3() + 1;
+                    let mut majorant = 0.0f32;
+                    for vz in min.z..=max.z {
+                        for vy in min.y..=max.y {
+                            for vx in min.x..=max.x {
+                                majorant = majorant.max(self.voxel(IVec3::new(vx, vy, vz)));
+                            }
+                        }
+                    }
+                    majorants.push(majorant);
+                }
+            }
+        }
+        majorants
+    }
+
+    /// Segments of a ray within [0, 1]^3 crossing the majorant grid cells, as
+    /// `(tmin, tmax, max_density)`. The ray is given in grid space and its
+    /// direction doesn't need to be normalized.
+    pub(crate) fn majorants(
+        &self,
+        origin: Vec3,
+        direction: Vec3,
+        tmin: f32,
+        tmax: f32,
+    ) -> MajorantTraversal<'_> {
+        MajorantTraversal::new(self, origin, direction, tmin, tmax)
+    }
+}
+
+/// Walk along a ray through the cells of a majorant grid, following Amanatides
+/// and Woo's voxel traversal.
+pub(crate) struct MajorantTraversal<'a> {
+    grid: &'a VoxelGrid,
+    cell: IVec3,
+    step: IVec3,
+    /// Distance at which the ray crosses the next cell boundary of each axis.
+    next_t: Vec3,
+    delta_t: Vec3,
+    t: f32,
+    tmax: f32,
+}
+
+impl<'a> MajorantTraversal<'a> {
+    fn new(grid: &'a VoxelGrid, origin: Vec3, direction: Vec3, tmin: f32, tmax: f32) -> Self {
+        // Cells have unit size in this space
+        let res = grid.majorant_resolution.as_vec3();
+        let origin = origin * res;
+        let direction = direction * res;
+
+        let (mut t_enter, mut t_exit) = (tmin, tmax);
+        for axis in 0..3 {
+            if direction[axis] == 0.0 {
+                if !(0.0..=res[axis]).contains(&origin[axis]) {
+                    t_exit = f32::NEG_INFINITY;
+                }
+            } else {
+                let t0 = -origin[axis] / direction[axis];
+                let t1 = (res[axis] - origin[axis]) / direction[axis];
+                t_enter = t_enter.max(t0.min(t1));
+                t_exit = t_exit.min(t0.max(t1));
+            }
+        }
+
+        let cell = (origin + direction * t_enter)
+            .floor()
+            .as_ivec3()
+            .clamp(IVec3::ZERO, grid.majorant_resolution.as_ivec3() - 1);
+        let step = IVec3::from_array(direction.to_array().map(|d| {
+            if d > 0.0 {
+                1
+            } else if d < 0.0 {
+                -1
+            } else {
+                0
+            }
+        }));
+        let next_boundary = cell.as_vec3() + step.max(IVec3::ZERO).as_vec3();
+        let next_t = Vec3::select(
+            direction.cmpne(Vec3::ZERO),
+            (next_boundary - origin) / direction,
+            Vec3::INFINITY,
+        );
+        let delta_t = Vec3::select(
+            direction.cmpne(Vec3::ZERO),
+            direction.abs().recip(),
+            Vec3::INFINITY,
+        );
+        Self {
+            grid,
+            cell,
+            step,
+            next_t,
+            delta_t,
+            t: t_enter,
+            tmax: t_exit,
+        }
+    }
+}
+
+impl Iterator for MajorantTraversal<'_> {
+    type Item = (f32, f32, f32);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.t >= self.tmax {
+            return None;
+        }
+        let axis = self.next_t.min_position();
+        let t_end = self.next_t[axis].clamp(self.t, self.tmax);
+        let res = self.grid.majorant_resolution;
+        let cell = self.cell.as_uvec3();
+        let majorant = self.grid.majorants[((cell.z * res.y + cell.y) * res.x + cell.x) as usize];
+        let segment = (self.t, t_end, majorant);
+
+        self.t = t_end;
+        self.cell[axis] += self.step[axis];
+        self.next_t[axis] += self.delta_t[axis];
+        if self.cell[axis] < 0 || self.cell[axis] >= res[axis] as i32 {
+            self.tmax = self.t;
+        }
+        Some(segment)
+    }
+}
+
+// Only the densities are sent to peers, majorants are rebuilt when decoding.
+impl Encode for VoxelGrid {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        self.resolution.to_array().encode(encoder)?;
+        self.densities.encode(encoder)?;
+        self.path.encode(encoder)
+    }
+}
+
+impl<Context> Decode<Context> for VoxelGrid {
+    fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let resolution = UVec3::from_array(<[u32; 3]>::decode(decoder)?);
+        let densities = Vec::<f32>::decode(decoder)?;
+        let path = Option::<PathBuf>::decode(decoder)?;
+        let grid = Self::from_parts(resolution, densities)
+            .ok_or(DecodeError::Other("Invalid voxel grid data"))?;
+        Ok(Self { path, ..grid })
+    }
+}
+
+bincode::impl_borrow_decode!(VoxelGrid);
+
+impl Serialize for VoxelGrid {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let Some(path) = &self.path else {
+            return Err(serde::ser::Error::custom(
+                "Voxel grids without a source file can't be serialized",
+            ));
+        };
+        GridSource { path: path.clone() }.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for VoxelGrid {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let source = GridSource::deserialize(deserializer)?;
+        Self::from_file(&source.path).map_err(|err| serde::de::Error::custom(err.to_string()))
+    }
+}
@@ -1,10 +1,14 @@
 use core::f32;
 use std::sync::Arc;
 
-use glam::Vec3;
+use glam::{Quat, UVec3, Vec3};
 use rand::Rng;
 
-use crate::raytracer::{Camera, Geometry, Material, Model, Scene};
+use crate::raytracer::{
+    Aabb, Aperture, Camera, CsgOperation, EnvironmentMap, Geometry, ImageTexture, Keyframe,
+    Material, Medium, Mesh, Model, Motion, NormalMap, Principled, Scene, Sdf, Sky, Texture,
+    Transform, VoxelGrid, fractal_noise,
+};
 
 pub fn spheres_scene(cam_aspect_ratio: f32) -> Scene {
     // Spheres
@@ -27,17 +31,17 @@ pub fn spheres_scene(cam_aspect_ratio: f32) -> Scene {
 
     // Materials
     let ground_mat = Arc::new(Material::Diffuse {
-        albedo: Vec3::new(0.05, 0.05, 0.05),
+        albedo: Vec3::new(0.05, 0.05, 0.05).into(),
     });
     let center_mat = Arc::new(Material::Diffuse {
-        albedo: Vec3::new(0.1, 0.2, 0.5),
+        albedo: Vec3::new(0.1, 0.2, 0.5).into(),
     });
     let left_mat = Arc::new(Material::Dielectric {
         refraction_index: 1.5,
     });
     let right_mat = Arc::new(Material::Metalic {
-        albedo: Vec3::new(0.8, 0.6, 0.2),
-        fuzzyness: 0.0,
+        albedo: Vec3::new(0.8, 0.6, 0.2).into(),
+        fuzzyness: 0.0.into(),
     });
 
     // Scene
@@ -50,22 +54,10 @@ pub fn spheres_scene(cam_aspect_ratio: f32) -> Scene {
             cam_aspect_ratio,
         ),
         vec![
-            Arc::new(Model {
-                geometry: sphere_left,
-                material: left_mat.clone(),
-            }),
-            Arc::new(Model {
-                geometry: sphere_center,
-                material: center_mat.clone(),
-            }),
-            Arc::new(Model {
-                geometry: sphere_right,
-                material: right_mat.clone(),
-            }),
-            Arc::new(Model {
-                geometry: sphere_ground,
-                material: ground_mat.clone(),
-            }),
+            Arc::new(Model::new(sphere_left, left_mat.clone())),
+            Arc::new(Model::new(sphere_center, center_mat.clone())),
+            Arc::new(Model::new(sphere_right, right_mat.clone())),
+            Arc::new(Model::new(sphere_ground, ground_mat.clone())),
         ],
         Vec3::new(0.70, 0.80, 1.00),
     )
@@ -75,15 +67,15 @@ pub fn spheres2_scene(cam_aspect_ratio: f32) -> Scene {
     let mut objects = Vec::new();
 
     // Ground sphere
-    objects.push(Arc::new(Model {
-        geometry: Geometry::Sphere {
+    objects.push(Arc::new(Model::new(
+        Geometry::Sphere {
             position: Vec3::new(0.0, -1000.5, -1.0),
             radius: 1000.0,
         },
-        material: Arc::new(Material::Diffuse {
-            albedo: Vec3::new(0.42, 0.42, 0.6),
+        Arc::new(Material::Diffuse {
+            albedo: Vec3::new(0.42, 0.42, 0.6).into(),
         }),
-    }));
+    )));
 
     let mut random_circle = |radius: f32, count: usize, mat: Arc<Material>| {
         for i in 0..count {
@@ -91,13 +83,13 @@ pub fn spheres2_scene(cam_aspect_ratio: f32) -> Scene {
 
             let x = radius * f32::sin(ang);
             let z = radius * f32::cos(ang);
-            objects.push(Arc::new(Model {
-                geometry: Geometry::Sphere {
+            objects.push(Arc::new(Model::new(
+                Geometry::Sphere {
                     position: Vec3 { x, y: 0.0, z },
                     radius: 0.5,
                 },
-                material: mat.clone(),
-            }));
+                mat.clone(),
+            )));
         }
     };
 
@@ -108,7 +100,8 @@ pub fn spheres2_scene(cam_aspect_ratio: f32) -> Scene {
                 rng.random_range(0f32..=1f32),
                 rng.random_range(0f32..=1f32),
                 rng.random_range(0f32..=1f32),
-            ),
+            )
+            .into(),
         })
     };
     let random_dialetric = || {
@@ -124,8 +117,9 @@ pub fn spheres2_scene(cam_aspect_ratio: f32) -> Scene {
                 rng.random_range(0f32..=1f32),
                 rng.random_range(0f32..=1f32),
                 rng.random_range(0f32..=1f32),
-            ),
-            fuzzyness: rng.random_range(0f32..=1f32),
+            )
+            .into(),
+            fuzzyness: rng.random_range(0f32..=1f32).into(),
         })
     };
     let random_mat = || {
@@ -160,23 +154,317 @@ pub fn spheres2_scene(cam_aspect_ratio: f32) -> Scene {
     )
 }
 
+pub fn depth_of_field_scene(cam_aspect_ratio: f32) -> Scene {
+    let ground_mat = Arc::new(Material::Diffuse {
+        albedo: Vec3::new(0.4, 0.4, 0.4).into(),
+    });
+    let light_mat = Arc::new(Material::DiffuseLight {
+        emission: Vec3::new(8.0, 6.0, 4.0).into(),
+    });
+    let mut objects = vec![Arc::new(Model::new(
+        Geometry::Sphere {
+            position: Vec3::new(0.0, -1000.5, 0.0),
+            radius: 1000.0,
+        },
+        ground_mat,
+    ))];
+
+    // Row of spheres going away from the camera, with the middle one in focus
+    for i in 0..7 {
+        let albedo = Vec3::new(0.8, 0.3, 0.1).lerp(Vec3::new(0.1, 0.3, 0.8), i as f32 / 6.0);
+        objects.push(Arc::new(Model::new(
+            Geometry::Sphere {
+                position: Vec3::new(i as f32 * 0.6 - 1.8, 0.0, -2.0 * i as f32),
+                radius: 0.5,
+            },
+            Arc::new(Material::Diffuse {
+                albedo: albedo.into(),
+            }),
+        )));
+    }
+
+    // Small lights in the background, blurred into hexagons
+    for i in 0..12 {
+        objects.push(Arc::new(Model::new(
+            Geometry::Sphere {
+                position: Vec3::new(i as f32 * 1.5 - 8.0, 1.0 + (i % 3) as f32, -25.0),
+                radius: 0.1,
+            },
+            light_mat.clone(),
+        )));
+    }
+
+    let camera = Camera::new(
+        Vec3::new(0.0, 1.0, 5.0),
+        Vec3::new(0.0, -0.1, -1.0).normalize(),
+        Vec3::new(0.0, -1.0, 0.0),
+        40.0,
+        cam_aspect_ratio,
+    )
+    .with_aperture(Aperture {
+        radius: 0.15,
+        focus_distance: 11.0,
+        blades: 6,
+        rotation: 15.0,
+    });
+    Scene::with_background(camera, objects, Vec3::new(0.1, 0.1, 0.15))
+}
+
+pub fn motion_blur_scene(cam_aspect_ratio: f32) -> Scene {
+    let ground_mat = Arc::new(Material::Diffuse {
+        albedo: Texture::Checker {
+            scale: 20.0,
+            even: Arc::new(Vec3::splat(0.2).into()),
+            odd: Arc::new(Vec3::splat(0.8).into()),
+        },
+    });
+    let red_mat = Arc::new(Material::Diffuse {
+        albedo: Vec3::new(0.8, 0.1, 0.1).into(),
+    });
+    let blue_mat = Arc::new(Material::Diffuse {
+        albedo: Vec3::new(0.1, 0.2, 0.8).into(),
+    });
+    let light_mat = Arc::new(Material::DiffuseLight {
+        emission: Vec3::splat(4.0).into(),
+    });
+
+    let objects = vec![
+        Arc::new(Model::new(
+            Geometry::Quad {
+                position: Vec3::new(-10.0, 0.0, 10.0),
+                u: Vec3::new(20.0, 0.0, 0.0),
+                v: Vec3::new(0.0, 0.0, -20.0),
+            },
+            ground_mat,
+        )),
+        // Sphere crossing the view
+        Arc::new(
+            Model::new(
+                Geometry::Sphere {
+                    position: Vec3::new(-1.5, 0.5, 0.0),
+                    radius: 0.5,
+                },
+                red_mat.clone(),
+            )
+            .with_motion(Motion::Linear {
+                start: Box::new(Transform::IDENTITY),
+                end: Box::new(Transform::from_translation(Vec3::new(1.0, 0.0, 0.0))),
+            }),
+        ),
+        // Still sphere for reference
+        Arc::new(Model::new(
+            Geometry::Sphere {
+                position: Vec3::new(0.0, 0.5, -1.5),
+                radius: 0.5,
+            },
+            red_mat,
+        )),
+        // Spinning box bouncing up and down
+        Arc::new(
+            Model::new(
+                Geometry::Cuboid {
+                    position: Vec3::ZERO,
+                    size: Vec3::new(1.2, 0.6, 0.6),
+                },
+                blue_mat,
+            )
+            .with_motion(Motion::Keyframed {
+                keyframes: [(0.0, 0.3, 0.0), (0.5, 0.9, 0.6), (1.0, 0.3, 1.2)]
+                    .into_iter()
+                    .map(|(time, height, angle)| Keyframe {
+                        time,
+                        transform: Transform::from_scale_rotation_translation(
+                            Vec3::ONE,
+                            Quat::from_rotation_y(angle),
+                            Vec3::new(1.8, height, 0.0),
+                        ),
+                    })
+                    .collect(),
+            }),
+        ),
+        Arc::new(Model::new(
+            Geometry::Quad {
+                position: Vec3::new(-2.0, 5.0, -2.0),
+                u: Vec3::new(4.0, 0.0, 0.0),
+                v: Vec3::new(0.0, 0.0, 4.0),
+            },
+            light_mat,
+        )),
+    ];
+
+    let camera = Camera::new(
+        Vec3::new(0.0, 2.0, 6.0),
+        Vec3::new(0.0, -0.3, -1.0).normalize(),
+        Vec3::new(0.0, -1.0, 0.0),
+        40.0,
+        cam_aspect_ratio,
+    )
+    .with_shutter(0.0, 1.0);
+    Scene::with_background(camera, objects, Vec3::new(0.1, 0.1, 0.15))
+}
+
+pub fn environment_scene(cam_aspect_ratio: f32) -> Scene {
+    // Studio environment with a dim gradient, a large softbox and a small
+    // bright spot, found by importance sampling
+    let (width, height) = (256, 128);
+    let pixels = (0..height)
+        .flat_map(|y| {
+            (0..width).map(move |x| {
+                let (u, v) = (x as f32 / width as f32, y as f32 / height as f32);
+                let softbox = (0.3..0.45).contains(&u) && (0.15..0.35).contains(&v);
+                let spot = (u - 0.7).abs() < 0.01 && (v - 0.25).abs() < 0.02;
+                if spot {
+                    Vec3::new(400.0, 350.0, 300.0)
+                } else if softbox {
+                    Vec3::splat(6.0)
+                } else {
+                    Vec3::new(0.05, 0.06, 0.08).lerp(Vec3::new(0.2, 0.25, 0.35), 1.0 - v)
+                }
+            })
+        })
+        .collect();
+    let environment = Arc::new(
+        EnvironmentMap::new(width, height, pixels)
+            .with_rotation(20.0)
+            .into(),
+    );
+
+    let ground_mat = Arc::new(Material::Diffuse {
+        albedo: Vec3::splat(0.5).into(),
+    });
+    let objects = vec![
+        Arc::new(Model::new(
+            Geometry::Quad {
+                position: Vec3::new(-10.0, 0.0, 10.0),
+                u: Vec3::new(20.0, 0.0, 0.0),
+                v: Vec3::new(0.0, 0.0, -20.0),
+            },
+            ground_mat,
+        )),
+        Arc::new(Model::new(
+            Geometry::Sphere {
+                position: Vec3::new(-1.2, 0.5, 0.0),
+                radius: 0.5,
+            },
+            Arc::new(Material::Principled(Principled {
+                base_color: Vec3::new(0.8, 0.2, 0.1).into(),
+                roughness: 0.3.into(),
+                clearcoat: 1.0,
+                ..Default::default()
+            })),
+        )),
+        Arc::new(Model::new(
+            Geometry::Sphere {
+                position: Vec3::new(0.0, 0.5, 0.0),
+                radius: 0.5,
+            },
+            Arc::new(Material::Principled(Principled {
+                base_color: Vec3::new(0.95, 0.75, 0.4).into(),
+                metallic: 1.0.into(),
+                roughness: 0.2.into(),
+                ..Default::default()
+            })),
+        )),
+        Arc::new(Model::new(
+            Geometry::Sphere {
+                position: Vec3::new(1.2, 0.5, 0.0),
+                radius: 0.5,
+            },
+            Arc::new(Material::Dielectric {
+                refraction_index: 1.5,
+            }),
+        )),
+    ];
+
+    let camera = Camera::new(
+        Vec3::new(0.0, 1.5, 4.5),
+        Vec3::new(0.0, -0.25, -1.0).normalize(),
+        Vec3::new(0.0, -1.0, 0.0),
+        40.0,
+        cam_aspect_ratio,
+    );
+    Scene::with_environment(camera, objects, environment)
+}
+
+pub fn sky_scene(cam_aspect_ratio: f32) -> Scene {
+    // Afternoon sun behind the spheres, casting long shadows towards the
+    // camera
+    let sun_direction = Vec3::new(-0.6, 0.5, -1.0);
+    let sky = Arc::new(Sky::new(sun_direction, 3.0).into());
+
+    let ground_mat = Arc::new(Material::Diffuse {
+        albedo: Vec3::splat(0.4).into(),
+    });
+    let objects = vec![
+        Arc::new(Model::new(
+            Geometry::Quad {
+                position: Vec3::new(-1000.0, 0.0, 1000.0),
+                u: Vec3::new(2000.0, 0.0, 0.0),
+                v: Vec3::new(0.0, 0.0, -2000.0),
+            },
+            ground_mat,
+        )),
+        Arc::new(Model::new(
+            Geometry::Sphere {
+                position: Vec3::new(-1.2, 0.5, 0.0),
+                radius: 0.5,
+            },
+            Arc::new(Material::Diffuse {
+                albedo: Vec3::splat(0.8).into(),
+            }),
+        )),
+        Arc::new(Model::new(
+            Geometry::Sphere {
+                position: Vec3::new(0.0, 0.5, 0.0),
+                radius: 0.5,
+            },
+            Arc::new(Material::Principled(Principled {
+                base_color: Vec3::new(0.9, 0.9, 0.9).into(),
+                metallic: 1.0.into(),
+                roughness: 0.05.into(),
+                ..Default::default()
+            })),
+        )),
+        Arc::new(Model::new(
+            Geometry::Sphere {
+                position: Vec3::new(1.2, 0.5, 0.0),
+                radius: 0.5,
+            },
+            Arc::new(Material::Principled(Principled {
+                base_color: Vec3::new(0.1, 0.3, 0.7).into(),
+                roughness: 0.4.into(),
+                ..Default::default()
+            })),
+        )),
+    ];
+
+    let camera = Camera::new(
+        Vec3::new(0.0, 1.2, 4.5),
+        Vec3::new(0.0, -0.1, -1.0).normalize(),
+        Vec3::new(0.0, -1.0, 0.0),
+        50.0,
+        cam_aspect_ratio,
+    );
+    Scene::with_environment(camera, objects, sky)
+}
+
 pub fn quads_scene(cam_aspect_ratio: f32) -> Scene {
     let mut objects = Vec::new();
 
     let right_mat = Arc::new(Material::Diffuse {
-        albedo: Vec3::new(1.0, 0.2, 0.2),
+        albedo: Vec3::new(1.0, 0.2, 0.2).into(),
     });
     let left_mat = Arc::new(Material::Diffuse {
-        albedo: Vec3::new(0.2, 1.0, 0.2),
+        albedo: Vec3::new(0.2, 1.0, 0.2).into(),
     });
     let front_mat = Arc::new(Material::Diffuse {
-        albedo: Vec3::new(0.2, 0.2, 1.0),
+        albedo: Vec3::new(0.2, 0.2, 1.0).into(),
     });
     let up_mat = Arc::new(Material::Diffuse {
-        albedo: Vec3::new(1.0, 0.5, 0.0),
+        albedo: Vec3::new(1.0, 0.5, 0.0).into(),
     });
     let down_mat = Arc::new(Material::Diffuse {
-        albedo: Vec3::new(0.2, 0.8, 0.8),
+        albedo: Vec3::new(0.2, 0.8, 0.8).into(),
     });
 
     objects.push(Arc::new(Model::new(
@@ -230,7 +518,7 @@ pub fn quads_scene(cam_aspect_ratio: f32) -> Scene {
             radius: 1.0,
         },
         Arc::new(Material::DiffuseLight {
-            emission: Vec3::new(4.0, 4.0, 4.0),
+            emission: Vec3::new(4.0, 4.0, 4.0).into(),
         }),
     )));
 
@@ -250,20 +538,20 @@ fn empty_cornell_box() -> Vec<Arc<Model>> {
     let mut objects = Vec::new();
 
     let red_mat = Arc::new(Material::Diffuse {
-        albedo: Vec3::new(0.65, 0.05, 0.05),
+        albedo: Vec3::new(0.65, 0.05, 0.05).into(),
     });
     let green_mat = Arc::new(Material::Diffuse {
-        albedo: Vec3::new(0.12, 0.45, 0.15),
+        albedo: Vec3::new(0.12, 0.45, 0.15).into(),
     });
     let white_mat = Arc::new(Material::Diffuse {
-        albedo: Vec3::new(0.73, 0.73, 0.73),
+        albedo: Vec3::new(0.73, 0.73, 0.73).into(),
     });
     let light_mat = Arc::new(Material::DiffuseLight {
-        emission: Vec3::new(15.0, 15.0, 15.0),
+        emission: Vec3::new(15.0, 15.0, 15.0).into(),
     });
     let metal_mat = Arc::new(Material::Metalic {
-        albedo: Vec3::new(0.8, 0.65, 0.7),
-        fuzzyness: 0.2,
+        albedo: Vec3::new(0.8, 0.65, 0.7).into(),
+        fuzzyness: 0.2.into(),
     });
     let glass_mat = Arc::new(Material::Dielectric {
         refraction_index: 1.5,
@@ -327,7 +615,7 @@ pub fn cornell_box_scene(cam_aspect_ratio: f32) -> Scene {
     let mut objects = empty_cornell_box();
 
     let white_mat = Arc::new(Material::Diffuse {
-        albedo: Vec3::new(0.73, 0.73, 0.73),
+        albedo: Vec3::new(0.73, 0.73, 0.73).into(),
     });
     let glass_mat = Arc::new(Material::Dielectric {
         refraction_index: 1.5,
@@ -363,12 +651,56 @@ pub fn cornell_box_scene(cam_aspect_ratio: f32) -> Scene {
     )
 }
 
+pub fn cornell_box_rotated_scene(cam_aspect_ratio: f32) -> Scene {
+    let mut objects = empty_cornell_box();
+
+    let white_mat = Arc::new(Material::Diffuse {
+        albedo: Vec3::new(0.73, 0.73, 0.73).into(),
+    });
+
+    // Both boxes are instances of a unit cube with its corner at the origin
+    let unit_cube = Arc::new(Geometry::Cuboid {
+        position: Vec3::splat(0.5),
+        size: Vec3::ONE,
+    });
+    objects.push(Arc::new(
+        Model::new(Geometry::Instance(unit_cube.clone()), white_mat.clone()).with_transform(
+            Transform::from_scale_rotation_translation(
+                Vec3::new(165.0, 330.0, 165.0),
+                Quat::from_rotation_y(15f32.to_radians()),
+                Vec3::new(265.0, 0.0, 295.0),
+            ),
+        ),
+    ));
+    objects.push(Arc::new(
+        Model::new(Geometry::Instance(unit_cube), white_mat).with_transform(
+            Transform::from_scale_rotation_translation(
+                Vec3::splat(165.0),
+                Quat::from_rotation_y(-18f32.to_radians()),
+                Vec3::new(130.0, 0.0, 65.0),
+            ),
+        ),
+    ));
+
+    Scene::with_background(
+        Camera::new(
+            Vec3::new(278.0, 278.0, -800.0),
+            Vec3::new(0.0, 0.0, 1.0).normalize(),
+            Vec3::new(0.0, -1.0, 0.0).normalize(),
+            40.0,
+            cam_aspect_ratio,
+        ),
+        objects,
+        Vec3::new(0.0, 0.0, 0.0),
+    )
+}
+
 pub fn cornell_box2_scene(cam_aspect_ratio: f32) -> Scene {
     let mut objects = empty_cornell_box();
 
     let metal_mat = Arc::new(Material::Metalic {
-        albedo: Vec3::new(0.8, 0.65, 0.7),
-        fuzzyness: 0.2,
+        albedo: Vec3::new(0.8, 0.65, 0.7).into(),
+        fuzzyness: 0.2.into(),
     });
     let glass_mat = Arc::new(Material::Dielectric {
         refraction_index: 1.5,
@@ -404,3 +736,638 @@ pub fn cornell_box2_scene(cam_aspect_ratio: f32) -> Scene {
         Vec3::new(0.0, 0.0, 0.0),
     )
 }
+
+pub fn oren_nayar_scene(cam_aspect_ratio: f32) -> Scene {
+    let mut objects = Vec::new();
+
+    let clay_color = Vec3::new(0.75, 0.45, 0.3);
+    let lambertian_mat = Arc::new(Material::Diffuse {
+        albedo: clay_color.into(),
+    });
+    let oren_nayar_mat = Arc::new(Material::OrenNayar {
+        albedo: clay_color.into(),
+        sigma: 30.0,
+    });
+    let ground_mat = Arc::new(Material::Diffuse {
+        albedo: Vec3::new(0.5, 0.5, 0.5).into(),
+    });
+    let light_mat = Arc::new(Material::DiffuseLight {
+        emission: Vec3::new(6.0, 6.0, 6.0).into(),
+    });
+
+    // Lambertian sphere on the left, Oren-Nayar sphere on the right
+    objects.push(Arc::new(Model::new(
+        Geometry::Sphere {
+            position: Vec3::new(-1.2, 1.0, 0.0),
+            radius: 1.0,
+        },
+        lambertian_mat.clone(),
+    )));
+    objects.push(Arc::new(Model::new(
+        Geometry::Sphere {
+            position: Vec3::new(1.2, 1.0, 0.0),
+            radius: 1.0,
+        },
+        oren_nayar_mat.clone(),
+    )));
+
+    // Ground
+    objects.push(Arc::new(Model::new(
+        Geometry::Quad {
+            position: Vec3::new(-10.0, 0.0, 10.0),
+            u: Vec3::new(20.0, 0.0, 0.0),
+            v: Vec3::new(0.0, 0.0, -20.0),
+        },
+        ground_mat.clone(),
+    )));
+
+    // Light behind the camera, so the retro-reflection of rough surfaces is
+    // noticeable
+    objects.push(Arc::new(Model::new(
+        Geometry::Quad {
+            position: Vec3::new(-2.0, 3.0, 7.0),
+            u: Vec3::new(4.0, 0.0, 0.0),
+            v: Vec3::new(0.0, 2.0, 0.0),
+        },
+        light_mat.clone(),
+    )));
+
+    Scene::with_background(
+        Camera::new(
+            Vec3::new(0.0, 1.5, 6.0),
+            Vec3::new(0.0, -0.1, -1.0).normalize(),
+            Vec3::new(0.0, -1.0, 0.0).normalize(),
+            45.0,
+            cam_aspect_ratio,
+        ),
+        objects,
+        Vec3::new(0.05, 0.05, 0.05),
+    )
+}
+
+pub fn textures_scene(cam_aspect_ratio: f32) -> Scene {
+    let mut objects = Vec::new();
+
+    let checker = Texture::Checker {
+        scale: 10.0,
+        even: Arc::new(Vec3::new(0.8, 0.8, 0.8).into()),
+        odd: Arc::new(Vec3::new(0.1, 0.1, 0.1).into()),
+    };
+    let marble = Texture::Noise {
+        scale: 2.0,
+        octaves: 5,
+        seed: 7,
+        low: Arc::new(Vec3::new(0.1, 0.15, 0.3).into()),
+        high: Arc::new(Vec3::new(0.9, 0.9, 0.85).into()),
+    };
+    // Small generated bitmap with a color gradient and vertical stripes
+    let (width, height) = (64, 32);
+    let pixels = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .flat_map(|(x, y)| {
+            let stripe = if (x / 8) % 2 == 0 { 255 } else { 128 };
+            [(x * 4) as u8, (y * 8) as u8, stripe]
+        })
+        .collect();
+    let bitmap = Texture::Image(Arc::new(ImageTexture::new(width, height, pixels, true)));
+
+    objects.push(Arc::new(Model::new(
+        Geometry::Quad {
+            position: Vec3::new(-10.0, 0.0, 10.0),
+            u: Vec3::new(20.0, 0.0, 0.0),
+            v: Vec3::new(0.0, 0.0, -20.0),
+        },
+        Arc::new(Material::Diffuse { albedo: checker }),
+    )));
+    objects.push(Arc::new(Model::new(
+        Geometry::Sphere {
+            position: Vec3::new(-1.2, 1.0, 0.0),
+            radius: 1.0,
+        },
+        Arc::new(Material::NormalMapped {
+            material: Arc::new(Material::Diffuse {
+                albedo: marble.clone(),
+            }),
+            normal_map: NormalMap::Bump {
+                height: marble,
+                scale: 0.3,
+            },
+        }),
+    )));
+    objects.push(Arc::new(Model::new(
+        Geometry::Sphere {
+            position: Vec3::new(1.2, 1.0, 0.0),
+            radius: 1.0,
+        },
+        Arc::new(Material::Principled(Principled {
+            base_color: bitmap,
+            roughness: 0.3.into(),
+            ..Default::default()
+        })),
+    )));
+    objects.push(Arc::new(Model::new(
+        Geometry::Quad {
+            position: Vec3::new(-2.0, 4.0, 3.0),
+            u: Vec3::new(4.0, 0.0, 0.0),
+            v: Vec3::new(0.0, 0.0, 2.0),
+        },
+        Arc::new(Material::DiffuseLight {
+            emission: Vec3::new(8.0, 8.0, 8.0).into(),
+        }),
+    )));
+
+    Scene::with_background(
+        Camera::new(
+            Vec3::new(0.0, 1.5, 6.0),
+            Vec3::new(0.0, -0.1, -1.0).normalize(),
+            Vec3::new(0.0, -1.0, 0.0).normalize(),
+            45.0,
+            cam_aspect_ratio,
+        ),
+        objects,
+        Vec3::new(0.1, 0.1, 0.12),
+    )
+}
+
+pub fn primitives_scene(cam_aspect_ratio: f32) -> Scene {
+    let mut objects = Vec::new();
+
+    let checker = Texture::Checker {
+        scale: 1.0,
+        even: Arc::new(Vec3::new(0.8, 0.8, 0.8).into()),
+        odd: Arc::new(Vec3::new(0.2, 0.3, 0.1).into()),
+    };
+    objects.push(Arc::new(Model::new(
+        Geometry::Plane {
+            position: Vec3::ZERO,
+            normal: Vec3::Y,
+        },
+        Arc::new(Material::Diffuse { albedo: checker }),
+    )));
+    objects.push(Arc::new(Model::new(
+        Geometry::Cylinder {
+            position: Vec3::new(-2.2, 0.0, 0.0),
+            axis: Vec3::new(0.0, 1.5, 0.0),
+            radius: 0.8,
+        },
+        Arc::new(Material::Diffuse {
+            albedo: Vec3::new(0.8, 0.2, 0.2).into(),
+        }),
+    )));
+    objects.push(Arc::new(Model::new(
+        Geometry::Cone {
+            position: Vec3::new(0.0, 0.0, -0.5),
+            axis: Vec3::new(0.0, 2.0, 0.0),
+            radius: 0.9,
+        },
+        Arc::new(Material::Metalic {
+            albedo: Vec3::new(0.8, 0.7, 0.3).into(),
+            fuzzyness: 0.1.into(),
+        }),
+    )));
+    objects.push(Arc::new(Model::new(
+        Geometry::Disk {
+            position: Vec3::new(2.2, 1.0, 0.0),
+            normal: Vec3::new(-0.5, 0.2, 1.0),
+            radius: 0.9,
+        },
+        Arc::new(Material::Diffuse {
+            albedo: Vec3::new(0.2, 0.3, 0.8).into(),
+        }),
+    )));
+    // Disk light above the objects
+    objects.push(Arc::new(Model::new(
+        Geometry::Disk {
+            position: Vec3::new(0.0, 5.0, 1.0),
+            normal: Vec3::NEG_Y,
+            radius: 1.5,
+        },
+        Arc::new(Material::DiffuseLight {
+            emission: Vec3::new(6.0, 6.0, 6.0).into(),
+        }),
+    )));
+
+    Scene::with_background(
+        Camera::new(
+            Vec3::new(0.0, 2.0, 7.0),
+            Vec3::new(0.0, -0.2, -1.0).normalize(),
+            Vec3::new(0.0, -1.0, 0.0).normalize(),
+            45.0,
+            cam_aspect_ratio,
+        ),
+        objects,
+        Vec3::new(0.1, 0.1, 0.12),
+    )
+}
+
+pub fn csg_scene(cam_aspect_ratio: f32) -> Scene {
+    let mut objects = Vec::new();
+
+    let checker = Texture::Checker {
+        scale: 2.0,
+        even: Arc::new(Vec3::new(0.8, 0.8, 0.8).into()),
+        odd: Arc::new(Vec3::new(0.1, 0.1, 0.1).into()),
+    };
+    objects.push(Arc::new(Model::new(
+        Geometry::Plane {
+            position: Vec3::ZERO,
+            normal: Vec3::Y,
+        },
+        Arc::new(Material::Diffuse {
+            albedo: checker.clone(),
+        }),
+    )));
+    objects.push(Arc::new(Model::new(
+        Geometry::Quad {
+            position: Vec3::new(-6.0, 0.0, -4.0),
+            u: Vec3::new(12.0, 0.0, 0.0),
+            v: Vec3::new(0.0, 6.0, 0.0),
+        },
+        Arc::new(Material::Diffuse { albedo: checker }),
+    )));
+
+    // Biconvex lens as the intersection of two spheres
+    let sphere = |x: f32| {
+        Arc::new(Geometry::Sphere {
+            position: Vec3::new(0.0, 1.5, x),
+            radius: 2.0,
+        })
+    };
+    objects.push(Arc::new(Model::new(
+        Geometry::Csg {
+            operation: CsgOperation::Intersection,
+            left: sphere(-1.6),
+            right: sphere(1.6),
+        },
+        Arc::new(Material::Dielectric {
+            refraction_index: 1.5,
+        }),
+    )));
+
+    // Cube with a spherical cut-away
+    objects.push(Arc::new(Model::new(
+        Geometry::Csg {
+            operation: CsgOperation::Difference,
+            left: Arc::new(Geometry::Cuboid {
+                position: Vec3::new(-2.8, 0.8, -1.0),
+                size: Vec3::splat(1.6),
+            }),
+            right: Arc::new(Geometry::Sphere {
+                position: Vec3::new(-2.0, 1.6, -0.2),
+                radius: 1.0,
+            }),
+        },
+        Arc::new(Material::Diffuse {
+            albedo: Vec3::new(0.8, 0.3, 0.2).into(),
+        }),
+    )));
+
+    // Union of a cylinder and a cone
+    objects.push(Arc::new(Model::new(
+        Geometry::Csg {
+            operation: CsgOperation::Union,
+            left: Arc::new(Geometry::Cylinder {
+                position: Vec3::new(2.8, 0.0, -1.0),
+                axis: Vec3::new(0.0, 1.0, 0.0),
+                radius: 0.6,
+            }),
+            right: Arc::new(Geometry::Cone {
+                position: Vec3::new(2.8, 0.8, -1.0),
+                axis: Vec3::new(0.0, 1.2, 0.0),
+                radius: 0.9,
+            }),
+        },
+        Arc::new(Material::Diffuse {
+            albedo: Vec3::new(0.2, 0.4, 0.8).into(),
+        }),
+    )));
+
+    objects.push(Arc::new(Model::new(
+        Geometry::Disk {
+            position: Vec3::new(0.0, 6.0, 2.0),
+            normal: Vec3::NEG_Y,
+            radius: 2.0,
+        },
+        Arc::new(Material::DiffuseLight {
+            emission: Vec3::new(5.0, 5.0, 5.0).into(),
+        }),
+    )));
+
+    Scene::with_background(
+        Camera::new(
+            Vec3::new(0.0, 2.0, 8.0),
+            Vec3::new(0.0, -0.1, -1.0).normalize(),
+            Vec3::new(0.0, -1.0, 0.0).normalize(),
+            45.0,
+            cam_aspect_ratio,
+        ),
+        objects,
+        Vec3::new(0.1, 0.1, 0.12),
+    )
+}
+
+pub fn sdf_scene(cam_aspect_ratio: f32) -> Scene {
+    let mut objects = Vec::new();
+
+    objects.push(Arc::new(Model::new(
+        Geometry::Plane {
+            position: Vec3::ZERO,
+            normal: Vec3::Y,
+        },
+        Arc::new(Material::Diffuse {
+            albedo: Vec3::new(0.5, 0.5, 0.55).into(),
+        }),
+    )));
+
+    // Twisted rounded column
+    let column = Sdf::Twist {
+        sdf: Arc::new(Sdf::RoundedBox {
+            size: Vec3::new(0.8, 3.0, 0.8),
+            radius: 0.1,
+        }),
+        rate: 0.8,
+    };
+    objects.push(Arc::new(Model::new(
+        Geometry::Sdf {
+            sdf: Arc::new(Sdf::Translate {
+                offset: Vec3::new(-2.0, 1.5, 0.0),
+                sdf: Arc::new(column),
+            }),
+            bounds: Aabb::from_positions(Vec3::new(-2.7, 0.0, -0.7), Vec3::new(-1.3, 3.0, 0.7)),
+        },
+        Arc::new(Material::Diffuse {
+            albedo: Vec3::new(0.8, 0.3, 0.2).into(),
+        }),
+    )));
+
+    // Torus melting into a sphere
+    let blob = Sdf::SmoothUnion {
+        left: Arc::new(Sdf::Torus {
+            major_radius: 0.9,
+            minor_radius: 0.3,
+        }),
+        right: Arc::new(Sdf::Translate {
+            offset: Vec3::new(0.0, 0.6, 0.0),
+            sdf: Arc::new(Sdf::Sphere { radius: 0.6 }),
+        }),
+        smoothness: 0.4,
+    };
+    objects.push(Arc::new(Model::new(
+        Geometry::Sdf {
+            sdf: Arc::new(Sdf::Translate {
+                offset: Vec3::new(0.3, 0.3, 0.5),
+                sdf: Arc::new(blob),
+            }),
+            bounds: Aabb::from_positions(Vec3::new(-1.0, 0.0, -0.8), Vec3::new(1.6, 1.6, 1.8)),
+        },
+        Arc::new(Material::Metalic {
+            albedo: Vec3::new(0.9, 0.8, 0.5).into(),
+            fuzzyness: 0.05.into(),
+        }),
+    )));
+
+    // Grid of small boxes from a single repeated one
+    objects.push(Arc::new(Model::new(
+        Geometry::Sdf {
+            sdf: Arc::new(Sdf::Repeat {
+                sdf: Arc::new(Sdf::Box {
+                    size: Vec3::splat(0.3),
+                }),
+                period: Vec3::new(0.6, 0.0, 0.6),
+            }),
+            bounds: Aabb::from_positions(Vec3::new(1.5, -0.15, -1.95), Vec3::new(3.3, 0.15, 1.95)),
+        },
+        Arc::new(Material::Diffuse {
+            albedo: Vec3::new(0.2, 0.4, 0.8).into(),
+        }),
+    )));
+
+    objects.push(Arc::new(Model::new(
+        Geometry::Disk {
+            position: Vec3::new(0.0, 6.0, 2.0),
+            normal: Vec3::NEG_Y,
+            radius: 2.0,
+        },
+        Arc::new(Material::DiffuseLight {
+            emission: Vec3::new(5.0, 5.0, 5.0).into(),
+        }),
+    )));
+
+    Scene::with_background(
+        Camera::new(
+            Vec3::new(0.0, 2.5, 7.0),
+            Vec3::new(0.0, -0.25, -1.0).normalize(),
+            Vec3::new(0.0, -1.0, 0.0).normalize(),
+            45.0,
+            cam_aspect_ratio,
+        ),
+        objects,
+        Vec3::new(0.1, 0.1, 0.12),
+    )
+}
+
+pub fn media_scene(cam_aspect_ratio: f32) -> Scene {
+    let mut objects = empty_cornell_box();
+
+    // Smoke box, whose boundary is invisible
+    let smoke = Arc::new(Medium::Homogeneous {
+        sigma_a: Vec3::splat(0.002),
+        sigma_s: Vec3::splat(0.01),
+        g: 0.3,
+    });
+    objects.push(Arc::new(
+        Model::new(
+            Geometry::Cuboid {
+                position: Vec3::splat(0.5),
+                size: Vec3::ONE,
+            },
+            Arc::new(Material::Interface),
+        )
+        .with_transform(Transform::from_scale_rotation_translation(
+            Vec3::new(165.0, 330.0, 165.0),
+            Quat::from_rotation_y(15f32.to_radians()),
+            Vec3::new(265.0, 0.0, 295.0),
+        ))
+        .with_medium(smoke),
+    ));
+
+    // Glass sphere tinted by absorption
+    let tint = Arc::new(Medium::Homogeneous {
+        sigma_a: Vec3::new(0.012, 0.004, 0.001),
+        sigma_s: Vec3::ZERO,
+        g: 0.0,
+    });
+    objects.push(Arc::new(
+        Model::new(
+            Geometry::Sphere {
+                position: Vec3::new(190.0, 90.0, 190.0),
+                radius: 90.0,
+            },
+            Arc::new(Material::Dielectric {
+                refraction_index: 1.5,
+            }),
+        )
+        .with_medium(tint),
+    ));
+
+    let fog = Arc::new(Medium::Homogeneous {
+        sigma_a: Vec3::ZERO,
+        sigma_s: Vec3::splat(0.0005),
+        g: 0.0,
+    });
+    Scene::with_background(
+        Camera::new(
+            Vec3::new(278.0, 278.0, -800.0),
+            Vec3::new(0.0, 0.0, 1.0).normalize(),
+            Vec3::new(0.0, -1.0, 0.0).normalize(),
+            40.0,
+            cam_aspect_ratio,
+        ),
+        objects,
+        Vec3::new(0.0, 0.0, 0.0),
+    )
+    .with_medium(fog)
+}
+
+pub fn volume_scene(cam_aspect_ratio: f32) -> Scene {
+    let mut objects = empty_cornell_box();
+
+    // Cloud of fractal noise fading towards the edges of a sphere
+    let resolution = UVec3::splat(64);
+    let mut densities = Vec::with_capacity(resolution.element_product() as usize);
+    for z in 0..resolution.z {
+        for y in 0..resolution.y {
+            for x in 0..resolution.x {
+                let p = (UVec3::new(x, y, z).as_vec3() + 0.5) / resolution.as_vec3();
+                let falloff = 1.0 - (p - 0.5).length() * 2.0;
+                let noise = fractal_noise(p * 4.0, 4, 7);
+                densities.push((falloff + 0.5 * noise).max(0.0) * 2.0);
+            }
+        }
+    }
+    let bounds = Aabb::from_positions(
+        Vec3::new(130.0, 100.0, 130.0),
+        Vec3::new(430.0, 400.0, 430.0),
+    );
+    let cloud = Arc::new(Medium::Grid {
+        grid: Arc::new(VoxelGrid::new(resolution, densities)),
+        bounds: bounds.clone(),
+        sigma_a: Vec3::splat(0.002),
+        sigma_s: Vec3::splat(0.03),
+        g: 0.5,
+    });
+    objects.push(Arc::new(
+        Model::new(
+            Geometry::Cuboid {
+                position: (bounds.min_position + bounds.max_position) / 2.0,
+                size: bounds.max_position - bounds.min_position,
+            },
+            Arc::new(Material::Interface),
+        )
+        .with_medium(cloud),
+    ));
+
+    Scene::with_background(
+        Camera::new(
+            Vec3::new(278.0, 278.0, -800.0),
+            Vec3::new(0.0, 0.0, 1.0).normalize(),
+            Vec3::new(0.0, -1.0, 0.0).normalize(),
+            40.0,
+            cam_aspect_ratio,
+        ),
+        objects,
+        Vec3::new(0.0, 0.0, 0.0),
+    )
+}
+
+/// Torus around the y axis with per vertex normals and UVs.
+fn torus_mesh(major_radius: f32, minor_radius: f32, segments: u32, rings: u32) -> Mesh {
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut uvs = Vec::new();
+    for i in 0..=segments {
+        let u = i as f32 / segments as f32;
+        let theta = 2.0 * f32::consts::PI * u;
+        for j in 0..=rings {
+            let v = j as f32 / rings as f32;
+            let phi = 2.0 * f32::consts::PI * v;
+            let normal = Vec3::new(phi.cos() * theta.cos(), phi.sin(), phi.cos() * theta.sin());
+            let center = major_radius * Vec3::new(theta.cos(), 0.0, theta.sin());
+            positions.push(center + minor_radius * normal);
+            normals.push(normal);
+            uvs.push(glam::Vec2::new(u, v));
+        }
+    }
+
+    let mut indices = Vec::new();
+    for i in 0..segments {
+        for j in 0..rings {
+            let a = i * (rings + 1) + j;
+            let b = (i + 1) * (rings + 1) + j;
+            indices.push([a, b + 1, b]);
+            indices.push([a, a + 1, b + 1]);
+        }
+    }
+
+    Mesh::new(positions, indices)
+        .with_normals(normals)
+        .with_uvs(uvs)
+}
+
+pub fn mesh_scene(cam_aspect_ratio: f32) -> Scene {
+    let mut objects = Vec::new();
+
+    let torus = torus_mesh(1.0, 0.4, 64, 32);
+    let torus_mat = Arc::new(Material::Principled(Principled {
+        base_color: Texture::Checker {
+            scale: 8.0,
+            even: Arc::new(Vec3::new(0.8, 0.3, 0.1).into()),
+            odd: Arc::new(Vec3::new(0.9, 0.8, 0.6).into()),
+        },
+        roughness: 0.4.into(),
+        ..Default::default()
+    }));
+    objects.push(Arc::new(Model::new(
+        Geometry::Mesh(Arc::new(torus)),
+        torus_mat,
+    )));
+
+    objects.push(Arc::new(Model::new(
+        Geometry::Quad {
+            position: Vec3::new(-10.0, -0.4, 10.0),
+            u: Vec3::new(20.0, 0.0, 0.0),
+            v: Vec3::new(0.0, 0.0, -20.0),
+        },
+        Arc::new(Material::Diffuse {
+            albedo: Vec3::new(0.5, 0.5, 0.5).into(),
+        }),
+    )));
+
+    // Triangle light above the torus
+    let light = Mesh::new(
+        vec![
+            Vec3::new(-1.5, 3.0, -1.0),
+            Vec3::new(1.5, 3.0, -1.0),
+            Vec3::new(0.0, 3.0, 1.5),
+        ],
+        vec![[0, 1, 2]],
+    );
+    objects.push(Arc::new(Model::new(
+        Geometry::Mesh(Arc::new(light)),
+        Arc::new(Material::DiffuseLight {
+            emission: Vec3::new(10.0, 10.0, 10.0).into(),
+        }),
+    )));
+
+    Scene::with_background(
+        Camera::new(
+            Vec3::new(0.0, 2.5, 4.5),
+            Vec3::new(0.0, -0.5, -1.0).normalize(),
+            Vec3::new(0.0, -1.0, 0.0).normalize(),
+            45.0,
+            cam_aspect_ratio,
+        ),
+        objects,
+        Vec3::new(0.1, 0.1, 0.12),
+    )
+}
@@ -1,6 +1,6 @@
 #[cfg(target_arch = "wasm32")]
 use futures::{FutureExt, future::RemoteHandle};
-use glam::Vec3;
+use glam::{Vec2, Vec3};
 use rand::Rng;
 use std::num::NonZero;
 #[cfg(not(target_arch = "wasm32"))]
@@ -32,10 +32,7 @@ pub fn spherical_to_cartesian(v: Vec3) -> Vec3 {
 
 /// Return a normalized random vector
 pub fn random_vector(rng: &mut impl Rng) -> Vec3 {
-    let polar = rng.random_range(0.0..std::f32::consts::PI);
-    let azimuth = rng.random_range(0.0..(2.0 * std::f32::consts::PI));
-
-    spherical_to_cartesian(Vec3::new(1.0, polar, azimuth))
+    sample_uniform_sphere(Vec2::new(rng.random(), rng.random()))
 }
 
 /// Return a normalized random vector in the hemisphere of a normal
@@ -48,6 +45,56 @@ pub fn random_in_hemisphere(rng: &mut impl Rng, normal: Vec3) -> Vec3 {
     }
 }
 
+/// Map a uniform sample in [0, 1)^2 to a uniformly distributed point on the
+/// unit sphere.
+pub fn sample_uniform_sphere(u: Vec2) -> Vec3 {
+    let z = 1.0 - 2.0 * u.x;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * std::f32::consts::PI * u.y;
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+/// Map a uniform sample in [0, 1)^2 to a uniformly distributed direction in
+/// the cone around the z axis whose half angle has a cosine of
+/// `1 - one_minus_cos_max`. The cone is given this way to keep precision for
+/// tiny cones, such as the sun seen from the earth.
+pub fn sample_uniform_cone(u: Vec2, one_minus_cos_max: f32) -> Vec3 {
+    let one_minus_cos = u.x * one_minus_cos_max;
+    let sin_theta = (one_minus_cos * (2.0 - one_minus_cos)).max(0.0).sqrt();
+    let phi = 2.0 * std::f32::consts::PI * u.y;
+    Vec3::new(
+        sin_theta * phi.cos(),
+        sin_theta * phi.sin(),
+        1.0 - one_minus_cos,
+    )
+}
+
+/// Map a uniform sample in [0, 1)^2 to a uniformly distributed point on the
+/// unit disk, with Shirley's concentric mapping.
+pub fn sample_uniform_disk(u: Vec2) -> Vec2 {
+    let u = 2.0 * u - 1.0;
+    if u == Vec2::ZERO {
+        return Vec2::ZERO;
+    }
+    let (r, theta) = if u.x.abs() > u.y.abs() {
+        (u.x, std::f32::consts::FRAC_PI_4 * (u.y / u.x))
+    } else {
+        (
+            u.y,
+            std::f32::consts::FRAC_PI_2 - std::f32::consts::FRAC_PI_4 * (u.x / u.y),
+        )
+    };
+    r * Vec2::new(theta.cos(), theta.sin())
+}
+
+/// Map a uniform sample in [0, 1)^2 to a cosine weighted direction in the
+/// hemisphere around the z axis.
+pub fn sample_cosine_hemisphere(u: Vec2) -> Vec3 {
+    let r = u.x.sqrt();
+    let phi = 2.0 * std::f32::consts::PI * u.y;
+    Vec3::new(r * phi.cos(), r * phi.sin(), (1.0 - u.x).max(0.0).sqrt())
+}
+
 pub fn ideal_processors() -> usize {
     #[cfg(not(target_arch = "wasm32"))]
     {
@@ -0,0 +1,306 @@
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use glam::{Vec2, Vec3};
+use mirror::raytracer::{
+    Bsdf, BurleyDiffuseBsdf, ClearcoatBsdf, ConductorBsdf, ConductorReflectance, DielectricBsdf,
+    Frame, FuzzyMetalBsdf, Geometry, Hittable, LambertianBsdf, Material, Model, OrenNayarBsdf,
+    Principled, PrincipledBsdf, Ray, RoughDielectricBsdf, TrowbridgeReitz, fresnel_conductor,
+    fresnel_dielectric, fresnel_schlick,
+};
+use mirror::utils::sample_uniform_sphere;
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+
+/// Outgoing directions in the local frame, from normal to grazing incidence.
+fn outgoing_directions() -> [Vec3; 3] {
+    [
+        Vec3::Z,
+        Vec3::new(0.6, 0.0, 0.8),
+        Vec3::new(0.3, 0.9, 0.2).normalize(),
+    ]
+}
+
+/// Integral of `f` over the sphere of directions, by jittered stratified
+/// Monte Carlo integration. Strata are randomly rotated so that they don't
+/// line up with features of `f` around the poles.
+fn sphere_integral(rng: &mut SmallRng, f: impl Fn(Vec3) -> f32) -> f32 {
+    let n = 512;
+    let frame = Frame::from_normal(sample_uniform_sphere(Vec2::new(rng.random(), rng.random())));
+    let mut integral = 0.0;
+    for i in 0..n {
+        for j in 0..n {
+            let u = Vec2::new(
+                i as f32 + rng.random::<f32>(),
+                j as f32 + rng.random::<f32>(),
+            );
+            integral += f(frame.to_world(sample_uniform_sphere(u / n as f32)));
+        }
+    }
+    integral * 4.0 * PI / (n * n) as f32
+}
+
+/// Directional albedo for `wo`, the fraction of light arriving along `wo`
+/// which is scattered, along with the fraction of non specular samples.
+fn albedo(bsdf: &dyn Bsdf, wo: Vec3, rng: &mut SmallRng) -> (Vec3, f32) {
+    let n = 50_000;
+    let mut albedo = Vec3::ZERO;
+    let mut non_specular = 0;
+    for _ in 0..n {
+        let u = Vec2::new(rng.random(), rng.random());
+        let Some(sample) = bsdf.sample(wo, rng.random(), u) else {
+            continue;
+        };
+        albedo += sample.f * sample.wi.z.abs() / sample.pdf;
+        non_specular += !sample.is_specular as usize;
+    }
+    (albedo / n as f32, non_specular as f32 / n as f32)
+}
+
+fn assert_near(a: f32, b: f32, tolerance: f32, context: &str) {
+    assert!(
+        (a - b).abs() <= tolerance * b.abs().max(1.0),
+        "{context}: {a} != {b}"
+    );
+}
+
+/// Check that samples agree with `eval` and `pdf`, that the density
+/// integrates to the fraction of directions `sample` returns, and that no
+/// energy is created.
+fn check_bsdf(name: &str, bsdf: &dyn Bsdf) {
+    let mut rng = SmallRng::seed_from_u64(13);
+    for wo in outgoing_directions() {
+        let context = format!("{name} with wo = {wo}");
+        for _ in 0..1000 {
+            let u = Vec2::new(rng.random(), rng.random());
+            let Some(sample) = bsdf.sample(wo, rng.random(), u) else {
+                continue;
+            };
+            assert!(sample.wi.is_normalized(), "{context}");
+            assert!(sample.pdf > 0.0, "{context}");
+            if sample.is_specular {
+                continue;
+            }
+            let f = bsdf.eval(wo, sample.wi);
+            assert!(
+                sample.f.abs_diff_eq(f, 1e-3 * f.max_element().max(1.0)),
+                "{context}: {} != {f}",
+                sample.f
+            );
+            assert_near(sample.pdf, bsdf.pdf(wo, sample.wi), 1e-3, &context);
+        }
+
+        let (albedo, non_specular) = albedo(bsdf, wo, &mut rng);
+        assert!(albedo.max_element() <= 1.01, "{context}: albedo {albedo}");
+        if !bsdf.is_specular() {
+            let total = sphere_integral(&mut rng, |wi| bsdf.pdf(wo, wi));
+            assert_near(total, non_specular, 2e-2, &context);
+        }
+    }
+}
+
+#[test]
+fn frame_is_orthonormal() {
+    for normal in [Vec3::Z, Vec3::NEG_Z, Vec3::new(1.0, 2.0, -3.0).normalize()] {
+        for frame in [
+            Frame::from_normal(normal),
+            Frame::from_normal_tangent(normal, Vec3::X),
+            Frame::from_normal_tangent(normal, normal),
+        ] {
+            assert!(frame.tangent.is_normalized() && frame.bitangent.is_normalized());
+            assert!(frame.tangent.dot(frame.normal).abs() < 1e-5);
+            assert!(frame.bitangent.dot(frame.normal).abs() < 1e-5);
+            assert!(frame.tangent.dot(frame.bitangent).abs() < 1e-5);
+            assert!(frame.to_local(normal).abs_diff_eq(Vec3::Z, 1e-5));
+            let v = Vec3::new(0.3, -0.5, 0.8);
+            assert!(frame.to_local(frame.to_world(v)).abs_diff_eq(v, 1e-5));
+        }
+    }
+}
+
+#[test]
+fn fresnel_reflectances() {
+    // Glass at normal incidence reflects 4%, and everything past the
+    // critical angle when leaving it
+    assert_near(fresnel_dielectric(1.0, 1.0 / 1.5), 0.04, 1e-4, "glass");
+    assert_near(fresnel_dielectric(1.0, 1.5), 0.04, 1e-4, "glass inside");
+    assert_eq!(fresnel_dielectric(0.3, 1.5), 1.0);
+    assert_near(fresnel_dielectric(0.0, 1.0 / 1.5), 1.0, 1e-4, "grazing");
+    // Conductors without absorption behave like dielectrics
+    let conductor = fresnel_conductor(0.7, Vec3::splat(1.5), Vec3::ZERO);
+    assert_near(
+        conductor.x,
+        fresnel_dielectric(0.7, 1.0 / 1.5),
+        1e-3,
+        "conductor",
+    );
+    let schlick = fresnel_schlick(1.0, Vec3::splat(0.04));
+    assert!(schlick.abs_diff_eq(Vec3::splat(0.04), 1e-6));
+    assert!(fresnel_schlick(0.0, Vec3::splat(0.04)).abs_diff_eq(Vec3::ONE, 1e-6));
+}
+
+#[test]
+fn trowbridge_reitz_distributions_are_normalized() {
+    let mut rng = SmallRng::seed_from_u64(13);
+    for alpha in [0.1, 0.5, 1.0] {
+        let distribution = TrowbridgeReitz { alpha };
+        // Projected microfacet area equals the macro surface area
+        let projected = sphere_integral(&mut rng, |wm| distribution.d(wm) * wm.z.max(0.0));
+        assert_near(projected, 1.0, 2e-2, "projected area");
+        for w in outgoing_directions() {
+            let visible = sphere_integral(&mut rng, |wm| {
+                if wm.z > 0.0 {
+                    distribution.d_visible(w, wm)
+                } else {
+                    0.0
+                }
+            });
+            assert_near(visible, 1.0, 2e-2, "visible normals");
+            // Sampled normals are visible from w
+            for _ in 0..100 {
+                let u = Vec2::new(rng.random(), rng.random());
+                let wm = distribution.sample_visible(w, u);
+                assert!(wm.is_normalized() && wm.z > 0.0 && wm.dot(w) >= -1e-4);
+            }
+        }
+    }
+}
+
+#[test]
+fn diffuse_bsdfs() {
+    check_bsdf("Lambertian", &LambertianBsdf { albedo: Vec3::ONE });
+    for sigma in [0.0, 20.0, 60.0] {
+        check_bsdf("Oren-Nayar", &OrenNayarBsdf::new(Vec3::ONE, sigma));
+    }
+}
+
+#[test]
+fn metal_bsdfs() {
+    for fuzzyness in [0.0, 1.0, 1.5] {
+        check_bsdf(
+            "fuzzy metal",
+            &FuzzyMetalBsdf {
+                albedo: Vec3::ONE,
+                fuzzyness,
+            },
+        );
+    }
+    for roughness in [0.0, 0.3, 0.7, 1.0] {
+        let distribution = TrowbridgeReitz::from_roughness(roughness);
+        check_bsdf(
+            "white conductor",
+            &ConductorBsdf {
+                reflectance: ConductorReflectance::Tint(Vec3::ONE),
+                distribution,
+            },
+        );
+        // Gold
+        check_bsdf(
+            "gold conductor",
+            &ConductorBsdf {
+                reflectance: ConductorReflectance::ComplexIor {
+                    eta: Vec3::new(0.143, 0.374, 1.442),
+                    k: Vec3::new(3.983, 2.385, 1.603),
+                },
+                distribution,
+            },
+        );
+    }
+}
+
+#[test]
+fn dielectric_bsdfs() {
+    for eta in [1.0 / 1.5, 1.5] {
+        check_bsdf("dielectric", &DielectricBsdf { eta });
+        // Reflectance doesn't jump when rough dielectrics become smooth
+        for wo in outgoing_directions() {
+            let sample = DielectricBsdf { eta }.sample(wo, 0.0, Vec2::ZERO).unwrap();
+            assert_near(
+                sample.pdf,
+                fresnel_dielectric(wo.z, eta),
+                1e-5,
+                "reflectance",
+            );
+        }
+        for roughness in [0.3, 0.7] {
+            check_bsdf(
+                "rough dielectric",
+                &RoughDielectricBsdf {
+                    eta,
+                    distribution: TrowbridgeReitz::from_roughness(roughness),
+                },
+            );
+        }
+    }
+}
+
+#[test]
+fn principled_lobes() {
+    for roughness in [0.0, 0.5, 1.0] {
+        check_bsdf(
+            "Burley diffuse",
+            &BurleyDiffuseBsdf {
+                base_color: Vec3::ONE,
+                roughness,
+                sheen: Vec3::ZERO,
+                specular_f0: Vec3::splat(0.04),
+            },
+        );
+    }
+    for alpha in [0.1, 0.5] {
+        check_bsdf("clearcoat", &ClearcoatBsdf { alpha });
+    }
+}
+
+/// Principled BSDF for the parameters at a hit on top of a unit sphere.
+fn principled_bsdf(params: Principled) -> PrincipledBsdf {
+    let sphere = Model::new(
+        Geometry::Sphere {
+            position: Vec3::ZERO,
+            radius: 1.0,
+        },
+        Arc::new(Material::Principled(params.clone())),
+    );
+    let hit = sphere
+        .hit(&Ray::new(Vec3::new(0.0, 5.0, 0.0), Vec3::NEG_Y))
+        .expect("Ray hits the sphere");
+    PrincipledBsdf::new(&params, &hit, 1.0 / params.ior)
+}
+
+#[test]
+fn principled_bsdf_conserves_energy() {
+    let white = Principled {
+        base_color: Vec3::ONE.into(),
+        ..Default::default()
+    };
+    for roughness in [0.3, 0.6, 1.0] {
+        for (metallic, specular) in [(0.0, 0.0), (0.0, 0.5), (0.0, 1.0), (0.5, 1.0), (1.0, 0.5)] {
+            check_bsdf(
+                &format!("principled metallic {metallic} specular {specular}"),
+                &principled_bsdf(Principled {
+                    roughness: roughness.into(),
+                    metallic: metallic.into(),
+                    specular,
+                    ..white.clone()
+                }),
+            );
+        }
+        check_bsdf(
+            "principled clearcoat",
+            &principled_bsdf(Principled {
+                roughness: roughness.into(),
+                clearcoat: 1.0,
+                clearcoat_gloss: 0.0,
+                ..white.clone()
+            }),
+        );
+        check_bsdf(
+            "principled glass",
+            &principled_bsdf(Principled {
+                roughness: roughness.into(),
+                transmission: 1.0,
+                ..white.clone()
+            }),
+        );
+    }
+}
@@ -0,0 +1,97 @@
+use std::path::{Path, PathBuf};
+
+use glam::{Vec2, Vec3};
+use mirror::loaders::{GltfError, import_gltf, load_gltf};
+use mirror::raytracer::{Hittable, Material, NormalMap, Projection, Ray};
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/gltf")
+        .join(name)
+}
+
+#[test]
+fn gltf_node_transforms_and_camera() {
+    let import = import_gltf(fixture("scene.gltf"), 1.0).unwrap();
+    assert_eq!(import.models.len(), 1);
+    let quad = &import.models[0];
+    // Child scale is applied before the parent translation
+    assert!((quad.area() - 16.0).abs() < 1e-5);
+
+    let ray = Ray::new(Vec3::new(1.0, 1.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+    let hit = quad.hit(&ray).expect("Ray hits the quad");
+    assert!((hit.distance - 5.0).abs() < 1e-5);
+    assert!(hit.is_front_face);
+    assert!(hit.uv.abs_diff_eq(Vec2::new(0.75, 0.75), 1e-5));
+
+    let camera = import.camera.expect("Scene has a camera");
+    assert!(camera.position().abs_diff_eq(Vec3::ZERO, 1e-6));
+    assert!(camera.forward().abs_diff_eq(Vec3::NEG_Z, 1e-6));
+    assert!(camera.up().abs_diff_eq(Vec3::NEG_Y, 1e-6));
+    let Projection::Perspective { fov } = *camera.projection() else {
+        panic!(
+            "Expected a perspective camera, found {:?}",
+            camera.projection()
+        );
+    };
+    assert!((fov - 0.8f32.to_degrees()).abs() < 1e-4);
+}
+
+#[test]
+fn gltf_metallic_roughness_material() {
+    let import = import_gltf(fixture("scene.gltf"), 1.0).unwrap();
+    let material = &import.models[0].material;
+    let Material::Principled(params) = &**material else {
+        panic!("Expected a principled material, found {material:?}");
+    };
+    // Base color factor multiplies the white external texture
+    let color = params.base_color.eval(Vec2::new(0.3, 0.6), Vec3::ZERO);
+    assert!(color.abs_diff_eq(Vec3::new(0.5, 0.25, 1.0), 1e-5));
+    assert_eq!(params.metallic.eval_scalar(Vec2::ZERO, Vec3::ZERO), 0.0);
+    assert_eq!(params.roughness.eval_scalar(Vec2::ZERO, Vec3::ZERO), 0.5);
+    let emission = params.emission.eval(Vec2::ZERO, Vec3::ZERO);
+    assert!(emission.abs_diff_eq(Vec3::new(4.0, 2.0, 1.0), 1e-5));
+    assert!(material.is_emissive());
+}
+
+#[test]
+fn gltf_embedded_data_and_mirroring() {
+    let scene = load_gltf(fixture("embedded.gltf"), 1.0).unwrap();
+    assert_eq!(scene.objects().len(), 1);
+    let triangle = &scene.objects()[0];
+
+    // Mirrored triangles keep facing +z
+    let ray = Ray::new(Vec3::new(-0.25, 0.25, 1.0), Vec3::new(0.0, 0.0, -1.0));
+    let hit = triangle.hit(&ray).expect("Ray hits the mirrored triangle");
+    assert!(hit.is_front_face);
+    assert!(hit.normal.abs_diff_eq(Vec3::Z, 1e-5));
+
+    let Material::NormalMapped {
+        material,
+        normal_map: NormalMap::Tangent { strength, .. },
+    } = &*triangle.material
+    else {
+        panic!(
+            "Expected a normal mapped material, found {:?}",
+            triangle.material
+        );
+    };
+    assert_eq!(*strength, 0.5);
+    let Material::Principled(params) = &**material else {
+        panic!("Expected a principled material, found {material:?}");
+    };
+    // Roughness and metallic are read from the green and blue channels
+    let roughness = params.roughness.eval_scalar(Vec2::ZERO, Vec3::ZERO);
+    assert!((roughness - 64.0 / 255.0).abs() < 1e-5);
+    assert_eq!(params.metallic.eval_scalar(Vec2::ZERO, Vec3::ZERO), 1.0);
+
+    // Without a camera the whole scene is framed
+    assert!(scene.camera().forward().abs_diff_eq(Vec3::NEG_Z, 1e-6));
+    assert!(scene.camera().position().z > 0.0);
+}
+
+#[test]
+fn gltf_missing_file() {
+    let error = load_gltf(fixture("does_not_exist.gltf"), 1.0).unwrap_err();
+    assert!(matches!(error, GltfError::Gltf(_)), "{error}");
+}
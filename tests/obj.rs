@@ -0,0 +1,126 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use glam::Vec3;
+use mirror::loaders::{ObjError, load_obj};
+use mirror::raytracer::{Geometry, Hittable, Material, Mesh, Model, Ray};
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/obj")
+        .join(name)
+}
+
+fn mesh(model: &Model) -> &Arc<Mesh> {
+    match &model.geometry {
+        Geometry::Mesh(mesh) => mesh,
+        geometry => panic!("Expected a mesh, found {geometry:?}"),
+    }
+}
+
+#[test]
+fn obj_quads_with_normals() {
+    let models = load_obj(fixture("cube.obj")).unwrap();
+    assert_eq!(models.len(), 1);
+    let cube = &models[0];
+    assert_eq!(mesh(cube).triangle_count(), 12);
+    assert!((mesh(cube).area() - 6.0).abs() < 1e-5);
+    assert!(matches!(*cube.material, Material::Diffuse { .. }));
+
+    let ray = Ray::new(Vec3::new(0.1, 0.2, 5.0), Vec3::new(0.0, 0.0, -1.0));
+    let hit = cube.hit(&ray).expect("Ray hits the cube");
+    assert!((hit.distance - 4.5).abs() < 1e-5);
+    assert!(hit.normal.abs_diff_eq(Vec3::Z, 1e-5));
+    assert!(hit.is_front_face);
+}
+
+#[test]
+fn obj_materials_and_uvs() {
+    let models = load_obj(fixture("textured.obj")).unwrap();
+    assert_eq!(models.len(), 4);
+    let [wood, glass, light, gold] = [0, 1, 2, 3].map(|idx| &models[idx]);
+
+    let Material::Principled(params) = &*wood.material else {
+        panic!("Expected a principled material, found {:?}", wood.material);
+    };
+    assert!((params.ior - 1.45).abs() < 1e-6);
+    assert!((params.specular - 0.5).abs() < 1e-6);
+    let color = params.base_color.eval(Default::default(), Vec3::ZERO);
+    assert!(color.abs_diff_eq(Vec3::new(0.578, 0.127, 0.032), 1e-3));
+
+    let Material::RoughDielectric {
+        refraction_index,
+        roughness,
+    } = &*glass.material
+    else {
+        panic!("Expected a dielectric material, found {:?}", glass.material);
+    };
+    assert_eq!(*refraction_index, 1.5);
+    assert_eq!(roughness.eval_scalar(Default::default(), Vec3::ZERO), 0.0);
+
+    assert!(light.material.is_emissive());
+    assert!(matches!(*gold.material, Material::Conductor { .. }));
+
+    // Texture coordinates are interpolated over the triangles
+    let ray = Ray::new(Vec3::new(0.5, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+    let hit = wood.hit(&ray).expect("Ray hits the wood triangle");
+    assert!(hit.uv.abs_diff_eq(glam::Vec2::new(0.75, 0.5), 1e-5));
+    assert!(hit.shading_normal.abs_diff_eq(Vec3::Y, 1e-5));
+}
+
+#[test]
+fn obj_relative_indices_and_groups() {
+    let models = load_obj(fixture("relative.obj")).unwrap();
+    assert_eq!(models.len(), 2);
+    // The pentagon is split into a triangle fan
+    assert_eq!(mesh(&models[0]).triangle_count(), 3);
+    assert!((mesh(&models[0]).area() - 1.25).abs() < 1e-5);
+    assert_eq!(mesh(&models[1]).triangle_count(), 1);
+    assert_eq!(mesh(&models[1]).positions()[0], Vec3::new(2.0, 0.0, 0.0));
+}
+
+#[test]
+fn obj_bad_face_index() {
+    let error = load_obj(fixture("bad_index.obj")).unwrap_err();
+    assert!(
+        matches!(
+            error,
+            ObjError::BadFaceIndex {
+                line: 5,
+                index: 4,
+                ..
+            }
+        ),
+        "{error}"
+    );
+}
+
+#[test]
+fn obj_unsupported_statement() {
+    let error = load_obj(fixture("unsupported.obj")).unwrap_err();
+    assert!(
+        matches!(
+            &error,
+            ObjError::UnsupportedStatement { line: 3, statement, .. } if statement == "l"
+        ),
+        "{error}"
+    );
+}
+
+#[test]
+fn obj_missing_files() {
+    let error = load_obj(fixture("does_not_exist.obj")).unwrap_err();
+    assert!(matches!(error, ObjError::Io { .. }), "{error}");
+
+    let error = load_obj(fixture("missing_mtl.obj")).unwrap_err();
+    assert!(
+        matches!(&error, ObjError::Io { path, .. } if path.ends_with("does_not_exist.mtl")),
+        "{error}"
+    );
+}
+
+#[test]
+fn obj_invalid_number() {
+    let error = load_obj(fixture("bad_number.obj")).unwrap_err();
+    assert!(matches!(error, ObjError::Syntax { line: 2, .. }), "{error}");
+}
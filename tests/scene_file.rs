@@ -0,0 +1,454 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use glam::{UVec3, Vec2, Vec3};
+use mirror::loaders::{SceneFileError, load_scene, save_scene};
+use mirror::raytracer::{
+    Environment, Geometry, Hittable, Material, Medium, NormalMap, Projection, Ray, Texture,
+    VolumeError, VoxelGrid,
+};
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/scene")
+        .join(name)
+}
+
+#[test]
+fn scene_file_cornell_box() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes/cornell.toml");
+    let scene = load_scene(path, 1.0).unwrap();
+    assert_eq!(scene.objects().len(), 8);
+    assert_eq!(scene.lights().len(), 1);
+    assert_eq!(scene.background(), Vec3::ZERO);
+
+    let camera = scene.camera();
+    assert_eq!(camera.position(), Vec3::new(278.0, 278.0, -800.0));
+    assert!(camera.forward().abs_diff_eq(Vec3::Z, 1e-6));
+    assert!(camera.up().abs_diff_eq(Vec3::NEG_Y, 1e-6));
+    assert_eq!(camera.projection(), &Projection::Perspective { fov: 40.0 });
+
+    // Objects naming the same material share it
+    let whites: Vec<_> = scene
+        .objects()
+        .iter()
+        .filter(|object| {
+            matches!(&*object.material, Material::Diffuse { albedo: Texture::Constant(albedo) }
+                if *albedo == Vec3::splat(0.73))
+        })
+        .collect();
+    assert_eq!(whites.len(), 4);
+    assert!(
+        whites
+            .iter()
+            .all(|object| Arc::ptr_eq(&object.material, &whites[0].material))
+    );
+}
+
+#[test]
+fn scene_file_textures_and_meshes() {
+    let scene = load_scene(fixture("textured.toml"), 1.0).unwrap();
+    assert_eq!(scene.objects().len(), 3);
+    let find = |predicate: fn(&Geometry) -> bool| {
+        scene
+            .objects()
+            .iter()
+            .find(|object| predicate(&object.geometry))
+            .unwrap()
+    };
+
+    // Image paths are relative to the scene file
+    let sphere = find(|geometry| matches!(geometry, Geometry::Sphere { .. }));
+    let Material::Principled(params) = &*sphere.material else {
+        panic!(
+            "Expected a principled material, found {:?}",
+            sphere.material
+        );
+    };
+    let Texture::Image(image) = &params.base_color else {
+        panic!("Expected an image texture, found {:?}", params.base_color);
+    };
+    assert_eq!(image.width(), 2);
+    assert_eq!(params.roughness.eval_scalar(Vec2::ZERO, Vec3::ZERO), 0.3);
+    assert_eq!(params.specular, 0.5);
+
+    let quad = find(|geometry| matches!(geometry, Geometry::Quad { .. }));
+    let Material::Diffuse { albedo } = &*quad.material else {
+        panic!("Expected a diffuse material, found {:?}", quad.material);
+    };
+    assert_eq!(albedo.eval(Vec2::splat(0.1), Vec3::ZERO), Vec3::splat(0.2));
+    assert_eq!(
+        albedo.eval(Vec2::new(0.6, 0.1), Vec3::ZERO),
+        Vec3::new(0.9, 0.8, 0.7)
+    );
+
+    let mesh = find(|geometry| matches!(geometry, Geometry::Mesh(_)));
+    assert!(matches!(
+        &*mesh.material,
+        Material::NormalMapped {
+            normal_map: NormalMap::Bump { .. },
+            ..
+        }
+    ));
+    let ray = Ray::new(Vec3::new(2.25, 0.25, 1.0), Vec3::NEG_Z);
+    let hit = mesh.hit(&ray).expect("Ray hits the mesh");
+    assert!(hit.uv.abs_diff_eq(Vec2::splat(0.25), 1e-5));
+}
+
+#[test]
+fn scene_file_save_round_trip() {
+    let scene = load_scene(fixture("textured.toml"), 1.0).unwrap();
+    let path = std::env::temp_dir().join(format!("mirror_round_trip_{}.toml", std::process::id()));
+    save_scene(&scene, &path).unwrap();
+    let loaded = load_scene(&path, 1.0);
+    std::fs::remove_file(&path).unwrap();
+    let loaded = loaded.unwrap();
+
+    assert_eq!(loaded.objects().len(), scene.objects().len());
+    assert!(
+        loaded
+            .camera()
+            .forward()
+            .abs_diff_eq(scene.camera().forward(), 1e-6)
+    );
+    assert!(loaded.camera().up().abs_diff_eq(scene.camera().up(), 1e-6));
+    assert_eq!(loaded.camera().aperture(), scene.camera().aperture());
+    assert_eq!(scene.camera().aperture().blades, 5);
+    for origin in [Vec3::new(0.0, 0.1, 5.0), Vec3::new(2.25, 0.25, 5.0)] {
+        let ray = Ray::new(origin, Vec3::NEG_Z);
+        let hit = scene.hit(&ray).expect("Ray hits the scene");
+        let loaded_hit = loaded.hit(&ray).expect("Ray hits the loaded scene");
+        assert_eq!(hit.distance, loaded_hit.distance);
+        assert_eq!(hit.uv, loaded_hit.uv);
+        assert_eq!(
+            format!("{:?}", hit.material),
+            format!("{:?}", loaded_hit.material)
+        );
+    }
+}
+
+#[test]
+fn scene_file_instances_and_transforms() {
+    let scene = load_scene(fixture("instances.toml"), 1.0).unwrap();
+    let instances: Vec<_> = scene
+        .objects()
+        .iter()
+        .filter_map(|object| match &object.geometry {
+            Geometry::Instance(geometry) => Some(geometry),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(instances.len(), 2);
+    assert!(Arc::ptr_eq(instances[0], instances[1]));
+
+    // Rotated cube is hit on its edge, scaled cube on its stretched face
+    let ray = Ray::new(Vec3::new(-2.0, 0.0, 10.0), Vec3::NEG_Z);
+    let hit = scene.hit(&ray).expect("Ray hits the rotated cube");
+    assert!((hit.distance - (10.0 - 0.5 * 2f32.sqrt())).abs() < 1e-4);
+    let ray = Ray::new(Vec3::new(10.0, 0.0, 0.0), Vec3::NEG_X);
+    let hit = scene.hit(&ray).expect("Ray hits the scaled cube");
+    assert!((hit.distance - 7.0).abs() < 1e-4);
+    assert!(hit.normal.abs_diff_eq(Vec3::X, 1e-5));
+    // Sheared sphere is moved up by the matrix translation
+    let ray = Ray::new(Vec3::new(0.0, 3.0, 10.0), Vec3::NEG_Z);
+    assert!(scene.hit(&ray).is_some());
+
+    // Saving keeps the instances shared and the transforms
+    let path = std::env::temp_dir().join(format!("mirror_instances_{}.toml", std::process::id()));
+    save_scene(&scene, &path).unwrap();
+    let content = std::fs::read_to_string(&path);
+    let loaded = load_scene(&path, 1.0);
+    std::fs::remove_file(&path).unwrap();
+    let (content, loaded) = (content.unwrap(), loaded.unwrap());
+    assert!(content.contains("[geometries.geometry0]"), "{content}");
+    assert!(content.contains("matrix"), "{content}");
+    for (object, loaded_object) in scene.objects().iter().zip(loaded.objects()) {
+        let (transform, loaded_transform) = (
+            object.transform.as_ref().unwrap(),
+            loaded_object.transform.as_ref().unwrap(),
+        );
+        assert!(
+            transform
+                .to_world()
+                .abs_diff_eq(loaded_transform.to_world(), 1e-5)
+        );
+    }
+}
+
+#[test]
+fn scene_file_csg() {
+    let scene = load_scene(fixture("csg.toml"), 1.0).unwrap();
+    let ray = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::NEG_Z);
+    let hit = scene.hit(&ray).expect("Ray hits the lens");
+    assert!((hit.distance - 4.6).abs() < 1e-4);
+    let ray = Ray::new(Vec3::new(0.0, 1.5, 5.0), Vec3::NEG_Z);
+    assert!(scene.hit(&ray).is_none());
+}
+
+#[test]
+fn scene_file_sdf() {
+    let scene = load_scene(fixture("sdf.toml"), 1.0).unwrap();
+    let ray = Ray::new(Vec3::new(-1.0, 0.0, 5.0), Vec3::NEG_Z);
+    let hit = scene.hit(&ray).expect("Ray hits the sphere");
+    assert!((hit.distance - 4.5).abs() < 1e-3);
+    let ray = Ray::new(Vec3::new(1.0, 0.0, 5.0), Vec3::NEG_Z);
+    let hit = scene.hit(&ray).expect("Ray hits the rounded box");
+    assert!((hit.distance - 4.5).abs() < 1e-3);
+    let ray = Ray::new(Vec3::new(0.0, 0.9, 5.0), Vec3::NEG_Z);
+    assert!(scene.hit(&ray).is_none());
+}
+
+#[test]
+fn scene_file_media() {
+    let scene = load_scene(fixture("media.toml"), 1.0).unwrap();
+    assert!(scene.medium().is_some());
+    let ray = Ray::new(Vec3::new(-1.0, 0.0, 5.0), Vec3::NEG_Z);
+    let hit = scene.hit(&ray).expect("Ray hits the glass sphere");
+    assert!(hit.medium.is_some());
+    let ray = Ray::new(Vec3::new(1.0, 0.0, 5.0), Vec3::NEG_Z);
+    let hit = scene.hit(&ray).expect("Ray hits the smoke boundary");
+    assert!(hit.material.is_interface());
+    assert!(hit.medium.is_some());
+
+    let path = std::env::temp_dir().join(format!("mirror_media_{}.toml", std::process::id()));
+    save_scene(&scene, &path).unwrap();
+    let reloaded = load_scene(&path, 1.0);
+    std::fs::remove_file(&path).unwrap();
+    let reloaded = reloaded.unwrap();
+    assert!(reloaded.medium().is_some());
+    assert_eq!(
+        reloaded
+            .objects()
+            .iter()
+            .filter(|o| o.medium.is_some())
+            .count(),
+        2
+    );
+}
+
+#[test]
+fn scene_file_voxel_grid() {
+    let scene = load_scene(fixture("volume.toml"), 1.0).unwrap();
+    let medium = scene.objects()[0].medium.as_deref();
+    let Some(Medium::Grid { grid, .. }) = medium else {
+        panic!("Expected a grid medium, found {medium:?}");
+    };
+    assert_eq!(grid.resolution(), UVec3::splat(4));
+    assert!((grid.density(Vec3::splat(0.125)) - 0.0).abs() < 1e-6);
+    assert!((grid.density(Vec3::splat(0.875)) - 1.0).abs() < 1e-6);
+
+    // Grid paths are written relative to the saved scene file
+    let path = std::env::temp_dir().join(format!("mirror_volume_{}.toml", std::process::id()));
+    save_scene(&scene, &path).unwrap();
+    let content = std::fs::read_to_string(&path);
+    std::fs::remove_file(&path).unwrap();
+    assert!(content.unwrap().contains("gradient.vol"));
+
+    let error = VoxelGrid::from_file(fixture("volume.toml")).unwrap_err();
+    assert!(matches!(error, VolumeError::Format { .. }), "{error}");
+}
+
+#[test]
+fn voxel_grid_rejects_oversized_headers() {
+    let mut bytes = b"VOL\x03".to_vec();
+    for value in [1, i32::MAX, i32::MAX, i32::MAX, 1] {
+        bytes.extend(i32::to_le_bytes(value));
+    }
+    bytes.resize(48, 0);
+    let path = std::env::temp_dir().join(format!("mirror_oversized_{}.vol", std::process::id()));
+    std::fs::write(&path, bytes).unwrap();
+    let error = VoxelGrid::from_file(&path);
+    std::fs::remove_file(&path).unwrap();
+    assert!(matches!(error, Err(VolumeError::Format { .. })));
+}
+
+#[test]
+fn scene_file_projections() {
+    let scene = load_scene(fixture("fisheye.toml"), 2.0).unwrap();
+    assert_eq!(
+        scene.camera().projection(),
+        &Projection::Fisheye { fov: 180.0 }
+    );
+    // The edge of the image circle looks sideways
+    let ray = scene
+        .camera()
+        .create_viewport_ray(0.5, 0.0, Vec2::ZERO, 0.0)
+        .expect("Viewport point within 180 degrees");
+    assert!(ray.direction().abs_diff_eq(Vec3::X, 1e-5));
+    assert!(
+        scene
+            .camera()
+            .create_viewport_ray(1.0, 1.0, Vec2::ZERO, 0.0)
+            .is_none()
+    );
+
+    let path = std::env::temp_dir().join(format!("mirror_fisheye_{}.toml", std::process::id()));
+    save_scene(&scene, &path).unwrap();
+    let loaded = load_scene(&path, 2.0);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        loaded.unwrap().camera().projection(),
+        scene.camera().projection()
+    );
+}
+
+#[test]
+fn scene_file_motion() {
+    let scene = load_scene(fixture("motion.toml"), 1.0).unwrap();
+    assert_eq!(scene.camera().shutter(), (0.0, 0.5));
+
+    // Motion applies after the transform of the sphere
+    let ray = Ray::new(Vec3::new(1.0, 0.0, 5.0), Vec3::NEG_Z);
+    assert!(scene.hit(&ray).is_none());
+    let hit = scene
+        .hit(&ray.with_time(0.75))
+        .expect("Ray hits the moving sphere");
+    assert!(hit.position.abs_diff_eq(Vec3::new(1.0, 0.0, 0.5), 1e-5));
+
+    let ray = Ray::new(Vec3::new(0.0, 3.0, 5.0), Vec3::NEG_Z);
+    assert!(scene.hit(&ray.with_time(0.5)).is_some());
+    let ray = Ray::new(Vec3::new(0.0, -3.0, 5.0), Vec3::NEG_Z);
+    assert!(scene.hit(&ray.with_time(1.5)).is_none());
+    let hit = scene
+        .hit(&ray.with_time(3.0))
+        .expect("Ray hits the cuboid after its last keyframe");
+    assert!((hit.distance - 4.5).abs() < 1e-5);
+
+    let path = std::env::temp_dir().join(format!("mirror_motion_{}.toml", std::process::id()));
+    save_scene(&scene, &path).unwrap();
+    let loaded = load_scene(&path, 1.0);
+    std::fs::remove_file(&path).unwrap();
+    let loaded = loaded.unwrap();
+    assert_eq!(loaded.camera().shutter(), scene.camera().shutter());
+    for (origin, time) in [
+        (Vec3::new(0.5, 0.0, 5.0), 0.6),
+        (Vec3::new(0.3, 2.0, 5.0), 1.2),
+    ] {
+        let ray = Ray::new(origin, Vec3::NEG_Z).with_time(time);
+        let hit = scene.hit(&ray).expect("Ray hits the scene");
+        let loaded_hit = loaded.hit(&ray).expect("Ray hits the loaded scene");
+        assert!((hit.distance - loaded_hit.distance).abs() < 1e-4);
+    }
+}
+
+#[test]
+fn scene_file_environment() {
+    let scene = load_scene(fixture("environment.toml"), 1.0).unwrap();
+    let Some(Environment::Map(environment)) = scene.environment().map(|e| &**e) else {
+        panic!("Scene has an environment map");
+    };
+    assert_eq!((environment.width(), environment.height()), (8, 4));
+    assert_eq!(environment.rotation(), 90.0);
+    assert!(
+        scene
+            .escaped_radiance(Vec3::Y)
+            .abs_diff_eq(Vec3::new(0.5, 0.75, 1.0), 1e-5)
+    );
+    assert!(
+        scene
+            .escaped_radiance(Vec3::NEG_Y)
+            .abs_diff_eq(Vec3::splat(0.25), 1e-5)
+    );
+
+    // Environment paths are written relative to the saved scene file
+    let path = std::env::temp_dir().join(format!("mirror_environment_{}.toml", std::process::id()));
+    save_scene(&scene, &path).unwrap();
+    let content = std::fs::read_to_string(&path).unwrap();
+    let loaded = load_scene(&path, 1.0);
+    std::fs::remove_file(&path).unwrap();
+    assert!(content.contains("sky.hdr"));
+    let loaded = loaded.unwrap();
+    let Some(Environment::Map(loaded_environment)) = loaded.environment().map(|e| &**e) else {
+        panic!("Environment map is saved");
+    };
+    assert_eq!(loaded_environment.rotation(), 90.0);
+    let direction = Vec3::new(0.2, 0.3, -0.9).normalize();
+    assert_eq!(
+        loaded.escaped_radiance(direction),
+        scene.escaped_radiance(direction)
+    );
+}
+
+#[test]
+fn scene_file_sky() {
+    let scene = load_scene(fixture("sky.toml"), 1.0).unwrap();
+    let Some(Environment::Sky(sky)) = scene.environment().map(|e| &**e) else {
+        panic!("Scene has a sky");
+    };
+    assert!(
+        sky.sun_direction()
+            .abs_diff_eq(Vec3::new(0.0, 1.0, 1.0).normalize(), 1e-6)
+    );
+    assert_eq!((sky.turbidity(), sky.intensity()), (4.0, 2.0));
+    assert_eq!(scene.escaped_radiance(Vec3::NEG_Y), Vec3::ZERO);
+
+    let path = std::env::temp_dir().join(format!("mirror_sky_{}.toml", std::process::id()));
+    save_scene(&scene, &path).unwrap();
+    let loaded = load_scene(&path, 1.0);
+    std::fs::remove_file(&path).unwrap();
+    let loaded = loaded.unwrap();
+    for direction in [
+        Vec3::Y,
+        sky.sun_direction(),
+        Vec3::new(0.2, 0.3, -0.9).normalize(),
+    ] {
+        assert_eq!(
+            loaded.escaped_radiance(direction),
+            scene.escaped_radiance(direction)
+        );
+    }
+}
+
+#[test]
+fn scene_file_errors() {
+    let error = load_scene(fixture("does_not_exist.toml"), 1.0).unwrap_err();
+    assert!(matches!(error, SceneFileError::Read { .. }), "{error}");
+
+    let error = load_scene(fixture("unknown_material.toml"), 1.0).unwrap_err();
+    assert!(
+        matches!(&error, SceneFileError::UnknownMaterial { name, .. } if name == "black"),
+        "{error}"
+    );
+
+    let error = load_scene(fixture("bad_camera.toml"), 1.0).unwrap_err();
+    assert!(
+        matches!(error, SceneFileError::InvalidCamera { .. }),
+        "{error}"
+    );
+
+    let error = load_scene(fixture("ambiguous_camera.toml"), 1.0).unwrap_err();
+    assert!(
+        matches!(error, SceneFileError::InvalidCamera { .. }),
+        "{error}"
+    );
+
+    let error = load_scene(fixture("invalid_scene.toml"), 1.0).unwrap_err();
+    let SceneFileError::InvalidScene { source, .. } = &error else {
+        panic!("Expected an invalid scene error, found {error}");
+    };
+    assert_eq!(source.issues.len(), 3, "{error}");
+
+    let error = load_scene(fixture("bad_geometry.toml"), 1.0).unwrap_err();
+    assert!(matches!(error, SceneFileError::Parse { .. }), "{error}");
+
+    let error = load_scene(fixture("ambiguous_object.toml"), 1.0).unwrap_err();
+    assert!(
+        matches!(
+            error,
+            SceneFileError::InvalidObjectGeometry { object: 0, .. }
+        ),
+        "{error}"
+    );
+
+    let error = load_scene(fixture("missing_environment.toml"), 1.0).unwrap_err();
+    assert!(
+        matches!(&error, SceneFileError::Parse { .. } if error.to_string().contains("missing.hdr")),
+        "{error}"
+    );
+
+    let error = load_scene(fixture("ambiguous_environment.toml"), 1.0).unwrap_err();
+    assert!(
+        matches!(error, SceneFileError::ConflictingEnvironment { .. }),
+        "{error}"
+    );
+}
@@ -1,5 +1,16 @@
-use glam::Vec3;
-use mirror::raytracer::{Aabb, Intersectable, Ray};
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use glam::{Quat, UVec3, Vec2, Vec3};
+use mirror::raytracer::{
+    Aabb, Aperture, Bounded, BvhNode, Camera, CsgOperation, Environment, EnvironmentMap, Geometry,
+    Hittable, ImageTexture, Intersectable, Keyframe, Material, Medium, MediumSample, Mesh, Model,
+    Motion, NormalMap, Principled, Projection, Ray, Renderer, SUN_ANGULAR_RADIUS, Scene,
+    SceneIssue, Sdf, Sky, Texture, Transform, VoxelGrid, sun_solid_angle,
+};
+use mirror::utils::sample_cosine_hemisphere;
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
 
 #[test]
 fn aabb_inner_intersection() {
@@ -41,3 +52,1892 @@ fn aabb_tangent_intersection() {
     let ray = Ray::new(Vec3::new(-1.0, 0.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
     assert_eq!(aabb.intersect(&ray), false);
 }
+
+#[test]
+fn checker_texture_alternates() {
+    let texture = Texture::Checker {
+        scale: 2.0,
+        even: Arc::new(Vec3::ONE.into()),
+        odd: Arc::new(Vec3::ZERO.into()),
+    };
+    assert_eq!(texture.eval(Vec2::new(0.25, 0.25), Vec3::ZERO), Vec3::ONE);
+    assert_eq!(texture.eval(Vec2::new(0.75, 0.25), Vec3::ZERO), Vec3::ZERO);
+    assert_eq!(texture.eval(Vec2::new(0.75, 0.75), Vec3::ZERO), Vec3::ONE);
+}
+
+#[test]
+fn scene_encoding_shares_materials() {
+    let material = Arc::new(Material::Diffuse {
+        albedo: Texture::Image(Arc::new(ImageTexture::new(2, 2, vec![255; 12], true))),
+    });
+    let objects = (0..4)
+        .map(|i| {
+            Arc::new(Model::new(
+                Geometry::Sphere {
+                    position: Vec3::new(i as f32 * 3.0, 0.0, 0.0),
+                    radius: 1.0,
+                },
+                material.clone(),
+            ))
+        })
+        .collect();
+    let camera = Camera::new(Vec3::ZERO, Vec3::Z, Vec3::Y, 45.0, 1.0);
+    let scene = Scene::with_background(camera, objects, Vec3::ONE);
+
+    let bytes = bincode::encode_to_vec(&scene, bincode::config::standard()).unwrap();
+    let (decoded, _): (Scene, _) =
+        bincode::decode_from_slice(&bytes, bincode::config::standard()).unwrap();
+    let decoded_objects = decoded.objects();
+    assert_eq!(decoded_objects.len(), 4);
+    assert!(
+        decoded_objects
+            .iter()
+            .all(|object| Arc::ptr_eq(&object.material, &decoded_objects[0].material))
+    );
+}
+
+#[test]
+fn scene_encoding_shares_image_textures() {
+    let image = Arc::new(ImageTexture::new(16, 16, vec![255; 16 * 16 * 3], true));
+    let materials = [
+        Material::Diffuse {
+            albedo: Texture::Image(image.clone()),
+        },
+        Material::Principled(Principled {
+            base_color: Texture::Checker {
+                scale: 4.0,
+                even: Arc::new(Texture::Image(image.clone())),
+                odd: Arc::new(Vec3::ZERO.into()),
+            },
+            roughness: Texture::Image(image.clone()),
+            ..Default::default()
+        }),
+    ];
+    let objects = materials
+        .into_iter()
+        .enumerate()
+        .map(|(i, material)| {
+            Arc::new(Model::new(
+                Geometry::Sphere {
+                    position: Vec3::new(i as f32 * 3.0, 0.0, 0.0),
+                    radius: 1.0,
+                },
+                Arc::new(material),
+            ))
+        })
+        .collect();
+    let camera = Camera::new(Vec3::ZERO, Vec3::Z, Vec3::Y, 45.0, 1.0);
+    let scene = Scene::with_background(camera, objects, Vec3::ONE);
+
+    let bytes = bincode::encode_to_vec(&scene, bincode::config::standard()).unwrap();
+    assert!(bytes.len() < 2 * 16 * 16 * 3);
+    let (decoded, _): (Scene, _) =
+        bincode::decode_from_slice(&bytes, bincode::config::standard()).unwrap();
+    let objects = decoded.objects();
+    let Material::Diffuse {
+        albedo: Texture::Image(image),
+    } = &*objects[0].material
+    else {
+        panic!("Expected an image texture");
+    };
+    let Material::Principled(params) = &*objects[1].material else {
+        panic!("Expected a principled material");
+    };
+    let (Texture::Checker { even, .. }, Texture::Image(roughness)) =
+        (&params.base_color, &params.roughness)
+    else {
+        panic!("Expected image textures");
+    };
+    assert!(matches!(&**even, Texture::Image(even) if Arc::ptr_eq(even, image)));
+    assert!(Arc::ptr_eq(roughness, image));
+    assert_eq!(image.sample(Vec2::splat(0.5)), Vec3::ONE);
+}
+
+#[test]
+fn image_texture_decoding_rejects_bad_sizes() {
+    // Same layout as an encoded texture: size, pixels, sRGB flag and path
+    let config = bincode::config::standard();
+    let decode = |width: u32, height: u32, pixels: Vec<u8>| {
+        let bytes =
+            bincode::encode_to_vec((width, height, pixels, true, None::<String>), config).unwrap();
+        bincode::decode_from_slice::<ImageTexture, _>(&bytes, config).map(|(texture, _)| texture)
+    };
+    let texture = decode(2, 1, vec![0, 0, 0, 255, 255, 255]).unwrap();
+    assert_eq!((texture.width(), texture.height()), (2, 1));
+    assert!(decode(0, 1, Vec::new()).is_err());
+    assert!(decode(2, 2, vec![0; 6]).is_err());
+    assert!(decode(u32::MAX, u32::MAX, vec![0; 3]).is_err());
+}
+
+fn assert_vec3_near(a: Vec3, b: Vec3) {
+    assert!(a.abs_diff_eq(b, 1e-4), "{a} != {b}");
+}
+
+fn diffuse_model(geometry: Geometry) -> Model {
+    Model::new(
+        geometry,
+        Arc::new(Material::Diffuse {
+            albedo: Vec3::ONE.into(),
+        }),
+    )
+}
+
+#[test]
+fn sphere_hit_surface_parameterization() {
+    let sphere = diffuse_model(Geometry::Sphere {
+        position: Vec3::new(0.0, 0.0, -5.0),
+        radius: 2.0,
+    });
+    let ray = Ray::new(Vec3::new(10.0, 0.0, -5.0), Vec3::new(-1.0, 0.0, 0.0));
+    let hit = sphere.hit(&ray).expect("Ray hits the sphere");
+
+    assert_vec3_near(hit.position, Vec3::new(2.0, 0.0, -5.0));
+    assert_vec3_near(hit.normal, Vec3::X);
+    assert_vec3_near(hit.shading_normal, Vec3::X);
+    assert!(hit.uv.abs_diff_eq(Vec2::new(0.5, 0.5), 1e-4));
+    assert_vec3_near(hit.dpdu, Vec3::new(0.0, 0.0, -4.0 * PI));
+    assert_vec3_near(hit.dpdv, Vec3::new(0.0, 2.0 * PI, 0.0));
+}
+
+#[test]
+fn sphere_hit_from_inside_keeps_tangents() {
+    let sphere = diffuse_model(Geometry::Sphere {
+        position: Vec3::ZERO,
+        radius: 1.0,
+    });
+    let ray = Ray::new(Vec3::ZERO, Vec3::new(0.0, 0.0, 1.0));
+    let hit = sphere.hit(&ray).expect("Ray hits the sphere");
+
+    assert!(!hit.is_front_face);
+    assert_vec3_near(hit.normal, -Vec3::Z);
+    assert!(hit.uv.abs_diff_eq(Vec2::new(0.25, 0.5), 1e-4));
+    // Tangents still follow the outward orientation
+    assert!(hit.dpdu.cross(hit.dpdv).dot(Vec3::Z) > 0.0);
+}
+
+#[test]
+fn quad_hit_surface_parameterization() {
+    let u = Vec3::new(4.0, 0.0, 0.0);
+    let v = Vec3::new(0.0, 2.0, 0.0);
+    let quad = diffuse_model(Geometry::Quad {
+        position: Vec3::new(-1.0, -1.0, -3.0),
+        u,
+        v,
+    });
+    let ray = Ray::new(Vec3::ZERO, Vec3::new(0.0, 0.0, -1.0));
+    let hit = quad.hit(&ray).expect("Ray hits the quad");
+
+    assert_vec3_near(hit.position, Vec3::new(0.0, 0.0, -3.0));
+    assert_vec3_near(hit.normal, Vec3::Z);
+    assert_vec3_near(hit.shading_normal, Vec3::Z);
+    assert!(hit.uv.abs_diff_eq(Vec2::new(0.25, 0.5), 1e-4));
+    assert_vec3_near(hit.dpdu, u);
+    assert_vec3_near(hit.dpdv, v);
+}
+
+#[test]
+fn cuboid_hit_surface_parameterization() {
+    let cuboid = diffuse_model(Geometry::Cuboid {
+        position: Vec3::ZERO,
+        size: Vec3::new(2.0, 4.0, 6.0),
+    });
+    let ray = Ray::new(Vec3::new(0.5, 10.0, 1.5), Vec3::new(0.0, -1.0, 0.0));
+    let hit = cuboid.hit(&ray).expect("Ray hits the cuboid");
+
+    assert_vec3_near(hit.position, Vec3::new(0.5, 2.0, 1.5));
+    assert_vec3_near(hit.normal, Vec3::Y);
+    assert!(hit.is_front_face);
+    assert!(hit.uv.min_element() >= 0.0 && hit.uv.max_element() <= 1.0);
+    assert_vec3_near(hit.dpdu.cross(hit.dpdv).normalize(), Vec3::Y);
+    // Moving along the derivatives from the face corner reaches the hit
+    let corner = hit.position - hit.uv.x * hit.dpdu - hit.uv.y * hit.dpdv;
+    assert_vec3_near(corner.abs(), Vec3::new(1.0, 2.0, 3.0));
+}
+
+#[test]
+fn disk_hit_surface_parameterization() {
+    let disk = diffuse_model(Geometry::Disk {
+        position: Vec3::new(0.0, 0.0, -5.0),
+        normal: Vec3::Z,
+        radius: 2.0,
+    });
+    let ray = Ray::new(Vec3::new(0.0, 1.0, 0.0), Vec3::NEG_Z);
+    let hit = disk.hit(&ray).expect("Ray hits the disk");
+
+    assert!((hit.distance - 5.0).abs() < 1e-5);
+    assert_vec3_near(hit.normal, Vec3::Z);
+    assert!((hit.uv.y - 0.5).abs() < 1e-5);
+    assert_vec3_near(hit.dpdu.cross(hit.dpdv).normalize(), Vec3::Z);
+    assert!((hit.object_area - 4.0 * PI).abs() < 1e-4);
+
+    let ray = Ray::new(Vec3::new(0.0, 2.1, 0.0), Vec3::NEG_Z);
+    assert!(disk.hit(&ray).is_none());
+}
+
+#[test]
+fn cylinder_and_cone_hits() {
+    let cylinder = diffuse_model(Geometry::Cylinder {
+        position: Vec3::ZERO,
+        axis: Vec3::new(0.0, 2.0, 0.0),
+        radius: 1.0,
+    });
+    // Side
+    let ray = Ray::new(Vec3::new(5.0, 0.5, 0.0), Vec3::NEG_X);
+    let hit = cylinder.hit(&ray).expect("Ray hits the cylinder side");
+    assert!((hit.distance - 4.0).abs() < 1e-5);
+    assert_vec3_near(hit.normal, Vec3::X);
+    assert!((hit.uv.y - 0.25).abs() < 1e-5);
+    assert_vec3_near(hit.dpdu.cross(hit.dpdv).normalize(), Vec3::X);
+    assert!((hit.object_area - cylinder.area()).abs() < 1e-4);
+    // Top cap, and no hit through the open ends of the side
+    let ray = Ray::new(Vec3::new(0.5, 5.0, 0.0), Vec3::NEG_Y);
+    let hit = cylinder.hit(&ray).expect("Ray hits the cylinder cap");
+    assert!((hit.distance - 3.0).abs() < 1e-5);
+    assert_vec3_near(hit.normal, Vec3::Y);
+    assert!((hit.object_area - 6.0 * PI).abs() < 1e-4);
+    let ray = Ray::new(Vec3::new(5.0, 2.5, 0.0), Vec3::NEG_X);
+    assert!(cylinder.hit(&ray).is_none());
+
+    let cone = diffuse_model(Geometry::Cone {
+        position: Vec3::ZERO,
+        axis: Vec3::new(0.0, 2.0, 0.0),
+        radius: 1.0,
+    });
+    // Side at half height, where the radius is halved
+    let ray = Ray::new(Vec3::new(5.0, 1.0, 0.0), Vec3::NEG_X);
+    let hit = cone.hit(&ray).expect("Ray hits the cone side");
+    assert!((hit.distance - 4.5).abs() < 1e-5);
+    assert_vec3_near(hit.normal, Vec3::new(2.0, 1.0, 0.0).normalize());
+    assert!(hit.dpdu.cross(hit.dpdv).dot(hit.normal) > 0.0);
+    // Base cap
+    let ray = Ray::new(Vec3::new(0.5, -5.0, 0.0), Vec3::Y);
+    let hit = cone.hit(&ray).expect("Ray hits the cone base");
+    assert!((hit.distance - 5.0).abs() < 1e-5);
+    assert_vec3_near(hit.normal, Vec3::NEG_Y);
+    // Ray passing over the apex
+    let ray = Ray::new(Vec3::new(5.0, 2.1, 0.0), Vec3::NEG_X);
+    assert!(cone.hit(&ray).is_none());
+}
+
+#[test]
+fn primitive_surface_samples_match_hits() {
+    let primitives = [
+        Geometry::Disk {
+            position: Vec3::new(1.0, 2.0, 3.0),
+            normal: Vec3::new(1.0, 1.0, 0.0),
+            radius: 1.5,
+        },
+        Geometry::Cylinder {
+            position: Vec3::new(-1.0, 0.0, 1.0),
+            axis: Vec3::new(0.5, 2.0, -1.0),
+            radius: 0.7,
+        },
+        Geometry::Cone {
+            position: Vec3::ZERO,
+            axis: Vec3::new(0.0, 0.0, 3.0),
+            radius: 1.2,
+        },
+    ];
+    for geometry in primitives {
+        let model = diffuse_model(geometry);
+        let aabb = model.aabb();
+        for i in 0..8 {
+            for j in 0..8 {
+                let u = Vec2::new(i as f32 + 0.5, j as f32 + 0.5) / 8.0;
+                let sample = model.sample_surface(u, 0.0);
+                assert!((sample.pdf * model.area() - 1.0).abs() < 1e-4);
+                assert!(
+                    sample.position.cmpge(aabb.min_position - 1e-4).all()
+                        && sample.position.cmple(aabb.max_position + 1e-4).all(),
+                    "{:?}: {} is out of bounds",
+                    model.geometry,
+                    sample.position
+                );
+
+                // Sampled position and normal agree with a ray hitting it
+                let origin = sample.position + 0.01 * sample.normal;
+                let hit = model
+                    .hit(&Ray::new(origin, -sample.normal))
+                    .expect("Ray hits the sampled position");
+                assert_vec3_near(hit.position, sample.position);
+                assert_vec3_near(hit.normal, sample.normal);
+                assert!((hit.object_area * sample.pdf - 1.0).abs() < 1e-4);
+                assert!(hit.uv.abs_diff_eq(sample.uv, 1e-3), "{:?}", model.geometry);
+            }
+        }
+    }
+}
+
+#[test]
+fn infinite_planes_are_outside_the_bvh() {
+    let light = Arc::new(Material::DiffuseLight {
+        emission: Vec3::ONE.into(),
+    });
+    let objects = vec![
+        Arc::new(Model::new(
+            Geometry::Plane {
+                position: Vec3::ZERO,
+                normal: Vec3::Y,
+            },
+            light.clone(),
+        )),
+        Arc::new(Model::new(
+            Geometry::Sphere {
+                position: Vec3::new(0.0, 1.0, 0.0),
+                radius: 1.0,
+            },
+            light,
+        )),
+    ];
+    let camera = Camera::new(Vec3::ZERO, Vec3::Z, Vec3::Y, 45.0, 1.0);
+    let scene = Scene::try_new(camera, objects).unwrap();
+    // Emissive planes can't be sampled
+    assert_eq!(scene.lights().len(), 1);
+
+    let ray = Ray::new(Vec3::new(1000.0, 10.0, -500.0), Vec3::NEG_Y);
+    let hit = scene.hit(&ray).expect("Ray hits the plane far away");
+    assert!((hit.distance - 10.0).abs() < 1e-3);
+    // Surface coordinates are distances along the plane
+    assert!((hit.uv.length() - Vec2::new(1000.0, 500.0).length()).abs() < 0.1);
+    assert_eq!(scene.light_pdf(&ray, &hit), 0.0);
+    // Closer bounded objects still occlude the plane
+    let ray = Ray::new(Vec3::new(0.0, 10.0, 0.0), Vec3::NEG_Y);
+    let hit = scene.hit(&ray).expect("Ray hits the sphere");
+    assert!((hit.distance - 8.0).abs() < 1e-4);
+}
+
+#[test]
+fn csg_lens_boundaries() {
+    let sphere = |z: f32| {
+        Arc::new(Geometry::Sphere {
+            position: Vec3::new(0.0, 0.0, z),
+            radius: 2.0,
+        })
+    };
+    let lens = diffuse_model(Geometry::Csg {
+        operation: CsgOperation::Intersection,
+        left: sphere(-1.6),
+        right: sphere(1.6),
+    });
+    let ray = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::NEG_Z);
+    let hit = lens.hit(&ray).expect("Ray enters the lens");
+    assert!((hit.distance - 4.6).abs() < 1e-4);
+    assert!(hit.is_front_face);
+    assert_vec3_near(hit.normal, Vec3::Z);
+
+    // Leaving the lens from the inside, as a refracted ray does
+    let ray = Ray::new(Vec3::ZERO, Vec3::NEG_Z);
+    let hit = lens.hit(&ray).expect("Ray leaves the lens");
+    assert!((hit.distance - 0.4).abs() < 1e-4);
+    assert!(!hit.is_front_face);
+    assert_vec3_near(hit.normal, Vec3::Z);
+
+    // Rays outside of the lens rim miss it even though they hit the spheres
+    let ray = Ray::new(Vec3::new(0.0, 1.5, 5.0), Vec3::NEG_Z);
+    assert!(lens.hit(&ray).is_none());
+    let aabb = lens.aabb();
+    assert!((aabb.min_position.z + 0.4).abs() < 1e-4 && (aabb.max_position.z - 0.4).abs() < 1e-4);
+}
+
+#[test]
+fn csg_difference_and_union() {
+    let cavity = diffuse_model(Geometry::Csg {
+        operation: CsgOperation::Difference,
+        left: Arc::new(Geometry::Cuboid {
+            position: Vec3::ZERO,
+            size: Vec3::splat(2.0),
+        }),
+        right: Arc::new(Geometry::Sphere {
+            position: Vec3::new(0.0, 0.0, 1.0),
+            radius: 0.5,
+        }),
+    });
+    // The ray enters the cube inside the sphere and reaches the cavity wall
+    let ray = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::NEG_Z);
+    let hit = cavity.hit(&ray).expect("Ray hits the cavity");
+    assert!((hit.distance - 4.5).abs() < 1e-4);
+    assert!(hit.is_front_face);
+    assert_vec3_near(hit.normal, Vec3::Z);
+    assert!(hit.dpdu.cross(hit.dpdv).dot(Vec3::Z) > 0.0);
+
+    let sphere = |x: f32| {
+        Arc::new(Geometry::Sphere {
+            position: Vec3::new(x, 0.0, 0.0),
+            radius: 1.0,
+        })
+    };
+    let union = diffuse_model(Geometry::Csg {
+        operation: CsgOperation::Union,
+        left: sphere(-0.5),
+        right: sphere(0.5),
+    });
+    // Surfaces inside the other solid are skipped
+    let ray = Ray::new(Vec3::ZERO, Vec3::NEG_X);
+    let hit = union.hit(&ray).expect("Ray leaves the union");
+    assert!((hit.distance - 1.5).abs() < 1e-4);
+    assert!(!hit.is_front_face);
+    assert!(union.hit(&ray.with_tmax(1.4)).is_none());
+    assert!(!union.is_sampleable());
+    assert_vec3_near(union.aabb().min_position, Vec3::new(-1.5, -1.0, -1.0));
+}
+
+#[test]
+fn sdf_primitive_distances() {
+    let sphere = Sdf::Sphere { radius: 1.0 };
+    assert!((sphere.distance(Vec3::new(0.0, 3.0, 0.0)) - 2.0).abs() < 1e-6);
+    let cube = Sdf::Box {
+        size: Vec3::splat(2.0),
+    };
+    assert!((cube.distance(Vec3::new(3.0, 0.0, 0.0)) - 2.0).abs() < 1e-6);
+    assert!((cube.distance(Vec3::new(2.0, 2.0, 0.0)) - 2f32.sqrt()).abs() < 1e-6);
+    assert!((cube.distance(Vec3::ZERO) + 1.0).abs() < 1e-6);
+    let rounded = Sdf::RoundedBox {
+        size: Vec3::splat(2.0),
+        radius: 0.5,
+    };
+    assert!((rounded.distance(Vec3::new(3.0, 0.0, 0.0)) - 2.0).abs() < 1e-6);
+    assert!((rounded.distance(Vec3::splat(2.0)) - (3f32.sqrt() * 1.5 - 0.5)).abs() < 1e-5);
+    let torus = Sdf::Torus {
+        major_radius: 2.0,
+        minor_radius: 0.5,
+    };
+    assert!((torus.distance(Vec3::new(0.0, 0.0, 2.0)) + 0.5).abs() < 1e-6);
+    assert!((torus.distance(Vec3::ZERO) - 1.5).abs() < 1e-6);
+
+    let repeated = Sdf::Repeat {
+        sdf: Arc::new(Sdf::Translate {
+            offset: Vec3::new(0.0, 1.0, 0.0),
+            sdf: Arc::new(sphere),
+        }),
+        period: Vec3::new(4.0, 0.0, 0.0),
+    };
+    assert!((repeated.distance(Vec3::new(8.0, 1.0, 0.0)) + 1.0).abs() < 1e-6);
+    assert!((repeated.distance(Vec3::new(8.0, 5.0, 0.0)) - 3.0).abs() < 1e-6);
+}
+
+fn sdf_model(sdf: Sdf, bounds: Aabb) -> Model {
+    diffuse_model(Geometry::Sdf {
+        sdf: Arc::new(sdf),
+        bounds,
+    })
+}
+
+#[test]
+fn sdf_sphere_tracing_matches_sphere() {
+    let sphere = sdf_model(
+        Sdf::Sphere { radius: 1.0 },
+        Aabb::new(Vec3::ZERO, Vec3::splat(2.0)),
+    );
+    let ray = Ray::new(Vec3::new(0.3, 0.2, 5.0), Vec3::NEG_Z);
+    let hit = sphere.hit(&ray).expect("Ray hits the sphere");
+    let expected_z = (1.0f32 - 0.3 * 0.3 - 0.2 * 0.2).sqrt();
+    assert!((hit.distance - (5.0 - expected_z)).abs() < 1e-3);
+    assert!(hit.is_front_face);
+    assert!(
+        hit.normal
+            .abs_diff_eq(Vec3::new(0.3, 0.2, expected_z), 1e-3)
+    );
+
+    // Refracted rays find the far side, reflected rays leave the surface
+    let inside = Ray::new(hit.position, Vec3::NEG_Z).with_tmin(Ray::MIN_RAY_DISTANCE);
+    let exit = sphere.hit(&inside).expect("Ray leaves the sphere");
+    assert!((exit.distance - 2.0 * expected_z).abs() < 1e-3);
+    assert!(!exit.is_front_face);
+    let reflected = Ray::new(hit.position, Vec3::Z);
+    assert!(sphere.hit(&reflected).is_none());
+
+    // Geometry outside of the bounds is ignored
+    let clipped = sdf_model(
+        Sdf::Sphere { radius: 1.0 },
+        Aabb::new(Vec3::new(0.0, 5.0, 0.0), Vec3::splat(2.0)),
+    );
+    assert!(clipped.hit(&ray).is_none());
+    assert!(!clipped.is_sampleable());
+}
+
+#[test]
+fn sdf_operators_are_traced() {
+    // Twisting a box around its own axis keeps its center column
+    let twisted = sdf_model(
+        Sdf::Twist {
+            sdf: Arc::new(Sdf::Box {
+                size: Vec3::new(1.0, 4.0, 1.0),
+            }),
+            rate: 1.0,
+        },
+        Aabb::new(Vec3::ZERO, Vec3::new(2.0, 4.0, 2.0)),
+    );
+    let ray = Ray::new(Vec3::new(0.0, 5.0, 0.0), Vec3::NEG_Y);
+    let hit = twisted.hit(&ray).expect("Ray hits the twisted box");
+    assert!((hit.distance - 3.0).abs() < 1e-3);
+    assert_vec3_near(hit.normal, Vec3::Y);
+
+    let blob = sdf_model(
+        Sdf::SmoothUnion {
+            left: Arc::new(Sdf::Translate {
+                offset: Vec3::new(-0.6, 0.0, 0.0),
+                sdf: Arc::new(Sdf::Sphere { radius: 0.5 }),
+            }),
+            right: Arc::new(Sdf::Translate {
+                offset: Vec3::new(0.6, 0.0, 0.0),
+                sdf: Arc::new(Sdf::Sphere { radius: 0.5 }),
+            }),
+            smoothness: 0.5,
+        },
+        Aabb::new(Vec3::ZERO, Vec3::new(3.0, 2.0, 2.0)),
+    );
+    // The gap between both spheres is filled
+    let ray = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::NEG_Z);
+    assert!(blob.hit(&ray).is_some());
+}
+
+#[test]
+fn sdf_scene_encoding_round_trip() {
+    let sdf = Sdf::SmoothUnion {
+        left: Arc::new(Sdf::Torus {
+            major_radius: 1.0,
+            minor_radius: 0.25,
+        }),
+        right: Arc::new(Sdf::Repeat {
+            sdf: Arc::new(Sdf::RoundedBox {
+                size: Vec3::splat(0.5),
+                radius: 0.1,
+            }),
+            period: Vec3::new(2.0, 0.0, 0.0),
+        }),
+        smoothness: 0.2,
+    };
+    let objects = vec![Arc::new(Model::new(
+        Geometry::Sdf {
+            sdf: Arc::new(sdf),
+            bounds: Aabb::new(Vec3::ZERO, Vec3::new(6.0, 2.0, 3.0)),
+        },
+        Arc::new(Material::Diffuse {
+            albedo: Vec3::ONE.into(),
+        }),
+    ))];
+    let camera = Camera::new(Vec3::new(0.0, 0.0, 5.0), Vec3::NEG_Z, Vec3::Y, 45.0, 1.0);
+    let scene = Scene::with_background(camera, objects, Vec3::ONE);
+
+    let bytes = bincode::encode_to_vec(&scene, bincode::config::standard()).unwrap();
+    let (decoded, _): (Scene, _) =
+        bincode::decode_from_slice(&bytes, bincode::config::standard()).unwrap();
+    for x in [-2.0, -1.0, 0.0, 2.0] {
+        let ray = Ray::new(Vec3::new(x, 0.0, 5.0), Vec3::NEG_Z);
+        let hit = scene.hit(&ray).expect("Ray hits the SDF");
+        let decoded_hit = decoded.hit(&ray).expect("Ray hits the decoded SDF");
+        assert_eq!(hit.distance, decoded_hit.distance);
+    }
+}
+
+#[test]
+fn transformed_sphere_hit() {
+    let sphere = diffuse_model(Geometry::Sphere {
+        position: Vec3::ZERO,
+        radius: 1.0,
+    })
+    .with_transform(Transform::from_scale_rotation_translation(
+        Vec3::splat(2.0),
+        Quat::from_rotation_x(1.0),
+        Vec3::new(0.0, 0.0, -5.0),
+    ));
+    let ray = Ray::new(Vec3::ZERO, Vec3::NEG_Z);
+    let hit = sphere.hit(&ray).expect("Ray hits the sphere");
+
+    assert!((hit.distance - 3.0).abs() < 1e-5);
+    assert_vec3_near(hit.position, Vec3::new(0.0, 0.0, -3.0));
+    assert_vec3_near(hit.normal, Vec3::Z);
+    assert!((hit.object_area - 16.0 * PI).abs() < 1e-3);
+    assert!((sphere.area() - 16.0 * PI).abs() < 1e-3);
+    // Ray interval is kept in world space
+    assert!(sphere.hit(&ray.with_tmax(2.9)).is_none());
+}
+
+#[test]
+fn rotated_cuboid_hit_and_bounds() {
+    let cuboid = diffuse_model(Geometry::Cuboid {
+        position: Vec3::ZERO,
+        size: Vec3::ONE,
+    })
+    .with_transform(Transform::from_rotation(Quat::from_rotation_y(
+        45f32.to_radians(),
+    )));
+    let half_diagonal = 0.5 * 2f32.sqrt();
+    let ray = Ray::new(Vec3::new(5.0, 0.1, 0.0), Vec3::NEG_X);
+    let hit = cuboid.hit(&ray).expect("Ray hits the cuboid");
+
+    assert!((hit.distance - (5.0 - half_diagonal)).abs() < 1e-4);
+    assert!((hit.normal.x - 0.5f32.sqrt()).abs() < 1e-4);
+    assert!(hit.normal.y.abs() < 1e-5);
+
+    let aabb = cuboid.aabb();
+    assert_vec3_near(
+        aabb.min_position,
+        Vec3::new(-half_diagonal, -0.5, -half_diagonal),
+    );
+    assert_vec3_near(
+        aabb.max_position,
+        Vec3::new(half_diagonal, 0.5, half_diagonal),
+    );
+}
+
+#[test]
+fn transformed_surface_samples_match_hit_density() {
+    let quad = diffuse_model(Geometry::Quad {
+        position: Vec3::ZERO,
+        u: Vec3::X,
+        v: Vec3::Y,
+    })
+    .with_transform(Transform::from_scale_rotation_translation(
+        Vec3::new(2.0, 3.0, 1.0),
+        Quat::from_rotation_x(0.5),
+        Vec3::new(0.0, 0.0, -4.0),
+    ));
+    let sample = quad.sample_surface(Vec2::new(0.3, 0.6), 0.0);
+    assert!((sample.pdf - 1.0 / 6.0).abs() < 1e-5);
+
+    let origin = Vec3::new(0.5, 0.5, 2.0);
+    let ray = Ray::new(origin, (sample.position - origin).normalize());
+    let hit = quad.hit(&ray).expect("Ray hits the sampled position");
+    assert_vec3_near(hit.position, sample.position);
+    assert!((hit.object_area * sample.pdf - 1.0).abs() < 1e-4);
+    assert!(
+        sample.normal.abs_diff_eq(hit.normal, 1e-5) || sample.normal.abs_diff_eq(-hit.normal, 1e-5)
+    );
+}
+
+#[test]
+fn non_uniformly_scaled_areas() {
+    let scale = Transform::from_scale_rotation_translation(
+        Vec3::new(1.0, 1.0, 2.0),
+        Quat::from_rotation_y(0.7),
+        Vec3::new(1.0, 2.0, 3.0),
+    );
+    let quad = diffuse_model(Geometry::Quad {
+        position: Vec3::ZERO,
+        u: Vec3::X,
+        v: Vec3::new(0.0, 1.0, 1.0),
+    })
+    .with_transform(scale.clone());
+    assert!((quad.area() - 5f32.sqrt()).abs() < 1e-5);
+    let cuboid = diffuse_model(Geometry::Cuboid {
+        position: Vec3::ZERO,
+        size: Vec3::ONE,
+    })
+    .with_transform(scale.clone());
+    assert!((cuboid.area() - 10.0).abs() < 1e-4);
+    let mesh = diffuse_model(Geometry::Mesh(Arc::new(Mesh::new(
+        vec![Vec3::ZERO, Vec3::X, Vec3::Z],
+        vec![[0, 1, 2]],
+    ))))
+    .with_transform(scale.clone());
+    assert!((mesh.area() - 1.0).abs() < 1e-5);
+
+    // Prolate spheroid, whose area is 2 pi (1 + c asin(e) / e) for its
+    // semi-axes 1, 1 and c = 2, with eccentricity e
+    let spheroid = diffuse_model(Geometry::Sphere {
+        position: Vec3::ZERO,
+        radius: 1.0,
+    })
+    .with_transform(scale);
+    let e = 0.75f32.sqrt();
+    let expected = 2.0 * PI * (1.0 + 2.0 * e.asin() / e);
+    assert!(
+        (spheroid.area() - expected).abs() < 1e-3 * expected,
+        "{} != {expected}",
+        spheroid.area()
+    );
+}
+
+#[test]
+fn instances_share_encoded_geometry() {
+    let cube = Arc::new(Geometry::Cuboid {
+        position: Vec3::ZERO,
+        size: Vec3::ONE,
+    });
+    let light = Arc::new(Material::DiffuseLight {
+        emission: Vec3::ONE.into(),
+    });
+    let objects = (0..100)
+        .map(|idx| {
+            Arc::new(
+                Model::new(Geometry::Instance(cube.clone()), light.clone()).with_transform(
+                    Transform::from_translation(Vec3::new(2.0 * idx as f32, 0.0, 0.0)),
+                ),
+            )
+        })
+        .collect();
+    let camera = Camera::new(Vec3::ZERO, Vec3::Z, Vec3::Y, 45.0, 1.0);
+    let scene = Scene::new(camera, objects);
+
+    let bytes = bincode::encode_to_vec(&scene, bincode::config::standard()).unwrap();
+    let (decoded, _): (Scene, _) =
+        bincode::decode_from_slice(&bytes, bincode::config::standard()).unwrap();
+    let Geometry::Instance(first) = &decoded.objects()[0].geometry else {
+        panic!("Expected an instance");
+    };
+    for (object, decoded_object) in scene.objects().iter().zip(decoded.objects()) {
+        let Geometry::Instance(geometry) = &decoded_object.geometry else {
+            panic!("Expected an instance");
+        };
+        assert!(Arc::ptr_eq(geometry, first));
+        assert_eq!(object.transform, decoded_object.transform);
+    }
+
+    let ray = Ray::new(Vec3::new(50.0, 5.0, 0.0), Vec3::NEG_Y);
+    let hit = decoded.hit(&ray).expect("Ray hits an instance");
+    assert_vec3_near(hit.position, Vec3::new(50.0, 0.5, 0.0));
+}
+
+#[test]
+fn singular_transforms_are_rejected() {
+    let objects = vec![Arc::new(
+        Model::new(
+            Geometry::Sphere {
+                position: Vec3::ZERO,
+                radius: 1.0,
+            },
+            Arc::new(Material::DiffuseLight {
+                emission: Vec3::ONE.into(),
+            }),
+        )
+        .with_transform(Transform::from_scale(Vec3::new(1.0, 0.0, 1.0))),
+    )];
+    let camera = Camera::new(Vec3::ZERO, Vec3::Z, Vec3::Y, 45.0, 1.0);
+    let error = Scene::try_new(camera, objects).unwrap_err();
+    assert_eq!(
+        error.issues,
+        vec![SceneIssue::NonInvertibleTransform { object: 0 }]
+    );
+}
+
+#[test]
+fn moving_models_follow_ray_time() {
+    let sphere = diffuse_model(Geometry::Sphere {
+        position: Vec3::ZERO,
+        radius: 1.0,
+    })
+    .with_transform(Transform::from_translation(Vec3::new(0.0, 0.0, -5.0)))
+    .with_motion(Motion::Linear {
+        start: Box::new(Transform::IDENTITY),
+        end: Box::new(Transform::from_translation(Vec3::new(4.0, 0.0, 0.0))),
+    });
+    let ray = Ray::new(Vec3::new(2.0, 0.0, 0.0), Vec3::NEG_Z);
+    assert!(sphere.hit(&ray).is_none());
+    let hit = sphere
+        .hit(&ray.with_time(0.5))
+        .expect("Ray hits the sphere halfway");
+    assert_vec3_near(hit.position, Vec3::new(2.0, 0.0, -4.0));
+    // Motion is held outside of its time span
+    assert!(sphere.hit(&ray.with_time(2.0)).is_none());
+    assert!(
+        sphere
+            .hit(&Ray::new(Vec3::new(4.0, 0.0, 0.0), Vec3::NEG_Z).with_time(2.0))
+            .is_some()
+    );
+    let sample = sphere.sample_surface(Vec2::new(0.3, 0.6), 0.25);
+    assert!((sample.position.distance(Vec3::new(1.0, 0.0, -5.0)) - 1.0).abs() < 1e-4);
+
+    // Keyframes are reached exactly, rotations following the shortest arc
+    let motion = Motion::Keyframed {
+        keyframes: vec![
+            Keyframe {
+                time: 1.0,
+                transform: Transform::IDENTITY,
+            },
+            Keyframe {
+                time: 2.0,
+                transform: Transform::from_rotation(Quat::from_rotation_y(PI / 2.0)),
+            },
+            Keyframe {
+                time: 4.0,
+                transform: Transform::from_translation(Vec3::Y),
+            },
+        ],
+    };
+    assert!(motion.is_valid());
+    let rotation = Quat::from_rotation_y(PI / 4.0);
+    assert_vec3_near(motion.at(1.5).point_to_world(Vec3::X), rotation * Vec3::X);
+    assert_vec3_near(motion.at(2.0).point_to_world(Vec3::X), Vec3::NEG_Z);
+    assert_vec3_near(
+        motion.at(3.0).point_to_world(Vec3::ZERO),
+        Vec3::new(0.0, 0.5, 0.0),
+    );
+    assert_vec3_near(motion.at(0.0).point_to_world(Vec3::X), Vec3::X);
+}
+
+#[test]
+fn motion_bounds_contain_swept_models() {
+    let cuboid = diffuse_model(Geometry::Cuboid {
+        position: Vec3::new(2.0, 0.0, 0.0),
+        size: Vec3::new(1.0, 0.5, 0.5),
+    })
+    .with_motion(Motion::Keyframed {
+        keyframes: vec![
+            Keyframe {
+                time: 0.0,
+                transform: Transform::IDENTITY,
+            },
+            Keyframe {
+                time: 1.0,
+                transform: Transform::from_scale_rotation_translation(
+                    Vec3::splat(1.5),
+                    Quat::from_rotation_y(3.0),
+                    Vec3::new(0.0, 1.0, 0.0),
+                ),
+            },
+        ],
+    });
+    let aabb = cuboid.aabb();
+    for i in 0..=100 {
+        let time = i as f32 / 100.0;
+        let bounds = cuboid
+            .transform_at(time)
+            .expect("Moving models have a transform")
+            .aabb_to_world(&cuboid.geometry.aabb());
+        assert!(bounds.min_position.cmpge(aabb.min_position - 1e-4).all());
+        assert!(bounds.max_position.cmple(aabb.max_position + 1e-4).all());
+    }
+    // Bounds follow the arc instead of joining its ends
+    assert!(aabb.min_position.z < -2.0);
+
+    // Moving models are found through the scene BVH at any time
+    let sphere = diffuse_model(Geometry::Sphere {
+        position: Vec3::new(0.0, 10.0, 0.0),
+        radius: 1.0,
+    });
+    let camera = Camera::new(Vec3::ZERO, Vec3::Z, Vec3::Y, 45.0, 1.0);
+    let objects = vec![Arc::new(cuboid), Arc::new(sphere)];
+    let scene = Scene::with_background(camera, objects, Vec3::ONE);
+    let position = Quat::from_rotation_y(1.5) * Vec3::new(2.0 * 1.25, 0.5, 0.0);
+    let ray = Ray::new(position + Vec3::Y * 5.0, Vec3::NEG_Y).with_time(0.5);
+    let hit = scene.hit(&ray).expect("Ray hits the cuboid halfway");
+    assert!((hit.position.y - 0.5 - 0.25 * 1.25).abs() < 1e-4);
+
+    let bytes = bincode::encode_to_vec(&scene, bincode::config::standard()).unwrap();
+    let (decoded, _): (Scene, _) =
+        bincode::decode_from_slice(&bytes, bincode::config::standard()).unwrap();
+    let decoded_hit = decoded.hit(&ray).expect("Ray hits the decoded cuboid");
+    assert_eq!(decoded_hit.position, hit.position);
+}
+
+#[test]
+fn invalid_motion_and_shutter_are_rejected() {
+    let light = Arc::new(Material::DiffuseLight {
+        emission: Vec3::ONE.into(),
+    });
+    let sphere = Geometry::Sphere {
+        position: Vec3::ZERO,
+        radius: 1.0,
+    };
+    let objects = vec![
+        Arc::new(
+            Model::new(sphere.clone(), light.clone()).with_motion(Motion::Keyframed {
+                keyframes: Vec::new(),
+            }),
+        ),
+        Arc::new(Model::new(sphere, light).with_motion(Motion::Keyframed {
+            keyframes: vec![
+                Keyframe {
+                    time: 1.0,
+                    transform: Transform::IDENTITY,
+                },
+                Keyframe {
+                    time: 1.0,
+                    transform: Transform::IDENTITY,
+                },
+            ],
+        })),
+    ];
+    let camera = Camera::new(Vec3::ZERO, Vec3::Z, Vec3::Y, 45.0, 1.0);
+    let error = Scene::try_new(camera.clone(), objects).unwrap_err();
+    assert_eq!(
+        error.issues,
+        vec![
+            SceneIssue::InvalidMotion { object: 0 },
+            SceneIssue::InvalidMotion { object: 1 },
+        ]
+    );
+
+    let error = camera.clone().try_with_shutter(1.0, 0.0).unwrap_err();
+    assert_eq!(
+        error.issues,
+        vec![SceneIssue::InvalidShutter {
+            open: 1.0,
+            close: 0.0
+        }]
+    );
+    let camera = camera.with_shutter(0.5, 1.5);
+    for shutter in [0.0, 0.5, 0.99] {
+        let ray = camera
+            .create_viewport_ray(0.0, 0.0, Vec2::ZERO, shutter)
+            .unwrap();
+        assert!((ray.time() - (0.5 + shutter)).abs() < 1e-6);
+    }
+}
+
+#[test]
+fn empty_sdf_bounds_are_rejected() {
+    let objects = vec![
+        Arc::new(sdf_model(
+            Sdf::Sphere { radius: 1.0 },
+            Aabb {
+                min_position: Vec3::ONE,
+                max_position: Vec3::NEG_ONE,
+            },
+        )),
+        Arc::new(sdf_model(
+            Sdf::Sphere { radius: 1.0 },
+            Aabb {
+                min_position: Vec3::NEG_INFINITY,
+                max_position: Vec3::ONE,
+            },
+        )),
+    ];
+    let camera = Camera::new(Vec3::ZERO, Vec3::Z, Vec3::Y, 45.0, 1.0);
+    let error = Scene::try_with_background(camera, objects, Vec3::ONE).unwrap_err();
+    assert_eq!(
+        error.issues,
+        vec![
+            SceneIssue::InvalidSdfBounds { object: 0 },
+            SceneIssue::InvalidSdfBounds { object: 1 },
+        ]
+    );
+}
+
+fn normal_mapped_quad(normal_map: NormalMap) -> Model {
+    let material = Arc::new(Material::NormalMapped {
+        material: Arc::new(Material::Diffuse {
+            albedo: Vec3::ONE.into(),
+        }),
+        normal_map,
+    });
+    Model::new(
+        Geometry::Quad {
+            position: Vec3::new(-1.0, -1.0, 0.0),
+            u: Vec3::new(2.0, 0.0, 0.0),
+            v: Vec3::new(0.0, 2.0, 0.0),
+        },
+        material,
+    )
+}
+
+#[test]
+fn flat_normal_map_keeps_geometric_normal() {
+    let quad = normal_mapped_quad(NormalMap::Tangent {
+        texture: Vec3::new(0.5, 0.5, 1.0).into(),
+        strength: 1.0,
+    });
+    let ray = Ray::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+    let hit = quad.hit(&ray).expect("Ray hits the quad");
+    assert_vec3_near(hit.material.shading_normal(&hit, -ray.direction()), Vec3::Z);
+}
+
+#[test]
+fn tangent_normal_map_tilts_towards_u() {
+    let quad = normal_mapped_quad(NormalMap::Tangent {
+        texture: Vec3::new(1.0, 0.5, 1.0).into(),
+        strength: 1.0,
+    });
+    let expected = Vec3::new(1.0, 0.0, 1.0).normalize();
+
+    let ray = Ray::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+    let hit = quad.hit(&ray).expect("Ray hits the quad");
+    assert_vec3_near(
+        hit.material.shading_normal(&hit, -ray.direction()),
+        expected,
+    );
+
+    // From the back side the perturbed normal is flipped along with the
+    // geometric normal
+    let ray = Ray::new(Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 0.0, 1.0));
+    let hit = quad.hit(&ray).expect("Ray hits the quad");
+    assert!(!hit.is_front_face);
+    assert_vec3_near(
+        hit.material.shading_normal(&hit, -ray.direction()),
+        -expected,
+    );
+}
+
+#[test]
+fn constant_bump_map_keeps_geometric_normal() {
+    let quad = normal_mapped_quad(NormalMap::Bump {
+        height: 0.7.into(),
+        scale: 1.0,
+    });
+    let ray = Ray::new(Vec3::new(0.3, 0.2, 1.0), Vec3::new(0.0, 0.0, -1.0));
+    let hit = quad.hit(&ray).expect("Ray hits the quad");
+    assert_vec3_near(hit.material.shading_normal(&hit, -ray.direction()), Vec3::Z);
+}
+
+fn mesh_model(mesh: Mesh) -> Model {
+    diffuse_model(Geometry::Mesh(Arc::new(mesh)))
+}
+
+#[test]
+fn triangle_hit_surface_parameterization() {
+    let mesh = Mesh::new(
+        vec![
+            Vec3::new(0.0, 0.0, -2.0),
+            Vec3::new(2.0, 0.0, -2.0),
+            Vec3::new(0.0, 2.0, -2.0),
+        ],
+        vec![[0, 1, 2]],
+    )
+    .with_uvs(vec![Vec2::ZERO, Vec2::new(1.0, 0.0), Vec2::new(0.0, 1.0)]);
+    let triangle = mesh_model(mesh);
+
+    let ray = Ray::new(Vec3::new(0.5, 0.5, 0.0), Vec3::new(0.0, 0.0, -1.0));
+    let hit = triangle.hit(&ray).expect("Ray hits the triangle");
+    assert!((hit.distance - 2.0).abs() < 1e-5);
+    assert_vec3_near(hit.position, Vec3::new(0.5, 0.5, -2.0));
+    assert_vec3_near(hit.normal, Vec3::Z);
+    assert!(hit.is_front_face);
+    assert!(hit.uv.abs_diff_eq(Vec2::new(0.25, 0.25), 1e-5));
+    assert_vec3_near(hit.dpdu, Vec3::new(2.0, 0.0, 0.0));
+    assert_vec3_near(hit.dpdv, Vec3::new(0.0, 2.0, 0.0));
+    assert!((hit.object_area - 2.0).abs() < 1e-5);
+
+    let ray = Ray::new(Vec3::new(1.5, 1.5, 0.0), Vec3::new(0.0, 0.0, -1.0));
+    assert!(triangle.hit(&ray).is_none());
+}
+
+#[test]
+fn mesh_intersection_is_watertight() {
+    // Square split along its diagonal, with a vertex shared by both triangles
+    let square = mesh_model(Mesh::new(
+        vec![
+            Vec3::new(-1.0, -1.0, 0.0),
+            Vec3::new(1.0, -1.0, 0.0),
+            Vec3::new(1.0, 1.0, 0.0),
+            Vec3::new(-1.0, 1.0, 0.0),
+        ],
+        vec![[0, 1, 2], [0, 2, 3]],
+    ));
+    for i in 1..100 {
+        let t = i as f32 / 50.0 - 1.0;
+        let direction = Vec3::new(0.1, 0.2, -1.0).normalize();
+        let origin = Vec3::new(t, t, 0.0) - direction;
+        let ray = Ray::new(origin, direction);
+        assert!(square.hit(&ray).is_some(), "Ray through ({t}, {t}) missed");
+    }
+}
+
+#[test]
+fn mesh_bvh_matches_individual_triangles() {
+    // Bumpy grid, with enough triangles for a multi level BVH
+    let n = 16;
+    let positions = (0..=n)
+        .flat_map(|i| (0..=n).map(move |j| (i, j)))
+        .map(|(i, j)| {
+            let (x, z) = (i as f32 / n as f32, j as f32 / n as f32);
+            Vec3::new(x, 0.2 * (7.0 * x).sin() * (5.0 * z).cos(), z)
+        })
+        .collect::<Vec<_>>();
+    let indices = (0..n)
+        .flat_map(|i| (0..n).map(move |j| (i, j)))
+        .flat_map(|(i, j)| {
+            let a = i * (n + 1) + j;
+            let b = a + n + 1;
+            [[a, b, b + 1], [a, b + 1, a + 1]]
+        })
+        .collect::<Vec<_>>();
+    let mesh = mesh_model(Mesh::new(positions.clone(), indices.clone()));
+    let triangles = indices
+        .iter()
+        .map(|&triangle| mesh_model(Mesh::new(positions.clone(), vec![triangle])))
+        .collect::<Vec<_>>();
+
+    for i in 0..200 {
+        let t = i as f32 * 0.618;
+        let origin = Vec3::new(0.5 + t.sin(), 1.0, 0.5 + (1.3 * t).cos());
+        let target = Vec3::new(t.fract(), 0.0, (3.7 * t).fract());
+        let ray = Ray::new(origin, (target - origin).normalize());
+
+        let expected = triangles
+            .iter()
+            .filter_map(|triangle| triangle.hit(&ray))
+            .map(|hit| hit.distance)
+            .min_by(f32::total_cmp);
+        let actual = mesh.hit(&ray).map(|hit| hit.distance);
+        assert_eq!(actual, expected);
+    }
+}
+
+#[test]
+fn mesh_encoding_round_trip() {
+    let mesh = Mesh::new(
+        vec![
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        ],
+        vec![[0, 2, 1], [0, 1, 3], [0, 3, 2], [1, 2, 3]],
+    )
+    .with_normals(vec![Vec3::ONE.normalize(); 4]);
+
+    let bytes = bincode::encode_to_vec(&mesh, bincode::config::standard()).unwrap();
+    let (decoded, _): (Mesh, _) =
+        bincode::decode_from_slice(&bytes, bincode::config::standard()).unwrap();
+    assert_eq!(decoded.positions(), mesh.positions());
+    assert_eq!(decoded.indices(), mesh.indices());
+    assert!((decoded.area() - mesh.area()).abs() < 1e-6);
+}
+
+#[test]
+fn scene_validation_reports_given_object_indices() {
+    // Planes are moved after the bounded objects and the BVH reorders the
+    // spheres, but issues still refer to the given list
+    let mut objects = vec![Arc::new(diffuse_model(Geometry::Plane {
+        position: Vec3::ZERO,
+        normal: Vec3::Y,
+    }))];
+    objects.extend((0..4).map(|i| {
+        Arc::new(diffuse_model(Geometry::Sphere {
+            position: Vec3::new(4.0 - i as f32, 1.0, 0.0),
+            radius: if i == 0 { -1.0 } else { 0.5 },
+        }))
+    }));
+    let camera = Camera::new(Vec3::ZERO, Vec3::Z, Vec3::Y, 45.0, 1.0);
+    let expected = vec![SceneIssue::NonPositiveRadius {
+        object: 1,
+        radius: -1.0,
+    }];
+    let scene = Scene::with_background(camera.clone(), objects.clone(), Vec3::ONE);
+    assert!(!Arc::ptr_eq(&scene.objects()[1], &objects[1]));
+    assert_eq!(scene.validate().unwrap_err().issues, expected);
+    let error = Scene::try_with_background(camera, objects, Vec3::ONE).unwrap_err();
+    assert_eq!(error.issues, expected);
+}
+
+#[test]
+fn scene_validation_lists_every_issue() {
+    let glass = Arc::new(Material::Dielectric {
+        refraction_index: 0.5,
+    });
+    let white = Arc::new(Material::Diffuse {
+        albedo: Vec3::splat(0.8).into(),
+    });
+    let objects = vec![
+        Arc::new(Model::new(
+            Geometry::Sphere {
+                position: Vec3::ZERO,
+                radius: 1.0,
+            },
+            white.clone(),
+        )),
+        Arc::new(Model::new(
+            Geometry::Quad {
+                position: Vec3::ZERO,
+                u: Vec3::X,
+                v: Vec3::X * 2.0,
+            },
+            white.clone(),
+        )),
+        Arc::new(Model::new(
+            Geometry::Sphere {
+                position: Vec3::ONE,
+                radius: -1.0,
+            },
+            glass,
+        )),
+        Arc::new(Model::new(
+            Geometry::Cuboid {
+                position: Vec3::new(f32::NAN, 0.0, 0.0),
+                size: Vec3::ONE,
+            },
+            white,
+        )),
+    ];
+    let camera = Camera::new(Vec3::ZERO, Vec3::Z, Vec3::Y, 45.0, 1.0);
+    let error = Scene::try_new(camera, objects).unwrap_err();
+    assert_eq!(
+        error.issues,
+        vec![
+            SceneIssue::DegenerateQuad { object: 1 },
+            SceneIssue::NonPositiveRadius {
+                object: 2,
+                radius: -1.0
+            },
+            SceneIssue::InvalidIor {
+                object: 2,
+                ior: 0.5
+            },
+            SceneIssue::NonFiniteGeometry { object: 3 },
+            SceneIssue::NoEmitters,
+        ]
+    );
+}
+
+#[test]
+fn fallible_constructors() {
+    let error = Camera::try_new(Vec3::ZERO, Vec3::ONE, Vec3::Y, 200.0, 0.0).unwrap_err();
+    assert_eq!(
+        error.issues,
+        vec![
+            SceneIssue::NonNormalizedCameraVectors,
+            SceneIssue::InvalidFov(200.0),
+            SceneIssue::InvalidAspectRatio(0.0),
+        ]
+    );
+    let error = Camera::try_new(Vec3::ZERO, Vec3::Y, Vec3::NEG_Y, 45.0, 1.0).unwrap_err();
+    assert_eq!(error.issues, vec![SceneIssue::ParallelCameraVectors]);
+    assert!(Camera::try_new(Vec3::ZERO, Vec3::Z, Vec3::Y, 45.0, 1.0).is_ok());
+
+    assert!(Aabb::try_new(Vec3::ZERO, Vec3::new(1.0, -1.0, 1.0)).is_err());
+    assert!(Ray::try_new(Vec3::ZERO, Vec3::ONE).is_err());
+    assert!(BvhNode::<Model>::try_new(&mut []).is_err());
+}
+
+#[test]
+fn thin_lens_rays_converge_on_focus_plane() {
+    let pinhole = Camera::new(Vec3::ZERO, Vec3::NEG_Z, Vec3::Y, 45.0, 1.5);
+    let aperture = Aperture {
+        radius: 0.5,
+        focus_distance: 4.0,
+        blades: 6,
+        rotation: 10.0,
+    };
+    let camera = pinhole.clone().with_aperture(aperture);
+    for (u, v) in [(0.0, 0.0), (-1.0, 1.0), (0.3, -0.7)] {
+        let center = pinhole
+            .create_viewport_ray(u, v, Vec2::splat(0.7), 0.0)
+            .unwrap();
+        assert_eq!(center.origin(), Vec3::ZERO);
+        // Distance along the center ray to the plane in focus
+        let t = 4.0 / center.direction().dot(Vec3::NEG_Z);
+        let focus_point = center.at(t);
+        for lens in [Vec2::ZERO, Vec2::new(0.2, 0.9), Vec2::new(0.99, 0.5)] {
+            let ray = camera.create_viewport_ray(u, v, lens, 0.0).unwrap();
+            assert_eq!(ray.origin().z, 0.0);
+            let t = 4.0 / ray.direction().dot(Vec3::NEG_Z);
+            assert_vec3_near(ray.at(t), focus_point);
+        }
+    }
+
+    // Samples stay within the hexagon, whose edges are at the inradius
+    let inradius = 0.5 * (PI / 6.0).cos();
+    for i in 0..64 {
+        for j in 0..64 {
+            let u = Vec2::new(i as f32 + 0.5, j as f32 + 0.5) / 64.0;
+            let p = aperture.sample(u);
+            assert!(p.length() <= 0.5 + 1e-5);
+            let angle = p.y.atan2(p.x) - 10f32.to_radians();
+            let edge_distance = p.length() * (angle.rem_euclid(PI / 3.0) - PI / 6.0).cos();
+            assert!(edge_distance <= inradius + 1e-5);
+        }
+    }
+
+    for aperture in [
+        Aperture {
+            radius: -1.0,
+            ..aperture
+        },
+        Aperture {
+            focus_distance: 0.0,
+            ..aperture
+        },
+        Aperture {
+            blades: 2,
+            ..aperture
+        },
+    ] {
+        let error = pinhole.clone().try_with_aperture(aperture).unwrap_err();
+        assert_eq!(error.issues, vec![SceneIssue::InvalidAperture]);
+    }
+}
+
+#[test]
+fn camera_projections() {
+    let camera =
+        |projection| Camera::new_with_projection(Vec3::ONE, Vec3::NEG_Z, Vec3::Y, projection, 2.0);
+    let ray = |camera: &Camera, u, v| camera.create_viewport_ray(u, v, Vec2::ZERO, 0.0).unwrap();
+
+    // Orthographic rays are parallel, from a viewport of the given height
+    let orthographic = camera(Projection::Orthographic { height: 4.0 });
+    let corner = ray(&orthographic, 1.0, -1.0);
+    assert_vec3_near(corner.direction(), Vec3::NEG_Z);
+    assert_vec3_near(corner.origin(), Vec3::new(5.0, -1.0, 1.0));
+
+    // Right is +x and up is +y
+    let equirectangular = camera(Projection::Equirectangular);
+    for (u, v, direction) in [
+        (0.0, 0.0, Vec3::NEG_Z),
+        (0.5, 0.0, Vec3::X),
+        (-0.5, 0.0, Vec3::NEG_X),
+        (1.0, 0.0, Vec3::Z),
+        (0.3, 1.0, Vec3::Y),
+    ] {
+        let ray = ray(&equirectangular, u, v);
+        assert_eq!(ray.origin(), Vec3::ONE);
+        assert_vec3_near(ray.direction(), direction);
+    }
+
+    let fisheye = camera(Projection::Fisheye { fov: 180.0 });
+    assert_vec3_near(ray(&fisheye, 0.0, 0.0).direction(), Vec3::NEG_Z);
+    assert_vec3_near(ray(&fisheye, 0.0, -1.0).direction(), Vec3::NEG_Y);
+    assert_vec3_near(ray(&fisheye, 1.0, 0.0).direction(), Vec3::Z);
+    assert!(
+        fisheye
+            .create_viewport_ray(1.0, 0.5, Vec2::ZERO, 0.0)
+            .is_none()
+    );
+
+    for (projection, issue) in [
+        (
+            Projection::Perspective { fov: 180.0 },
+            SceneIssue::InvalidFov(180.0),
+        ),
+        (
+            Projection::Orthographic { height: 0.0 },
+            SceneIssue::InvalidViewportHeight(0.0),
+        ),
+        (
+            Projection::Fisheye { fov: 400.0 },
+            SceneIssue::InvalidFisheyeFov(400.0),
+        ),
+    ] {
+        let error = Camera::try_new_with_projection(Vec3::ZERO, Vec3::Z, Vec3::Y, projection, 1.0)
+            .unwrap_err();
+        assert_eq!(error.issues, vec![issue]);
+    }
+}
+
+#[test]
+fn scene_decoding_rejects_invalid_scenes() {
+    let objects = vec![Arc::new(Model::new(
+        Geometry::Sphere {
+            position: Vec3::ZERO,
+            radius: -1.0,
+        },
+        Arc::new(Material::DiffuseLight {
+            emission: Vec3::ONE.into(),
+        }),
+    ))];
+    let camera = Camera::new(Vec3::ZERO, Vec3::Z, Vec3::Y, 45.0, 1.0);
+    let scene = Scene::new(camera, objects);
+    assert!(scene.validate().is_err());
+
+    let bytes = bincode::encode_to_vec(&scene, bincode::config::standard()).unwrap();
+    let decoded: Result<(Scene, _), _> =
+        bincode::decode_from_slice(&bytes, bincode::config::standard());
+    assert!(decoded.is_err());
+}
+
+#[test]
+fn absorbing_medium_follows_beer_lambert() {
+    let medium = Medium::Homogeneous {
+        sigma_a: Vec3::new(0.0, 0.5, 1.0),
+        sigma_s: Vec3::ZERO,
+        g: 0.0,
+    };
+    let ray = Ray::new(Vec3::ZERO, Vec3::X);
+    let expected = Vec3::new(1.0, (-1.0f32).exp(), (-2.0f32).exp());
+    let mut rng = rand::rng();
+    assert_vec3_near(
+        medium.transmittance(&ray, 2.0 + ray.tmin(), &mut rng),
+        expected,
+    );
+    let MediumSample::Transmitted { weight } = medium.sample(&ray, 2.0 + ray.tmin(), &mut rng)
+    else {
+        panic!("Absorbing media only transmit light");
+    };
+    assert_vec3_near(weight, expected);
+}
+
+#[test]
+fn henyey_greenstein_sampling_matches_eval() {
+    for g in [-0.7, 0.0, 0.5] {
+        let phase = Medium::Homogeneous {
+            sigma_a: Vec3::ZERO,
+            sigma_s: Vec3::ONE,
+            g,
+        }
+        .phase();
+        let wo = Vec3::new(0.3, -0.4, 0.5).normalize();
+        for u in [
+            Vec2::new(0.1, 0.2),
+            Vec2::new(0.5, 0.9),
+            Vec2::new(0.95, 0.4),
+        ] {
+            let (wi, pdf) = phase.sample(wo, u);
+            assert!(wi.is_normalized());
+            assert!((pdf - phase.eval(wo, wi)).abs() < 1e-4 * pdf.max(1.0));
+        }
+        // Phase functions integrate to one over the sphere
+        let n = 256;
+        let mut integral = 0.0;
+        for i in 0..n {
+            for j in 0..n {
+                let cos_theta = 1.0 - 2.0 * (i as f32 + 0.5) / n as f32;
+                let phi = 2.0 * PI * (j as f32 + 0.5) / n as f32;
+                let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+                let wi = Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+                integral += phase.eval(wo, wi) * 4.0 * PI / (n * n) as f32;
+            }
+        }
+        assert!((integral - 1.0).abs() < 1e-2, "{g}: {integral}");
+    }
+}
+
+#[test]
+fn invalid_media_are_rejected() {
+    let medium = Arc::new(Medium::Homogeneous {
+        sigma_a: Vec3::new(-1.0, 0.0, 0.0),
+        sigma_s: Vec3::ZERO,
+        g: 1.0,
+    });
+    let objects = vec![Arc::new(
+        Model::new(
+            Geometry::Sphere {
+                position: Vec3::ZERO,
+                radius: 1.0,
+            },
+            Arc::new(Material::Interface),
+        )
+        .with_medium(medium.clone()),
+    )];
+    let camera = Camera::new(Vec3::ZERO, Vec3::Z, Vec3::Y, 45.0, 1.0);
+    let scene = Scene::with_background(camera, objects, Vec3::ONE);
+    assert_eq!(
+        scene.validate().unwrap_err().issues,
+        vec![SceneIssue::InvalidMedium { object: 0 }]
+    );
+    let error = scene.try_with_medium(medium).unwrap_err();
+    assert_eq!(error.issues, vec![SceneIssue::InvalidFog]);
+}
+
+fn grid_medium(grid: VoxelGrid, sigma_t: f32) -> Medium {
+    Medium::Grid {
+        grid: Arc::new(grid),
+        bounds: Aabb::from_positions(Vec3::ZERO, Vec3::new(2.0, 1.0, 1.0)),
+        sigma_a: Vec3::splat(0.5 * sigma_t),
+        sigma_s: Vec3::splat(0.5 * sigma_t),
+        g: 0.0,
+    }
+}
+
+#[test]
+fn voxel_grid_interpolates_densities() {
+    let grid = VoxelGrid::new(UVec3::new(2, 1, 1), vec![0.0, 1.0]);
+    assert_eq!(grid.density(Vec3::new(0.25, 0.5, 0.5)), 0.0);
+    assert_eq!(grid.density(Vec3::new(0.5, 0.5, 0.5)), 0.5);
+    assert_eq!(grid.density(Vec3::new(0.75, 0.5, 0.5)), 1.0);
+    // Densities are clamped to the outer voxel centers
+    assert_eq!(grid.density(Vec3::new(1.0, 0.0, 1.0)), 1.0);
+}
+
+#[test]
+fn grid_medium_tracking_matches_beer_lambert() {
+    // Only the second half of the grid is dense, so rays through the grid
+    // cross 1 unit of density 2, plus the interpolation ramp
+    let resolution = UVec3::new(32, 4, 4);
+    let densities = (0..resolution.element_product())
+        .map(|idx| if idx % 32 < 16 { 0.0 } else { 2.0 })
+        .collect();
+    let medium = grid_medium(VoxelGrid::new(resolution, densities), 0.5);
+    let ray = Ray::new(Vec3::new(-1.0, 0.5, 0.5), Vec3::X);
+    let expected = (-0.5f32 * 2.0).exp();
+
+    let mut rng = rand::rng();
+    let n = 20000;
+    let mut transmittance = Vec3::ZERO;
+    let mut transmitted = 0;
+    for _ in 0..n {
+        transmittance += medium.transmittance(&ray, 4.0, &mut rng) / n as f32;
+        if let MediumSample::Transmitted { weight } = medium.sample(&ray, 4.0, &mut rng) {
+            assert_vec3_near(weight, Vec3::ONE);
+            transmitted += 1;
+        }
+    }
+    assert!((transmittance.x - expected).abs() < 0.02, "{transmittance}");
+    let transmitted = transmitted as f32 / n as f32;
+    assert!((transmitted - expected).abs() < 0.02, "{transmitted}");
+
+    // Rays missing the bounds go through unchanged
+    let ray = Ray::new(Vec3::new(-1.0, 2.0, 0.5), Vec3::X);
+    assert_eq!(medium.transmittance(&ray, 4.0, &mut rng), Vec3::ONE);
+}
+
+#[test]
+fn scene_media_share_voxel_grids() {
+    let medium = Arc::new(grid_medium(
+        VoxelGrid::new(UVec3::splat(2), vec![1.0; 8]),
+        1.0,
+    ));
+    let objects = (0..2)
+        .map(|i| {
+            Arc::new(
+                Model::new(
+                    Geometry::Sphere {
+                        position: Vec3::new(i as f32 * 3.0, 0.0, 0.0),
+                        radius: 1.0,
+                    },
+                    Arc::new(Material::Interface),
+                )
+                .with_medium(medium.clone()),
+            )
+        })
+        .collect();
+    let camera = Camera::new(Vec3::ZERO, Vec3::Z, Vec3::Y, 45.0, 1.0);
+    let scene = Scene::with_background(camera, objects, Vec3::ONE);
+    let grid = |scene: &Scene, idx: usize| match scene.objects()[idx].medium.as_deref() {
+        Some(Medium::Grid { grid, .. }) => grid.clone(),
+        _ => panic!("Expected a grid medium"),
+    };
+    assert!(Arc::ptr_eq(&grid(&scene, 0), &grid(&scene.clone(), 0)));
+
+    let bytes = bincode::encode_to_vec(&scene, bincode::config::standard()).unwrap();
+    let (decoded, _): (Scene, _) =
+        bincode::decode_from_slice(&bytes, bincode::config::standard()).unwrap();
+    assert!(Arc::ptr_eq(&grid(&decoded, 0), &grid(&decoded, 1)));
+    assert_eq!(
+        decoded.objects()[0].medium.as_ref().map(|m| m.is_valid()),
+        Some(true)
+    );
+}
+
+/// Environment map with a dim gradient and a small bright spot above the
+/// horizon.
+fn spot_environment() -> EnvironmentMap {
+    let (width, height) = (32, 16);
+    let pixels = (0..height)
+        .flat_map(|y| {
+            (0..width).map(move |x| match (x, y) {
+                (20, 5) => Vec3::new(200.0, 150.0, 100.0),
+                _ => Vec3::splat(0.1 + y as f32 * 0.02),
+            })
+        })
+        .collect();
+    EnvironmentMap::new(width, height, pixels).with_rotation(30.0)
+}
+
+/// Integral of `f` over the sphere of directions, by midpoint quadrature.
+fn sphere_integral(f: impl Fn(Vec3) -> f32) -> f32 {
+    let n = 512;
+    let mut integral = 0.0;
+    for i in 0..n {
+        for j in 0..n {
+            let cos_theta = 1.0 - 2.0 * (i as f32 + 0.5) / n as f32;
+            let phi = 2.0 * PI * (j as f32 + 0.5) / n as f32;
+            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+            let direction = Vec3::new(sin_theta * phi.cos(), cos_theta, sin_theta * phi.sin());
+            integral += f(direction) * 4.0 * PI / (n * n) as f32;
+        }
+    }
+    integral
+}
+
+#[test]
+fn environment_map_sampling_matches_pdf() {
+    let environment = spot_environment();
+    let n = 256;
+    let mut estimate = 0.0;
+    for i in 0..n {
+        for j in 0..n {
+            let u = Vec2::new((i as f32 + 0.5) / n as f32, (j as f32 + 0.5) / n as f32);
+            let (direction, radiance, pdf) = environment.sample(u);
+            assert!(direction.is_normalized());
+            assert!(pdf > 0.0);
+            assert!((pdf - environment.pdf(direction)).abs() < 1e-3 * pdf);
+            assert_eq!(radiance, environment.eval(direction));
+            estimate += radiance.y / pdf / (n * n) as f32;
+        }
+    }
+    let integral = sphere_integral(|direction| environment.eval(direction).y);
+    assert!(
+        (estimate - integral).abs() < 2e-2 * integral,
+        "{estimate} != {integral}"
+    );
+    // Densities integrate to one over the sphere
+    let total = sphere_integral(|direction| environment.pdf(direction));
+    assert!((total - 1.0).abs() < 1e-2, "{total}");
+}
+
+#[test]
+fn environment_map_lights_escaped_rays() {
+    // Columns of increasing red, with the image center facing -z
+    let pixels = (0..2)
+        .flat_map(|_| (0..4).map(|x| Vec3::new(x as f32, 0.0, 1.0)))
+        .collect();
+    let environment = EnvironmentMap::new(4, 2, pixels);
+    assert_vec3_near(environment.eval(Vec3::NEG_Z), Vec3::new(1.5, 0.0, 1.0));
+    assert_vec3_near(environment.eval(Vec3::X), Vec3::new(0.5, 0.0, 1.0));
+    let rotated = environment.clone().with_rotation(90.0);
+    assert_vec3_near(rotated.eval(Vec3::NEG_X), environment.eval(Vec3::NEG_Z));
+
+    // Environment maps light scenes without emissive objects
+    let sphere = diffuse_model(Geometry::Sphere {
+        position: Vec3::new(0.0, 0.0, -5.0),
+        radius: 1.0,
+    });
+    let camera = Camera::new(Vec3::ZERO, Vec3::NEG_Z, Vec3::NEG_Y, 45.0, 1.0);
+    let scene = Scene::try_with_environment(
+        camera.clone(),
+        vec![Arc::new(sphere.clone())],
+        Arc::new(rotated.clone().into()),
+    )
+    .unwrap();
+    assert_eq!(scene.num_lights(), 1);
+    assert_eq!(scene.escaped_radiance(Vec3::X), rotated.eval(Vec3::X));
+    let mut rng = SmallRng::seed_from_u64(7);
+    let ray = Ray::new(Vec3::ZERO, Vec3::NEG_Y);
+    assert_eq!(
+        Renderer::new().trace(&scene, &ray, 4, &mut rng),
+        rotated.eval(Vec3::NEG_Y)
+    );
+
+    let bytes = bincode::encode_to_vec(&scene, bincode::config::standard()).unwrap();
+    let (decoded, _): (Scene, _) =
+        bincode::decode_from_slice(&bytes, bincode::config::standard()).unwrap();
+    let Some(Environment::Map(decoded_environment)) = decoded.environment().map(|e| &**e) else {
+        panic!("Environment map is decoded");
+    };
+    assert_eq!(decoded_environment.rotation(), 90.0);
+    for direction in [Vec3::X, Vec3::NEG_Z, Vec3::new(0.3, 0.5, 0.8).normalize()] {
+        assert_eq!(decoded_environment.eval(direction), rotated.eval(direction));
+        assert_eq!(decoded_environment.pdf(direction), rotated.pdf(direction));
+    }
+
+    let black = EnvironmentMap::new(1, 1, vec![Vec3::ZERO]).with_rotation(f32::NAN);
+    let error = Scene::try_with_environment(camera, vec![Arc::new(sphere)], Arc::new(black.into()))
+        .unwrap_err();
+    assert_eq!(
+        error.issues,
+        vec![
+            SceneIssue::NonFiniteEnvironmentRotation,
+            SceneIssue::NoEmitters
+        ]
+    );
+}
+
+#[test]
+fn sky_sampling_matches_pdf() {
+    let sky = Sky::new(Vec3::new(0.4, 0.5, -0.7), 3.0);
+    let sun = sky.sun_direction();
+    let n = 256;
+    let mut estimate = 0.0;
+    for i in 0..n {
+        for j in 0..n {
+            let u = Vec2::new((i as f32 + 0.5) / n as f32, (j as f32 + 0.5) / n as f32);
+            let (direction, radiance, pdf) = sky.sample(u);
+            assert!(direction.is_normalized());
+            assert!(pdf > 0.0);
+            assert!((pdf - sky.pdf(direction)).abs() < 1e-3 * pdf);
+            assert_eq!(radiance, sky.eval(direction));
+            estimate += radiance.y / pdf / (n * n) as f32;
+        }
+    }
+    // Quadrature misses the tiny sun disc, whose radiance is constant
+    let cos_sun = SUN_ANGULAR_RADIUS.cos();
+    let integral = sphere_integral(|direction| {
+        if direction.dot(sun) > cos_sun {
+            0.0
+        } else {
+            sky.eval(direction).y
+        }
+    }) + sky.sun_radiance().y * sun_solid_angle();
+    assert!(
+        (estimate - integral).abs() < 2e-2 * integral,
+        "{estimate} != {integral}"
+    );
+}
+
+#[test]
+fn sky_radiance() {
+    // The sun covers about 6.8e-5 steradians
+    assert!((sun_solid_angle() - 6.8e-5).abs() < 1e-6);
+
+    let sky = Sky::new(Vec3::new(1.0, 0.2, 0.0), 2.5);
+    let sun = sky.sun_direction();
+    assert!(sun.is_normalized());
+    let beside_sun = Quat::from_rotation_z(2.0 * SUN_ANGULAR_RADIUS) * sun;
+    let sun_radiance = sky.sun_radiance();
+    assert!(
+        (sky.eval(sun) - sky.eval(beside_sun)).abs_diff_eq(sun_radiance, 1e-3 * sun_radiance.x)
+    );
+    assert_eq!(sky.eval(Vec3::NEG_Y), Vec3::ZERO);
+    assert_eq!(sky.pdf(Vec3::NEG_Y), 0.0);
+    // Brighter around the sun than opposite to it, and bluer at the zenith
+    let around_sun = Vec3::new(1.0, 0.3, 0.1).normalize();
+    let opposite = Vec3::new(-1.0, 0.3, 0.1).normalize();
+    assert!(sky.eval(around_sun).y > 2.0 * sky.eval(opposite).y);
+    let zenith = sky.eval(Vec3::Y);
+    assert!(zenith.z > zenith.x);
+    // Sunlight crosses more atmosphere near the horizon, reddening it
+    assert!(sun_radiance.x > sun_radiance.z);
+    let high_sun = Sky::new(Vec3::Y, 2.5).sun_radiance();
+    assert!(high_sun.z > sun_radiance.z);
+
+    let bright = sky.clone().with_intensity(2.0);
+    assert_vec3_near(bright.eval(around_sun), sky.eval(around_sun) * 2.0);
+    assert_eq!(bright.pdf(around_sun), sky.pdf(around_sun));
+}
+
+#[test]
+fn invalid_skies_are_rejected() {
+    for (sun_direction, turbidity) in [
+        (Vec3::new(0.0, -0.1, 1.0), 3.0),
+        (Vec3::ZERO, 3.0),
+        (Vec3::new(f32::NAN, 1.0, 0.0), 3.0),
+        (Vec3::Y, 1.0),
+        (Vec3::Y, 20.0),
+    ] {
+        let error = Sky::try_new(sun_direction, turbidity).unwrap_err();
+        assert_eq!(error.issues, vec![SceneIssue::InvalidSky]);
+    }
+
+    // Skies light scenes without emissive objects, unless they are black
+    let sphere = Arc::new(diffuse_model(Geometry::Sphere {
+        position: Vec3::new(0.0, 0.0, -5.0),
+        radius: 1.0,
+    }));
+    let camera = Camera::new(Vec3::ZERO, Vec3::NEG_Z, Vec3::NEG_Y, 45.0, 1.0);
+    let sky = Sky::new(Vec3::new(0.0, 1.0, 1.0), 3.0);
+    let scene = Scene::try_with_environment(
+        camera.clone(),
+        vec![sphere.clone()],
+        Arc::new(sky.clone().with_intensity(1.5).into()),
+    )
+    .unwrap();
+    let bytes = bincode::encode_to_vec(&scene, bincode::config::standard()).unwrap();
+    let (decoded, _): (Scene, _) =
+        bincode::decode_from_slice(&bytes, bincode::config::standard()).unwrap();
+    let Some(Environment::Sky(decoded_sky)) = decoded.environment().map(|e| &**e) else {
+        panic!("Sky is decoded");
+    };
+    assert_eq!(decoded_sky.intensity(), 1.5);
+    for direction in [Vec3::Y, Vec3::new(0.3, 0.5, 0.8).normalize()] {
+        assert_eq!(
+            decoded.escaped_radiance(direction),
+            scene.escaped_radiance(direction)
+        );
+        assert_eq!(
+            decoded.environment_pdf(direction),
+            scene.environment_pdf(direction)
+        );
+    }
+
+    let error = Scene::try_with_environment(
+        camera.clone(),
+        vec![sphere.clone()],
+        Arc::new(sky.clone().with_intensity(f32::NAN).into()),
+    )
+    .unwrap_err();
+    assert_eq!(error.issues, vec![SceneIssue::InvalidSky]);
+    let error = Scene::try_with_environment(
+        camera,
+        vec![sphere],
+        Arc::new(sky.with_intensity(0.0).into()),
+    )
+    .unwrap_err();
+    assert_eq!(error.issues, vec![SceneIssue::NoEmitters]);
+}
+
+#[test]
+fn diffuse_surface_under_environment_map() {
+    // Lambertian surface facing up, so that it reflects the irradiance of the
+    // upper hemisphere
+    let environment = Arc::new(Environment::from(spot_environment()));
+    let quad = Model::new(
+        Geometry::Quad {
+            position: Vec3::new(-100.0, 0.0, -100.0),
+            u: Vec3::new(200.0, 0.0, 0.0),
+            v: Vec3::new(0.0, 0.0, 200.0),
+        },
+        Arc::new(Material::Diffuse {
+            albedo: Vec3::splat(0.5).into(),
+        }),
+    );
+    let camera = Camera::new(Vec3::Y, Vec3::NEG_Y, Vec3::Z, 45.0, 1.0);
+    let scene = Scene::with_environment(camera, vec![Arc::new(quad)], environment.clone());
+    let expected = 0.5 / PI
+        * sphere_integral(|direction| environment.eval(direction).y * direction.y.max(0.0));
+
+    let renderer = Renderer::new();
+    let mut rng = SmallRng::seed_from_u64(1);
+    let ray = Ray::new(Vec3::Y, Vec3::NEG_Y);
+    let n = 20000;
+    let estimate = (0..n)
+        .map(|_| renderer.trace(&scene, &ray, 4, &mut rng).y)
+        .sum::<f32>()
+        / n as f32;
+    assert!(
+        (estimate - expected).abs() < 3e-2 * expected,
+        "{estimate} != {expected}"
+    );
+}
+
+#[test]
+fn direct_light_sampling_is_unbiased() {
+    // Lambertian surface lit by a square light right above it, which is
+    // reached both by light sampling and by BSDF sampling
+    let albedo = 0.5;
+    let emission = 4.0;
+    let floor = Model::new(
+        Geometry::Quad {
+            position: Vec3::new(-100.0, 0.0, -100.0),
+            u: Vec3::new(200.0, 0.0, 0.0),
+            v: Vec3::new(0.0, 0.0, 200.0),
+        },
+        Arc::new(Material::Diffuse {
+            albedo: Vec3::splat(albedo).into(),
+        }),
+    );
+    let light = Model::new(
+        Geometry::Quad {
+            position: Vec3::new(-0.5, 1.0, -0.5),
+            u: Vec3::new(1.0, 0.0, 0.0),
+            v: Vec3::new(0.0, 0.0, 1.0),
+        },
+        Arc::new(Material::DiffuseLight {
+            emission: Vec3::splat(emission).into(),
+        }),
+    );
+    let camera = Camera::new(Vec3::Y, Vec3::NEG_Y, Vec3::Z, 45.0, 1.0);
+    let scene = Scene::new(camera, vec![Arc::new(floor), Arc::new(light)]);
+
+    // Form factor between the origin and the light, summed over the four
+    // quarters of the light which have a corner above the origin
+    let (x, y) = (0.5_f32, 0.5_f32);
+    let quarter = (x / (1.0 + x * x).sqrt() * (y / (1.0 + x * x).sqrt()).atan()
+        + y / (1.0 + y * y).sqrt() * (x / (1.0 + y * y).sqrt()).atan())
+        / (2.0 * PI);
+    let expected = albedo * emission * 4.0 * quarter;
+
+    // Cosine weighted sampling of the floor BSDF alone
+    let mut rng = SmallRng::seed_from_u64(1);
+    let n = 100000;
+    let bsdf_estimate = (0..n)
+        .filter_map(|_| {
+            let u = Vec2::new(rng.random(), rng.random());
+            let local = sample_cosine_hemisphere(u);
+            let ray = Ray::new(Vec3::ZERO, Vec3::new(local.x, local.z, local.y));
+            scene.hit(&ray)
+        })
+        .map(|hit| albedo * hit.material.emission(hit.uv, hit.position).y)
+        .sum::<f32>()
+        / n as f32;
+    assert!(
+        (bsdf_estimate - expected).abs() < 3e-2 * expected,
+        "{bsdf_estimate} != {expected}"
+    );
+
+    // Light sampling combined with BSDF sampling
+    let renderer = Renderer::new();
+    let ray = Ray::new(Vec3::new(0.0, 0.5, 0.0), Vec3::NEG_Y);
+    let n = 20000;
+    let estimate = (0..n)
+        .map(|_| renderer.trace(&scene, &ray, 4, &mut rng).y)
+        .sum::<f32>()
+        / n as f32;
+    assert!(
+        (estimate - bsdf_estimate).abs() < 3e-2 * expected,
+        "{estimate} != {bsdf_estimate}"
+    );
+    assert!(
+        (estimate - expected).abs() < 3e-2 * expected,
+        "{estimate} != {expected}"
+    );
+}